@@ -1,23 +1,47 @@
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
 use raidhos_core as core;
+use std::io::IsTerminal;
 
 #[derive(Parser)]
 #[command(name = "raidhos-cli", version, about = "RaidhOS CLI")]
 struct Cli {
+    /// Output format; json wraps every result in {ok, data, error}, the
+    /// same envelope the privileged helper prints.
+    #[arg(long, global = true, value_enum, default_value = "text")]
+    format: Format,
     #[command(subcommand)]
     command: Commands,
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Format {
+    Text,
+    Json,
+}
+
+// Parsed once at startup; boxing `Install` would only complicate the match.
+#[allow(clippy::large_enum_variant)]
 #[derive(Subcommand)]
 enum Commands {
     ListDisks,
     ScanIsos {
         #[arg(long, value_delimiter = ',', default_value = "/media,/mnt,/home")]
         dirs: Vec<String>,
+        /// Also print each ISO's SHA-256. Reads every file in full.
+        #[arg(long, default_value_t = false)]
+        checksums: bool,
     },
-    Install {
+    /// Scan /boot/isos on a data partition, mounting it read-only if needed.
+    ScanDevice {
         #[arg(long)]
         device: String,
+    },
+    Install {
+        #[arg(long, required_unless_present = "serial", conflicts_with = "serial")]
+        device: Option<String>,
+        /// Install to the disk with this serial number instead of --device.
+        #[arg(long)]
+        serial: Option<String>,
         #[arg(long, default_value = "0.1.0")]
         payload_version: String,
         #[arg(long, default_value_t = true)]
@@ -26,7 +50,133 @@ enum Commands {
         dry_run: bool,
         #[arg(long, default_value_t = false)]
         allow_write: bool,
+        /// Bootloader architecture (x86_64 or aarch64); defaults to the host.
+        #[arg(long)]
+        arch: Option<String>,
+        /// Payload directory with esp/ and data/; overrides RAIDHOS_PAYLOAD_DIR.
+        #[arg(long)]
+        payload_dir: Option<String>,
+        #[arg(
+            long,
+            default_value_t = false,
+            help = format!(
+                "Single exFAT data partition labelled {}, no ESP or bootloader.",
+                core::defaults::DATA_LABEL
+            )
+        )]
+        data_only: bool,
+        /// Leave the ESP/data mounts in place if the payload copy fails.
+        #[arg(long, default_value_t = false)]
+        keep_mounts_on_error: bool,
+        /// Add a hybrid MBR entry for the ESP, for firmware that ignores GPT.
+        #[arg(long, default_value_t = false)]
+        hybrid_mbr: bool,
+        /// Refuse a disk that already has partitions.
+        #[arg(long, default_value_t = false)]
+        require_empty: bool,
+        /// Zero the first and last MiB of the disk before partitioning.
+        #[arg(long, default_value_t = false)]
+        zero_ends: bool,
+        /// Append every external command run to this file as JSON Lines.
+        #[arg(long)]
+        command_log: Option<std::path::PathBuf>,
+        /// Keep existing partitions and install into the free region
+        /// starting this many MiB into the disk.
+        #[arg(long, requires = "free_size_mib")]
+        free_start_mib: Option<u64>,
+        /// Size in MiB of the free region used with --free-start-mib.
+        #[arg(long, requires = "free_start_mib")]
+        free_size_mib: Option<u64>,
+        /// DEVICE is an existing partition: format it as the data partition
+        /// and leave the partition table alone.
+        #[arg(long, default_value_t = false, conflicts_with = "free_start_mib")]
+        in_partition: bool,
+        /// Sibling partition to use as the ESP with --in-partition.
+        #[arg(long, requires = "in_partition")]
+        esp_partition: Option<String>,
+        /// GPT type GUID for the ESP (default: EFI System).
+        #[arg(long)]
+        esp_type_guid: Option<String>,
+        /// GPT type GUID for the data partition (default: Microsoft Basic Data).
+        #[arg(long)]
+        data_type_guid: Option<String>,
+        /// Generate the boot menu from the ISOs the payload copies to
+        /// /boot/isos instead of using the payload's grub.cfg.
+        #[arg(long, default_value_t = false)]
+        auto_menu: bool,
+        /// Scan the ESP for bad blocks and zero the data partition instead
+        /// of a quick format.
+        #[arg(long, default_value_t = false)]
+        full_format: bool,
+        /// Boot menu to install: grub, or syslinux for BIOS machines.
+        #[arg(long, default_value = "grub")]
+        menu_backend: String,
+        /// Start the data partition at this MiB offset, a multiple of 4,
+        /// for controllers that want it aligned (default: right after the ESP).
+        #[arg(long, conflicts_with_all = ["free_start_mib", "in_partition"])]
+        data_start_mib: Option<u32>,
+        /// ESP size in MiB, at least 32 (default: 32).
+        #[arg(long, conflicts_with_all = ["data_only", "in_partition"])]
+        esp_size_mib: Option<u64>,
+        /// Data partition size in MiB, leaving the rest of the disk
+        /// unallocated (default: all of it).
+        #[arg(long, conflicts_with = "in_partition")]
+        data_size_mib: Option<u64>,
+        /// Label for the data partition (default: DATA).
+        #[arg(long)]
+        data_label: Option<String>,
+        /// Data partition filesystem: exfat, ext4 or ntfs.
+        #[arg(long, default_value = "exfat")]
+        data_fs: String,
+        /// Without --data-label, use DATA2, DATA3, ... when another
+        /// attached disk already has a DATA partition.
+        #[arg(long, default_value_t = false)]
+        unique_labels: bool,
     },
+    /// Remove RaidhOS from a stick, keeping ISOs unless --reformat is given.
+    Uninstall {
+        #[arg(long)]
+        device: String,
+        /// Repartition as a single plain exFAT partition instead.
+        #[arg(long, default_value_t = false)]
+        reformat: bool,
+        #[arg(long, default_value_t = false)]
+        allow_write: bool,
+    },
+    /// Wipe DEVICE and format it as one plain partition, without RaidhOS.
+    Format {
+        #[arg(long)]
+        device: String,
+        /// exfat, fat32 or ext4.
+        #[arg(long, default_value = "exfat")]
+        fs: String,
+        #[arg(long, default_value = core::defaults::DATA_LABEL)]
+        label: String,
+        #[arg(long, default_value_t = false)]
+        allow_write: bool,
+    },
+    /// Rename the data partition and update grub.cfg to search for it.
+    Relabel {
+        #[arg(long)]
+        device: String,
+        #[arg(long)]
+        label: String,
+    },
+    /// Check that a RaidhOS stick is bootable, without changing it. Exits 1
+    /// when a check fails.
+    Verify {
+        #[arg(long)]
+        device: String,
+    },
+    /// List the versioned payloads staged under ROOT, newest first.
+    ListPayloads {
+        #[arg(long)]
+        root: std::path::PathBuf,
+    },
+    /// Print the versions of parted, mkfs and grub-install as JSON.
+    Diagnostics,
+    /// Print the JSON Schema for boot.json.
+    Schema,
     WriteConfig {
         #[arg(long)]
         mount_path: String,
@@ -35,62 +185,293 @@ enum Commands {
     },
 }
 
+/// Exit status after Ctrl-C, following the 128 + SIGINT convention.
+const EXIT_CANCELLED: i32 = 130;
+
+/// Prints `result` as an envelope in JSON mode, exiting 1 on failure, or
+/// hands the value to `text` and panics with `context` on failure.
+fn output<T: serde::Serialize>(
+    format: Format,
+    context: &str,
+    result: core::Result<T>,
+    text: impl FnOnce(T),
+) {
+    match format {
+        Format::Text => text(result.unwrap_or_else(|e| panic!("{context}: {e}"))),
+        Format::Json => {
+            let envelope = core::Envelope::from_result(result);
+            println!("{}", envelope.to_json());
+            if !envelope.ok {
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
 fn main() {
     let cli = Cli::parse();
+    let format = cli.format;
     match cli.command {
         Commands::ListDisks => {
-            let disks = core::list_disks().expect("list_disks failed");
-            for d in disks {
-                println!(
-                    "{} {} {} removable={} system={} mounts={}",
-                    d.id,
-                    d.model,
-                    d.size_bytes,
-                    d.removable,
-                    d.is_system,
-                    d.mountpoints.join(",")
-                );
+            let listing = core::list_disks_diagnostic();
+            if let Ok(listing) = &listing {
+                if let Some(diagnostic) = &listing.diagnostic {
+                    eprintln!("{diagnostic}");
+                }
+                for warning in &listing.warnings {
+                    eprintln!("warning: {warning}");
+                }
             }
+            let disks = listing.map(|l| l.disks);
+            output(format, "list_disks failed", disks, |disks| {
+                for d in disks {
+                    println!(
+                        "{} {} {} removable={} external={} system={} partitions={} mounts={}",
+                        d.id,
+                        d.model,
+                        d.size_bytes
+                            .map(|b| b.to_string())
+                            .unwrap_or_else(|| "unknown".to_string()),
+                        d.removable,
+                        d.external,
+                        d.is_system,
+                        d.partition_count,
+                        d.mountpoints.join(",")
+                    );
+                }
+            });
         }
-        Commands::ScanIsos { dirs } => {
-            let entries = core::scan_isos(dirs).expect("scan_isos failed");
-            for e in entries {
-                println!("{} {} {} {}", e.title, e.path, e.size_bytes, e.params);
-            }
+        Commands::ScanIsos { dirs, checksums } => {
+            let entries = core::scan_isos_with_checksums(dirs, checksums);
+            output(format, "scan_isos failed", entries, print_isos);
+        }
+        Commands::ScanDevice { device } => {
+            let entries = core::scan_isos_on_device(device);
+            output(format, "scan_isos_on_device failed", entries, print_isos);
         }
         Commands::Install {
             device,
+            serial,
             payload_version,
             wipe,
             dry_run,
             allow_write,
+            arch,
+            payload_dir,
+            data_only,
+            keep_mounts_on_error,
+            hybrid_mbr,
+            require_empty,
+            zero_ends,
+            command_log,
+            free_start_mib,
+            free_size_mib,
+            in_partition,
+            esp_partition,
+            esp_type_guid,
+            data_type_guid,
+            auto_menu,
+            full_format,
+            menu_backend,
+            data_start_mib,
+            esp_size_mib,
+            data_size_mib,
+            data_label,
+            data_fs,
+            unique_labels,
         } => {
-            struct StdoutSink;
+            /// Progress goes to stderr in JSON mode, keeping stdout to the
+            /// final envelope.
+            struct StdoutSink(Format);
             impl core::ProgressSink for StdoutSink {
                 fn emit(&self, event: core::ProgressEvent) {
                     let pct = event.percent.map(|p| format!("{p}%")).unwrap_or_default();
-                    println!("{} {} {}", event.phase, event.message, pct);
+                    if self.0 == Format::Json {
+                        eprintln!("{} {} {}", event.phase, event.message, pct);
+                        return;
+                    }
+                    if !std::io::stdout().is_terminal() {
+                        println!("{} {} {}", event.phase, event.message, pct);
+                        return;
+                    }
+                    let color = match event.phase_id() {
+                        core::PhaseId::Validate | core::PhaseId::Prepare => "36",
+                        core::PhaseId::Partition
+                        | core::PhaseId::Format
+                        | core::PhaseId::Layout => "33",
+                        core::PhaseId::Payload | core::PhaseId::Write => "34",
+                        core::PhaseId::Finalize => "35",
+                        core::PhaseId::Complete => "32",
+                        core::PhaseId::Cancelled | core::PhaseId::Rollback => "31",
+                        core::PhaseId::Paused | core::PhaseId::Plan => "90",
+                        core::PhaseId::Other => "0",
+                    };
+                    println!("\x1b[{color}m{}\x1b[0m {} {}", event.phase, event.message, pct);
                 }
             }
 
+            let device = match (device, serial) {
+                (Some(device), _) => device,
+                (None, Some(serial)) => {
+                    let disks = core::list_disks().expect("list_disks failed");
+                    let disk = core::find_disk_by_serial(&disks, &serial).expect("--serial");
+                    disk.id.clone()
+                }
+                (None, None) => unreachable!("clap requires --device or --serial"),
+            };
+            let arch = match arch {
+                Some(a) => a.parse().expect("invalid arch"),
+                None => core::Arch::default(),
+            };
+            let menu_backend = menu_backend.parse().expect("invalid menu backend");
+            let data_fstype = data_fs.parse().expect("invalid data filesystem");
+            let layout = if data_only {
+                core::Layout::DataOnly
+            } else {
+                core::Layout::BootAndData
+            };
+            let mode = match (free_start_mib, free_size_mib) {
+                (Some(start_mib), Some(size_mib)) => core::InstallMode::FreeSpace {
+                    start_mib,
+                    size_mib,
+                },
+                _ if in_partition => core::InstallMode::Partition { esp: esp_partition },
+                _ => core::InstallMode::Wipe,
+            };
+            let defaults = core::PartitionTypes::default();
+            let partition_types = core::PartitionTypes {
+                esp: esp_type_guid.unwrap_or(defaults.esp),
+                data: data_type_guid.unwrap_or(defaults.data),
+            };
             let req = core::InstallRequest {
                 device,
                 payload_version,
                 wipe,
                 dry_run,
                 allow_write,
+                arch,
+                payload_dir,
+                require_empty,
+                options: core::InstallOptions {
+                    layout,
+                    keep_mounts_on_error,
+                    hybrid_mbr,
+                    mode,
+                    partition_types,
+                    zero_ends,
+                    command_log,
+                    auto_menu,
+                    quick_format: !full_format,
+                    menu_backend,
+                    data_start_mib,
+                    esp_size_mib,
+                    data_size_mib,
+                    data_label,
+                    data_fstype,
+                    unique_labels,
+                    ..Default::default()
+                },
             };
-            core::install(req, &StdoutSink).expect("install failed");
+            let cancel = req.options.cancel.clone();
+            ctrlc::set_handler(move || {
+                eprintln!("cancelling…");
+                cancel.cancel();
+            })
+            .expect("install Ctrl-C handler");
+            match core::install(req, &StdoutSink(format)) {
+                Err(core::CoreError::Cancelled) => std::process::exit(EXIT_CANCELLED),
+                result => output(format, "install failed", result.map(|_| ()), |()| {}),
+            }
+        }
+        Commands::Uninstall {
+            device,
+            reformat,
+            allow_write,
+        } => {
+            let result = core::uninstall(&device, reformat, allow_write);
+            output(format, "uninstall failed", result, |()| {});
+        }
+        Commands::Format {
+            device,
+            fs,
+            label,
+            allow_write,
+        } => {
+            let result = fs
+                .parse()
+                .and_then(|fs| core::format_disk(&device, fs, &label, allow_write));
+            output(format, "format failed", result, |()| {});
+        }
+        Commands::Relabel { device, label } => {
+            let result = core::relabel_data(&device, &label);
+            output(format, "relabel failed", result, |()| {});
+        }
+        Commands::Verify { device } => {
+            let report = core::verify_stick(&device);
+            output(format, "verify failed", report, |report| {
+                for check in &report.checks {
+                    let status = match check.status {
+                        core::CheckStatus::Pass => "pass",
+                        core::CheckStatus::Warn => "warn",
+                        core::CheckStatus::Fail => "FAIL",
+                    };
+                    println!("{status:<4} {}: {}", check.name, check.detail);
+                }
+                if report.status() == core::CheckStatus::Fail {
+                    std::process::exit(1);
+                }
+            });
+        }
+        Commands::ListPayloads { root } => {
+            let payloads = core::list_payloads(&root);
+            output(format, "list_payloads failed", payloads, |payloads| {
+                for p in payloads {
+                    println!(
+                        "{} {} {}",
+                        p.version,
+                        p.name.as_deref().unwrap_or("-"),
+                        p.path.display()
+                    );
+                }
+            });
+        }
+        Commands::Diagnostics => {
+            let versions = core::tool_versions();
+            output(format, "diagnostics", Ok(versions), |versions| {
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&versions).expect("serialize diagnostics")
+                );
+            });
+        }
+        Commands::Schema => {
+            let schema = core::boot_config_schema();
+            let parsed = serde_json::from_str::<serde_json::Value>(&schema)
+                .map_err(|e| core::CoreError::Parse(e.to_string()));
+            output(format, "schema", parsed, |_| print!("{schema}"));
         }
         Commands::WriteConfig {
             mount_path,
             config_path,
         } => {
-            let body = std::fs::read(&config_path).expect("read config");
-            let dir = std::path::Path::new(&mount_path).join("raidhos");
-            std::fs::create_dir_all(&dir).expect("create dir");
-            let path = dir.join("boot.json");
-            std::fs::write(path, body).expect("write config");
+            let io = |what: &str, e: std::io::Error| core::CoreError::Io(format!("{what}: {e}"));
+            let result = std::fs::read(&config_path)
+                .map_err(|e| io("read config", e))
+                .and_then(|body| {
+                    let dir = std::path::Path::new(&mount_path).join("raidhos");
+                    std::fs::create_dir_all(&dir).map_err(|e| io("create dir", e))?;
+                    core::write_atomic(&dir.join("boot.json"), &body)
+                });
+            output(format, "write config", result, |()| {});
+        }
+    }
+}
+
+fn print_isos(entries: Vec<core::IsoEntry>) {
+    for e in entries {
+        match &e.sha256 {
+            Some(sha) => println!("{} {} {} {} {sha}", e.title, e.path, e.size_bytes, e.params),
+            None => println!("{} {} {} {}", e.title, e.path, e.size_bytes, e.params),
         }
     }
 }
@@ -0,0 +1,214 @@
+//! Validation for arguments handed to external commands.
+//!
+//! Commands are spawned without a shell, so quoting is not a concern, but
+//! control characters in a path would still end up in logs and in the
+//! tools' own output verbatim.
+
+use crate::{CoreError, Result};
+
+/// Rejects any argument containing a control character.
+pub(crate) fn validate_arg(arg: &str) -> Result<()> {
+    if let Some(c) = arg.chars().find(|c| c.is_control()) {
+        return Err(CoreError::Validation(format!(
+            "argument contains control character {:?}: {}",
+            c,
+            arg.escape_debug()
+        )));
+    }
+    Ok(())
+}
+
+/// A `/dev` node: `/dev/` followed by ASCII alphanumerics, `/`, `_`, `-`, `.`
+/// or `:`, with no `..` segments.
+pub(crate) fn validate_device_path(device: &str) -> Result<()> {
+    validate_arg(device)?;
+    let rest = device.strip_prefix("/dev/").unwrap_or("");
+    let ok = !rest.is_empty()
+        && rest
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '/' | '_' | '-' | '.' | ':'))
+        && !rest.split('/').any(|seg| seg == "..");
+    if !ok {
+        return Err(CoreError::Validation(format!(
+            "invalid device path: {}",
+            device.escape_debug()
+        )));
+    }
+    Ok(())
+}
+
+/// Checks every argument of a command line, applying the stricter device
+/// rule to anything under `/dev/`.
+pub(crate) fn validate_args(args: &[&str]) -> Result<()> {
+    for arg in args {
+        if arg.starts_with("/dev/") {
+            validate_device_path(arg)?;
+        } else {
+            validate_arg(arg)?;
+        }
+    }
+    Ok(())
+}
+
+/// Value-taking and bare flags accepted as extra `mkfs.vfat` arguments.
+pub(crate) const ESP_MKFS_FLAGS: (&[&str], &[&str]) =
+    (&["-s", "-S", "-f", "-r", "-R", "-i"], &["-v"]);
+
+/// Value-taking and bare flags accepted as extra data-partition mkfs
+/// arguments (cluster size, alignment, ext4 features).
+pub(crate) const DATA_MKFS_FLAGS: (&[&str], &[&str]) = (
+    &["-c", "--cluster-size", "-b", "--boundary-align", "-s", "-O", "-E"],
+    &["-f", "--full-format", "-q", "--quiet", "-v"],
+);
+
+/// Checks user-supplied mkfs arguments against an allowlist. Values must be
+/// plain tokens so nothing can name another device or file.
+pub(crate) fn validate_mkfs_args(args: &[String], flags: (&[&str], &[&str])) -> Result<()> {
+    let (value_flags, bare_flags) = flags;
+    let is_plain = |v: &str| {
+        !v.is_empty()
+            && !v.starts_with('-')
+            && v
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | ':' | ',' | '^' | '='))
+    };
+    let reject = |arg: &str| {
+        Err(CoreError::Validation(format!(
+            "mkfs argument not allowed: {}",
+            arg.escape_debug()
+        )))
+    };
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        validate_arg(arg)?;
+        if bare_flags.contains(&arg.as_str()) {
+            continue;
+        }
+        if let Some((flag, value)) = arg.split_once('=') {
+            if flag.starts_with("--") && value_flags.contains(&flag) && is_plain(value) {
+                continue;
+            }
+            return reject(arg);
+        }
+        if value_flags.contains(&arg.as_str()) {
+            match iter.next() {
+                Some(value) if is_plain(value) => continue,
+                Some(value) => return reject(value),
+                None => return reject(arg),
+            }
+        }
+        return reject(arg);
+    }
+    Ok(())
+}
+
+/// A GPT type GUID in the 8-4-4-4-12 hex form `sgdisk -t` accepts.
+pub(crate) fn validate_type_guid(guid: &str) -> Result<()> {
+    let groups: Vec<&str> = guid.split('-').collect();
+    let ok = groups.len() == 5
+        && groups
+            .iter()
+            .zip([8, 4, 4, 4, 12])
+            .all(|(g, len)| g.len() == len && g.chars().all(|c| c.is_ascii_hexdigit()));
+    if !ok {
+        return Err(CoreError::Validation(format!(
+            "malformed partition type GUID: {}",
+            guid.escape_debug()
+        )));
+    }
+    Ok(())
+}
+
+/// A volume label RaidhOS can write to FAT and exFAT and search for from
+/// grub.cfg: 1 to 11 ASCII letters, digits, `_` or `-`.
+pub(crate) fn validate_volume_label(label: &str) -> Result<()> {
+    let ok = (1..=11).contains(&label.len())
+        && label
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-'));
+    if !ok {
+        return Err(CoreError::Validation(format!(
+            "invalid volume label (1-11 of A-Z, 0-9, _ or -): {}",
+            label.escape_debug()
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_ordinary_arguments() {
+        assert!(validate_args(&["/dev/sdb", "-s", "mklabel", "gpt"]).is_ok());
+        assert!(validate_args(&["/dev/nvme0n1p2", "/mnt/raidhos-data"]).is_ok());
+        assert!(validate_args(&["/dev/disk/by-id/usb-SanDisk_3.2Gen1-0:0"]).is_ok());
+        assert!(validate_args(&["/home/me/ISOs/Ubuntu 24.04 LTS.iso"]).is_ok());
+    }
+
+    #[test]
+    fn rejects_control_characters() {
+        assert!(validate_arg("/tmp/a\nb").is_err());
+        assert!(validate_arg("label\u{1b}[31m").is_err());
+        assert!(validate_args(&["-n", "DATA\0"]).is_err());
+    }
+
+    #[test]
+    fn type_guids_must_be_well_formed() {
+        assert!(validate_type_guid("C12A7328-F81F-11D2-BA4B-00A0C93EC93B").is_ok());
+        assert!(validate_type_guid("ebd0a0a2-b9e5-4433-87c0-68b6b97199c7").is_ok());
+        assert!(validate_type_guid("EF00").is_err());
+        assert!(validate_type_guid("C12A7328F81F-11D2-BA4B-00A0C93EC93B").is_err());
+        assert!(validate_type_guid("G12A7328-F81F-11D2-BA4B-00A0C93EC93B").is_err());
+    }
+
+    #[test]
+    fn volume_labels_fit_fat_and_grub() {
+        assert!(validate_volume_label("DATA").is_ok());
+        assert!(validate_volume_label("RAIDHOS_2-b").is_ok());
+        assert!(validate_volume_label("").is_err());
+        assert!(validate_volume_label("TWELVE_CHARS").is_err());
+        assert!(validate_volume_label("MY DATA").is_err());
+        assert!(validate_volume_label("DÄTA").is_err());
+    }
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn mkfs_args_accept_allowlisted_flags() {
+        let ok = strings(&["--cluster-size=128K", "-b", "4M", "-q"]);
+        assert!(validate_mkfs_args(&ok, DATA_MKFS_FLAGS).is_ok());
+        let ok = strings(&["-O", "^has_journal,extent"]);
+        assert!(validate_mkfs_args(&ok, DATA_MKFS_FLAGS).is_ok());
+        assert!(validate_mkfs_args(&strings(&["-s", "8"]), ESP_MKFS_FLAGS).is_ok());
+    }
+
+    #[test]
+    fn mkfs_args_reject_target_redirection() {
+        for bad in [
+            &["/dev/sda"][..],
+            &["-c", "/dev/sda"],
+            &["--cluster-size=/dev/sda"],
+            &["-n", "OTHER"],
+            &["-s"],
+            &["-C"],
+        ] {
+            assert!(
+                validate_mkfs_args(&strings(bad), DATA_MKFS_FLAGS).is_err(),
+                "{bad:?}"
+            );
+        }
+        assert!(validate_mkfs_args(&strings(&["-I"]), ESP_MKFS_FLAGS).is_err());
+    }
+
+    #[test]
+    fn rejects_malformed_device_paths() {
+        assert!(validate_device_path("/dev/").is_err());
+        assert!(validate_device_path("/dev/../etc/passwd").is_err());
+        assert!(validate_device_path("/dev/sd b").is_err());
+        assert!(validate_device_path("/dev/sdb\t").is_err());
+    }
+}
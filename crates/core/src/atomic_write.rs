@@ -0,0 +1,82 @@
+//! Whole-file replacement that never leaves a half-written file behind.
+//!
+//! Boot menus live on a FAT ESP that users unplug without warning; a
+//! grub.cfg truncated mid-write stops the stick from booting at all.
+
+use crate::{CoreError, Result};
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Where [`write_atomic`] stages the new contents of `path`: a hidden
+/// sibling, so the final rename stays on one filesystem.
+pub(crate) fn temp_path(path: &Path) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    path.with_file_name(format!(".{name}.tmp-{}", std::process::id()))
+}
+
+/// Writes `contents` to a temporary file next to `path`, syncs it, and
+/// renames it over `path`, so readers see the old file or the new one but
+/// never a truncated mix. The parent directory is synced afterwards where
+/// the platform allows it.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> Result<()> {
+    let io = |e: std::io::Error| CoreError::Io(format!("{}: {e}", path.display()));
+    let temp = temp_path(path);
+    let staged = File::create(&temp)
+        .and_then(|mut file| {
+            file.write_all(contents)?;
+            file.sync_all()
+        })
+        .and_then(|()| fs::rename(&temp, path));
+    if let Err(e) = staged {
+        let _ = fs::remove_file(&temp);
+        return Err(io(e));
+    }
+    // Makes the rename itself durable. Opening a directory fails on
+    // Windows, and FAT drivers may not support the sync; the file's own
+    // data is already on disk either way.
+    if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        let _ = File::open(dir).and_then(|d| d.sync_all());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replaces_the_file_through_a_temp_path() {
+        let dir = std::env::temp_dir().join(format!("raidhos-atomic-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("grub.cfg");
+        fs::write(&path, b"old menu").unwrap();
+        // A hard link keeps the old inode reachable: an in-place write
+        // would change it too, a rename leaves it alone.
+        let old = dir.join("grub.cfg.old");
+        let _ = fs::remove_file(&old);
+        fs::hard_link(&path, &old).unwrap();
+
+        let temp = temp_path(&path);
+        assert_eq!(temp.parent(), Some(dir.as_path()));
+        assert!(temp
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .starts_with(".grub.cfg.tmp-"));
+
+        let menu = "menuentry \"Ubuntu\" {\n}\n".repeat(1000);
+        write_atomic(&path, menu.as_bytes()).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), menu);
+        assert_eq!(fs::read(&old).unwrap(), b"old menu");
+        assert!(!temp.exists());
+
+        let missing = dir.join("no-such-dir").join("grub.cfg");
+        assert!(write_atomic(&missing, b"menu").is_err());
+        assert!(!temp_path(&missing).exists());
+        let _ = fs::remove_dir_all(dir);
+    }
+}
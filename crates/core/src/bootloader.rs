@@ -0,0 +1,194 @@
+//! Bootloader placement on the ESP.
+
+use crate::{CoreError, Result};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Target CPU architecture of the stick's bootloader.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Arch {
+    X86_64,
+    Aarch64,
+}
+
+impl Arch {
+    /// Architecture this binary was built for, falling back to x86_64.
+    pub fn host() -> Self {
+        if cfg!(target_arch = "aarch64") {
+            Arch::Aarch64
+        } else {
+            Arch::X86_64
+        }
+    }
+
+    /// Value for `grub-install --target`.
+    pub fn grub_target(self) -> &'static str {
+        match self {
+            Arch::X86_64 => "x86_64-efi",
+            Arch::Aarch64 => "arm64-efi",
+        }
+    }
+
+    /// UEFI removable-media fallback loader name.
+    pub fn fallback_efi_name(self) -> &'static str {
+        match self {
+            Arch::X86_64 => "BOOTX64.EFI",
+            Arch::Aarch64 => "BOOTAA64.EFI",
+        }
+    }
+
+    fn grub_efi_name(self) -> &'static str {
+        match self {
+            Arch::X86_64 => "grubx64.efi",
+            Arch::Aarch64 => "grubaa64.efi",
+        }
+    }
+}
+
+impl Default for Arch {
+    fn default() -> Self {
+        Arch::host()
+    }
+}
+
+impl fmt::Display for Arch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Arch::X86_64 => write!(f, "x86_64"),
+            Arch::Aarch64 => write!(f, "aarch64"),
+        }
+    }
+}
+
+impl FromStr for Arch {
+    type Err = CoreError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "x86_64" | "amd64" | "x64" => Ok(Arch::X86_64),
+            "aarch64" | "arm64" => Ok(Arch::Aarch64),
+            other => Err(CoreError::Validation(format!("unsupported arch: {other}"))),
+        }
+    }
+}
+
+/// Arguments for `grub-install` writing a removable-media loader to the ESP.
+pub(crate) fn grub_install_args(arch: Arch, esp_mount: &str) -> Vec<String> {
+    vec![
+        format!("--target={}", arch.grub_target()),
+        format!("--efi-directory={esp_mount}"),
+        format!("--boot-directory={esp_mount}/boot"),
+        "--removable".to_string(),
+        "--no-nvram".to_string(),
+    ]
+}
+
+/// Locates a GRUB EFI binary under `root`, preferring one already at the
+/// fallback path.
+pub(crate) fn find_grub_efi(root: &Path, arch: Arch) -> Option<PathBuf> {
+    let fallback = arch.fallback_efi_name();
+    let grub = arch.grub_efi_name();
+    let candidates = [
+        Path::new("EFI").join("BOOT").join(fallback),
+        Path::new("EFI").join("BOOT").join(grub),
+        Path::new("EFI").join("grub").join(grub),
+        Path::new("EFI").join("raidhos").join(grub),
+        PathBuf::from(grub),
+    ];
+    candidates
+        .into_iter()
+        .map(|c| root.join(c))
+        .find(|p| p.is_file())
+}
+
+/// Copies the GRUB EFI binary found under `source_root` to
+/// `esp_root/EFI/BOOT/BOOTX64.EFI` (or `BOOTAA64.EFI`), the path firmware
+/// boots from removable media without an NVRAM entry.
+///
+/// Returns the written path, or `None` when no GRUB binary was found.
+pub fn install_fallback_bootloader(
+    source_root: &Path,
+    esp_root: &Path,
+    arch: Arch,
+) -> Result<Option<PathBuf>> {
+    let name = arch.fallback_efi_name();
+    let Some(source) = find_grub_efi(source_root, arch) else {
+        return Ok(None);
+    };
+    let dest_dir = esp_root.join("EFI").join("BOOT");
+    let dest = dest_dir.join(name);
+    if source == dest {
+        return Ok(Some(dest));
+    }
+    fs::create_dir_all(&dest_dir).map_err(|e| CoreError::Io(e.to_string()))?;
+    fs::copy(&source, &dest).map_err(|e| CoreError::Io(e.to_string()))?;
+    Ok(Some(dest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("raidhos-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn arch_selects_fallback_name() {
+        assert_eq!(Arch::X86_64.fallback_efi_name(), "BOOTX64.EFI");
+        assert_eq!(Arch::Aarch64.fallback_efi_name(), "BOOTAA64.EFI");
+    }
+
+    #[test]
+    fn arch_selects_grub_install_target() {
+        let x64 = grub_install_args(Arch::X86_64, "/mnt/raidhos-esp");
+        assert!(x64.contains(&"--target=x86_64-efi".to_string()));
+        let arm = grub_install_args(Arch::Aarch64, "/mnt/raidhos-esp");
+        assert!(arm.contains(&"--target=arm64-efi".to_string()));
+        assert!(arm.contains(&"--efi-directory=/mnt/raidhos-esp".to_string()));
+    }
+
+    #[test]
+    fn arch_parses_common_aliases() {
+        assert_eq!("amd64".parse::<Arch>().unwrap(), Arch::X86_64);
+        assert_eq!("ARM64".parse::<Arch>().unwrap(), Arch::Aarch64);
+        assert!("riscv64".parse::<Arch>().is_err());
+    }
+
+    #[test]
+    fn install_fallback_creates_removable_media_path() {
+        let arch = Arch::Aarch64;
+        let (fallback, grub) = (arch.fallback_efi_name(), arch.grub_efi_name());
+        let payload = temp_dir("fallback-payload");
+        let esp = temp_dir("fallback-esp");
+        fs::create_dir_all(payload.join("EFI/grub")).unwrap();
+        fs::write(payload.join("EFI/grub").join(grub), b"grub-efi").unwrap();
+
+        let written = install_fallback_bootloader(&payload, &esp, arch)
+            .unwrap()
+            .unwrap();
+        let expected = esp.join("EFI").join("BOOT").join(fallback);
+        assert_eq!(written, expected);
+        assert_eq!(fs::read(expected).unwrap(), b"grub-efi");
+
+        let _ = fs::remove_dir_all(payload);
+        let _ = fs::remove_dir_all(esp);
+    }
+
+    #[test]
+    fn install_fallback_without_grub_is_none() {
+        let payload = temp_dir("fallback-empty");
+        let esp = temp_dir("fallback-empty-esp");
+        assert!(install_fallback_bootloader(&payload, &esp, Arch::X86_64)
+            .unwrap()
+            .is_none());
+        let _ = fs::remove_dir_all(payload);
+        let _ = fs::remove_dir_all(esp);
+    }
+}
@@ -0,0 +1,136 @@
+//! Cooperative cancellation and pausing for long-running installs.
+
+use crate::{CoreError, Result};
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+
+/// Shared control checked between install phases and between the chunks
+/// of a byte copy. Clones observe the same state, so one can be handed to a
+/// signal handler or UI while the install holds another.
+#[derive(Clone, Debug, Default)]
+pub struct ControlToken {
+    flag: Arc<AtomicBool>,
+    /// Paused flag, with a condvar to wake waiters on resume or cancel.
+    paused: Arc<(Mutex<bool>, Condvar)>,
+}
+
+/// The token's original name, from before it could pause.
+pub type CancelToken = ControlToken;
+
+impl ControlToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Also wakes an install blocked in [`wait_while_paused`](Self::wait_while_paused).
+    pub fn cancel(&self) {
+        self.flag.store(true, Ordering::SeqCst);
+        self.paused.1.notify_all();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.flag.load(Ordering::SeqCst)
+    }
+
+    /// `Err(CoreError::Cancelled)` once [`cancel`](Self::cancel) was called.
+    pub fn check(&self) -> Result<()> {
+        if self.is_cancelled() {
+            return Err(CoreError::Cancelled);
+        }
+        Ok(())
+    }
+
+    /// Holds the install at its next pause point until [`resume`](Self::resume).
+    pub fn pause(&self) {
+        *self.paused.0.lock().expect("pause lock") = true;
+    }
+
+    pub fn resume(&self) {
+        *self.paused.0.lock().expect("pause lock") = false;
+        self.paused.1.notify_all();
+    }
+
+    pub fn is_paused(&self) -> bool {
+        *self.paused.0.lock().expect("pause lock")
+    }
+
+    /// Blocks while paused. `Err(CoreError::Cancelled)` if cancelled
+    /// before or during the pause.
+    pub fn wait_while_paused(&self) -> Result<()> {
+        let (lock, resumed) = &*self.paused;
+        let mut paused = lock.lock().expect("pause lock");
+        while *paused && !self.is_cancelled() {
+            paused = resumed.wait(paused).expect("pause lock");
+        }
+        drop(paused);
+        self.check()
+    }
+}
+
+thread_local! {
+    static ACTIVE: RefCell<Option<ControlToken>> = const { RefCell::new(None) };
+}
+
+/// Makes `token` the one the command executor checks before each command
+/// on this thread, until the guard is dropped.
+pub(crate) struct ActiveCancel {
+    previous: Option<ControlToken>,
+}
+
+impl ActiveCancel {
+    pub(crate) fn start(token: &ControlToken) -> Self {
+        let previous = ACTIVE.with(|a| a.borrow_mut().replace(token.clone()));
+        ActiveCancel { previous }
+    }
+}
+
+impl Drop for ActiveCancel {
+    fn drop(&mut self) {
+        ACTIVE.with(|a| *a.borrow_mut() = self.previous.take());
+    }
+}
+
+/// `Err(CoreError::Cancelled)` once the active token, if any, was cancelled.
+pub(crate) fn check_active() -> Result<()> {
+    ACTIVE.with(|a| a.borrow().as_ref().map_or(Ok(()), ControlToken::check))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clones_share_the_flag() {
+        let token = CancelToken::new();
+        let handler = token.clone();
+        assert!(token.check().is_ok());
+        handler.cancel();
+        assert!(token.is_cancelled());
+        assert!(matches!(token.check(), Err(CoreError::Cancelled)));
+    }
+
+    #[test]
+    fn cancel_wakes_a_paused_waiter() {
+        let token = ControlToken::new();
+        token.pause();
+        let waiter = {
+            let token = token.clone();
+            std::thread::spawn(move || token.wait_while_paused())
+        };
+        token.cancel();
+        assert!(matches!(waiter.join().unwrap(), Err(CoreError::Cancelled)));
+    }
+
+    #[test]
+    fn active_token_is_scoped_to_the_guard() {
+        let token = CancelToken::new();
+        token.cancel();
+        assert!(check_active().is_ok());
+        {
+            let _active = ActiveCancel::start(&token);
+            assert!(matches!(check_active(), Err(CoreError::Cancelled)));
+        }
+        assert!(check_active().is_ok());
+    }
+}
@@ -0,0 +1,77 @@
+//! SHA-256 digests of ISO files, streamed rather than read into memory.
+
+use crate::{CoreError, Result};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+
+const CHUNK: usize = 1 << 20;
+
+/// Lowercase hex SHA-256 of the file at `path`, read in 1 MiB chunks.
+pub(crate) fn sha256_file(path: &Path) -> Result<String> {
+    let io = |e: std::io::Error| CoreError::Io(format!("{}: {e}", path.display()));
+    let mut reader = BufReader::with_capacity(CHUNK, File::open(path).map_err(io)?);
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; CHUNK];
+    loop {
+        let n = reader.read(&mut buf).map_err(io)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect())
+}
+
+/// SHA-256 digests by path, each computed on first request only. Keep one
+/// around across checks so a multi-gigabyte ISO is hashed once.
+#[derive(Clone, Debug, Default)]
+pub struct HashCache {
+    digests: HashMap<PathBuf, String>,
+}
+
+impl HashCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn sha256(&mut self, path: &Path) -> Result<String> {
+        if let Some(digest) = self.digests.get(path) {
+            return Ok(digest.clone());
+        }
+        let digest = sha256_file(path)?;
+        self.digests.insert(path.to_path_buf(), digest.clone());
+        Ok(digest)
+    }
+
+    /// Number of files hashed so far.
+    pub fn len(&self) -> usize {
+        self.digests.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.digests.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn digest_matches_a_known_value() {
+        let path = std::env::temp_dir().join(format!("raidhos-sha-{}", std::process::id()));
+        std::fs::write(&path, b"abc").unwrap();
+        assert_eq!(
+            sha256_file(&path).unwrap(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -0,0 +1,193 @@
+//! Raw trail of every external command an install runs, as JSON Lines.
+//!
+//! The log is switched on per thread for the duration of an install, so the
+//! command executor can append to it without the path being threaded
+//! through every helper.
+
+use crate::runner::{CommandOutput, CommandRunner, CommandStatus};
+use crate::{CoreError, Result};
+use serde::Serialize;
+use std::cell::RefCell;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
+
+thread_local! {
+    static LOG_PATH: RefCell<Option<PathBuf>> = const { RefCell::new(None) };
+}
+
+#[derive(Serialize)]
+struct Entry<'a> {
+    command: &'a str,
+    args: &'a [&'a str],
+    /// `None` when the command could not be started or died from a signal.
+    exit_status: Option<i32>,
+    duration_ms: u64,
+}
+
+/// Logs commands to `path` until the guard is dropped.
+pub(crate) struct CommandLog {
+    previous: Option<PathBuf>,
+}
+
+impl CommandLog {
+    pub(crate) fn start(path: &Path) -> Self {
+        let previous = LOG_PATH.with(|p| p.borrow_mut().replace(path.to_path_buf()));
+        CommandLog { previous }
+    }
+}
+
+impl Drop for CommandLog {
+    fn drop(&mut self) {
+        LOG_PATH.with(|p| *p.borrow_mut() = self.previous.take());
+    }
+}
+
+/// Wraps a runner so every command it runs or looks up lands in the active
+/// log, whoever asked for it.
+#[derive(Debug)]
+pub(crate) struct Logged(pub(crate) Arc<dyn CommandRunner>);
+
+impl CommandRunner for Logged {
+    fn run(&self, cmd: &str, args: &[&str]) -> Result<CommandStatus> {
+        let started = Instant::now();
+        let status = self.0.run(cmd, args);
+        let code = status.as_ref().ok().and_then(|s| s.code);
+        record(cmd, args, code, elapsed_ms(started))?;
+        status
+    }
+
+    fn has_cmd(&self, cmd: &str) -> bool {
+        let started = Instant::now();
+        let found = self.0.has_cmd(cmd);
+        // A lookup cannot fail; a log that cannot be written shows up on
+        // the next command instead.
+        let code = Some(i32::from(!found));
+        let _ = record("command", &["-v", cmd], code, elapsed_ms(started));
+        found
+    }
+
+    fn node_exists(&self, node: &str) -> bool {
+        self.0.node_exists(node)
+    }
+
+    fn output(&self, cmd: &str, args: &[&str]) -> Result<CommandOutput> {
+        let started = Instant::now();
+        let output = self.0.output(cmd, args);
+        let code = output.as_ref().ok().and_then(|o| o.status.code);
+        record(cmd, args, code, elapsed_ms(started))?;
+        output
+    }
+
+    fn read_mbr(&self, device: &str) -> Result<Vec<u8>> {
+        self.0.read_mbr(device)
+    }
+}
+
+fn elapsed_ms(started: Instant) -> u64 {
+    u64::try_from(started.elapsed().as_millis()).unwrap_or(u64::MAX)
+}
+
+/// Appends one line for a finished command, if a log is active.
+pub(crate) fn record(
+    command: &str,
+    args: &[&str],
+    exit_status: Option<i32>,
+    duration_ms: u64,
+) -> Result<()> {
+    let Some(path) = LOG_PATH.with(|p| p.borrow().clone()) else {
+        return Ok(());
+    };
+    let entry = Entry {
+        command,
+        args,
+        exit_status,
+        duration_ms,
+    };
+    let mut line = serde_json::to_string(&entry).map_err(|e| CoreError::Parse(e.to_string()))?;
+    line.push('\n');
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .and_then(|mut file| file.write_all(line.as_bytes()))
+        .map_err(|e| CoreError::Io(format!("command log {}: {e}", path.display())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_only_while_started() {
+        let path = std::env::temp_dir().join(format!("raidhos-cmdlog-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        record("parted", &["/dev/sdb", "-s", "print"], Some(0), 3).unwrap();
+        {
+            let _log = CommandLog::start(&path);
+            record("parted", &["/dev/sdb", "-s", "print"], Some(0), 3).unwrap();
+        }
+        record("sync", &[], Some(0), 1).unwrap();
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(
+            text,
+            "{\"command\":\"parted\",\"args\":[\"/dev/sdb\",\"-s\",\"print\"],\"exit_status\":0,\"duration_ms\":3}\n"
+        );
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[derive(Debug)]
+    struct Lsblk;
+
+    impl CommandRunner for Lsblk {
+        fn run(&self, _cmd: &str, _args: &[&str]) -> Result<CommandStatus> {
+            Ok(CommandStatus::success())
+        }
+
+        fn has_cmd(&self, cmd: &str) -> bool {
+            cmd == "lsblk"
+        }
+
+        fn output(&self, _cmd: &str, _args: &[&str]) -> Result<CommandOutput> {
+            Ok(CommandOutput {
+                status: CommandStatus::success(),
+                stdout: b"{}".to_vec(),
+            })
+        }
+    }
+
+    #[test]
+    fn logged_runner_records_queries_and_lookups() {
+        let path = std::env::temp_dir().join(format!("raidhos-logged-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let runner = Logged(Arc::new(Lsblk));
+        {
+            let _log = CommandLog::start(&path);
+            assert!(runner.has_cmd("lsblk"));
+            assert!(!runner.has_cmd("sgdisk"));
+            assert_eq!(runner.output("lsblk", &["-J"]).unwrap().stdout, b"{}");
+        }
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<serde_json::Value> = text
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        let commands: Vec<String> = lines
+            .iter()
+            .map(|l| format!("{} {} {}", l["command"], l["args"], l["exit_status"]))
+            .collect();
+        assert_eq!(
+            commands,
+            vec![
+                "\"command\" [\"-v\",\"lsblk\"] 0",
+                "\"command\" [\"-v\",\"sgdisk\"] 1",
+                "\"lsblk\" [\"-J\"] 0",
+            ]
+        );
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -0,0 +1,77 @@
+//! Values RaidhOS uses unless told otherwise, shared with the binaries so
+//! help text and fallbacks agree with what an install actually does.
+
+/// FAT label given to the ESP.
+pub const ESP_LABEL: &str = "RAIDHOS_EFI";
+
+/// exFAT label given to the data partition, and the label `grub.cfg`
+/// searches for when none is detected.
+pub const DATA_LABEL: &str = "DATA";
+
+/// Where an install mounts the ESP while copying the payload.
+pub const ESP_MOUNT: &str = "/mnt/raidhos-esp";
+
+/// Where an install mounts the data partition while copying the payload.
+pub const DATA_MOUNT: &str = "/mnt/raidhos-data";
+
+/// Parent of the temporary mounts used to scan or strip existing sticks.
+pub const SCAN_MOUNT_ROOT: &str = "/run/raidhos";
+
+/// Directory on the data partition the boot menu expects ISOs in, unless
+/// the boot config names another.
+pub const ISO_ROOT: &str = "/boot/isos";
+
+/// Seconds the GRUB menu waits before booting the default entry.
+pub const GRUB_TIMEOUT_SECS: u32 = 5;
+
+/// [`DATA_LABEL`] when no label in `existing_labels` takes it, else the
+/// first free `DATA2`, `DATA3`, … so GRUB's label search finds this stick
+/// and not another one plugged in. Labels compare case-insensitively, as
+/// FAT stores them upper-case; the result stays within FAT's 11 characters.
+pub fn suggest_data_label(existing_labels: &[String]) -> String {
+    let taken = |label: &str| {
+        existing_labels
+            .iter()
+            .any(|l| l.eq_ignore_ascii_case(label))
+    };
+    if !taken(DATA_LABEL) {
+        return DATA_LABEL.to_string();
+    }
+    (2u32..=9_999_999)
+        .map(|n| format!("{DATA_LABEL}{n}"))
+        .find(|label| !taken(label))
+        .expect("some numbered label is free")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn labels(labels: &[&str]) -> Vec<String> {
+        labels.iter().map(|l| l.to_string()).collect()
+    }
+
+    #[test]
+    fn data_label_is_suggested_when_free() {
+        assert_eq!(suggest_data_label(&[]), "DATA");
+        assert_eq!(
+            suggest_data_label(&labels(&["RAIDHOS_EFI", "DATA2", "root"])),
+            "DATA"
+        );
+    }
+
+    #[test]
+    fn taken_data_labels_are_numbered_past() {
+        assert_eq!(suggest_data_label(&labels(&["DATA"])), "DATA2");
+        assert_eq!(
+            suggest_data_label(&labels(&["data", "DATA2", "Data3", "DATA5"])),
+            "DATA4"
+        );
+        let many: Vec<String> = std::iter::once("DATA".to_string())
+            .chain((2..=99).map(|n| format!("DATA{n}")))
+            .collect();
+        let label = suggest_data_label(&many);
+        assert_eq!(label, "DATA100");
+        assert!(label.len() <= 11);
+    }
+}
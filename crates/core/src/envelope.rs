@@ -0,0 +1,102 @@
+//! The `{ok, data, error}` shape every JSON entry point answers with, so
+//! scripts parse one schema whether they call the CLI or the helper.
+
+use crate::Result;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Outcome of one command: `data` on success, `error` on failure.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Envelope<T> {
+    pub ok: bool,
+    pub data: Option<T>,
+    pub error: Option<String>,
+}
+
+impl<T> Envelope<T> {
+    pub fn success(data: T) -> Self {
+        Envelope {
+            ok: true,
+            data: Some(data),
+            error: None,
+        }
+    }
+
+    pub fn failure(error: impl fmt::Display) -> Self {
+        Envelope {
+            ok: false,
+            data: None,
+            error: Some(error.to_string()),
+        }
+    }
+
+    pub fn from_result(result: Result<T>) -> Self {
+        match result {
+            Ok(data) => Self::success(data),
+            Err(err) => Self::failure(err),
+        }
+    }
+}
+
+impl<T: Serialize> Envelope<T> {
+    /// Pretty-printed JSON, as the CLI and helper print it.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("envelope serializes")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CoreError, DiskInfo, HelperResponse};
+    use serde_json::Value;
+
+    fn disk() -> DiskInfo {
+        DiskInfo {
+            id: "/dev/sdb".to_string(),
+            model: "USB Stick".to_string(),
+            serial: None,
+            size_bytes: Some(16 << 30),
+            removable: true,
+            external: true,
+            transport: Some("usb".to_string()),
+            mountpoints: vec![],
+            is_system: false,
+            partition_count: 0,
+            partitions: vec![],
+        }
+    }
+
+    /// Object keys at each level, with values replaced by their JSON type.
+    fn shape(value: &Value) -> Value {
+        match value {
+            Value::Object(map) => map.iter().map(|(k, v)| (k.clone(), shape(v))).collect(),
+            Value::Array(items) => items.iter().map(shape).collect(),
+            Value::Null => Value::Null,
+            Value::Bool(_) => "bool".into(),
+            Value::Number(_) => "number".into(),
+            Value::String(_) => "string".into(),
+        }
+    }
+
+    #[test]
+    fn list_disks_envelopes_match_the_helper_response() {
+        let ok: Value = serde_json::from_str(&Envelope::success(vec![disk()]).to_json()).unwrap();
+        assert_eq!(ok["ok"], true);
+        assert_eq!(ok["data"][0]["id"], "/dev/sdb");
+        assert_eq!(ok["error"], Value::Null);
+
+        let err = Envelope::<Vec<DiskInfo>>::from_result(Err(CoreError::Io("lsblk failed".into())));
+        let err: Value = serde_json::from_str(&err.to_json()).unwrap();
+        let helper = HelperResponse::failure("io error: lsblk failed");
+        let helper: Value = serde_json::from_str(&helper.to_json()).unwrap();
+        assert_eq!(err, helper);
+
+        let keys = |v: &Value| v.as_object().unwrap().keys().cloned().collect::<Vec<_>>();
+        assert_eq!(keys(&ok), keys(&helper));
+        assert_eq!(
+            shape(&ok)["data"][0],
+            shape(&serde_json::to_value(disk()).unwrap())
+        );
+    }
+}
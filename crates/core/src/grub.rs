@@ -0,0 +1,1219 @@
+//! GRUB configuration rendering for the ESP.
+
+use crate::{defaults, CoreError, DataFsType, IsoEntry, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+const BOOT_CONFIG_SCHEMA: &str = include_str!("../../../docs/BOOT_CONFIG_SCHEMA.json");
+
+/// JSON Schema (draft 2020-12) for the `boot.json` a [`BootConfig`]
+/// serializes to, for validating configs written by other tools.
+pub fn boot_config_schema() -> String {
+    BOOT_CONFIG_SCHEMA.to_string()
+}
+
+/// Boot menu definition, matching `docs/BOOT_CONFIG_SCHEMA.json`.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BootConfig {
+    pub entries: Vec<BootEntryConfig>,
+    #[serde(alias = "default_entry")]
+    pub default_entry: Option<String>,
+    /// Remember the last entry booted (GRUB `savedefault`) in a grubenv
+    /// file on the ESP, falling back to `default_entry` on first boot.
+    #[serde(default, alias = "save_default")]
+    pub save_default: bool,
+    /// Filesystem UUID of the data partition. When set, GRUB finds it by
+    /// UUID instead of by label, which stays unambiguous with several
+    /// RaidhOS sticks plugged in.
+    #[serde(default, alias = "data_uuid")]
+    pub data_uuid: Option<String>,
+    /// Directory on the data partition holding the ISOs, exported to GRUB
+    /// as `isopath`; relative entry paths are taken from it. Defaults to
+    /// `/boot/isos`.
+    #[serde(default, alias = "iso_root")]
+    pub iso_root: Option<String>,
+    /// Filesystem of the data partition, for the GRUB module the menu
+    /// loads to read it.
+    #[serde(default, alias = "data_fstype")]
+    pub data_fstype: DataFsType,
+}
+
+impl BootConfig {
+    /// `iso_root`, sanitized and made absolute without a trailing slash,
+    /// or the default when unset or empty.
+    pub fn iso_root(&self) -> String {
+        let root = self.iso_root.as_deref().map(sanitize).unwrap_or_default();
+        match root.trim_end_matches('/') {
+            _ if root.is_empty() => defaults::ISO_ROOT.to_string(),
+            "" => "/".to_string(),
+            trimmed => path_prefix(trimmed),
+        }
+    }
+
+    /// One entry per scanned ISO, in scan order, with the params the scan
+    /// picked. ISOs known not to boot are left out. Entry paths are taken
+    /// as-is, so scan results should already be relative to the data
+    /// partition root (`/boot/isos/...`).
+    pub fn from_iso_entries(entries: &[IsoEntry]) -> Self {
+        BootConfig {
+            entries: entries
+                .iter()
+                .filter(|e| e.is_bootable != Some(false))
+                .map(|e| BootEntryConfig {
+                    title: e.title.clone(),
+                    path: e.path.clone(),
+                    params: e.params.clone(),
+                    initrd: String::new(),
+                    kargs: String::new(),
+                    size_bytes: Some(e.size_bytes),
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+}
+
+/// Environment block GRUB loads and saves the last entry in, relative to
+/// the ESP root.
+const GRUBENV_PATH: &str = "EFI/BOOT/grubenv";
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BootEntryConfig {
+    pub title: String,
+    pub path: String,
+    pub params: String,
+    pub initrd: String,
+    pub kargs: String,
+    /// ISO size when known, e.g. from a scan. Large ISOs get a memory note
+    /// in their menu entry.
+    #[serde(
+        default,
+        rename = "sizeBytes",
+        alias = "size_bytes",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub size_bytes: Option<u64>,
+}
+
+/// ISOs from this size up are called out in the menu: loopback reads them
+/// in place, but firmware and initrds on machines with little RAM may
+/// still fail to boot them.
+pub(crate) const LARGE_ISO_BYTES: u64 = 4 << 30;
+
+/// `"5.2 GiB"` when `entry` is a known large ISO.
+pub(crate) fn large_iso_size(entry: &BootEntryConfig) -> Option<String> {
+    let bytes = entry.size_bytes.filter(|&b| b >= LARGE_ISO_BYTES)?;
+    Some(format!("{:.1} GiB", bytes as f64 / (1u64 << 30) as f64))
+}
+
+/// One warning per large ISO in `config`, for the install to report.
+pub fn large_iso_warnings(config: &BootConfig) -> Vec<String> {
+    config
+        .entries
+        .iter()
+        .filter_map(|entry| {
+            let size = large_iso_size(entry)?;
+            Some(format!(
+                "{} is {size} and may not loopback on machines with little RAM",
+                sanitize(&entry.title)
+            ))
+        })
+        .collect()
+}
+
+/// Firmware the stick is expected to boot under.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BootMode {
+    #[default]
+    Uefi,
+    Bios,
+    /// Both: UEFI firmware and legacy BIOS / CSM.
+    Hybrid,
+}
+
+impl BootMode {
+    /// Where GRUB looks for its config under the ESP mounted at `esp_mount`:
+    /// `EFI/BOOT/grub.cfg` for the UEFI loader, `boot/grub/grub.cfg` for
+    /// the BIOS core image installed with `--boot-directory=<esp>/boot`.
+    pub fn grub_cfg_paths(self, esp_mount: &Path) -> Vec<PathBuf> {
+        let uefi = esp_mount.join("EFI").join("BOOT").join("grub.cfg");
+        let bios = esp_mount.join("boot").join("grub").join("grub.cfg");
+        match self {
+            BootMode::Uefi => vec![uefi],
+            BootMode::Bios => vec![bios],
+            BootMode::Hybrid => vec![uefi, bios],
+        }
+    }
+}
+
+impl FromStr for BootMode {
+    type Err = CoreError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "uefi" | "efi" => Ok(BootMode::Uefi),
+            "bios" | "legacy" => Ok(BootMode::Bios),
+            "hybrid" => Ok(BootMode::Hybrid),
+            other => Err(CoreError::Validation(format!(
+                "unsupported boot mode: {other}"
+            ))),
+        }
+    }
+}
+
+/// Punctuation allowed in kernel arguments besides ASCII letters, digits
+/// and spaces: enough for `key=value,value`, paths and `console=ttyS0,115200n8`,
+/// but none of the characters GRUB's script parser expands or splits on.
+const KARGS_PUNCTUATION: &[char] = &['-', '_', '.', ',', ':', '=', '/', '+', '@', '%', '~', '"'];
+
+/// Errors unless `kargs` is safe to splice into a `linux` line: printable
+/// ASCII from [`KARGS_PUNCTUATION`], with double quotes balanced. Rejects
+/// control characters and the `$`, `;`, `#`, `{`, `\` and similar that
+/// GRUB would expand, split on or treat as a comment.
+pub fn validate_kargs(kargs: &str) -> Result<()> {
+    match kargs_problem(kargs) {
+        Some(problem) => Err(CoreError::Validation(problem)),
+        None => Ok(()),
+    }
+}
+
+/// Why [`validate_kargs`] rejects `kargs`, if it does.
+pub(crate) fn kargs_problem(kargs: &str) -> Option<String> {
+    let allowed = |c: char| c.is_ascii_alphanumeric() || c == ' ' || KARGS_PUNCTUATION.contains(&c);
+    if let Some(c) = kargs.chars().find(|&c| !allowed(c)) {
+        return Some(format!(
+            "kernel arguments contain {c:?}: {}",
+            kargs.escape_debug()
+        ));
+    }
+    if !kargs.matches('"').count().is_multiple_of(2) {
+        return Some(format!(
+            "kernel arguments have an unbalanced quote: {}",
+            kargs.escape_debug()
+        ));
+    }
+    None
+}
+
+/// One message per entry whose params or kargs [`validate_kargs`] rejects,
+/// naming the entry.
+pub(crate) fn unsafe_kargs(config: &BootConfig) -> Vec<String> {
+    config
+        .entries
+        .iter()
+        .filter_map(|entry| {
+            let problem = [&entry.params, &entry.kargs]
+                .into_iter()
+                .find_map(|args| kargs_problem(args))?;
+            Some(format!("entry \"{}\": {problem}", sanitize(&entry.title)))
+        })
+        .collect()
+}
+
+/// Runs [`validate_kargs`] on the params and kargs of every entry, naming
+/// the first offending entry in the error.
+pub fn validate_boot_config(config: &BootConfig) -> Result<()> {
+    match unsafe_kargs(config).into_iter().next() {
+        Some(problem) => Err(CoreError::Validation(problem)),
+        None => Ok(()),
+    }
+}
+
+/// Writes the same rendered config to every path in `targets`, creating
+/// parent directories as needed. Each file is replaced atomically, so a
+/// crash leaves the old menu rather than a truncated one. Fails before
+/// writing anything when [`validate_boot_config`] rejects `config`.
+pub fn write_grub_cfg(targets: &[PathBuf], config: &BootConfig, data_label: &str) -> Result<()> {
+    validate_boot_config(config)?;
+    let cfg = render_grub_cfg(config, data_label);
+    for path in targets {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|e| CoreError::Io(e.to_string()))?;
+        }
+        crate::write_atomic(path, cfg.as_bytes())?;
+    }
+    Ok(())
+}
+
+/// Writes the rendered config to `EFI/BOOT/grub.cfg` under `esp_mount`,
+/// returning the written path. Creates the grubenv file `save_default`
+/// needs.
+pub fn write_grub_cfg_to_esp(
+    esp_mount: &Path,
+    config: &BootConfig,
+    data_label: &str,
+) -> Result<PathBuf> {
+    let path = esp_mount.join("EFI").join("BOOT").join("grub.cfg");
+    write_grub_cfg(std::slice::from_ref(&path), config, data_label)?;
+    if config.save_default {
+        ensure_grubenv(esp_mount)?;
+    }
+    Ok(path)
+}
+
+/// Creates an empty GRUB environment block under `esp_mount` unless one
+/// exists; `save_env` can only rewrite a block, not create it. An existing
+/// block is kept so the remembered entry survives a config rewrite.
+pub fn ensure_grubenv(esp_mount: &Path) -> Result<PathBuf> {
+    let path = esp_mount.join(GRUBENV_PATH);
+    if path.is_file() {
+        return Ok(path);
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| CoreError::Io(e.to_string()))?;
+    }
+    let mut block = b"# GRUB Environment Block\n".to_vec();
+    block.resize(1024, b'#');
+    fs::write(&path, block).map_err(|e| CoreError::Io(e.to_string()))?;
+    Ok(path)
+}
+
+/// Structured form of a rendered grub.cfg, for asserting on structure
+/// rather than on substrings. Its `Display` output is the file content.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GrubModel {
+    /// Top-level directives before the first menu entry, one per line.
+    pub header: Vec<String>,
+    pub entries: Vec<RenderedEntry>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RenderedEntry {
+    /// Sanitized title, before GRUB quoting.
+    pub title: String,
+    /// ISO path on the data partition, before GRUB quoting.
+    pub isofile: String,
+    /// Kernel layouts probed in order; `configfile` handoff comes first.
+    pub branches: Vec<KernelBranch>,
+    /// Calls `savedefault` when chosen, so GRUB boots it next time.
+    pub savedefault: bool,
+    /// Comment lines at the top of the entry, without the `#`.
+    pub notes: Vec<String>,
+}
+
+/// One `elif [ -f probe ]` arm: the `linux` and `initrd` lines it runs.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KernelBranch {
+    pub probe: String,
+    pub linux: String,
+    pub initrd: String,
+}
+
+impl GrubModel {
+    /// Module names loaded with `insmod` in the header.
+    pub fn modules(&self) -> Vec<&str> {
+        self.header
+            .iter()
+            .filter_map(|line| line.strip_prefix("insmod "))
+            .collect()
+    }
+}
+
+impl fmt::Display for GrubModel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for line in &self.header {
+            writeln!(f, "{line}")?;
+        }
+        for entry in &self.entries {
+            writeln!(f, "menuentry \"{}\" {{", escape_quoted(&entry.title))?;
+            for note in &entry.notes {
+                writeln!(f, "  # {note}")?;
+            }
+            if entry.savedefault {
+                writeln!(f, "  savedefault")?;
+            }
+            writeln!(f, "  set isofile=\"{}\"", escape_quoted(&entry.isofile))?;
+            writeln!(f, "  loopback loop \"($root)${{isofile}}\"")?;
+            writeln!(f, "  if [ -f (loop)/boot/grub/grub.cfg ]; then")?;
+            writeln!(f, "    configfile (loop)/boot/grub/grub.cfg")?;
+            for branch in &entry.branches {
+                writeln!(f, "  elif [ -f {} ]; then", branch.probe)?;
+                writeln!(f, "    {}", branch.linux)?;
+                writeln!(f, "    {}", branch.initrd)?;
+            }
+            writeln!(f, "  else")?;
+            writeln!(f, "    echo \"No known kernel path found in ISO.\"")?;
+            writeln!(f, "  fi")?;
+            writeln!(f, "}}")?;
+        }
+        Ok(())
+    }
+}
+
+pub fn render_grub_cfg(config: &BootConfig, data_label: &str) -> String {
+    render_grub_model(config, data_label).to_string()
+}
+
+pub fn render_grub_model(config: &BootConfig, data_label: &str) -> GrubModel {
+    let mut header = vec![format!("set timeout={}", defaults::GRUB_TIMEOUT_SECS)];
+    if let Some(default) = &config.default_entry {
+        header.push(format!(
+            "set default=\"{}\"",
+            escape_quoted(&sanitize(default))
+        ));
+    }
+    let data_module = config.data_fstype.grub_module();
+    for module in [
+        "part_gpt",
+        "fat",
+        data_module,
+        "iso9660",
+        "loopback",
+        "search",
+    ] {
+        header.push(format!("insmod {module}"));
+    }
+    if config.save_default {
+        // `$root` is still the ESP here, before the search below.
+        header.extend([
+            "insmod loadenv".to_string(),
+            format!("set envfile=($root)/{GRUBENV_PATH}"),
+            "if [ -s $envfile ]; then load_env --file $envfile; fi".to_string(),
+            "if [ \"${saved_entry}\" ]; then set default=\"${saved_entry}\"; fi".to_string(),
+            "function savedefault {".to_string(),
+            "  saved_entry=\"${chosen}\"".to_string(),
+            "  save_env --file $envfile saved_entry".to_string(),
+            "}".to_string(),
+        ]);
+    }
+    let search = match config.data_uuid.as_deref().filter(|u| is_fs_uuid(u)) {
+        Some(uuid) => format!("search --no-floppy --fs-uuid {uuid} --set=root"),
+        None => format!(
+            "search --no-floppy --label {} --set=root",
+            sanitize(data_label)
+        ),
+    };
+    header.push(search);
+    let iso_root = config.iso_root();
+    header.push(format!("set isopath=\"{}\"", escape_quoted(&iso_root)));
+    header.push("export root".to_string());
+    header.push("export isopath".to_string());
+
+    GrubModel {
+        header,
+        entries: config
+            .entries
+            .iter()
+            .map(|entry| rendered_entry(entry, &iso_root, config.save_default))
+            .collect(),
+    }
+}
+
+/// FAT/exFAT serials (`1234-ABCD`) and ext4-style UUIDs: hex digits and
+/// dashes only. Anything else is ignored in favour of the label search.
+fn is_fs_uuid(uuid: &str) -> bool {
+    !uuid.is_empty() && uuid.chars().all(|c| c.is_ascii_hexdigit() || c == '-')
+}
+
+fn rendered_entry(entry: &BootEntryConfig, iso_root: &str, savedefault: bool) -> RenderedEntry {
+    let params = sanitize(&entry.params);
+    let initrd = sanitize(&entry.initrd);
+    let kargs = sanitize(&entry.kargs);
+    let initrd_line = |fallback: &str| {
+        if initrd.is_empty() {
+            format!("initrd {fallback}")
+        } else {
+            format!("initrd {initrd}")
+        }
+    };
+
+    RenderedEntry {
+        title: sanitize(&entry.title),
+        isofile: iso_path(&entry.path, iso_root),
+        branches: vec![
+            KernelBranch {
+                probe: "(loop)/casper/vmlinuz".to_string(),
+                linux: format!(
+                    "linux (loop)/casper/vmlinuz {} {} \"iso-scan/filename=${{isofile}}\"",
+                    params, kargs
+                ),
+                initrd: initrd_line("(loop)/casper/initrd"),
+            },
+            KernelBranch {
+                probe: "(loop)/live/vmlinuz".to_string(),
+                linux: format!(
+                    "linux (loop)/live/vmlinuz {} {} boot=live \"findiso=${{isofile}}\"",
+                    params, kargs
+                ),
+                initrd: initrd_line("(loop)/live/initrd.img"),
+            },
+        ],
+        savedefault,
+        notes: large_iso_size(entry)
+            .map(|size| {
+                format!(
+                    "{size} ISO: loopback reads it in place, but low-RAM machines may not boot it"
+                )
+            })
+            .into_iter()
+            .collect(),
+    }
+}
+
+/// Recovers a [`BootConfig`] from a grub.cfg this crate rendered, so an
+/// installed menu can be edited and re-rendered. Best-effort: lines it
+/// does not recognise are skipped. Rendering joins `params` and `kargs`,
+/// so both come back in `params` and `kargs` is left empty.
+pub fn parse_grub_cfg(text: &str) -> BootConfig {
+    let mut config = BootConfig::default();
+    let mut current: Option<BootEntryConfig> = None;
+    // Only the casper branch is read; the initrd line right after its kernel.
+    let mut casper_initrd = false;
+    for line in text.lines().map(str::trim) {
+        if let Some(title) = quoted_between(line, "menuentry \"", "\" {") {
+            current = Some(BootEntryConfig {
+                title,
+                path: String::new(),
+                params: String::new(),
+                initrd: String::new(),
+                kargs: String::new(),
+                size_bytes: None,
+            });
+            continue;
+        }
+        let Some(entry) = current.as_mut() else {
+            if let Some(default) = quoted_between(line, "set default=\"", "\"") {
+                config.default_entry = Some(default);
+            } else if line == "function savedefault {" {
+                config.save_default = true;
+            } else if let Some(root) = quoted_between(line, "set isopath=\"", "\"") {
+                config.iso_root = Some(root).filter(|r| r != defaults::ISO_ROOT);
+            } else if let Some(rest) = line.strip_prefix("search --no-floppy --fs-uuid ") {
+                config.data_uuid = rest.strip_suffix(" --set=root").map(str::to_string);
+            } else if let Some(fs) = line.strip_prefix("insmod ").and_then(data_fstype_for) {
+                config.data_fstype = fs;
+            }
+            continue;
+        };
+        if line == "}" {
+            config.entries.extend(current.take());
+        } else if let Some(path) = quoted_between(line, "set isofile=\"", "\"") {
+            entry.path = path;
+        } else if let Some(rest) = line.strip_prefix("linux (loop)/casper/vmlinuz ") {
+            let params = rest.trim_end_matches("\"iso-scan/filename=${isofile}\"");
+            entry.params = params.trim().to_string();
+            casper_initrd = true;
+        } else if let Some(initrd) = line.strip_prefix("initrd ").filter(|_| casper_initrd) {
+            // The casper fallback path means no initrd was configured.
+            if initrd != "(loop)/casper/initrd" {
+                entry.initrd = initrd.to_string();
+            }
+            casper_initrd = false;
+        }
+    }
+    config
+}
+
+/// Reads back the grub.cfg under the ESP mounted at `esp_mount`, trying the
+/// UEFI location before the BIOS one.
+pub fn read_grub_cfg(esp_mount: &Path) -> Result<BootConfig> {
+    let paths = BootMode::Hybrid.grub_cfg_paths(esp_mount);
+    let text = paths
+        .iter()
+        .find_map(|path| fs::read_to_string(path).ok())
+        .ok_or_else(|| CoreError::Io(format!("no grub.cfg under {}", esp_mount.display())))?;
+    Ok(parse_grub_cfg(&text))
+}
+
+/// The GRUB-quoted word between `prefix` and `suffix`, unescaped.
+fn quoted_between(line: &str, prefix: &str, suffix: &str) -> Option<String> {
+    let word = line.strip_prefix(prefix)?.strip_suffix(suffix)?;
+    let mut out = String::with_capacity(word.len());
+    let mut chars = word.chars();
+    while let Some(c) = chars.next() {
+        out.push(if c == '\\' { chars.next()? } else { c });
+    }
+    Some(out)
+}
+
+/// Entry ISO paths, as GRUB would resolve them, that do not exist under the
+/// mounted data partition `data_mount`.
+pub fn missing_isos(data_mount: &Path, config: &BootConfig) -> Vec<String> {
+    config
+        .entries
+        .iter()
+        .map(|entry| iso_path(&entry.path, &config.iso_root()))
+        .filter(|iso| !data_mount.join(iso.trim_start_matches('/')).is_file())
+        .collect()
+}
+
+/// `cfg` with every `search ... --label <label>` line searching for
+/// `new_label` instead. UUID searches are left alone: a relabel keeps the
+/// filesystem UUID.
+pub(crate) fn replace_search_label(cfg: &str, new_label: &str) -> String {
+    cfg.split_inclusive('\n')
+        .map(|line| {
+            let trimmed = line.trim_start();
+            let Some(at) = trimmed
+                .find("--label ")
+                .filter(|_| trimmed.starts_with("search "))
+            else {
+                return line.to_string();
+            };
+            let indent = &line[..line.len() - trimmed.len()];
+            let value_at = at + "--label ".len();
+            let rest = &trimmed[value_at..];
+            let value_end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            format!(
+                "{indent}{}{}{}",
+                &trimmed[..value_at],
+                sanitize(new_label),
+                &rest[value_end..]
+            )
+        })
+        .collect()
+}
+
+/// The label the first `search ... --label <label>` line of `cfg` looks
+/// for; `None` for a menu that searches by UUID or not at all.
+pub(crate) fn search_label(cfg: &str) -> Option<String> {
+    cfg.lines()
+        .map(str::trim_start)
+        .filter(|line| line.starts_with("search "))
+        .find_map(|line| {
+            let rest = &line[line.find("--label ")? + "--label ".len()..];
+            rest.split_whitespace().next().map(str::to_string)
+        })
+}
+
+/// The data filesystem an `insmod` of `module` is loaded for.
+fn data_fstype_for(module: &str) -> Option<DataFsType> {
+    [DataFsType::ExFat, DataFsType::Ext4, DataFsType::Ntfs]
+        .into_iter()
+        .find(|fs| fs.grub_module() == module)
+}
+
+/// `cfg` with every `insmod` of a data filesystem module loading the
+/// module for `fs` instead, so a payload menu written for exFAT can read
+/// an ext4 or NTFS data partition.
+pub(crate) fn replace_data_module(cfg: &str, fs: DataFsType) -> String {
+    cfg.split_inclusive('\n')
+        .map(|line| {
+            let trimmed = line.trim_start();
+            let body = trimmed.trim_end();
+            let module = body.strip_prefix("insmod ");
+            if module.and_then(data_fstype_for).is_none() {
+                return line.to_string();
+            }
+            let indent = &line[..line.len() - trimmed.len()];
+            let ending = &trimmed[body.len()..];
+            format!("{indent}insmod {}{ending}", fs.grub_module())
+        })
+        .collect()
+}
+
+/// Escapes text for a GRUB double-quoted word. The whole word is passed on
+/// to the kernel command line re-quoted, so spaces survive intact.
+fn escape_quoted(input: &str) -> String {
+    let mut out = String::with_capacity(input.len());
+    for c in input.chars() {
+        if matches!(c, '\\' | '"' | '$') {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+pub(crate) fn sanitize(input: &str) -> String {
+    input.replace('"', "").replace('\n', " ").trim().to_string()
+}
+
+pub(crate) fn path_prefix(path: &str) -> String {
+    if path.starts_with('/') {
+        path.to_string()
+    } else {
+        format!("/{}", path)
+    }
+}
+
+/// Where `path` is on the data partition: absolute paths as they are,
+/// relative ones under `iso_root` unless they already start with it.
+pub(crate) fn iso_path(path: &str, iso_root: &str) -> String {
+    let path = sanitize(path);
+    if path.starts_with('/') {
+        return path;
+    }
+    let root = iso_root.trim_start_matches('/');
+    match path.strip_prefix(root) {
+        Some(rest) if root.is_empty() || rest.starts_with('/') => format!("/{path}"),
+        _ => format!("{iso_root}/{path}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sanitize_strips_quotes_and_newlines() {
+        let s = "\"hello\nworld\"";
+        assert_eq!(sanitize(s), "hello world");
+    }
+
+    #[test]
+    fn kargs_allow_ordinary_arguments() {
+        for kargs in [
+            "",
+            "quiet splash",
+            "boot=live components nomodeset",
+            "console=ttyS0,115200n8 rd.luks.uuid=1234-abcd",
+            "acpi_osi=\"Windows 2015\" i915.enable_psr=0",
+            "archisobasedir=arch archisolabel=ARCH_202401 mem=4G@0x1000 ~user%x+y",
+        ] {
+            assert!(validate_kargs(kargs).is_ok(), "{kargs}");
+        }
+    }
+
+    #[test]
+    fn kargs_reject_control_and_grub_metacharacters() {
+        for kargs in [
+            "quiet\nsplash",
+            "quiet\r",
+            "quiet\tsplash",
+            "quiet\u{0}",
+            "quiet; reboot",
+            "init=$(reboot)",
+            "root=${isofile}",
+            "quiet # rest is a comment",
+            "x } menuentry evil {",
+            "a && b",
+            "a | b",
+            "`id`",
+            "quiet 'single'",
+            "C:\\path",
+            "acpi_osi=\"Windows 2015",
+            "lang=fr_FR.UTF-8 café",
+        ] {
+            assert!(validate_kargs(kargs).is_err(), "{kargs:?}");
+        }
+    }
+
+    #[test]
+    fn boot_config_with_bad_kargs_is_not_written() {
+        let dir = std::env::temp_dir().join(format!("raidhos-kargs-{}", std::process::id()));
+        let mut config = BootConfig {
+            entries: vec![entry(
+                "Ubuntu",
+                "/boot/isos/u.iso",
+                "quiet splash",
+                "",
+                "nomodeset",
+            )],
+            ..Default::default()
+        };
+        assert!(validate_boot_config(&config).is_ok());
+        config.entries[0].kargs = "nomodeset\ninit=/bin/sh".to_string();
+        let err = write_grub_cfg_to_esp(&dir, &config, "DATA").unwrap_err();
+        assert!(format!("{err}").contains("entry \"Ubuntu\""), "{err}");
+        assert!(!dir.join("EFI/BOOT/grub.cfg").exists());
+
+        config.entries[0].kargs = String::new();
+        config.entries[0].params = "quiet $foo".to_string();
+        assert!(validate_boot_config(&config).is_err());
+    }
+
+    #[test]
+    fn path_prefix_adds_slash() {
+        assert_eq!(path_prefix("/boot/isos/a.iso"), "/boot/isos/a.iso");
+        assert_eq!(path_prefix("boot/isos/a.iso"), "/boot/isos/a.iso");
+    }
+
+    #[test]
+    fn render_uses_custom_iso_root() {
+        let mut config = BootConfig {
+            entries: vec![
+                entry("Ubuntu", "ubuntu.iso", "", "", ""),
+                entry("Arch", "/boot/isos/arch.iso", "", "", ""),
+                entry("Debian", "my isos/debian.iso", "", "", ""),
+            ],
+            iso_root: Some("my isos/\"".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(config.iso_root(), "/my isos");
+        let model = render_grub_model(&config, "DATA");
+        assert!(model
+            .header
+            .contains(&"set isopath=\"/my isos\"".to_string()));
+        let isofiles: Vec<&str> = model.entries.iter().map(|e| e.isofile.as_str()).collect();
+        assert_eq!(
+            isofiles,
+            vec![
+                "/my isos/ubuntu.iso",
+                "/boot/isos/arch.iso",
+                "/my isos/debian.iso"
+            ]
+        );
+        let parsed = parse_grub_cfg(&model.to_string());
+        assert_eq!(parsed.iso_root.as_deref(), Some("/my isos"));
+
+        config.iso_root = None;
+        let model = render_grub_model(&config, "DATA");
+        assert!(model
+            .header
+            .contains(&"set isopath=\"/boot/isos\"".to_string()));
+        assert_eq!(model.entries[0].isofile, "/boot/isos/ubuntu.iso");
+        assert_eq!(parse_grub_cfg(&model.to_string()).iso_root, None);
+        config.iso_root = Some("/".to_string());
+        assert_eq!(
+            render_grub_model(&config, "DATA").entries[0].isofile,
+            "/ubuntu.iso"
+        );
+    }
+
+    #[test]
+    fn serialized_config_validates_against_schema() {
+        let schema: serde_json::Value = serde_json::from_str(&boot_config_schema()).unwrap();
+        let validator = jsonschema::validator_for(&schema).unwrap();
+        let config = BootConfig {
+            default_entry: Some("Ubuntu".to_string()),
+            save_default: false,
+            data_uuid: None,
+            iso_root: None,
+            data_fstype: DataFsType::ExFat,
+            entries: vec![BootEntryConfig {
+                title: "Ubuntu".to_string(),
+                path: "/boot/isos/ubuntu.iso".to_string(),
+                params: "quiet splash".to_string(),
+                initrd: "/casper/initrd".to_string(),
+                kargs: "".to_string(),
+                size_bytes: Some(5_000_000_000),
+            }],
+        };
+        let mut json = serde_json::to_value(&config).unwrap();
+        assert!(validator.is_valid(&json));
+        json["entries"][0]["timeout"] = serde_json::json!(5);
+        assert!(!validator.is_valid(&json));
+    }
+
+    fn entry(title: &str, path: &str, params: &str, initrd: &str, kargs: &str) -> BootEntryConfig {
+        BootEntryConfig {
+            title: title.to_string(),
+            path: path.to_string(),
+            params: params.to_string(),
+            initrd: initrd.to_string(),
+            kargs: kargs.to_string(),
+            size_bytes: None,
+        }
+    }
+
+    #[test]
+    fn rendered_config_parses_back() {
+        let config = BootConfig {
+            default_entry: Some("Ubuntu \\ \"Noble\"".to_string()),
+            save_default: false,
+            data_uuid: None,
+            iso_root: None,
+            data_fstype: DataFsType::ExFat,
+            entries: vec![
+                entry(
+                    "Ubuntu \\ Noble",
+                    "/boot/isos/ubuntu.iso",
+                    "quiet splash",
+                    "",
+                    "",
+                ),
+                entry(
+                    "Debian $HOME",
+                    "/boot/isos/debian live.iso",
+                    "",
+                    "/live/initrd.gz",
+                    "",
+                ),
+            ],
+        };
+        let parsed = parse_grub_cfg(&render_grub_cfg(&config, "DATA"));
+        assert_eq!(parsed.default_entry.as_deref(), Some("Ubuntu \\ Noble"));
+        let key = |e: &BootEntryConfig| {
+            (
+                e.title.clone(),
+                e.path.clone(),
+                e.params.clone(),
+                e.initrd.clone(),
+            )
+        };
+        let expected: Vec<_> = config.entries.iter().map(key).collect();
+        assert_eq!(parsed.entries.iter().map(key).collect::<Vec<_>>(), expected);
+    }
+
+    #[test]
+    fn parsed_kargs_fold_into_params() {
+        let config = BootConfig {
+            default_entry: None,
+            save_default: false,
+            data_uuid: None,
+            iso_root: None,
+            data_fstype: DataFsType::ExFat,
+            entries: vec![entry(
+                "Arch",
+                "/boot/isos/arch.iso",
+                "quiet",
+                "",
+                "nomodeset",
+            )],
+        };
+        let parsed = parse_grub_cfg(&render_grub_cfg(&config, "DATA"));
+        assert_eq!(parsed.entries[0].params, "quiet nomodeset");
+        assert_eq!(parsed.entries[0].kargs, "");
+        assert_eq!(
+            render_grub_cfg(&parsed, "DATA").replace("  ", " "),
+            render_grub_cfg(&config, "DATA").replace("  ", " ")
+        );
+    }
+
+    #[test]
+    fn render_contains_search_label() {
+        let config = BootConfig {
+            default_entry: None,
+            save_default: false,
+            data_uuid: None,
+            iso_root: None,
+            data_fstype: DataFsType::ExFat,
+            entries: vec![],
+        };
+        let out = render_grub_cfg(&config, "DATA");
+        assert!(out.contains("search --no-floppy --label DATA --set=root"));
+    }
+
+    #[test]
+    fn render_searches_by_fs_uuid_when_known() {
+        let mut config = BootConfig {
+            default_entry: None,
+            save_default: false,
+            data_uuid: Some("4A1F-09C2".to_string()),
+            iso_root: None,
+            data_fstype: DataFsType::ExFat,
+            entries: vec![],
+        };
+        let out = render_grub_cfg(&config, "DATA");
+        assert!(out.contains("search --no-floppy --fs-uuid 4A1F-09C2 --set=root\n"));
+        assert!(!out.contains("--label"));
+        assert_eq!(parse_grub_cfg(&out).data_uuid.as_deref(), Some("4A1F-09C2"));
+
+        config.data_uuid = Some("4A1F; reboot".to_string());
+        let out = render_grub_cfg(&config, "DATA");
+        assert!(out.contains("search --no-floppy --label DATA --set=root"));
+        assert!(!out.contains("reboot"));
+    }
+
+    #[test]
+    fn render_menuentry_contains_loopback() {
+        let config = BootConfig {
+            default_entry: None,
+            save_default: false,
+            data_uuid: None,
+            iso_root: None,
+            data_fstype: DataFsType::ExFat,
+            entries: vec![BootEntryConfig {
+                title: "Test".to_string(),
+                path: "/boot/isos/test.iso".to_string(),
+                params: "quiet".to_string(),
+                initrd: "".to_string(),
+                kargs: "".to_string(),
+                size_bytes: None,
+            }],
+        };
+        let out = render_grub_cfg(&config, "DATA");
+        assert!(out.contains("loopback loop \"($root)${isofile}\""));
+        assert!(out.contains("menuentry \"Test\""));
+    }
+
+    #[test]
+    fn large_iso_entry_is_annotated() {
+        let mut big = entry("Windows 11", "/boot/isos/win11.iso", "", "", "");
+        big.size_bytes = Some(5_600_000_000);
+        let mut small = entry("Alpine", "/boot/isos/alpine.iso", "", "", "");
+        small.size_bytes = Some(200 << 20);
+        let config = BootConfig {
+            entries: vec![big, small],
+            ..Default::default()
+        };
+
+        let model = render_grub_model(&config, "DATA");
+        assert_eq!(
+            model.entries[0].notes,
+            vec!["5.2 GiB ISO: loopback reads it in place, but low-RAM machines may not boot it"]
+        );
+        assert!(model.entries[1].notes.is_empty());
+        let out = model.to_string();
+        assert!(out.contains("menuentry \"Windows 11\" {\n  # 5.2 GiB ISO: loopback"));
+        assert_eq!(parse_grub_cfg(&out).entries.len(), 2);
+        assert_eq!(
+            large_iso_warnings(&config),
+            vec!["Windows 11 is 5.2 GiB and may not loopback on machines with little RAM"]
+        );
+        let json = serde_json::to_value(&config).unwrap();
+        assert_eq!(json["entries"][0]["sizeBytes"], 5_600_000_000u64);
+    }
+
+    #[test]
+    fn render_quotes_spaced_and_unicode_paths() {
+        let config = BootConfig {
+            default_entry: None,
+            save_default: false,
+            data_uuid: None,
+            iso_root: None,
+            data_fstype: DataFsType::ExFat,
+            entries: vec![BootEntryConfig {
+                title: "Ubuntu 24.04 LTS – Édition $5".to_string(),
+                path: "/boot/isos/Ubuntu 24.04 LTS – Édition.iso".to_string(),
+                params: "quiet".to_string(),
+                initrd: "".to_string(),
+                kargs: "".to_string(),
+                size_bytes: None,
+            }],
+        };
+        let out = render_grub_cfg(&config, "DATA");
+        assert!(out.contains("menuentry \"Ubuntu 24.04 LTS – Édition \\$5\" {"));
+        assert!(out.contains("  set isofile=\"/boot/isos/Ubuntu 24.04 LTS – Édition.iso\"\n"));
+        assert!(out.contains("\"iso-scan/filename=${isofile}\""));
+        assert!(out.contains("boot=live \"findiso=${isofile}\""));
+        assert!(!out.contains("$isofile\n"));
+    }
+
+    #[test]
+    fn model_exposes_entry_structure() {
+        let config = BootConfig {
+            default_entry: None,
+            save_default: false,
+            data_uuid: None,
+            iso_root: None,
+            data_fstype: DataFsType::ExFat,
+            entries: vec![BootEntryConfig {
+                title: "Debian \"Live\"".to_string(),
+                path: "boot/isos/debian live.iso".to_string(),
+                params: "quiet".to_string(),
+                initrd: "".to_string(),
+                kargs: "nomodeset".to_string(),
+                size_bytes: None,
+            }],
+        };
+        let model = render_grub_model(&config, "DATA");
+        assert_eq!(model.header[0], "set timeout=5");
+        assert_eq!(
+            model.modules(),
+            vec!["part_gpt", "fat", "exfat", "iso9660", "loopback", "search"]
+        );
+        assert!(model
+            .header
+            .contains(&"search --no-floppy --label DATA --set=root".to_string()));
+
+        let entry = &model.entries[0];
+        assert_eq!(entry.title, "Debian Live");
+        assert_eq!(entry.isofile, "/boot/isos/debian live.iso");
+        let probes: Vec<&str> = entry.branches.iter().map(|b| b.probe.as_str()).collect();
+        assert_eq!(probes, vec!["(loop)/casper/vmlinuz", "(loop)/live/vmlinuz"]);
+        assert_eq!(
+            entry.branches[1].linux,
+            "linux (loop)/live/vmlinuz quiet nomodeset boot=live \"findiso=${isofile}\""
+        );
+        assert_eq!(entry.branches[1].initrd, "initrd (loop)/live/initrd.img");
+        assert_eq!(model.to_string(), render_grub_cfg(&config, "DATA"));
+    }
+
+    #[test]
+    fn savedefault_directives_only_when_enabled() {
+        let mut config = BootConfig {
+            default_entry: Some("Ubuntu".to_string()),
+            save_default: false,
+            data_uuid: None,
+            iso_root: None,
+            data_fstype: DataFsType::ExFat,
+            entries: vec![entry("Ubuntu", "/boot/isos/ubuntu.iso", "quiet", "", "")],
+        };
+        let plain = render_grub_cfg(&config, "DATA");
+        for directive in [
+            "load_env",
+            "save_env",
+            "savedefault",
+            "saved_entry",
+            "loadenv",
+        ] {
+            assert!(!plain.contains(directive), "{directive}");
+        }
+
+        config.save_default = true;
+        let model = render_grub_model(&config, "DATA");
+        assert!(model.modules().contains(&"loadenv"));
+        let search = model
+            .header
+            .iter()
+            .position(|l| l.starts_with("search "))
+            .unwrap();
+        let env = model
+            .header
+            .iter()
+            .position(|l| l == "set envfile=($root)/EFI/BOOT/grubenv")
+            .unwrap();
+        assert!(env < search, "grubenv must be found on the ESP");
+        let out = model.to_string();
+        assert!(out.contains("load_env --file $envfile"));
+        assert!(out.contains("set default=\"${saved_entry}\""));
+        assert!(out.contains("  save_env --file $envfile saved_entry\n"));
+        assert!(out.contains("menuentry \"Ubuntu\" {\n  savedefault\n"));
+        assert!(parse_grub_cfg(&out).save_default);
+        assert!(!parse_grub_cfg(&plain).save_default);
+    }
+
+    #[test]
+    fn grubenv_is_created_once() {
+        let esp = std::env::temp_dir().join(format!("raidhos-grubenv-{}", std::process::id()));
+        let config = BootConfig {
+            save_default: true,
+            data_uuid: None,
+            iso_root: None,
+            data_fstype: DataFsType::ExFat,
+            ..Default::default()
+        };
+        write_grub_cfg_to_esp(&esp, &config, "DATA").unwrap();
+        let path = esp.join("EFI/BOOT/grubenv");
+        let block = fs::read(&path).unwrap();
+        assert_eq!(block.len(), 1024);
+        assert!(block.starts_with(b"# GRUB Environment Block\n#"));
+
+        let saved = String::from_utf8(block)
+            .unwrap()
+            .replacen("####", "x=1\n", 1);
+        fs::write(&path, &saved).unwrap();
+        assert_eq!(ensure_grubenv(&esp).unwrap(), path);
+        assert_eq!(fs::read_to_string(&path).unwrap(), saved);
+        let _ = fs::remove_dir_all(esp);
+    }
+
+    #[test]
+    fn missing_isos_lists_absent_entries() {
+        let data =
+            std::env::temp_dir().join(format!("raidhos-grub-missing-{}", std::process::id()));
+        fs::create_dir_all(data.join("boot/isos")).unwrap();
+        fs::write(data.join("boot/isos/present.iso"), b"iso").unwrap();
+        let entry = |path: &str| BootEntryConfig {
+            title: path.to_string(),
+            path: path.to_string(),
+            params: String::new(),
+            initrd: String::new(),
+            kargs: String::new(),
+            size_bytes: None,
+        };
+        let config = BootConfig {
+            default_entry: None,
+            save_default: false,
+            data_uuid: None,
+            iso_root: None,
+            data_fstype: DataFsType::ExFat,
+            entries: vec![
+                entry("boot/isos/present.iso"),
+                entry("/boot/isos/missing.iso"),
+                entry("/boot/isos"),
+            ],
+        };
+        assert_eq!(
+            missing_isos(&data, &config),
+            vec!["/boot/isos/missing.iso", "/boot/isos"]
+        );
+        let _ = fs::remove_dir_all(data);
+    }
+
+    #[test]
+    fn escape_quoted_escapes_grub_specials() {
+        assert_eq!(escape_quoted(r"a\b$c"), r"a\\b\$c");
+        assert_eq!(escape_quoted("plain name é.iso"), "plain name é.iso");
+    }
+
+    #[test]
+    fn preview_matches_written_file() {
+        let config = BootConfig {
+            default_entry: Some("Test".to_string()),
+            save_default: false,
+            data_uuid: None,
+            iso_root: None,
+            data_fstype: DataFsType::ExFat,
+            entries: vec![BootEntryConfig {
+                title: "Test".to_string(),
+                path: "boot/isos/test.iso".to_string(),
+                params: "quiet".to_string(),
+                initrd: "".to_string(),
+                kargs: "nomodeset".to_string(),
+                size_bytes: None,
+            }],
+        };
+        let preview = render_grub_cfg(&config, "DATA");
+        let esp = std::env::temp_dir().join(format!("raidhos-grub-preview-{}", std::process::id()));
+        let path = write_grub_cfg_to_esp(&esp, &config, "DATA").unwrap();
+        assert_eq!(path, esp.join("EFI/BOOT/grub.cfg"));
+        assert_eq!(fs::read_to_string(&path).unwrap(), preview);
+        let _ = fs::remove_dir_all(esp);
+    }
+
+    #[test]
+    fn hybrid_writes_identical_config_to_both_paths() {
+        let esp = std::env::temp_dir().join(format!("raidhos-grub-hybrid-{}", std::process::id()));
+        let targets = BootMode::Hybrid.grub_cfg_paths(&esp);
+        assert_eq!(
+            targets,
+            vec![
+                esp.join("EFI/BOOT/grub.cfg"),
+                esp.join("boot/grub/grub.cfg")
+            ]
+        );
+        write_grub_cfg(&targets, &BootConfig::default(), "DATA").unwrap();
+        let uefi = fs::read_to_string(&targets[0]).unwrap();
+        let bios = fs::read_to_string(&targets[1]).unwrap();
+        assert_eq!(uefi, bios);
+        assert_eq!(uefi, render_grub_cfg(&BootConfig::default(), "DATA"));
+        let _ = fs::remove_dir_all(esp);
+    }
+
+    #[test]
+    fn boot_config_reads_schema_field_names() {
+        let json = r#"{"defaultEntry": "Test", "entries": []}"#;
+        let config: BootConfig = serde_json::from_str(json).unwrap();
+        assert_eq!(config.default_entry.as_deref(), Some("Test"));
+    }
+
+    #[test]
+    fn data_module_follows_the_data_filesystem() {
+        let exfat = render_grub_cfg(&BootConfig::default(), "DATA");
+        for (fs, module) in [
+            (DataFsType::Ext4, "ext2"),
+            (DataFsType::Ntfs, "ntfs"),
+            (DataFsType::ExFat, "exfat"),
+        ] {
+            let config = BootConfig {
+                data_fstype: fs,
+                ..Default::default()
+            };
+            let model = render_grub_model(&config, "DATA");
+            assert!(model.modules().contains(&module), "{:?}", model.modules());
+            let cfg = model.to_string();
+            assert_eq!(parse_grub_cfg(&cfg).data_fstype, fs);
+            assert_eq!(replace_data_module(&exfat, fs), cfg);
+        }
+        let indented = "if true; then\r\n  insmod exfat\r\nfi\r\n";
+        assert_eq!(
+            replace_data_module(indented, DataFsType::Ntfs),
+            "if true; then\r\n  insmod ntfs\r\nfi\r\n"
+        );
+    }
+
+    #[test]
+    fn search_label_is_replaced_and_uuid_search_kept() {
+        let cfg = render_grub_cfg(&BootConfig::default(), "DATA");
+        let out = replace_search_label(&cfg, "STICK2");
+        assert!(out.contains("search --no-floppy --label STICK2 --set=root\n"));
+        assert!(!out.contains("--label DATA"));
+        assert_eq!(out.lines().count(), cfg.lines().count());
+        assert_eq!(search_label(&out).as_deref(), Some("STICK2"));
+
+        let uuid = BootConfig {
+            data_uuid: Some("4A1F-0B2C".to_string()),
+            ..Default::default()
+        };
+        let cfg = render_grub_cfg(&uuid, "DATA");
+        assert_eq!(replace_search_label(&cfg, "STICK2"), cfg);
+        assert_eq!(search_label(&cfg), None);
+    }
+}
@@ -0,0 +1,252 @@
+//! ISO9660 header parsing.
+
+use crate::{CoreError, Result};
+use std::fs::File;
+use std::io::{ErrorKind, Read, Seek, SeekFrom};
+use std::path::Path;
+
+const SECTOR: usize = 2048;
+/// The primary volume descriptor sits in sector 16, after the system area.
+const PVD_OFFSET: usize = 16 * SECTOR;
+/// Volume descriptors looked at before giving up on a set terminator.
+const MAX_DESCRIPTORS: u64 = 16;
+/// Directory extents larger than this are read only this far.
+const MAX_DIR_BYTES: u32 = 64 * 1024;
+/// Root directories of live and installer images (Ubuntu, Debian live,
+/// Fedora/RHEL `images/pxeboot` is checked separately).
+const LIVE_DIRS: &[&str] = &["CASPER", "LIVE"];
+
+/// Identifiers from the primary volume descriptor.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct IsoMetadata {
+    pub volume_id: Option<String>,
+    pub publisher: Option<String>,
+}
+
+/// Reads the primary volume descriptor of the ISO at `path`, touching only
+/// the first 34 KiB. `None` when the file is not ISO9660.
+pub fn read_iso_metadata(path: &Path) -> Result<Option<IsoMetadata>> {
+    let file = File::open(path).map_err(|e| CoreError::Io(e.to_string()))?;
+    let mut buf = Vec::with_capacity(PVD_OFFSET + SECTOR);
+    file.take((PVD_OFFSET + SECTOR) as u64)
+        .read_to_end(&mut buf)
+        .map_err(|e| CoreError::Io(e.to_string()))?;
+    Ok(parse_pvd(&buf))
+}
+
+fn parse_pvd(image: &[u8]) -> Option<IsoMetadata> {
+    let pvd = image.get(PVD_OFFSET..PVD_OFFSET + SECTOR)?;
+    if pvd[0] != 1 || &pvd[1..6] != b"CD001" {
+        return None;
+    }
+    let publisher = field(&pvd[318..446]).filter(|p| !p.starts_with('_'));
+    Some(IsoMetadata {
+        volume_id: field(&pvd[40..72]),
+        publisher,
+    })
+}
+
+/// Whether the ISO at `path` looks bootable: it has an El Torito boot
+/// record, or a `casper/`, `live/` or `images/pxeboot/` directory. `None`
+/// when the file is not ISO9660.
+pub fn read_iso_bootable(path: &Path) -> Result<Option<bool>> {
+    let mut file = File::open(path).map_err(|e| CoreError::Io(e.to_string()))?;
+    is_bootable(&mut file).map_err(|e| CoreError::Io(e.to_string()))
+}
+
+fn is_bootable(image: &mut (impl Read + Seek)) -> std::io::Result<Option<bool>> {
+    let mut root = None;
+    let mut el_torito = false;
+    for lba in 16..16 + MAX_DESCRIPTORS as u32 {
+        let Some(desc) = read_sector(image, lba)? else {
+            break;
+        };
+        if &desc[1..6] != b"CD001" {
+            break;
+        }
+        match desc[0] {
+            0 if desc[7..30] == *b"EL TORITO SPECIFICATION" => el_torito = true,
+            1 => root = Some(extent(&desc[156..190])),
+            255 => break,
+            _ => {}
+        }
+    }
+    let Some((root_lba, root_len)) = root else {
+        return Ok(None);
+    };
+    if el_torito {
+        return Ok(Some(true));
+    }
+    let entries = read_dir(image, root_lba, root_len)?;
+    if entries
+        .iter()
+        .any(|(name, _)| LIVE_DIRS.iter().any(|d| name.eq_ignore_ascii_case(d)))
+    {
+        return Ok(Some(true));
+    }
+    let images = entries
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case("IMAGES"));
+    if let Some(&(_, (lba, len))) = images {
+        let pxeboot = read_dir(image, lba, len)?
+            .iter()
+            .any(|(name, _)| name.eq_ignore_ascii_case("PXEBOOT"));
+        return Ok(Some(pxeboot));
+    }
+    Ok(Some(false))
+}
+
+/// Sector `lba`, or `None` past the end of the image.
+fn read_sector(image: &mut (impl Read + Seek), lba: u32) -> std::io::Result<Option<Vec<u8>>> {
+    image.seek(SeekFrom::Start(u64::from(lba) * SECTOR as u64))?;
+    let mut sector = vec![0u8; SECTOR];
+    match image.read_exact(&mut sector) {
+        Ok(()) => Ok(Some(sector)),
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Location and length of the extent a directory record points at.
+fn extent(record: &[u8]) -> (u32, u32) {
+    let le = |b: &[u8]| u32::from_le_bytes([b[0], b[1], b[2], b[3]]);
+    (le(&record[2..6]), le(&record[10..14]))
+}
+
+/// Subdirectories of the directory at `lba`, as (name, extent).
+fn read_dir(
+    image: &mut (impl Read + Seek),
+    lba: u32,
+    len: u32,
+) -> std::io::Result<Vec<(String, (u32, u32))>> {
+    let mut dirs = Vec::new();
+    let sectors = len.min(MAX_DIR_BYTES).div_ceil(SECTOR as u32);
+    for n in 0..sectors {
+        let Some(sector) = read_sector(image, lba + n)? else {
+            break;
+        };
+        let mut pos = 0;
+        // Records never cross a sector; a zero length pads to the next one.
+        while pos < SECTOR && sector[pos] != 0 {
+            let record = &sector[pos..(pos + sector[pos] as usize).min(SECTOR)];
+            pos += sector[pos] as usize;
+            if record.len() < 34 || record[25] & 0x02 == 0 {
+                continue;
+            }
+            let name = record.get(33..33 + record[32] as usize).unwrap_or_default();
+            // `.` and `..` are the single bytes 0 and 1.
+            if name.len() > 1 || name.first().is_some_and(|&b| b > 1) {
+                let name = String::from_utf8_lossy(name).to_string();
+                dirs.push((name, extent(record)));
+            }
+        }
+    }
+    Ok(dirs)
+}
+
+/// A space-padded identifier field, trimmed; `None` when blank.
+fn field(bytes: &[u8]) -> Option<String> {
+    let text = String::from_utf8_lossy(bytes);
+    let text = text.trim_matches(|c: char| c == ' ' || c == '\0');
+    (!text.is_empty()).then(|| text.to_string())
+}
+
+#[cfg(test)]
+pub(crate) fn fixture_header(volume_id: &str, publisher: &str) -> Vec<u8> {
+    let mut image = vec![0u8; PVD_OFFSET + SECTOR];
+    let pvd = &mut image[PVD_OFFSET..];
+    pvd[0] = 1;
+    pvd[1..6].copy_from_slice(b"CD001");
+    pvd[6] = 1;
+    pvd[40..72].fill(b' ');
+    pvd[40..40 + volume_id.len()].copy_from_slice(volume_id.as_bytes());
+    pvd[318..446].fill(b' ');
+    pvd[318..318 + publisher.len()].copy_from_slice(publisher.as_bytes());
+    image
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// Directory record for subdirectory `name` at `lba`.
+    fn dir_record(name: &str, lba: u32) -> Vec<u8> {
+        let len = 33 + name.len() + (name.len() + 1) % 2;
+        let mut record = vec![0u8; len];
+        record[0] = len as u8;
+        record[2..6].copy_from_slice(&lba.to_le_bytes());
+        record[10..14].copy_from_slice(&(SECTOR as u32).to_le_bytes());
+        record[25] = 0x02;
+        record[32] = name.len() as u8;
+        record[33..33 + name.len()].copy_from_slice(name.as_bytes());
+        record
+    }
+
+    /// An image with an optional boot record and the given root
+    /// directories; `IMAGES` gets a `PXEBOOT` subdirectory.
+    fn fixture_image(boot_record: bool, root_dirs: &[&str]) -> Vec<u8> {
+        const ROOT: u32 = 20;
+        let mut image = fixture_header("LINUX", "");
+        image.resize(24 * SECTOR, 0);
+        let root = dir_record("\0", ROOT);
+        image[PVD_OFFSET + 156..PVD_OFFSET + 190].copy_from_slice(&root);
+        let mut next = 17;
+        if boot_record {
+            let desc = &mut image[next * SECTOR..];
+            desc[1..6].copy_from_slice(b"CD001");
+            desc[7..30].copy_from_slice(b"EL TORITO SPECIFICATION");
+            next += 1;
+        }
+        image[next * SECTOR] = 255;
+        image[next * SECTOR + 1..next * SECTOR + 6].copy_from_slice(b"CD001");
+
+        let mut pos = ROOT as usize * SECTOR;
+        for record in [dir_record("\0", ROOT), dir_record("\u{1}", ROOT)] {
+            image[pos..pos + record.len()].copy_from_slice(&record);
+            pos += record.len();
+        }
+        for name in root_dirs {
+            let record = dir_record(name, ROOT + 1);
+            image[pos..pos + record.len()].copy_from_slice(&record);
+            pos += record.len();
+        }
+        let pxeboot = dir_record("PXEBOOT", ROOT + 2);
+        let sub = (ROOT as usize + 1) * SECTOR;
+        image[sub..sub + pxeboot.len()].copy_from_slice(&pxeboot);
+        image
+    }
+
+    #[test]
+    fn detects_boot_record_and_live_directories() {
+        let bootable = |image: Vec<u8>| is_bootable(&mut Cursor::new(image)).unwrap();
+        assert_eq!(bootable(fixture_image(true, &[])), Some(true));
+        assert_eq!(bootable(fixture_image(false, &["CASPER"])), Some(true));
+        assert_eq!(bootable(fixture_image(false, &["IMAGES"])), Some(true));
+        assert_eq!(
+            bootable(fixture_image(false, &["DATA", "DOCS"])),
+            Some(false)
+        );
+        assert_eq!(bootable(b"not an iso".to_vec()), None);
+    }
+
+    #[test]
+    fn reads_volume_label_and_publisher() {
+        let image = fixture_header("Ubuntu 24.04 LTS amd64", "Canonical Ltd.");
+        let meta = parse_pvd(&image).unwrap();
+        assert_eq!(meta.volume_id.as_deref(), Some("Ubuntu 24.04 LTS amd64"));
+        assert_eq!(meta.publisher.as_deref(), Some("Canonical Ltd."));
+    }
+
+    #[test]
+    fn blank_or_file_reference_fields_are_none() {
+        let meta = parse_pvd(&fixture_header("", "_PUBLISHER.TXT")).unwrap();
+        assert_eq!(meta, IsoMetadata::default());
+    }
+
+    #[test]
+    fn non_iso_data_is_none() {
+        assert!(parse_pvd(b"not an iso").is_none());
+        assert!(parse_pvd(&vec![0u8; PVD_OFFSET + SECTOR]).is_none());
+    }
+}
@@ -0,0 +1,582 @@
+//! Copying ISOs onto the data partition.
+
+use crate::grub::{self, BootConfig, BootMode};
+use crate::{defaults, HashCache, IsoEntry, PhaseId, ProgressEvent, ProgressSink, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CopyItem {
+    pub source: String,
+    pub dest: String,
+    pub size_bytes: u64,
+}
+
+/// How [`copy_isos_to_data`](crate::copy_isos_to_data) treats a batch.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CopyOptions {
+    /// Run every check and report the plan without writing anything.
+    pub dry_run: bool,
+    /// File-name globs (`*` and `?`) of sources to leave out, e.g.
+    /// `*-beta.iso`. Excluded sources are listed as skipped.
+    pub exclude: Vec<String>,
+    /// Mountpoint of the ESP whose grub.cfg gains an entry as each ISO
+    /// finishes copying, so an interrupted batch still boots the ISOs
+    /// copied so far. `None` leaves the menu alone.
+    pub update_menu: Option<PathBuf>,
+}
+
+/// Outcome of a batch copy, or of its dry run. A bad source is recorded,
+/// never fatal.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CopyReport {
+    pub copied: Vec<CopyItem>,
+    /// Sources that are excluded, do not exist, have no file name, or are
+    /// already in the destination under any name.
+    pub skipped: Vec<String>,
+    /// Sources that exist but could not be copied, with the error.
+    pub failed: Vec<(String, String)>,
+    /// Copied sources the boot menu could not be updated for, with the
+    /// error. Only set with [`CopyOptions::update_menu`].
+    pub menu_failed: Vec<(String, String)>,
+}
+
+/// Copies each source into `dest_dir` under its own file name, announcing
+/// each one on `sink` as "Copying 2/5: ubuntu.iso" before it starts.
+/// Sources identical to a file already in `dest_dir` are skipped, and those
+/// that would overrun `free_bytes` fail without being started. A dry run
+/// makes the same checks and reports the same plan, but writes nothing.
+/// Excluded sources are set aside first and not announced.
+pub(crate) fn copy_into(
+    dest_dir: &Path,
+    sources: &[String],
+    free_bytes: Option<u64>,
+    options: &CopyOptions,
+    sink: &dyn ProgressSink,
+) -> CopyReport {
+    let mut report = CopyReport::default();
+    let (excluded, sources): (Vec<&String>, Vec<&String>) = sources
+        .iter()
+        .partition(|source| is_excluded(source, &options.exclude));
+    report.skipped.extend(excluded.into_iter().cloned());
+    let mut existing: Vec<(String, u64)> = fs::read_dir(dest_dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| {
+            let meta = entry.metadata().ok().filter(|m| m.is_file())?;
+            Some((entry.path().display().to_string(), meta.len()))
+        })
+        .collect();
+    let mut free_bytes = free_bytes;
+    let mut cache = HashCache::new();
+    let verb = if options.dry_run {
+        "Would copy"
+    } else {
+        "Copying"
+    };
+    for (i, &source) in sources.iter().enumerate() {
+        let src = Path::new(source);
+        let shown = src
+            .file_name()
+            .map_or_else(|| source.clone(), |n| n.to_string_lossy().into_owned());
+        sink.emit(ProgressEvent {
+            phase: PhaseId::Payload.as_str().to_string(),
+            message: format!("{verb} {}/{}: {shown}", i + 1, sources.len()),
+            percent: None,
+            ..Default::default()
+        });
+        let name = match src.file_name() {
+            Some(name) if src.exists() => name,
+            _ => {
+                report.skipped.push(source.clone());
+                continue;
+            }
+        };
+        let size = match fs::metadata(src) {
+            Ok(meta) if meta.is_file() => meta.len(),
+            Ok(_) => {
+                report
+                    .failed
+                    .push((source.clone(), "not a file".to_string()));
+                continue;
+            }
+            Err(e) => {
+                report.failed.push((source.clone(), e.to_string()));
+                continue;
+            }
+        };
+        if duplicate_of(source, size, &existing, &mut cache).is_some() {
+            report.skipped.push(source.clone());
+            continue;
+        }
+        if let Some(free) = free_bytes {
+            if size > free {
+                report.failed.push((
+                    source.clone(),
+                    format!("needs {size} bytes, {free} free on the data partition"),
+                ));
+                continue;
+            }
+            free_bytes = Some(free - size);
+        }
+        let dest = dest_dir.join(name);
+        let copied = if options.dry_run {
+            Ok(size)
+        } else {
+            fs::copy(src, &dest)
+        };
+        match copied {
+            Ok(size_bytes) => {
+                existing.push((dest.display().to_string(), size_bytes));
+                report.copied.push(CopyItem {
+                    source: source.clone(),
+                    dest: dest.display().to_string(),
+                    size_bytes,
+                });
+                let esp = options.update_menu.as_deref().filter(|_| !options.dry_run);
+                if let Some(Err(e)) = esp.map(|esp| add_to_menu(esp, &dest, size_bytes)) {
+                    report.menu_failed.push((source.clone(), e.to_string()));
+                }
+            }
+            Err(e) => report.failed.push((source.clone(), e.to_string())),
+        }
+    }
+    report
+}
+
+/// Adds an entry for the ISO copied to `iso` to the grub.cfg under the
+/// ESP mounted at `esp_mount`. An existing menu only gains the new
+/// `menuentry` block at its end, so lines this crate did not write survive;
+/// the entry follows the menu's ISO root and `savedefault` setting. Writes
+/// every grub.cfg the ESP has, or a fresh UEFI one when it has none. ISOs
+/// already in the menu, and ISOs known not to boot, leave it unchanged.
+fn add_to_menu(esp_mount: &Path, iso: &Path, size_bytes: u64) -> Result<()> {
+    let mut targets: Vec<PathBuf> = BootMode::Hybrid
+        .grub_cfg_paths(esp_mount)
+        .into_iter()
+        .filter(|path| path.is_file())
+        .collect();
+    let text = match targets.first() {
+        Some(path) => fs::read_to_string(path)
+            .map_err(|e| crate::CoreError::Io(format!("{}: {e}", path.display())))?,
+        None => String::new(),
+    };
+    if targets.is_empty() {
+        targets = BootMode::Uefi.grub_cfg_paths(esp_mount);
+    }
+    let mut config = grub::parse_grub_cfg(&text);
+    let name = iso.file_name().unwrap_or_default().to_string_lossy();
+    let mut entry = crate::iso_entry(iso, size_bytes);
+    // copy_isos_to_data always copies into /boot/isos, whatever the
+    // menu's iso_root.
+    entry.path = format!("{}/{name}", defaults::ISO_ROOT);
+    let iso_root = config.iso_root();
+    if config
+        .entries
+        .iter()
+        .any(|e| grub::iso_path(&e.path, &iso_root) == entry.path)
+    {
+        return Ok(());
+    }
+    let added = BootConfig::from_iso_entries(std::slice::from_ref(&entry)).entries;
+    if added.is_empty() {
+        return Ok(());
+    }
+    config.entries.extend(added);
+    let label = grub::search_label(&text).unwrap_or_else(|| defaults::DATA_LABEL.to_string());
+    if text.is_empty() {
+        grub::write_grub_cfg(&targets, &config, &label)?;
+    } else {
+        grub::validate_boot_config(&config)?;
+        let mut block = grub::render_grub_model(&config, &label);
+        block.header.clear();
+        block.entries = block.entries.split_off(block.entries.len() - 1);
+        for path in &targets {
+            let mut cfg = fs::read_to_string(path)
+                .map_err(|e| crate::CoreError::Io(format!("{}: {e}", path.display())))?;
+            if !cfg.is_empty() && !cfg.ends_with('\n') {
+                cfg.push('\n');
+            }
+            cfg.push_str(&block.to_string());
+            crate::write_atomic(path, cfg.as_bytes())?;
+        }
+    }
+    if config.save_default {
+        grub::ensure_grubenv(esp_mount)?;
+    }
+    Ok(())
+}
+
+fn is_excluded(source: &str, patterns: &[String]) -> bool {
+    let name = Path::new(source)
+        .file_name()
+        .map_or_else(|| source.into(), |n| n.to_string_lossy());
+    patterns.iter().any(|p| glob_match(p, &name))
+}
+
+/// Whole-string match of `name` against `pattern`, where `*` matches any
+/// run of characters and `?` exactly one.
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // Where the last `*` was, and the name position it has absorbed up to.
+    let mut star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((star_p, star_n)) => {
+                    p = star_p + 1;
+                    n = star_n + 1;
+                    star = Some((star_p, star_n + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// A host ISO whose contents are already on the stick.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DuplicateIso {
+    pub source: String,
+    /// The identical ISO on the data partition, possibly under another name.
+    pub existing: String,
+}
+
+/// Host ISOs that match an ISO on the stick by size and SHA-256. Only
+/// same-size pairs are hashed, through `cache`. `on_stick` paths must be
+/// readable, e.g. from [`scan_isos`](crate::scan_isos) over the mounted
+/// data partition's `boot/isos`. Files that cannot be read never match.
+pub fn find_duplicate_isos(
+    host: &[IsoEntry],
+    on_stick: &[IsoEntry],
+    cache: &mut HashCache,
+) -> Vec<DuplicateIso> {
+    let on_stick: Vec<(String, u64)> = on_stick
+        .iter()
+        .map(|e| (e.path.clone(), e.size_bytes))
+        .collect();
+    host.iter()
+        .filter_map(|source| {
+            let existing = duplicate_of(&source.path, source.size_bytes, &on_stick, cache)?;
+            Some(DuplicateIso {
+                source: source.path.clone(),
+                existing,
+            })
+        })
+        .collect()
+}
+
+/// The first of `candidates` (path, size) with the size and SHA-256 of
+/// `path`. Nothing is hashed unless some candidate has the same size.
+fn duplicate_of(
+    path: &str,
+    size: u64,
+    candidates: &[(String, u64)],
+    cache: &mut HashCache,
+) -> Option<String> {
+    let mut same_size = candidates.iter().filter(|(_, s)| *s == size).peekable();
+    same_size.peek()?;
+    let digest = cache.sha256(Path::new(path)).ok()?;
+    same_size
+        .find(|(other, _)| cache.sha256(Path::new(other)).is_ok_and(|d| d == digest))
+        .map(|(other, _)| other.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct Messages(Mutex<Vec<String>>);
+
+    impl ProgressSink for Messages {
+        fn emit(&self, event: ProgressEvent) {
+            self.0.lock().unwrap().push(event.message);
+        }
+    }
+
+    #[test]
+    fn bad_sources_are_reported_without_aborting() {
+        let root = std::env::temp_dir().join(format!("raidhos-iso-copy-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let dest = root.join("isos");
+        fs::create_dir_all(&dest).unwrap();
+        let good = root.join("ubuntu.iso");
+        fs::write(&good, b"iso").unwrap();
+        let missing = root.join("missing.iso");
+        let unreadable = root.join("folder.iso");
+        fs::create_dir_all(&unreadable).unwrap();
+
+        let sources: Vec<String> = [&unreadable, &missing, &good]
+            .iter()
+            .map(|p| p.display().to_string())
+            .collect();
+        let sink = Messages::default();
+        let report = copy_into(&dest, &sources, None, &CopyOptions::default(), &sink);
+
+        assert_eq!(report.copied.len(), 1);
+        assert_eq!(report.copied[0].size_bytes, 3);
+        assert!(dest.join("ubuntu.iso").is_file());
+        assert_eq!(report.skipped, vec![sources[1].clone()]);
+        assert_eq!(report.failed.len(), 1);
+        assert_eq!(report.failed[0].0, sources[0]);
+        assert_eq!(
+            sink.0.into_inner().unwrap(),
+            vec![
+                "Copying 1/3: folder.iso",
+                "Copying 2/3: missing.iso",
+                "Copying 3/3: ubuntu.iso",
+            ]
+        );
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn dry_run_plans_the_copy_without_writing() {
+        let root = std::env::temp_dir().join(format!("raidhos-iso-plan-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let dest = root.join("isos");
+        fs::create_dir_all(&dest).unwrap();
+        fs::write(dest.join("debian-old-name.iso"), b"debian").unwrap();
+        let debian = root.join("debian.iso");
+        let ubuntu = root.join("ubuntu.iso");
+        let fedora = root.join("fedora.iso");
+        fs::write(&debian, b"debian").unwrap();
+        fs::write(&ubuntu, b"ubuntu iso").unwrap();
+        fs::write(&fedora, b"fedora iso").unwrap();
+        let sources: Vec<String> = [&debian, &ubuntu, &fedora]
+            .iter()
+            .map(|p| p.display().to_string())
+            .collect();
+
+        let sink = Messages::default();
+        let dry_run = CopyOptions {
+            dry_run: true,
+            ..Default::default()
+        };
+        let plan = copy_into(&dest, &sources, Some(15), &dry_run, &sink);
+
+        assert_eq!(
+            plan.copied,
+            vec![CopyItem {
+                source: sources[1].clone(),
+                dest: dest.join("ubuntu.iso").display().to_string(),
+                size_bytes: 10,
+            }]
+        );
+        assert_eq!(plan.skipped, vec![sources[0].clone()]);
+        assert_eq!(plan.failed.len(), 1);
+        assert_eq!(plan.failed[0].0, sources[2]);
+        assert!(plan.failed[0].1.contains("5 free"));
+        assert_eq!(
+            sink.0.into_inner().unwrap()[0],
+            "Would copy 1/3: debian.iso"
+        );
+        let left: Vec<_> = fs::read_dir(&dest).unwrap().flatten().collect();
+        assert_eq!(left.len(), 1);
+
+        let options = CopyOptions::default();
+        let report = copy_into(&dest, &sources, Some(15), &options, &Messages::default());
+        assert_eq!(report, plan);
+        assert!(dest.join("ubuntu.iso").is_file());
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn exclude_globs_filter_sources_by_file_name() {
+        let root = std::env::temp_dir().join(format!("raidhos-iso-exclude-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let dest = root.join("isos");
+        fs::create_dir_all(&dest).unwrap();
+        let names = [
+            "fedora-41-beta.iso",
+            "fedora-40.iso",
+            "ubuntu-beta.iso.part",
+            "mint-22-beta.iso",
+            "beta-tools.iso",
+        ];
+        let sources: Vec<String> = names
+            .iter()
+            .map(|name| {
+                let path = root.join(name);
+                fs::write(&path, name.as_bytes()).unwrap();
+                path.display().to_string()
+            })
+            .collect();
+        let options = CopyOptions {
+            dry_run: true,
+            exclude: vec!["*-beta.iso".to_string()],
+            ..Default::default()
+        };
+
+        let sink = Messages::default();
+        let plan = copy_into(&dest, &sources, None, &options, &sink);
+
+        assert_eq!(plan.skipped, vec![sources[0].clone(), sources[3].clone()]);
+        let copied: Vec<&str> = plan.copied.iter().map(|c| c.source.as_str()).collect();
+        assert_eq!(copied, vec![&sources[1], &sources[2], &sources[4]]);
+        assert_eq!(
+            sink.0.into_inner().unwrap()[0],
+            "Would copy 1/3: fedora-40.iso"
+        );
+        assert!(glob_match(
+            "ubuntu-2?.04*.iso",
+            "ubuntu-24.04.1-desktop.iso"
+        ));
+        assert!(!glob_match("ubuntu-2?.04*.iso", "ubuntu-24.10.iso"));
+        assert!(glob_match("*", ""));
+        let _ = fs::remove_dir_all(root);
+    }
+
+    /// Snapshots the ESP's grub.cfg each time a copy is announced.
+    struct MenuSnapshots {
+        grub_cfg: PathBuf,
+        seen: Mutex<Vec<String>>,
+    }
+
+    impl ProgressSink for MenuSnapshots {
+        fn emit(&self, _event: ProgressEvent) {
+            let menu = fs::read_to_string(&self.grub_cfg).unwrap_or_default();
+            self.seen.lock().unwrap().push(menu);
+        }
+    }
+
+    #[test]
+    fn menu_gains_each_iso_as_it_is_copied() {
+        let root = std::env::temp_dir().join(format!("raidhos-iso-menu-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let dest = root.join("data/boot/isos");
+        let esp = root.join("esp");
+        fs::create_dir_all(&dest).unwrap();
+        let existing = BootConfig {
+            entries: vec![crate::BootEntryConfig {
+                title: "Arch".to_string(),
+                path: "/boot/isos/arch.iso".to_string(),
+                params: String::new(),
+                initrd: String::new(),
+                kargs: String::new(),
+                size_bytes: None,
+            }],
+            ..Default::default()
+        };
+        let grub_cfg = esp.join("EFI/BOOT/grub.cfg");
+        grub::write_grub_cfg(std::slice::from_ref(&grub_cfg), &existing, "STICK2").unwrap();
+        let ubuntu = root.join("ubuntu.iso");
+        let debian = root.join("debian.iso");
+        fs::write(&ubuntu, b"ubuntu iso").unwrap();
+        fs::write(&debian, b"debian iso!").unwrap();
+        let sources: Vec<String> = [&ubuntu, &debian]
+            .iter()
+            .map(|p| p.display().to_string())
+            .collect();
+
+        let sink = MenuSnapshots {
+            grub_cfg: grub_cfg.clone(),
+            seen: Mutex::default(),
+        };
+        let options = CopyOptions {
+            update_menu: Some(esp.clone()),
+            ..Default::default()
+        };
+        let report = copy_into(&dest, &sources, None, &options, &sink);
+        assert_eq!(report.copied.len(), 2);
+        assert!(report.menu_failed.is_empty(), "{:?}", report.menu_failed);
+
+        let seen = sink.seen.into_inner().unwrap();
+        assert!(!seen[0].contains("ubuntu.iso"));
+        // Announced before the second copy starts: the first is bootable.
+        assert!(seen[1].contains("set isofile=\"/boot/isos/ubuntu.iso\""));
+        assert!(!seen[1].contains("debian.iso"));
+        let menu = grub::parse_grub_cfg(&fs::read_to_string(&grub_cfg).unwrap());
+        let paths: Vec<&str> = menu.entries.iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            vec![
+                "/boot/isos/arch.iso",
+                "/boot/isos/ubuntu.iso",
+                "/boot/isos/debian.iso"
+            ]
+        );
+        let text = fs::read_to_string(&grub_cfg).unwrap();
+        assert_eq!(grub::search_label(&text).as_deref(), Some("STICK2"));
+
+        // An ISO already in the menu is not added twice.
+        add_to_menu(&esp, &dest.join("ubuntu.iso"), 10).unwrap();
+        assert_eq!(fs::read_to_string(&grub_cfg).unwrap(), text);
+
+        // Hand-written lines the parser does not model are kept as they are.
+        let custom = "set timeout=30\nset menu_color_normal=white/black\n\
+                      menuentry \"Reboot\" {\n  reboot\n}";
+        fs::write(&grub_cfg, custom).unwrap();
+        add_to_menu(&esp, &dest.join("debian.iso"), 11).unwrap();
+        let text = fs::read_to_string(&grub_cfg).unwrap();
+        let (kept, added) = text.split_at(custom.len() + 1);
+        assert_eq!(kept, format!("{custom}\n"));
+        assert!(added.starts_with("menuentry \"debian\" {\n"), "{added}");
+        assert!(added.contains("set isofile=\"/boot/isos/debian.iso\""));
+        let _ = fs::remove_dir_all(root);
+    }
+
+    fn iso(path: &Path) -> IsoEntry {
+        IsoEntry {
+            title: String::new(),
+            path: path.display().to_string(),
+            size_bytes: fs::metadata(path).unwrap().len(),
+            params: String::new(),
+            publisher: None,
+            is_bootable: None,
+            sha256: None,
+        }
+    }
+
+    #[test]
+    fn renamed_identical_iso_is_a_duplicate() {
+        let root = std::env::temp_dir().join(format!("raidhos-iso-dups-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let (host, stick) = (root.join("host"), root.join("stick"));
+        fs::create_dir_all(&host).unwrap();
+        fs::create_dir_all(&stick).unwrap();
+        fs::write(host.join("debian-12.iso"), b"debian contents").unwrap();
+        fs::write(host.join("fedora-40.iso"), b"fedora contents").unwrap();
+        fs::write(host.join("arch.iso"), b"arch").unwrap();
+        fs::write(stick.join("my-debian.iso"), b"debian contents").unwrap();
+        fs::write(stick.join("old-fedora.iso"), b"FEDORA contents").unwrap();
+
+        let host_isos: Vec<IsoEntry> = ["debian-12.iso", "fedora-40.iso", "arch.iso"]
+            .iter()
+            .map(|name| iso(&host.join(name)))
+            .collect();
+        let stick_isos: Vec<IsoEntry> = ["my-debian.iso", "old-fedora.iso"]
+            .iter()
+            .map(|name| iso(&stick.join(name)))
+            .collect();
+        let mut cache = HashCache::new();
+        let duplicates = find_duplicate_isos(&host_isos, &stick_isos, &mut cache);
+
+        assert_eq!(
+            duplicates,
+            vec![DuplicateIso {
+                source: host_isos[0].path.clone(),
+                existing: stick_isos[0].path.clone(),
+            }]
+        );
+        // arch.iso matches no size, so it is never hashed.
+        assert_eq!(cache.len(), 4);
+        find_duplicate_isos(&host_isos, &stick_isos, &mut cache);
+        assert_eq!(cache.len(), 4);
+        let _ = fs::remove_dir_all(root);
+    }
+}
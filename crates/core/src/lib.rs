@@ -2,7 +2,63 @@
 //!
 //! Provides disk discovery, safety checks, and installation orchestration.
 
+use std::collections::HashMap;
 use std::fmt;
+use std::path::{Path, PathBuf};
+use unicode_normalization::UnicodeNormalization;
+
+mod argv;
+mod atomic_write;
+mod bootloader;
+mod cancel;
+mod checksum;
+mod command_log;
+pub mod defaults;
+mod envelope;
+mod grub;
+mod iso;
+mod iso_copy;
+mod lock;
+mod params_db;
+mod payload;
+#[cfg(any(target_os = "macos", test))]
+mod plist;
+mod preflight;
+mod progress;
+mod runner;
+mod speed;
+mod syslinux;
+#[cfg(feature = "test-hooks")]
+pub mod test_hooks;
+#[cfg(target_os = "linux")]
+mod tree_copy;
+#[cfg(any(target_os = "windows", test))]
+mod windisk;
+mod worker;
+
+pub use atomic_write::write_atomic;
+pub use bootloader::{install_fallback_bootloader, Arch};
+pub use cancel::{CancelToken, ControlToken};
+pub use checksum::HashCache;
+pub use defaults::suggest_data_label;
+pub use envelope::Envelope;
+pub use grub::{
+    boot_config_schema, ensure_grubenv, missing_isos, parse_grub_cfg, read_grub_cfg,
+    large_iso_warnings, render_grub_cfg, render_grub_model, validate_boot_config, validate_kargs,
+    write_grub_cfg, BootConfig, BootEntryConfig, BootMode, GrubModel, KernelBranch,
+    RenderedEntry,
+};
+pub use iso::{read_iso_bootable, read_iso_metadata, IsoMetadata};
+pub use iso_copy::{find_duplicate_isos, CopyItem, CopyOptions, CopyReport, DuplicateIso};
+pub use lock::DeviceLock;
+pub use params_db::{ParamsDb, ParamsRule};
+pub use payload::{list_payloads, PayloadSource};
+pub use preflight::{check_boot_config, check_boot_config_with, PreflightIssue, Severity};
+pub use progress::{copy_with_progress, PercentProgress};
+pub use runner::{CommandOutput, CommandRunner, CommandStatus, SystemRunner};
+pub use speed::estimate_install_secs;
+pub use syslinux::{render_syslinux_cfg, MenuBackend};
+pub use worker::{progress_line, result_line, HelperResponse, WorkerProgress};
 
 pub type Result<T> = std::result::Result<T, CoreError>;
 
@@ -13,6 +69,28 @@ pub enum CoreError {
     Validation(String),
     NotImplemented(String),
     Parse(String),
+    Cancelled,
+    /// An external command ran but failed. `code` is `None` when it died
+    /// from a signal.
+    Command {
+        cmd: String,
+        code: Option<i32>,
+        stderr: String,
+    },
+}
+
+/// How much of a failed command's stderr its error message keeps; the
+/// end is where tools report what went wrong.
+const STDERR_TAIL_CHARS: usize = 400;
+
+/// The last [`STDERR_TAIL_CHARS`] characters of `stderr`, trimmed, with
+/// `...` in front when some were cut.
+fn stderr_tail(stderr: &str) -> String {
+    let stderr = stderr.trim();
+    match stderr.char_indices().rev().nth(STDERR_TAIL_CHARS - 1) {
+        Some((at, _)) if at > 0 => format!("...{}", stderr[at..].trim_start()),
+        _ => stderr.to_string(),
+    }
 }
 
 impl fmt::Display for CoreError {
@@ -23,97 +101,905 @@ impl fmt::Display for CoreError {
             CoreError::Validation(msg) => write!(f, "validation error: {msg}"),
             CoreError::NotImplemented(msg) => write!(f, "not implemented: {msg}"),
             CoreError::Parse(msg) => write!(f, "parse error: {msg}"),
+            CoreError::Cancelled => write!(f, "cancelled"),
+            CoreError::Command { cmd, code, stderr } => {
+                match code {
+                    Some(code) => write!(f, "command failed: {cmd} (exit code {code})")?,
+                    None => write!(f, "command failed: {cmd} (killed by a signal)")?,
+                }
+                match stderr_tail(stderr).as_str() {
+                    "" => Ok(()),
+                    tail => write!(f, ": {tail}"),
+                }
+            }
         }
     }
 }
 
 impl std::error::Error for CoreError {}
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
 pub struct DiskInfo {
     pub id: String,
     pub model: String,
-    pub size_bytes: u64,
+    /// Hardware serial number; `None` when the device reports none, as
+    /// many card readers and virtual disks do.
+    pub serial: Option<String>,
+    /// `None` when lsblk reports no size (empty card-reader slots, some
+    /// device-mapper nodes); distinct from a genuine 0-byte device.
+    pub size_bytes: Option<u64>,
+    /// The kernel's `rm` flag. USB-SATA bridges often report `false` for
+    /// drives that are external; see `external`.
     pub removable: bool,
+    /// Attached from outside the machine: `removable`, on the USB bus, or
+    /// flagged removable in `/sys/block/<name>/removable`.
+    pub external: bool,
+    pub transport: Option<String>,
     pub mountpoints: Vec<String>,
     pub is_system: bool,
+    /// Partitions anywhere below the disk in the lsblk tree.
+    pub partition_count: usize,
+    /// Device paths of those partitions.
+    pub partitions: Vec<String>,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
 pub struct PartitionInfo {
     pub id: String,
     pub label: String,
     pub fstype: String,
+    pub fsver: Option<String>,
+    pub fssize: Option<u64>,
+    pub mountpoints: Vec<String>,
+    /// Devices stacked directly on this partition.
+    pub holders: Vec<Holder>,
+}
+
+/// A device stacked on a disk or partition, such as a dm-crypt mapping or
+/// an LVM volume, with whatever is stacked on it in turn.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Holder {
+    /// Kernel name as lsblk reports it, e.g. `luks-1234` or `vg0-root`.
+    pub name: String,
+    /// lsblk `TYPE`: `crypt`, `lvm`, `raid1`, ...
+    pub kind: String,
+    pub fstype: String,
     pub mountpoints: Vec<String>,
+    pub holders: Vec<Holder>,
+}
+
+/// A disk together with its partitions.
+#[derive(Clone, Debug)]
+pub struct DiskWithPartitions {
+    pub disk: DiskInfo,
+    pub partitions: Vec<PartitionInfo>,
+    /// Devices stacked on the whole disk rather than on a partition.
+    pub holders: Vec<Holder>,
 }
 
+/// [`list_disks`] with a reason when the list is empty because of the
+/// environment rather than because nothing is plugged in.
 #[derive(Clone, Debug)]
+pub struct DiskListing {
+    pub disks: Vec<DiskInfo>,
+    /// Set when the platform tool enumerated no disks at all, e.g. lsblk in
+    /// a container without `/sys/block`. `None` whenever `disks` is
+    /// non-empty.
+    pub diagnostic: Option<String>,
+    /// Devices left out because lsblk described them in a way that could
+    /// not be parsed.
+    pub warnings: Vec<String>,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
 pub struct InstallRequest {
     pub device: String,
     pub payload_version: String,
     pub wipe: bool,
     pub dry_run: bool,
     pub allow_write: bool,
+    pub arch: Arch,
+    /// Payload source; overrides `RAIDHOS_PAYLOAD_DIR` when set.
+    pub payload_dir: Option<String>,
+    /// Refuse a target that already has partitions, so reusing a disk
+    /// takes a deliberate wipe first.
+    pub require_empty: bool,
+    #[serde(default)]
+    pub options: InstallOptions,
+}
+
+/// Advanced install knobs; the defaults reproduce the standard layout.
+/// The cancel token, the runner and the mounter are process-local and do
+/// not serialize; a deserialized request gets fresh ones.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct InstallOptions {
+    /// Extra `mkfs.vfat` arguments for the ESP, checked against an allowlist.
+    pub esp_mkfs_args: Vec<String>,
+    /// Extra data-partition mkfs arguments, checked against an allowlist.
+    pub data_mkfs_args: Vec<String>,
+    /// Checked between phases; a cancelled install unmounts and stops.
+    #[serde(skip)]
+    pub cancel: CancelToken,
+    pub layout: Layout,
+    pub mode: InstallMode,
+    /// Leave the payload mounts in place when the copy fails, and name them
+    /// in the error, so the half-written stick can be inspected.
+    pub keep_mounts_on_error: bool,
+    /// Add a hybrid MBR entry for the ESP (via `sgdisk`) for firmware that
+    /// will not boot a GPT-only stick.
+    pub hybrid_mbr: bool,
+    pub partition_types: PartitionTypes,
+    /// Zero the first and last MiB of the device before partitioning, so
+    /// no stale primary or backup GPT (or MBR) survives `mklabel`. Not
+    /// accepted by `ensure_layout`, which keeps the partition table.
+    pub zero_ends: bool,
+    /// Append one JSON line per external command run (command, args, exit
+    /// status, duration) to this file.
+    pub command_log: Option<PathBuf>,
+    /// Runs the install's external commands instead of the host, e.g. to
+    /// record them in a test.
+    #[serde(skip)]
+    pub runner: Option<std::sync::Arc<dyn CommandRunner>>,
+    /// Mounts the install's filesystems instead of the host's `mount`,
+    /// e.g. to record them in a test.
+    #[serde(skip)]
+    pub mounter: Option<std::sync::Arc<dyn Mounter>>,
+    /// Replace the payload's grub.cfg with one generated from the ISOs the
+    /// payload puts in `/boot/isos`.
+    pub auto_menu: bool,
+    /// Format without scanning or zeroing the partitions (the default).
+    /// When false, mkfs.vfat checks for bad blocks and mkfs.exfat zeroes
+    /// the data partition; a formatter that cannot do a full format warns
+    /// and formats quickly.
+    pub quick_format: bool,
+    /// `Syslinux` also installs syslinux for BIOS boot, with a menu that
+    /// chain-loads the payload's BIOS GRUB.
+    pub menu_backend: MenuBackend,
+    /// Start the data partition at this MiB offset instead of right after
+    /// the ESP, leaving the gap unallocated; some USB controllers are
+    /// faster with the data partition on a 4 MiB boundary. Must lie after
+    /// the ESP and be a multiple of 4 MiB. Only used when wiping the disk.
+    pub data_start_mib: Option<u32>,
+    /// ESP size; `None` means the standard 32 MiB, the smallest a FAT32
+    /// filesystem fits in. Not used with the data-only layout or when
+    /// installing to a partition.
+    pub esp_size_mib: Option<u64>,
+    /// Data partition size; `None` fills the rest of the disk (or of the
+    /// free region), otherwise the space after it stays unallocated. Not
+    /// used when installing to a partition.
+    pub data_size_mib: Option<u64>,
+    /// Label for the data partition; `None` means [`defaults::DATA_LABEL`],
+    /// or with `unique_labels` the label [`suggest_data_label`] picks.
+    pub data_label: Option<String>,
+    /// Filesystem for the data partition. The generated boot menu loads
+    /// the matching GRUB module.
+    pub data_fstype: DataFsType,
+    /// Without an explicit `data_label`, pick `DATA2`, `DATA3`, … when a
+    /// partition on another attached disk is already labelled `DATA`, so
+    /// the menu's label search cannot boot from the wrong stick.
+    pub unique_labels: bool,
+}
+
+impl Default for InstallOptions {
+    fn default() -> Self {
+        InstallOptions {
+            esp_mkfs_args: Vec::new(),
+            data_mkfs_args: Vec::new(),
+            cancel: CancelToken::default(),
+            layout: Layout::default(),
+            mode: InstallMode::default(),
+            keep_mounts_on_error: false,
+            hybrid_mbr: false,
+            partition_types: PartitionTypes::default(),
+            zero_ends: false,
+            command_log: None,
+            runner: None,
+            mounter: None,
+            auto_menu: false,
+            quick_format: true,
+            menu_backend: MenuBackend::default(),
+            data_start_mib: None,
+            esp_size_mib: None,
+            data_size_mib: None,
+            data_label: None,
+            data_fstype: DataFsType::default(),
+            unique_labels: false,
+        }
+    }
+}
+
+/// GPT type GUID of an EFI System Partition.
+pub const ESP_TYPE_GUID: &str = "C12A7328-F81F-11D2-BA4B-00A0C93EC93B";
+/// Microsoft Basic Data: what Windows and macOS expect for exFAT.
+pub const BASIC_DATA_TYPE_GUID: &str = "EBD0A0A2-B9E5-4433-87C0-68B6B97199C7";
+/// Linux filesystem data, parted's default for new partitions.
+pub const LINUX_DATA_TYPE_GUID: &str = "0FC63DAF-8483-4772-8E79-3D69D8477DE4";
+
+/// GPT type GUIDs set with `sgdisk -t` after partitioning.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct PartitionTypes {
+    pub esp: String,
+    pub data: String,
+}
+
+impl Default for PartitionTypes {
+    fn default() -> Self {
+        PartitionTypes {
+            esp: ESP_TYPE_GUID.to_string(),
+            data: BASIC_DATA_TYPE_GUID.to_string(),
+        }
+    }
+}
+
+/// Where an install puts its partitions.
+#[derive(Clone, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InstallMode {
+    /// A new partition table across the whole disk.
+    #[default]
+    Wipe,
+    /// Keep the existing GPT and its partitions and create the new ones in
+    /// the unallocated region of `size_mib` MiB starting `start_mib` MiB
+    /// into the disk. Whole-MiB offsets keep the partitions aligned.
+    FreeSpace { start_mib: u64, size_mib: u64 },
+    /// `device` is an existing partition: format it as the data partition
+    /// without touching the partition table, using the sibling partition
+    /// `esp` as the ESP. The boot-and-data layout needs `esp`.
+    Partition { esp: Option<String> },
+}
+
+/// Partition layout written by an install.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Layout {
+    /// ESP with the bootloader plus an exFAT data partition.
+    #[default]
+    BootAndData,
+    /// A single exFAT data partition across the disk; not bootable.
+    DataOnly,
+}
+
+/// Filesystem [`format_disk`] puts on its single partition.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FormatFs {
+    /// Readable on Linux, Windows and macOS; no 4 GiB file limit.
+    #[default]
+    ExFat,
+    Fat32,
+    Ext4,
+}
+
+impl std::str::FromStr for FormatFs {
+    type Err = CoreError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "exfat" => Ok(FormatFs::ExFat),
+            "fat32" | "vfat" => Ok(FormatFs::Fat32),
+            "ext4" => Ok(FormatFs::Ext4),
+            other => Err(CoreError::Validation(format!(
+                "unsupported filesystem: {other}"
+            ))),
+        }
+    }
+}
+
+/// Filesystem an install puts on the data partition.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DataFsType {
+    /// Readable on Linux, Windows and macOS; no 4 GiB file limit.
+    #[default]
+    ExFat,
+    /// Journaled; read-only or unreadable outside Linux.
+    Ext4,
+    /// Read-write on Windows and Linux, read-only on macOS.
+    Ntfs,
+}
+
+impl DataFsType {
+    /// The name lsblk reports for the filesystem.
+    pub fn fstype(self) -> &'static str {
+        match self {
+            DataFsType::ExFat => "exfat",
+            DataFsType::Ext4 => "ext4",
+            DataFsType::Ntfs => "ntfs",
+        }
+    }
+
+    /// The GRUB module that reads the filesystem; GRUB's `ext2` module
+    /// covers ext4.
+    pub fn grub_module(self) -> &'static str {
+        match self {
+            DataFsType::ExFat => "exfat",
+            DataFsType::Ext4 => "ext2",
+            DataFsType::Ntfs => "ntfs",
+        }
+    }
+}
+
+impl std::str::FromStr for DataFsType {
+    type Err = CoreError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "exfat" => Ok(DataFsType::ExFat),
+            "ext4" => Ok(DataFsType::Ext4),
+            "ntfs" => Ok(DataFsType::Ntfs),
+            other => Err(CoreError::Validation(format!(
+                "unsupported data filesystem: {other}"
+            ))),
+        }
+    }
+}
+
+/// What [`plan_install`] found an install would do.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct InstallPlan {
+    pub device: String,
+    /// Command lines in the order the install would run them.
+    pub commands: Vec<String>,
 }
 
 #[derive(Clone, Debug)]
+pub struct InstallOutcome {
+    pub device: String,
+    pub dry_run: bool,
+    pub duration_ms: u64,
+    /// Steps applied to the device (or planned, for a dry run).
+    pub changes: Vec<LayoutChange>,
+    /// The disk's partitions as re-read after the install. Empty for a dry
+    /// run or when the disk could not be re-read.
+    pub partitions: Vec<PartitionInfo>,
+    /// Menu entries in the grub.cfg the payload put on the ESP; `None` when
+    /// no grub.cfg was written.
+    pub grub_entries: Option<usize>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LayoutChange {
+    Partition,
+    FormatEsp,
+    RelabelEsp,
+    FormatData,
+    RelabelData,
+    RefreshPayload,
+}
+
+impl fmt::Display for LayoutChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LayoutChange::Partition => write!(f, "create GPT partitions"),
+            LayoutChange::FormatEsp => write!(f, "format ESP"),
+            LayoutChange::RelabelEsp => write!(f, "relabel ESP"),
+            LayoutChange::FormatData => write!(f, "format data partition"),
+            LayoutChange::RelabelData => write!(f, "relabel data partition"),
+            LayoutChange::RefreshPayload => write!(f, "refresh payload"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize)]
 pub struct ProgressEvent {
     pub phase: String,
     pub message: String,
     pub percent: Option<u8>,
+    /// Bytes copied so far, on events from a copy that counts them.
+    #[serde(default)]
+    pub bytes_done: Option<u64>,
+    /// Bytes the copy will write in all, alongside `bytes_done`.
+    #[serde(default)]
+    pub bytes_total: Option<u64>,
 }
 
-#[derive(Clone, Debug)]
+/// Known install phases, as carried in [`ProgressEvent::phase`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum PhaseId {
+    Validate,
+    Prepare,
+    Partition,
+    Format,
+    Layout,
+    Payload,
+    Write,
+    Finalize,
+    Complete,
+    Cancelled,
+    Paused,
+    /// A command a dry run would have run.
+    Plan,
+    /// Cleanup after an install failed part-way through.
+    Rollback,
+    Other,
+}
+
+impl PhaseId {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PhaseId::Validate => "validate",
+            PhaseId::Prepare => "prepare",
+            PhaseId::Partition => "partition",
+            PhaseId::Format => "format",
+            PhaseId::Layout => "layout",
+            PhaseId::Payload => "payload",
+            PhaseId::Write => "write",
+            PhaseId::Finalize => "finalize",
+            PhaseId::Complete => "complete",
+            PhaseId::Cancelled => "cancelled",
+            PhaseId::Paused => "paused",
+            PhaseId::Plan => "plan",
+            PhaseId::Rollback => "rollback",
+            PhaseId::Other => "other",
+        }
+    }
+}
+
+impl From<&str> for PhaseId {
+    fn from(phase: &str) -> Self {
+        match phase {
+            "validate" => PhaseId::Validate,
+            "prepare" => PhaseId::Prepare,
+            "partition" => PhaseId::Partition,
+            "format" => PhaseId::Format,
+            "layout" => PhaseId::Layout,
+            "payload" => PhaseId::Payload,
+            "write" => PhaseId::Write,
+            "finalize" => PhaseId::Finalize,
+            "complete" => PhaseId::Complete,
+            "cancelled" => PhaseId::Cancelled,
+            "paused" => PhaseId::Paused,
+            "plan" => PhaseId::Plan,
+            "rollback" => PhaseId::Rollback,
+            _ => PhaseId::Other,
+        }
+    }
+}
+
+impl fmt::Display for PhaseId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl ProgressEvent {
+    pub fn phase_id(&self) -> PhaseId {
+        PhaseId::from(self.phase.as_str())
+    }
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
 pub struct IsoEntry {
+    /// Volume label from the ISO header, or the file stem without one.
     pub title: String,
     pub path: String,
     pub size_bytes: u64,
     pub params: String,
+    pub publisher: Option<String>,
+    /// El Torito boot record or live-image directories found; `None` when
+    /// the header could not be read. The boot menu skips `Some(false)`.
+    pub is_bootable: Option<bool>,
+    /// Lowercase hex SHA-256 of the file; only [`scan_isos_with_checksums`]
+    /// fills it in.
+    pub sha256: Option<String>,
+}
+
+/// Aggregate over a set of scanned ISOs, e.g. for a "12 ISOs, 34.5 GiB" header.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct IsoScanSummary {
+    pub count: usize,
+    pub total_bytes: u64,
+    /// Entry count keyed by distro, taken from the leading word of the title.
+    pub by_distro: HashMap<String, usize>,
+}
+
+impl IsoScanSummary {
+    pub fn from_entries(entries: &[IsoEntry]) -> Self {
+        let mut summary = IsoScanSummary::default();
+        for entry in entries {
+            summary.count += 1;
+            summary.total_bytes += entry.size_bytes;
+            *summary.by_distro.entry(distro_key(&entry.title)).or_default() += 1;
+        }
+        summary
+    }
+}
+
+/// The scan entry for the ISO at `path`: title and publisher from its
+/// header, params from the built-in rules.
+fn iso_entry(path: &Path, size_bytes: u64) -> IsoEntry {
+    let header = read_iso_metadata(path).ok().flatten().unwrap_or_default();
+    let title = header.volume_id.unwrap_or_else(|| {
+        path.file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or("ISO")
+            .to_string()
+    });
+    let file_name = path.file_name().and_then(|s| s.to_str()).unwrap_or("");
+    let params = ParamsDb::builtin()
+        .lookup(file_name)
+        .map(|rule| rule.params.clone())
+        .unwrap_or_else(|| "quiet splash".to_string());
+    IsoEntry {
+        title,
+        path: path.display().to_string(),
+        size_bytes,
+        params,
+        publisher: header.publisher,
+        is_bootable: read_iso_bootable(path).ok().flatten(),
+        sha256: None,
+    }
+}
+
+/// `"ubuntu-24.04-desktop-amd64"` → `"ubuntu"`; titles without a leading
+/// word fall under `"other"`.
+fn distro_key(title: &str) -> String {
+    let key: String = title
+        .chars()
+        .take_while(|c| c.is_alphabetic())
+        .flat_map(char::to_lowercase)
+        .collect();
+    if key.is_empty() {
+        "other".to_string()
+    } else {
+        key
+    }
+}
+
+/// Sort key for ISO titles that does not depend on the host locale: the
+/// lowercased title with accents stripped (`"Åland"` sorts with `"aland"`),
+/// then the lowercased title in NFC so accented and plain forms keep a
+/// fixed order. Other scripts follow Latin in code point order.
+fn title_sort_key(title: &str) -> (String, String) {
+    let lower = title.to_lowercase();
+    let folded = lower
+        .nfd()
+        .filter(|c| !unicode_normalization::char::is_combining_mark(*c))
+        .collect();
+    (folded, lower.nfc().collect())
+}
+
+/// First `--version` line of each external tool an install relies on, for
+/// bug reports. `None` when the tool is missing or printed nothing.
+#[derive(Clone, Debug, Default, PartialEq, Eq, serde::Serialize)]
+pub struct ToolVersions {
+    pub parted: Option<String>,
+    pub mkfs_vfat: Option<String>,
+    /// The first exFAT formatter found, in the installer's preference order.
+    pub exfat: Option<String>,
+    pub grub_install: Option<String>,
+}
+
+/// Outcome of one [`verify_stick`] check.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckStatus {
+    Pass,
+    /// The stick boots, but something is off (an empty menu, say).
+    Warn,
+    /// The stick will not boot as is.
+    Fail,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize)]
+pub struct StickCheck {
+    pub name: String,
+    pub status: CheckStatus,
+    pub detail: String,
+}
+
+/// What [`verify_stick`] found, one item per check, in the order run.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize)]
+pub struct StickReport {
+    pub device: String,
+    pub checks: Vec<StickCheck>,
+}
+
+impl StickReport {
+    /// The worst status among the checks; `Pass` when there are none.
+    pub fn status(&self) -> CheckStatus {
+        self.checks
+            .iter()
+            .map(|c| c.status)
+            .max()
+            .unwrap_or(CheckStatus::Pass)
+    }
 }
 
 pub trait ProgressSink {
     fn emit(&self, event: ProgressEvent);
 }
 
+/// Mounts filesystems for the install steps that write to them, so the
+/// orchestration does not depend on one platform's mount tools.
+pub trait Mounter: std::fmt::Debug + Send + Sync {
+    fn mount(&self, device: &str, target: &Path) -> Result<()>;
+    fn mount_read_only(&self, device: &str, target: &Path) -> Result<()>;
+    fn unmount(&self, target: &Path) -> Result<()>;
+}
+
 pub fn list_disks() -> Result<Vec<DiskInfo>> {
     platform::list_disks()
 }
 
-pub fn install(req: InstallRequest, sink: &dyn ProgressSink) -> Result<()> {
+/// [`list_disks`], explaining an empty result that points at the
+/// environment (lsblk seeing no block devices) rather than at the hardware.
+pub fn list_disks_diagnostic() -> Result<DiskListing> {
+    platform::list_disks_diagnostic()
+}
+
+/// Disks with their partitions nested, from a single lsblk call.
+pub fn list_disks_tree() -> Result<Vec<DiskWithPartitions>> {
+    platform::list_disks_tree()
+}
+
+/// Checks `device` without touching it. Errors are what `install` would
+/// refuse; warnings (e.g. an internal disk) call for an extra confirmation.
+pub fn check_target(device: &str) -> Result<Vec<PreflightIssue>> {
+    let disks = list_disks()?;
+    let disk = disks
+        .iter()
+        .find(|d| d.id == device)
+        .ok_or_else(|| CoreError::Validation("device not found".to_string()))?;
+    Ok(preflight::check_disk(disk))
+}
+
+/// The disk the running system booted from when it is a live session off
+/// a USB stick, e.g. an ISO loopback-booted from a RaidhOS stick. `None`
+/// on an installed system. Installs and the other destructive operations
+/// refuse this disk.
+pub fn boot_device() -> Result<Option<String>> {
+    platform::boot_device()
+}
+
+/// Disks that are plausible install targets: external, not the system disk,
+/// not mounted, and not carrying swap or LVM/RAID members.
+pub fn list_install_targets() -> Result<Vec<DiskInfo>> {
+    platform::list_install_targets()
+}
+
+/// The one disk in `disks` whose serial is `serial`, for scripts where
+/// `/dev/sdX` names move between boots. No match and several matches (some
+/// USB bridges report the same serial for every drive) are both errors.
+pub fn find_disk_by_serial<'a>(disks: &'a [DiskInfo], serial: &str) -> Result<&'a DiskInfo> {
+    let serial = serial.trim();
+    let matches: Vec<&DiskInfo> = disks
+        .iter()
+        .filter(|d| d.serial.as_deref() == Some(serial))
+        .collect();
+    match matches.as_slice() {
+        [disk] => Ok(disk),
+        [] => Err(CoreError::Validation(format!(
+            "no disk has serial {serial}"
+        ))),
+        several => {
+            let ids: Vec<&str> = several.iter().map(|d| d.id.as_str()).collect();
+            Err(CoreError::Validation(format!(
+                "serial {serial} matches several disks: {}",
+                ids.join(", ")
+            )))
+        }
+    }
+}
+
+pub fn install(req: InstallRequest, sink: &dyn ProgressSink) -> Result<InstallOutcome> {
     platform::install(req, sink)
 }
 
+/// The commands [`install`] would run for `req`, collected from the
+/// `plan` events of a dry run of it. Nothing is written to the device.
+pub fn plan_install(req: InstallRequest) -> Result<InstallPlan> {
+    struct Commands(std::cell::RefCell<Vec<String>>);
+
+    impl ProgressSink for Commands {
+        fn emit(&self, event: ProgressEvent) {
+            if event.phase_id() == PhaseId::Plan {
+                self.0.borrow_mut().push(event.message);
+            }
+        }
+    }
+
+    let device = req.device.clone();
+    let commands = Commands(std::cell::RefCell::new(Vec::new()));
+    install(
+        InstallRequest {
+            dry_run: true,
+            ..req
+        },
+        &commands,
+    )?;
+    Ok(InstallPlan {
+        device,
+        commands: commands.0.into_inner(),
+    })
+}
+
+/// Brings an existing stick to the RaidhOS layout, repartitioning or
+/// reformatting only what differs, then refreshes the payload.
+pub fn ensure_layout(req: InstallRequest, sink: &dyn ProgressSink) -> Result<InstallOutcome> {
+    platform::ensure_layout(req, sink)
+}
+
 pub fn scan_isos(dirs: Vec<String>) -> Result<Vec<IsoEntry>> {
     platform::scan_isos(dirs)
 }
 
+/// [`scan_isos`], hashing each ISO found when `compute_hashes` is set so
+/// corrupt or truncated downloads show up before an install. Files are
+/// streamed through the hasher 1 MiB at a time; one that cannot be read
+/// keeps `sha256: None`.
+pub fn scan_isos_with_checksums(dirs: Vec<String>, compute_hashes: bool) -> Result<Vec<IsoEntry>> {
+    let mut entries = scan_isos(dirs)?;
+    if compute_hashes {
+        for entry in &mut entries {
+            entry.sha256 = checksum::sha256_file(Path::new(&entry.path)).ok();
+        }
+    }
+    Ok(entries)
+}
+
+/// [`scan_isos`] plus an [`IsoScanSummary`] over every directory scanned.
+pub fn scan_isos_summary(dirs: Vec<String>) -> Result<(Vec<IsoEntry>, IsoScanSummary)> {
+    let entries = scan_isos(dirs)?;
+    let summary = IsoScanSummary::from_entries(&entries);
+    Ok((entries, summary))
+}
+
+/// Scans `/boot/isos` on a data partition, mounting it read-only for the
+/// duration of the scan if it is not already mounted. Entry paths are
+/// relative to the partition root.
+pub fn scan_isos_on_device(device: String) -> Result<Vec<IsoEntry>> {
+    platform::scan_isos_on_device(device)
+}
+
 pub fn list_partitions(device: String) -> Result<Vec<PartitionInfo>> {
     platform::list_partitions(device)
 }
 
+/// Removes RaidhOS from `device`: with `reformat`, repartitions it as one
+/// plain exFAT partition; otherwise deletes the `raidhos/` directories and
+/// grub.cfg files and keeps everything else, including ISOs. A disk with
+/// no RaidhOS ESP or `raidhos/` directory is refused. Nothing is changed
+/// unless `allow_write` is set.
+pub fn uninstall(device: &str, reformat: bool, allow_write: bool) -> Result<()> {
+    platform::uninstall(device, reformat, allow_write)
+}
+
+/// Health-checks the RaidhOS stick on `device` without changing it: the
+/// ESP and data partitions and their labels, the grub.cfg and fallback
+/// bootloader on the ESP, and the ISO behind every menu entry. Only a
+/// device that cannot be read fails the call; problems are in the report.
+pub fn verify_stick(device: &str) -> Result<StickReport> {
+    platform::verify_stick(device)
+}
+
+/// Renames the data partition of the stick on `device` to `new_label`
+/// without reformatting, and points the `search --label` line of each
+/// grub.cfg on the ESP at it. `boot.json` names no label and is left as is.
+pub fn relabel_data(device: &str, new_label: &str) -> Result<()> {
+    platform::relabel_data(device, new_label)
+}
+
+pub fn tool_versions() -> ToolVersions {
+    platform::tool_versions()
+}
+
+/// Write speed of `device` in bytes/s, for [`estimate_install_secs`].
+/// Opt-in benchmark: the target must pass the install checks, and the
+/// sampled region is read and written back unchanged.
+pub fn measure_write_speed(device: &str) -> Result<u64> {
+    platform::measure_write_speed(device)
+}
+
+/// Wipes `device` and formats it as a single `fs` partition labelled
+/// `label`: a plain stick, with no ESP and no payload. Refuses the same
+/// disks [`install`] does, and writes nothing unless `allow_write` is set.
+pub fn format_disk(device: &str, fs: FormatFs, label: &str, allow_write: bool) -> Result<()> {
+    platform::format_disk(device, fs, label, allow_write)
+}
+
+/// Reads the filesystem label of the data partition that sits next to the
+/// ESP mounted at `esp_mount`.
+pub fn detect_data_label(esp_mount: &str) -> Result<Option<String>> {
+    platform::detect_data_label(esp_mount)
+}
+
+/// `<mount_path>/boot/isos`, after checking that `mount_path` is where a
+/// RaidhOS data partition is mounted, so ISOs never land on the ESP or the
+/// host filesystem.
+pub fn iso_destination(mount_path: &str) -> Result<PathBuf> {
+    platform::iso_destination(mount_path)
+}
+
+/// Errors unless `esp_mount` is the mountpoint of a FAT partition with
+/// room for a boot menu, so grub.cfg never lands on the data partition or
+/// the host filesystem.
+pub fn check_esp_mount(esp_mount: &str) -> Result<()> {
+    platform::check_esp_mount(esp_mount)
+}
+
+/// [`check_esp_mount`], then writes the rendered config to
+/// `EFI/BOOT/grub.cfg` under `esp_mount`, returning the written path.
+/// Creates the grubenv file `save_default` needs.
+pub fn write_grub_cfg_to_esp(
+    esp_mount: &Path,
+    config: &BootConfig,
+    data_label: &str,
+) -> Result<PathBuf> {
+    check_esp_mount(&esp_mount.display().to_string())?;
+    grub::write_grub_cfg_to_esp(esp_mount, config, data_label)
+}
+
+/// Mountpoints of currently mounted FAT, exFAT and ext filesystems that
+/// could hold ISOs, system mounts excluded, to offer as scan dirs.
+pub fn mounted_scan_candidates() -> Result<Vec<String>> {
+    platform::mounted_scan_candidates()
+}
+
+/// Copies ISOs into `/boot/isos` on the data partition mounted at
+/// `mount_path`. Only the destination check fails the call; per-file
+/// problems, ISOs already there and ISOs that do not fit are listed in the
+/// report. Each file is announced on `sink` as it starts. On a dry run
+/// nothing is written and the report is the plan. With
+/// [`CopyOptions::update_menu`], each ISO joins the boot menu as soon as it
+/// is copied.
+pub fn copy_isos_to_data(
+    mount_path: &str,
+    sources: &[String],
+    options: &CopyOptions,
+    sink: &dyn ProgressSink,
+) -> Result<CopyReport> {
+    let dest_dir = iso_destination(mount_path)?;
+    let free_bytes = platform::free_bytes(mount_path)?;
+    if !options.dry_run {
+        std::fs::create_dir_all(&dest_dir).map_err(|e| CoreError::Io(e.to_string()))?;
+    }
+    Ok(iso_copy::copy_into(
+        &dest_dir, sources, free_bytes, options, sink,
+    ))
+}
+
 #[cfg(target_os = "linux")]
 mod platform {
-    use super::{CoreError, DiskInfo, InstallRequest, PartitionInfo, ProgressEvent, ProgressSink, Result};
-    use serde::Deserialize;
-    use std::process::Command;
-    use std::{fs, path::PathBuf};
+    use super::argv;
+    use super::bootloader::grub_install_args;
+    use super::cancel::{self, ActiveCancel};
+    use super::command_log::{self, CommandLog};
+    use super::defaults::{DATA_LABEL, DATA_MOUNT, ESP_LABEL, ESP_MOUNT, SCAN_MOUNT_ROOT};
+    use super::preflight;
+    use super::runner::{self, ActiveRunner, CommandRunner};
+    use super::{
+        Arch, CancelToken, CoreError, DataFsType, DiskListing, FormatFs, InstallMode, InstallOptions, Layout, MenuBackend, PartitionTypes, DiskInfo, DiskWithPartitions, Holder, InstallOutcome, InstallRequest, LayoutChange, Mounter, PartitionInfo,
+        ProgressEvent, ProgressSink, Result, ToolVersions, CheckStatus, StickCheck, StickReport,
+    };
+    use serde::{Deserialize, Deserializer};
+    use std::time::Instant;
+    use std::fs;
+    use std::path::{Path, PathBuf};
 
     #[derive(Deserialize)]
     struct LsblkOutput {
+        #[serde(default)]
         blockdevices: Vec<LsblkDevice>,
+        /// Devices [`parse_lsblk`] had to drop, one line each.
+        #[serde(skip)]
+        warnings: Vec<String>,
     }
 
     #[derive(Deserialize)]
     struct LsblkDevice {
         name: String,
-        #[serde(default)]
-        size: Option<String>,
+        #[serde(default, deserialize_with = "de_opt_u64")]
+        size: Option<u64>,
         #[serde(default)]
         model: Option<String>,
         #[serde(default)]
+        serial: Option<String>,
+        #[serde(default)]
         rm: Option<bool>,
         #[serde(default)]
-        #[allow(dead_code)]
+        tran: Option<String>,
+        #[serde(default, rename = "type")]
         type_field: Option<String>,
         #[serde(default)]
         mountpoints: Option<Vec<Option<String>>>,
@@ -124,51 +1010,243 @@ mod platform {
         #[serde(default)]
         fstype: Option<String>,
         #[serde(default)]
+        fsver: Option<String>,
+        #[serde(default, deserialize_with = "de_opt_u64")]
+        fssize: Option<u64>,
+        #[serde(default, deserialize_with = "de_opt_u64")]
+        fsavail: Option<u64>,
+        #[serde(default)]
         pkname: Option<String>,
+        /// First sector, in 512-byte units.
+        #[serde(default, deserialize_with = "de_opt_u64")]
+        start: Option<u64>,
     }
 
-    pub fn list_disks() -> Result<Vec<DiskInfo>> {
-        let output = Command::new("lsblk")
-            .args(["-b", "-J", "-o", "NAME,MODEL,SIZE,RM,TYPE,MOUNTPOINTS"])
-            .output()
-            .map_err(|e| CoreError::Io(e.to_string()))?;
+    /// lsblk emits byte counts as JSON numbers on newer util-linux and as
+    /// strings on older releases; accept either.
+    fn de_opt_u64<'de, D>(deserializer: D) -> std::result::Result<Option<u64>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum NumOrStr {
+            Num(u64),
+            Str(String),
+        }
+        Ok(match Option::<NumOrStr>::deserialize(deserializer)? {
+            Some(NumOrStr::Num(n)) => Some(n),
+            Some(NumOrStr::Str(s)) => s.trim().parse().ok(),
+            None => None,
+        })
+    }
 
-        if !output.status.success() {
-            return Err(CoreError::Io("lsblk failed".to_string()));
+    /// Parses lsblk's JSON. When one device has a field of an unexpected
+    /// type, the other devices are kept and the bad one is named in
+    /// `warnings` instead of failing the whole listing.
+    fn parse_lsblk(json: &[u8]) -> Result<LsblkOutput> {
+        let strict_err = match serde_json::from_slice(json) {
+            Ok(parsed) => return Ok(parsed),
+            Err(e) => e,
+        };
+        let value: serde_json::Value =
+            serde_json::from_slice(json).map_err(|e| CoreError::Parse(e.to_string()))?;
+        let Some(devices) = value.get("blockdevices").and_then(|v| v.as_array()) else {
+            return Err(CoreError::Parse(strict_err.to_string()));
+        };
+        let mut parsed = LsblkOutput {
+            blockdevices: Vec::new(),
+            warnings: Vec::new(),
+        };
+        for device in devices {
+            match LsblkDevice::deserialize(device) {
+                Ok(dev) => parsed.blockdevices.push(dev),
+                Err(e) => {
+                    let name = device.get("name").and_then(|n| n.as_str()).unwrap_or("?");
+                    parsed
+                        .warnings
+                        .push(format!("skipped lsblk device {name}: {e}"));
+                }
+            }
         }
+        Ok(parsed)
+    }
 
-        let parsed: LsblkOutput = serde_json::from_slice(&output.stdout)
-            .map_err(|e| CoreError::Parse(e.to_string()))?;
+    const DISK_COLUMNS: &str = "NAME,MODEL,SERIAL,SIZE,RM,TYPE,TRAN,FSTYPE,MOUNTPOINTS";
 
-        let mut disks = Vec::new();
+    fn lsblk(columns: &str) -> Result<Vec<u8>> {
+        let output = current_runner().output("lsblk", &["-b", "-J", "-o", columns])?;
 
-        for dev in parsed.blockdevices {
-            let is_disk = dev.type_field.as_deref() == Some("disk");
-            if !is_disk {
-                continue;
-            }
-            let size_bytes = dev
-                .size
+        if output.status.code != Some(0) {
+            return Err(CoreError::Command {
+                cmd: "lsblk".to_string(),
+                code: output.status.code,
+                stderr: output.status.stderr,
+            });
+        }
+        Ok(output.stdout)
+    }
+
+    pub fn list_disks() -> Result<Vec<DiskInfo>> {
+        parse_disks(&lsblk(DISK_COLUMNS)?)
+    }
+
+    pub fn list_disks_diagnostic() -> Result<DiskListing> {
+        diagnose_disks(&lsblk(DISK_COLUMNS)?)
+    }
+
+    /// lsblk always lists the loop and ram devices the kernel provides, so
+    /// a successful run with no block devices at all means it could not
+    /// see the system's devices.
+    fn diagnose_disks(json: &[u8]) -> Result<DiskListing> {
+        let parsed = parse_lsblk(json)?;
+        let disks = parse_disks(json)?;
+        let diagnostic = if !disks.is_empty() {
+            None
+        } else if parsed.blockdevices.is_empty() && parsed.warnings.is_empty() {
+            Some(
+                "lsblk could not enumerate any block devices; /sys/block or /dev may be \
+                 hidden (container or sandbox?)"
+                    .to_string(),
+            )
+        } else {
+            Some(format!(
+                "lsblk found {} block devices but no disks",
+                parsed.blockdevices.len() + parsed.warnings.len()
+            ))
+        };
+        Ok(DiskListing {
+            disks,
+            diagnostic,
+            warnings: parsed.warnings,
+        })
+    }
+
+    fn parse_disks(json: &[u8]) -> Result<Vec<DiskInfo>> {
+        let parsed = parse_lsblk(json)?;
+        Ok(parsed
+            .blockdevices
+            .iter()
+            .filter(|dev| dev.type_field.as_deref() == Some("disk"))
+            .map(disk_from_lsblk)
+            .collect())
+    }
+
+    fn disk_from_lsblk(dev: &LsblkDevice) -> DiskInfo {
+        let mut mounts = Vec::new();
+        collect_mounts(dev, &mut mounts);
+        let mut partitions = Vec::new();
+        collect_partition_names(dev, &mut partitions);
+        let is_system = mounts.iter().any(|m| m == "/" || m == "/boot" || m == "/boot/efi");
+        let removable = dev.rm.unwrap_or(false);
+        let transport = dev.tran.clone().filter(|t| !t.is_empty());
+
+        DiskInfo {
+            id: format!("/dev/{}", dev.name),
+            model: dev.model.clone().unwrap_or_else(|| "Unknown".to_string()),
+            serial: dev
+                .serial
                 .as_deref()
-                .unwrap_or("0")
-                .parse::<u64>()
-                .unwrap_or(0);
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(String::from),
+            size_bytes: dev.size,
+            removable,
+            external: is_external(removable, transport.as_deref(), sysfs_removable(&dev.name)),
+            transport,
+            mountpoints: mounts,
+            is_system,
+            partition_count: partitions.len(),
+            partitions,
+        }
+    }
 
-            let mut mounts = Vec::new();
-            collect_mounts(&dev, &mut mounts);
-            let is_system = mounts.iter().any(|m| m == "/" || m == "/boot" || m == "/boot/efi");
+    /// No single signal is reliable: USB-SATA bridges clear `rm`, and some
+    /// card readers report no transport at all.
+    fn is_external(rm: bool, transport: Option<&str>, sysfs_removable: Option<bool>) -> bool {
+        rm || transport == Some("usb") || sysfs_removable == Some(true)
+    }
 
-            disks.push(DiskInfo {
-                id: format!("/dev/{}", dev.name),
-                model: dev.model.unwrap_or_else(|| "Unknown".to_string()),
-                size_bytes,
-                removable: dev.rm.unwrap_or(false),
-                mountpoints: mounts,
-                is_system,
-            });
+    #[cfg(not(test))]
+    fn sysfs_removable(name: &str) -> Option<bool> {
+        let flag = fs::read_to_string(format!("/sys/block/{name}/removable")).ok()?;
+        Some(flag.trim() == "1")
+    }
+
+    #[cfg(test)]
+    fn sysfs_removable(_name: &str) -> Option<bool> {
+        None
+    }
+
+    fn collect_partition_names(dev: &LsblkDevice, names: &mut Vec<String>) {
+        for child in dev.children.iter().flatten() {
+            if child.type_field.as_deref() == Some("part") {
+                names.push(format!("/dev/{}", child.name));
+            }
+            collect_partition_names(child, names);
         }
+    }
 
-        Ok(disks)
+    const TREE_COLUMNS: &str =
+        "NAME,MODEL,SIZE,RM,TYPE,TRAN,LABEL,FSTYPE,FSVER,FSSIZE,MOUNTPOINTS,PKNAME";
+
+    pub fn list_disks_tree() -> Result<Vec<DiskWithPartitions>> {
+        parse_disks_tree(&lsblk(TREE_COLUMNS)?)
+    }
+
+    fn parse_disks_tree(json: &[u8]) -> Result<Vec<DiskWithPartitions>> {
+        let parsed = parse_lsblk(json)?;
+        Ok(parsed
+            .blockdevices
+            .iter()
+            .filter(|dev| dev.type_field.as_deref() == Some("disk"))
+            .map(|dev| {
+                let mut partitions = Vec::new();
+                collect_parts(dev, &dev.name, &mut partitions);
+                DiskWithPartitions {
+                    disk: disk_from_lsblk(dev),
+                    partitions,
+                    holders: collect_holders(dev),
+                }
+            })
+            .collect())
+    }
+
+    pub fn list_install_targets() -> Result<Vec<DiskInfo>> {
+        parse_install_targets(&lsblk(DISK_COLUMNS)?)
+    }
+
+    fn parse_install_targets(json: &[u8]) -> Result<Vec<DiskInfo>> {
+        let parsed = parse_lsblk(json)?;
+        Ok(parsed
+            .blockdevices
+            .iter()
+            .filter(|dev| dev.type_field.as_deref() == Some("disk"))
+            .filter(|dev| !has_unsafe_member(dev))
+            .map(disk_from_lsblk)
+            .filter(|disk| disk.external && !disk.is_system && disk.mountpoints.is_empty())
+            .collect())
+    }
+
+    /// True when the device or anything below it is swap or belongs to an
+    /// LVM/RAID stack, none of which may be wiped from under the host.
+    fn has_unsafe_member(dev: &LsblkDevice) -> bool {
+        let fstype_unsafe = matches!(
+            dev.fstype.as_deref(),
+            Some("swap" | "LVM2_member" | "linux_raid_member")
+        );
+        let type_unsafe = dev
+            .type_field
+            .as_deref()
+            .map(|t| t == "lvm" || t.starts_with("raid"))
+            .unwrap_or(false);
+        fstype_unsafe
+            || type_unsafe
+            || dev
+                .children
+                .as_ref()
+                .map(|c| c.iter().any(has_unsafe_member))
+                .unwrap_or(false)
     }
 
     fn collect_mounts(dev: &LsblkDevice, mounts: &mut Vec<String>) {
@@ -186,42 +1264,317 @@ mod platform {
         }
     }
 
+    const PARTITION_COLUMNS: &str = "NAME,TYPE,LABEL,FSTYPE,FSVER,FSSIZE,MOUNTPOINTS,PKNAME";
+
     pub fn list_partitions(device: String) -> Result<Vec<PartitionInfo>> {
-        let output = Command::new("lsblk")
-            .args(["-b", "-J", "-o", "NAME,TYPE,LABEL,FSTYPE,MOUNTPOINTS,PKNAME"])
-            .output()
-            .map_err(|e| CoreError::Io(e.to_string()))?;
-        if !output.status.success() {
-            return Err(CoreError::Io("lsblk failed".to_string()));
-        }
-        let parsed: LsblkOutput = serde_json::from_slice(&output.stdout)
-            .map_err(|e| CoreError::Parse(e.to_string()))?;
-        let dev_name = device.trim_start_matches("/dev/").to_string();
-        let mut parts = Vec::new();
-        for dev in parsed.blockdevices {
-            collect_parts(&dev, &dev_name, &mut parts);
+        parse_partitions(&lsblk(PARTITION_COLUMNS)?, &device)
+    }
+
+    pub fn detect_data_label(esp_mount: &str) -> Result<Option<String>> {
+        parse_data_label(&lsblk(PARTITION_COLUMNS)?, esp_mount)
+    }
+
+    /// Finds the disk with a partition mounted at `esp_mount` and returns the
+    /// first non-empty label among its other partitions.
+    fn parse_data_label(json: &[u8], esp_mount: &str) -> Result<Option<String>> {
+        let parsed = parse_lsblk(json)?;
+        let esp_mount = match esp_mount.trim_end_matches('/') {
+            "" => "/",
+            m => m,
+        };
+        for disk in &parsed.blockdevices {
+            let mut parts = Vec::new();
+            collect_parts(disk, &disk.name, &mut parts);
+            let Some(esp) = parts
+                .iter()
+                .find(|p| p.mountpoints.iter().any(|m| m == esp_mount))
+            else {
+                continue;
+            };
+            return Ok(parts
+                .iter()
+                .find(|p| p.id != esp.id && !p.label.is_empty())
+                .map(|p| p.label.clone()));
         }
-        Ok(parts)
+        Ok(None)
     }
 
-    fn collect_parts(dev: &LsblkDevice, parent: &str, parts: &mut Vec<PartitionInfo>) {
-        if dev.type_field.as_deref() == Some("part") {
-            if dev.pkname.as_deref() == Some(parent) {
-                let mut mounts = Vec::new();
-                if let Some(mps) = &dev.mountpoints {
-                    for mp in mps.iter().flatten() {
-                        if !mp.is_empty() {
-                            mounts.push(mp.clone());
-                        }
-                    }
+    pub fn iso_destination(mount_path: &str) -> Result<PathBuf> {
+        check_data_mount(&lsblk(PARTITION_COLUMNS)?, mount_path)?;
+        Ok(Path::new(mount_path).join("boot").join("isos"))
+    }
+
+    const ESP_CHECK_COLUMNS: &str = "NAME,TYPE,FSTYPE,FSAVAIL,MOUNTPOINTS";
+
+    /// Free space below which the ESP is refused: a grub.cfg plus the 1 KiB
+    /// grubenv block, with room to spare.
+    const ESP_MIN_FREE_BYTES: u64 = 64 << 10;
+
+    pub fn check_esp_mount(esp_mount: &str) -> Result<()> {
+        check_esp(&lsblk(ESP_CHECK_COLUMNS)?, esp_mount)
+    }
+
+    /// Free bytes on the filesystem mounted at `mount_path`, when lsblk
+    /// reports them.
+    pub fn free_bytes(mount_path: &str) -> Result<Option<u64>> {
+        let parsed = parse_lsblk(&lsblk(ESP_CHECK_COLUMNS)?)?;
+        let mount_path = match mount_path.trim_end_matches('/') {
+            "" => "/",
+            m => m,
+        };
+        Ok(parsed
+            .blockdevices
+            .iter()
+            .find_map(|dev| find_mounted(dev, mount_path))
+            .and_then(|dev| dev.fsavail))
+    }
+
+    fn find_mounted<'a>(dev: &'a LsblkDevice, mount_path: &str) -> Option<&'a LsblkDevice> {
+        if own_mounts(dev).iter().any(|m| m == mount_path) {
+            return Some(dev);
+        }
+        dev.children
+            .iter()
+            .flatten()
+            .find_map(|child| find_mounted(child, mount_path))
+    }
+
+    /// Filesystem types a stick or external drive full of ISOs is likely to
+    /// carry.
+    const SCAN_CANDIDATE_FSTYPES: &[&str] = &["vfat", "exfat", "ext2", "ext3", "ext4"];
+
+    /// Mountpoints, and the trees below them, that belong to the running
+    /// system rather than to a drive the user plugged in.
+    const SYSTEM_MOUNT_ROOTS: &[&str] = &["/boot", "/usr", "/var", "/opt", "/srv", "/snap", "/nix"];
+
+    pub fn mounted_scan_candidates() -> Result<Vec<String>> {
+        let mounts =
+            fs::read_to_string("/proc/mounts").map_err(|e| CoreError::Io(e.to_string()))?;
+        Ok(scan_candidates(&mounts))
+    }
+
+    /// Mountpoints in `/proc/mounts` text worth offering as scan dirs:
+    /// block-device filesystems of a [`SCAN_CANDIDATE_FSTYPES`] type that are
+    /// neither system mounts nor RaidhOS's own working mounts.
+    fn scan_candidates(mounts: &str) -> Vec<String> {
+        let mut candidates: Vec<String> = Vec::new();
+        for line in mounts.lines() {
+            let mut fields = line.split_whitespace();
+            let (Some(source), Some(mountpoint), Some(fstype)) =
+                (fields.next(), fields.next(), fields.next())
+            else {
+                continue;
+            };
+            let mountpoint = unescape_mount_field(mountpoint);
+            let own = [DATA_MOUNT, ESP_MOUNT, SCAN_MOUNT_ROOT];
+            let under =
+                |root: &str| mountpoint == root || mountpoint.starts_with(&format!("{root}/"));
+            if !source.starts_with("/dev/")
+                || !SCAN_CANDIDATE_FSTYPES.contains(&fstype)
+                || mountpoint == "/"
+                || SYSTEM_MOUNT_ROOTS
+                    .iter()
+                    .chain(own.iter())
+                    .any(|root| under(root))
+                || candidates.contains(&mountpoint)
+            {
+                continue;
+            }
+            candidates.push(mountpoint);
+        }
+        candidates
+    }
+
+    /// Where live-boot initramfs scripts mount the medium they booted
+    /// from: the partition holding a loopback-booted ISO (casper's
+    /// `iso-scan`, live-boot's `findiso`, dracut's `iso-scan`) or a
+    /// whole-disk image (Debian live, Ubuntu, Fedora, Arch).
+    const BOOT_MEDIUM_MOUNTS: &[&str] = &[
+        "/isodevice",
+        "/run/live/findiso",
+        "/run/initramfs/isoscan",
+        "/run/live/medium",
+        "/cdrom",
+        "/run/initramfs/live",
+        "/run/archiso/bootmnt",
+    ];
+
+    pub fn boot_device() -> Result<Option<String>> {
+        Ok(boot_device_in(&proc_mounts(), &list_disks()?))
+    }
+
+    /// The disk in `disks` holding a [`BOOT_MEDIUM_MOUNTS`] mount listed in
+    /// `/proc/mounts` text, if the system booted live from one of them.
+    fn boot_device_in(mounts: &str, disks: &[DiskInfo]) -> Option<String> {
+        mounts.lines().find_map(|line| {
+            let mut fields = line.split_whitespace();
+            let (source, mountpoint) = (fields.next()?, fields.next()?);
+            if !BOOT_MEDIUM_MOUNTS.contains(&unescape_mount_field(mountpoint).as_str()) {
+                return None;
+            }
+            let source = fs::canonicalize(source)
+                .map(|p| p.display().to_string())
+                .unwrap_or_else(|_| source.to_string());
+            disks
+                .iter()
+                .find(|d| d.id == source || d.partitions.contains(&source))
+                .map(|d| d.id.clone())
+        })
+    }
+
+    #[cfg(not(test))]
+    fn proc_mounts() -> String {
+        fs::read_to_string("/proc/mounts").unwrap_or_default()
+    }
+
+    #[cfg(test)]
+    fn proc_mounts() -> String {
+        PROC_MOUNTS.with(|mounts| mounts.borrow().clone())
+    }
+
+    /// Undoes the octal escapes (`\040` for a space) the kernel writes for
+    /// whitespace and backslashes in `/proc/mounts` fields.
+    fn unescape_mount_field(field: &str) -> String {
+        let bytes = field.as_bytes();
+        let mut out = Vec::with_capacity(bytes.len());
+        let mut i = 0;
+        while i < bytes.len() {
+            let octal = bytes
+                .get(i + 1..i + 4)
+                .filter(|d| bytes[i] == b'\\' && d.iter().all(|b| (b'0'..=b'7').contains(b)));
+            match octal {
+                Some(d) => {
+                    out.push((d[0] - b'0') * 64 + (d[1] - b'0') * 8 + (d[2] - b'0'));
+                    i += 4;
                 }
-                parts.push(PartitionInfo {
-                    id: format!("/dev/{}", dev.name),
-                    label: dev.label.clone().unwrap_or_default(),
-                    fstype: dev.fstype.clone().unwrap_or_default(),
-                    mountpoints: mounts,
-                });
+                None => {
+                    out.push(bytes[i]);
+                    i += 1;
+                }
+            }
+        }
+        String::from_utf8_lossy(&out).into_owned()
+    }
+
+    /// Errors unless `esp_mount` is where a vfat filesystem is mounted with
+    /// at least [`ESP_MIN_FREE_BYTES`] free. A missing FSAVAIL is let
+    /// through: the write itself then reports a full filesystem.
+    fn check_esp(json: &[u8], esp_mount: &str) -> Result<()> {
+        let parsed = parse_lsblk(json)?;
+        let esp_mount = match esp_mount.trim_end_matches('/') {
+            "" => "/",
+            m => m,
+        };
+        let Some(dev) = parsed
+            .blockdevices
+            .iter()
+            .find_map(|dev| find_mounted(dev, esp_mount))
+        else {
+            return Err(CoreError::Validation(format!(
+                "{esp_mount} is not the mountpoint of a partition"
+            )));
+        };
+        let fstype = dev.fstype.as_deref().unwrap_or("no filesystem");
+        if fstype != "vfat" {
+            return Err(CoreError::Validation(format!(
+                "{esp_mount} (/dev/{}) holds {fstype}, not FAT; grub.cfg belongs on the ESP",
+                dev.name
+            )));
+        }
+        match dev.fsavail {
+            Some(avail) if avail < ESP_MIN_FREE_BYTES => Err(CoreError::Validation(format!(
+                "{esp_mount} has {avail} bytes free; writing grub.cfg needs {ESP_MIN_FREE_BYTES}"
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Errors unless `mount_path` is the mountpoint of a partition that is not
+    /// the ESP and sits on a RaidhOS stick: next to a RaidhOS ESP, or
+    /// labelled like a data-only stick.
+    fn check_data_mount(json: &[u8], mount_path: &str) -> Result<()> {
+        let parsed = parse_lsblk(json)?;
+        let mount_path = match mount_path.trim_end_matches('/') {
+            "" => "/",
+            m => m,
+        };
+        for disk in &parsed.blockdevices {
+            let mut parts = Vec::new();
+            collect_parts(disk, &disk.name, &mut parts);
+            let Some(part) = parts
+                .iter()
+                .find(|p| p.mountpoints.iter().any(|m| m == mount_path))
+            else {
+                continue;
+            };
+            if part.label == ESP_LABEL {
+                return Err(CoreError::Validation(format!(
+                    "{mount_path} is the RaidhOS ESP, not the data partition"
+                )));
             }
+            let has_esp = parts.iter().any(|p| p.label == ESP_LABEL);
+            if has_esp || part.label == DATA_LABEL {
+                return Ok(());
+            }
+            return Err(CoreError::Validation(format!(
+                "{mount_path} ({}) is not on a RaidhOS stick",
+                part.id
+            )));
+        }
+        Err(CoreError::Validation(format!(
+            "{mount_path} is not the mountpoint of a partition"
+        )))
+    }
+
+    fn parse_partitions(json: &[u8], device: &str) -> Result<Vec<PartitionInfo>> {
+        let parsed = parse_lsblk(json)?;
+        let dev_name = device.trim_start_matches("/dev/").to_string();
+        let mut parts = Vec::new();
+        for dev in parsed.blockdevices {
+            collect_parts(&dev, &dev_name, &mut parts);
+        }
+        Ok(parts)
+    }
+
+    /// The device's own mountpoints, not those of its children.
+    fn own_mounts(dev: &LsblkDevice) -> Vec<String> {
+        dev.mountpoints
+            .iter()
+            .flatten()
+            .flatten()
+            .filter(|mp| !mp.is_empty())
+            .cloned()
+            .collect()
+    }
+
+    /// Everything stacked on `dev` other than its partitions, nested the
+    /// way lsblk reports it (crypt on part, lvm on crypt, ...).
+    fn collect_holders(dev: &LsblkDevice) -> Vec<Holder> {
+        dev.children
+            .iter()
+            .flatten()
+            .filter(|child| child.type_field.as_deref() != Some("part"))
+            .map(|child| Holder {
+                name: child.name.clone(),
+                kind: child.type_field.clone().unwrap_or_default(),
+                fstype: child.fstype.clone().unwrap_or_default(),
+                mountpoints: own_mounts(child),
+                holders: collect_holders(child),
+            })
+            .collect()
+    }
+
+    fn collect_parts(dev: &LsblkDevice, parent: &str, parts: &mut Vec<PartitionInfo>) {
+        if dev.type_field.as_deref() == Some("part") && dev.pkname.as_deref() == Some(parent) {
+            parts.push(PartitionInfo {
+                id: format!("/dev/{}", dev.name),
+                label: dev.label.clone().unwrap_or_default(),
+                fstype: dev.fstype.clone().unwrap_or_default(),
+                fsver: dev.fsver.clone(),
+                fssize: dev.fssize,
+                mountpoints: own_mounts(dev),
+                holders: collect_holders(dev),
+            });
         }
         if let Some(children) = &dev.children {
             for child in children {
@@ -230,401 +1583,5731 @@ mod platform {
         }
     }
 
-    pub fn install(req: InstallRequest, sink: &dyn ProgressSink) -> Result<()> {
+    pub fn install(req: InstallRequest, sink: &dyn ProgressSink) -> Result<InstallOutcome> {
+        let started = Instant::now();
+        let _lock = if req.dry_run {
+            None
+        } else {
+            Some(super::DeviceLock::acquire(&req.device)?)
+        };
+        // Started before the target is resolved, so an injected runner
+        // answers the lsblk too.
+        let _runner = req.options.runner.as_ref().map(ActiveRunner::start);
+        let _cancel = ActiveCancel::start(&req.options.cancel);
         let disks = list_disks()?;
-        install_with_disks(req, sink, &disks)
+        install_with_disks(req, sink, &disks, started)
     }
 
     fn install_with_disks(
         req: InstallRequest,
         sink: &dyn ProgressSink,
         disks: &[DiskInfo],
-    ) -> Result<()> {
+        started: Instant,
+    ) -> Result<InstallOutcome> {
+        let mounter = req.options.mounter.clone();
+        let mounter = mounter.as_deref().unwrap_or(&SystemMounter);
+        report_cancelled(
+            install_with_mounter(req, sink, disks, started, mounter),
+            sink,
+        )
+    }
+
+    fn install_with_mounter(
+        req: InstallRequest,
+        sink: &dyn ProgressSink,
+        disks: &[DiskInfo],
+        started: Instant,
+        mounter: &dyn Mounter,
+    ) -> Result<InstallOutcome> {
+        let _command_log = req.options.command_log.as_deref().map(CommandLog::start);
         validate_install(&req, sink, disks)?;
+        let _runner = req.options.runner.as_ref().map(ActiveRunner::start);
+        let _cancel = ActiveCancel::start(&req.options.cancel);
+        let free_space = free_space_for(&req, disks)?;
 
         if req.dry_run {
+            let mut warnings = Vec::new();
+            for command in plan_commands(&req, disks, free_space.as_ref(), &mut warnings)? {
+                sink.emit(ProgressEvent {
+                    phase: "plan".to_string(),
+                    message: command,
+                    percent: None,
+                    ..Default::default()
+                });
+            }
+            emit_warnings(sink, "format", warnings);
+            let duration_ms = elapsed_ms(started);
             sink.emit(ProgressEvent {
                 phase: "complete".to_string(),
-                message: "Dry-run complete. No changes made.".to_string(),
+                message: format!("Dry-run complete. No changes made. ({duration_ms} ms)"),
                 percent: Some(100),
+                ..Default::default()
+            });
+            return Ok(InstallOutcome {
+                device: req.device,
+                dry_run: true,
+                duration_ms,
+                changes: planned_changes(&req.options),
+                partitions: vec![],
+                grub_entries: None,
             });
-            return Ok(());
         }
         if !req.allow_write {
-            return Err(CoreError::Validation(
-                "write blocked: set allow_write to proceed".to_string(),
-            ));
+            return Err(write_blocked());
         }
+        let mut req = req;
+        if req.options.unique_labels && req.options.data_label.is_none() {
+            let in_use = labels_in_use(&req.device, &req.options.mode)?;
+            req.options.data_label = Some(crate::suggest_data_label(&in_use));
+        }
+
+        let layout = req.options.layout;
+        let payload = match layout {
+            Layout::BootAndData => Some(resolve_payload_dir(
+                req.payload_dir.as_deref(),
+                std::env::var("RAIDHOS_PAYLOAD_DIR").ok(),
+            )?),
+            Layout::DataOnly => None,
+        };
+        let cancel = &req.options.cancel;
+
+        let (esp_part, data_part) = if let InstallMode::Partition { esp } = &req.options.mode {
+            checkpoint(cancel, sink)?;
+            sink.emit(ProgressEvent {
+                phase: "partition".to_string(),
+                message: format!("Keeping the partition table; installing to {}", req.device),
+                percent: Some(30),
+                ..Default::default()
+            });
+            (esp.clone(), req.device.clone())
+        } else {
+            create_partitions(&req, sink, disks, free_space.as_ref())?
+        };
+
+        let disk = match partition_parent(&req.device, disks) {
+            Some(parent) => parent.id.as_str(),
+            None => req.device.as_str(),
+        };
+        let written = write_partitions(
+            &req,
+            sink,
+            payload.as_deref(),
+            esp_part.as_deref(),
+            &data_part,
+            disk,
+            mounter,
+        );
+        let auto_entries = match written {
+            Ok(entries) => entries,
+            Err(e) => {
+                rollback(sink, &req.options, &e, mounter);
+                return Err(e);
+            }
+        };
 
+        let duration_ms = elapsed_ms(started);
         sink.emit(ProgressEvent {
-            phase: "partition".to_string(),
-            message: "Creating GPT partitions".to_string(),
-            percent: Some(30),
+            phase: "complete".to_string(),
+            message: format!("Install complete in {duration_ms} ms."),
+            percent: Some(100),
+            ..Default::default()
         });
+        let partitions = installed_partitions(disk).unwrap_or_default();
+        Ok(InstallOutcome {
+            changes: planned_changes(&req.options),
+            partitions,
+            grub_entries: auto_entries.or_else(|| payload.as_deref().and_then(grub_entry_count)),
+            device: req.device,
+            dry_run: false,
+            duration_ms,
+        })
+    }
 
-        run("parted", &[&req.device, "-s", "mklabel", "gpt"])?;
-        run(
-            "parted",
-            &[
-                &req.device,
-                "-s",
-                "mkpart",
-                "primary",
-                "fat32",
-                "1MiB",
-                "33MiB",
-            ],
-        )?;
-        run("parted", &[&req.device, "-s", "set", "1", "esp", "on"])?;
-        run(
-            "parted",
-            &[
-                &req.device,
-                "-s",
-                "mkpart",
-                "primary",
-                "33MiB",
-                "100%",
-            ],
-        )?;
-        run("parted", &[&req.device, "-s", "print"])?;
-
+    /// Formats the new partitions and copies the payload onto them,
+    /// returning the entry count of the menu `auto_menu` generated.
+    fn write_partitions(
+        req: &InstallRequest,
+        sink: &dyn ProgressSink,
+        payload: Option<&Path>,
+        esp_part: Option<&str>,
+        data_part: &str,
+        disk: &str,
+        mounter: &dyn Mounter,
+    ) -> Result<Option<usize>> {
+        let cancel = &req.options.cancel;
+        checkpoint(cancel, sink)?;
         sink.emit(ProgressEvent {
             phase: "format".to_string(),
             message: "Formatting partitions".to_string(),
             percent: Some(60),
+            ..Default::default()
         });
 
-        let part1 = part_path(&req.device, 1);
-        let part2 = part_path(&req.device, 2);
-        run("mkfs.vfat", &["-F", "32", "-n", "RAIDHOS_EFI", &part1])?;
+        match (payload, esp_part) {
+            (Some(payload), Some(esp)) => {
+                checkpoint(cancel, sink)?;
+                emit_warnings(sink, "format", format_esp(esp, &req.options)?);
+                checkpoint(cancel, sink)?;
+                emit_warnings(sink, "format", format_data(data_part, &req.options)?);
 
-        if has_cmd("mkfs.exfat") {
-            if run("mkfs.exfat", &["-n", "DATA", &part2]).is_err() {
-                run("mkfs.exfat", &[&part2])?;
-                let _ = run("exfatlabel", &[&part2, "DATA"]);
+                checkpoint(cancel, sink)?;
+                let entries = payload_copy(
+                    sink,
+                    &req.options,
+                    payload,
+                    esp,
+                    data_part,
+                    req.arch,
+                    mounter,
+                )?;
+                if req.options.menu_backend == MenuBackend::Syslinux {
+                    checkpoint(cancel, sink)?;
+                    emit_warnings(sink, "finalize", install_syslinux(disk, esp)?);
+                }
+                Ok(entries)
             }
-        } else if has_cmd("mkexfatfs") {
-            if run("mkexfatfs", &["-n", "DATA", &part2]).is_err() {
-                run("mkexfatfs", &[&part2])?;
-                let _ = run("exfatlabel", &[&part2, "DATA"]);
+            _ => {
+                checkpoint(cancel, sink)?;
+                emit_warnings(sink, "format", format_data(data_part, &req.options)?);
+                Ok(None)
             }
-        } else {
-            return Err(CoreError::Io(
-                "exFAT formatter not found (mkfs.exfat or mkexfatfs)".to_string(),
-            ));
         }
-
-        payload_copy(sink, &part1, &part2)?;
-
-        sink.emit(ProgressEvent {
-            phase: "complete".to_string(),
-            message: "Install complete.".to_string(),
-            percent: Some(100),
-        });
-        Ok(())
     }
 
-    fn validate_install(req: &InstallRequest, sink: &dyn ProgressSink, disks: &[DiskInfo]) -> Result<()> {
-        if !req.device.starts_with("/dev/") {
-            return Err(CoreError::Validation(
-                "device must be an absolute /dev path".to_string(),
-            ));
-        }
-
+    /// Cleans up after an install that failed once the partitions were
+    /// written: unmounts whichever payload mounts are still mounted, unless
+    /// `keep_mounts_on_error` asks to keep them. The disk itself is left as
+    /// it is; a rerun of the install starts over from partitioning.
+    fn rollback(
+        sink: &dyn ProgressSink,
+        options: &InstallOptions,
+        err: &CoreError,
+        mounter: &dyn Mounter,
+    ) {
+        let mounted = mounted_payload_mounts(&proc_mounts());
+        let keep = options.keep_mounts_on_error && !matches!(err, CoreError::Cancelled);
+        let message = match (mounted.is_empty(), keep) {
+            (true, _) => format!("Install stopped ({err}); nothing left mounted."),
+            (false, true) => format!("Install stopped ({err}); mounts kept for inspection."),
+            (false, false) => format!(
+                "Install stopped ({err}); unmounting {}.",
+                mounted.join(", ")
+            ),
+        };
         sink.emit(ProgressEvent {
-            phase: "validate".to_string(),
-            message: format!("Validating target {}", req.device),
-            percent: Some(5),
+            phase: "rollback".to_string(),
+            message,
+            percent: None,
+            ..Default::default()
         });
-
-        if !req.wipe {
-            return Err(CoreError::Validation(
-                "wipe flag must be set for destructive install".to_string(),
-            ));
+        if !keep {
+            for target in &mounted {
+                let _ = mounter.unmount(Path::new(target));
+            }
         }
+    }
 
-        let target = disks
+    /// The payload mount points (`/mnt/raidhos-esp`, `/mnt/raidhos-data`)
+    /// that appear in `mounts`, in `/proc/mounts` format.
+    fn mounted_payload_mounts(mounts: &str) -> Vec<String> {
+        let mounted: Vec<String> = mounts
+            .lines()
+            .filter_map(|line| line.split_whitespace().nth(1))
+            .map(unescape_mount_field)
+            .collect();
+        [ESP_MOUNT, DATA_MOUNT]
+            .into_iter()
+            .filter(|target| mounted.iter().any(|m| m == target))
+            .map(str::to_string)
+            .collect()
+    }
+
+    /// Where a free-space install puts its partitions; `None` for the
+    /// other modes.
+    fn free_space_for(req: &InstallRequest, disks: &[DiskInfo]) -> Result<Option<FreeSpacePlan>> {
+        let InstallMode::FreeSpace {
+            start_mib,
+            size_mib,
+        } = req.options.mode
+        else {
+            return Ok(None);
+        };
+        let disk_size = disks
             .iter()
             .find(|d| d.id == req.device)
-            .ok_or_else(|| CoreError::Validation("device not found".to_string()))?;
-
-        if target.is_system {
-            return Err(CoreError::Validation(
-                "refusing to operate on system disk".to_string(),
-            ));
-        }
-
-        if !target.mountpoints.is_empty() {
-            return Err(CoreError::Validation(
-                "device has mounted partitions; unmount first".to_string(),
-            ));
-        }
-
-        sink.emit(ProgressEvent {
-            phase: "prepare".to_string(),
-            message: "Preparing partition layout".to_string(),
-            percent: Some(20),
-        });
-
-        sink.emit(ProgressEvent {
-            phase: "payload".to_string(),
-            message: format!("Staging payload {}", req.payload_version),
-            percent: Some(45),
-        });
+            .and_then(|d| d.size_bytes);
+        let existing = existing_extents(&req.device)?;
+        Ok(Some(plan_free_space(
+            start_mib,
+            size_mib,
+            &req.options,
+            disk_size,
+            &existing,
+        )?))
+    }
 
-        sink.emit(ProgressEvent {
-            phase: "write".to_string(),
-            message: "Writing boot structures".to_string(),
-            percent: Some(70),
-        });
+    /// The command lines a real install of `req` would run, in order, with
+    /// the partition paths it would create. Polling for the new partition
+    /// nodes shows as a single `partprobe`; files the install writes itself
+    /// (the payload copy, boot menus, the fallback bootloader) are not
+    /// commands and are not listed.
+    fn plan_commands(
+        req: &InstallRequest,
+        disks: &[DiskInfo],
+        free_space: Option<&FreeSpacePlan>,
+        warnings: &mut Vec<String>,
+    ) -> Result<Vec<String>> {
+        let device = req.device.as_str();
+        let options = &req.options;
+        let mut plan = Vec::new();
+        let mut push = |cmd: &str, args: &[&str]| plan.push([&[cmd], args].concat().join(" "));
+        let parted = |push: &mut dyn FnMut(&str, &[&str]), steps: Vec<Vec<String>>| {
+            for step in steps {
+                let args: Vec<&str> = step.iter().map(String::as_str).collect();
+                push("parted", &[&[device, "-s"], &args[..]].concat());
+            }
+        };
 
-        sink.emit(ProgressEvent {
-            phase: "finalize".to_string(),
-            message: "Final checks".to_string(),
-            percent: Some(90),
-        });
+        let (esp_part, data_part) = match (&options.mode, free_space) {
+            (InstallMode::Partition { esp }, _) => (esp.clone(), req.device.clone()),
+            (_, free_space) => {
+                let (esp_number, data_number) = match free_space {
+                    None => {
+                        if options.zero_ends {
+                            let size = disks
+                                .iter()
+                                .find(|d| d.id == req.device)
+                                .and_then(|d| d.size_bytes)
+                                .unwrap_or_default();
+                            for args in zero_ends_args(device, size) {
+                                push("dd", &args.iter().map(String::as_str).collect::<Vec<_>>());
+                            }
+                        }
+                        parted(&mut push, wipe_parted_steps(options)?);
+                        layout_numbers(options.layout)
+                    }
+                    Some(plan) => {
+                        parted(&mut push, free_space_parted_steps(plan));
+                        (plan.esp.as_ref().map(|p| p.number), plan.data.number)
+                    }
+                };
+                let types = &options.partition_types;
+                if let Some(args) = partition_type_args(device, esp_number, data_number, types)? {
+                    push(
+                        "sgdisk",
+                        &args.iter().map(String::as_str).collect::<Vec<_>>(),
+                    );
+                }
+                if free_space.is_none() && options.hybrid_mbr {
+                    push("sgdisk", &["-h", "1:EE", device]);
+                    push("sgdisk", &["-p", device]);
+                }
+                push("partprobe", &[device]);
+                (
+                    esp_number.map(|n| part_path(device, n)),
+                    part_path(device, data_number),
+                )
+            }
+        };
 
-        Ok(())
-    }
+        let payload = match options.layout {
+            Layout::BootAndData => Some(
+                resolve_payload_dir(
+                    req.payload_dir.as_deref(),
+                    std::env::var("RAIDHOS_PAYLOAD_DIR").ok(),
+                )
+                .unwrap_or_else(|_| PathBuf::from("$RAIDHOS_PAYLOAD_DIR")),
+            ),
+            Layout::DataOnly => None,
+        };
+        if let (Some(_), Some(esp)) = (&payload, &esp_part) {
+            push("mkfs.vfat", &esp_mkfs_args(esp, options, warnings));
+        }
+        let label = match &options.data_label {
+            None if options.unique_labels => {
+                crate::suggest_data_label(&labels_in_use(device, &options.mode)?)
+            }
+            _ => data_label(options).to_string(),
+        };
+        if let Some((tool, args)) = data_mkfs_command(&data_part, &label, options, warnings) {
+            push(tool, &args.iter().map(String::as_str).collect::<Vec<_>>());
+        } else {
+            // A plan still lists the format step on a host without a
+            // formatter; the install itself stops there.
+            let tool = available_tools(EXFAT_FORMATTERS, has_cmd)
+                .into_iter()
+                .next()
+                .unwrap_or_else(|| {
+                    warnings.push(format!(
+                        "exFAT formatter not found ({}); the install will fail at this step",
+                        EXFAT_FORMATTERS.join(", ")
+                    ));
+                    EXFAT_FORMATTERS[0]
+                });
+            let mode = format_mode_args(tool, options.quick_format, warnings);
+            let extra: Vec<&str> = options.data_mkfs_args.iter().map(String::as_str).collect();
+            push(
+                tool,
+                &[&mode[..], &["-n", &label], &extra, &[&data_part]].concat(),
+            );
+        }
 
-    fn payload_copy(sink: &dyn ProgressSink, part1: &str, part2: &str) -> Result<()> {
-        let payload_dir = std::env::var("RAIDHOS_PAYLOAD_DIR").map_err(|_| {
-            CoreError::Validation("RAIDHOS_PAYLOAD_DIR is not set".to_string())
-        })?;
-        let payload = PathBuf::from(payload_dir);
-        if !payload.exists() {
-            return Err(CoreError::Validation(
-                "RAIDHOS_PAYLOAD_DIR does not exist".to_string(),
-            ));
+        let (Some(payload), Some(esp)) = (payload, esp_part) else {
+            return Ok(plan);
+        };
+        push("mount", &[&esp, ESP_MOUNT]);
+        push("mount", &[&data_part, DATA_MOUNT]);
+        if crate::bootloader::find_grub_efi(&payload.join("esp"), req.arch).is_none() {
+            let args = grub_install_args(req.arch, ESP_MOUNT);
+            push(
+                "grub-install",
+                &args.iter().map(String::as_str).collect::<Vec<_>>(),
+            );
         }
-        let esp_payload = payload.join("esp");
-        let data_payload = payload.join("data");
-        if !esp_payload.exists() || !data_payload.exists() {
-            return Err(CoreError::Validation(
-                "RAIDHOS_PAYLOAD_DIR must contain esp/ and data/ directories".to_string(),
-            ));
+        push("umount", &[ESP_MOUNT]);
+        push("umount", &[DATA_MOUNT]);
+        if options.menu_backend == MenuBackend::Syslinux {
+            let directory = format!("/{}", crate::syslinux::SYSLINUX_DIR);
+            push("syslinux", &["--install", "--directory", &directory, &esp]);
         }
+        Ok(plan)
+    }
 
-        let esp_mount = PathBuf::from("/mnt/raidhos-esp");
-        let data_mount = PathBuf::from("/mnt/raidhos-data");
-        fs::create_dir_all(&esp_mount).map_err(|e| CoreError::Io(e.to_string()))?;
-        fs::create_dir_all(&data_mount).map_err(|e| CoreError::Io(e.to_string()))?;
+    fn labels_in_use(device: &str, mode: &InstallMode) -> Result<Vec<String>> {
+        parse_labels_in_use(&lsblk(PARTITION_COLUMNS)?, device, mode)
+    }
 
-        run("mount", &[part1, esp_mount.to_str().unwrap_or("/mnt/raidhos-esp")])?;
-        run("mount", &[part2, data_mount.to_str().unwrap_or("/mnt/raidhos-data")])?;
+    /// Labels of the partitions that survive an install to `device`: all of
+    /// them except the one it formats, or except the whole disk it wipes.
+    fn parse_labels_in_use(json: &[u8], device: &str, mode: &InstallMode) -> Result<Vec<String>> {
+        let parsed = parse_lsblk(json)?;
+        let mut parts = Vec::new();
+        for disk in &parsed.blockdevices {
+            if *mode == InstallMode::Wipe && format!("/dev/{}", disk.name) == device {
+                continue;
+            }
+            collect_parts(disk, &disk.name, &mut parts);
+        }
+        Ok(parts
+            .into_iter()
+            .filter(|p| p.id != device && !p.label.is_empty())
+            .map(|p| p.label)
+            .collect())
+    }
 
-        sink.emit(ProgressEvent {
-            phase: "payload".to_string(),
-            message: "Copying payload files".to_string(),
-            percent: Some(85),
-        });
+    /// The label an install gives the data partition.
+    fn data_label(options: &InstallOptions) -> &str {
+        options.data_label.as_deref().unwrap_or(DATA_LABEL)
+    }
 
-        run(
-            "cp",
-            &[
-                "-a",
-                &format!("{}/.", esp_payload.to_string_lossy()),
-                esp_mount.to_str().unwrap(),
-            ],
-        )?;
-        run(
-            "cp",
-            &[
-                "-a",
-                &format!("{}/.", data_payload.to_string_lossy()),
-                data_mount.to_str().unwrap(),
-            ],
-        )?;
+    fn installed_partitions(disk: &str) -> Result<Vec<PartitionInfo>> {
+        parse_partitions(&lsblk(PARTITION_COLUMNS)?, disk)
+    }
 
-        let _ = run("umount", &[esp_mount.to_str().unwrap()]);
-        let _ = run("umount", &[data_mount.to_str().unwrap()]);
+    /// Menu entries in the payload's grub.cfg, which the copy put on the ESP
+    /// unchanged.
+    fn grub_entry_count(payload: &Path) -> Option<usize> {
+        crate::read_grub_cfg(&payload.join("esp"))
+            .ok()
+            .map(|config| config.entries.len())
+    }
 
+    /// Creates the install's partitions on the disk `req.device`, returning
+    /// the ESP (if any) and data partition paths.
+    fn create_partitions(
+        req: &InstallRequest,
+        sink: &dyn ProgressSink,
+        disks: &[DiskInfo],
+        free_space: Option<&FreeSpacePlan>,
+    ) -> Result<(Option<String>, String)> {
+        let layout = req.options.layout;
+        checkpoint(&req.options.cancel, sink)?;
         sink.emit(ProgressEvent {
-            phase: "payload".to_string(),
-            message: "Payload copy complete.".to_string(),
-            percent: Some(90),
+            phase: "partition".to_string(),
+            message: "Creating GPT partitions".to_string(),
+            percent: Some(30),
+            ..Default::default()
         });
 
-        Ok(())
-    }
-
-    pub fn scan_isos(dirs: Vec<String>) -> Result<Vec<super::IsoEntry>> {
-        let mut results = Vec::new();
-        for dir in dirs {
-            let root = PathBuf::from(dir);
-            if !root.exists() {
-                continue;
-            }
-            let entries = fs::read_dir(&root).map_err(|e| CoreError::Io(e.to_string()))?;
-            for entry in entries.flatten() {
-                let path = entry.path();
-                if path.is_file() {
-                    push_iso(&mut results, &path);
-                } else if path.is_dir() {
-                    if let Ok(subs) = fs::read_dir(&path) {
-                        for sub in subs.flatten() {
-                            let subpath = sub.path();
-                            if subpath.is_file() {
-                                push_iso(&mut results, &subpath);
-                            }
-                        }
-                    }
+        let (esp_number, data_number) = match free_space {
+            None => {
+                if req.options.zero_ends {
+                    let size = disks
+                        .iter()
+                        .find(|d| d.id == req.device)
+                        .and_then(|d| d.size_bytes)
+                        .unwrap_or_default();
+                    zero_device_ends(&req.device, size)?;
                 }
+                emit_warnings(
+                    sink,
+                    "partition",
+                    partition_disk(&req.device, &req.options)?,
+                );
+                layout_numbers(layout)
+            }
+            Some(plan) => {
+                emit_warnings(sink, "partition", partition_free_space(&req.device, plan)?);
+                (plan.esp.as_ref().map(|p| p.number), plan.data.number)
             }
+        };
+        let types = &req.options.partition_types;
+        set_partition_types(&req.device, esp_number, data_number, types)?;
+        if free_space.is_none() {
+            ensure_mbr(&req.device, req.options.hybrid_mbr)?;
         }
-        results.sort_by(|a, b| a.title.to_lowercase().cmp(&b.title.to_lowercase()));
-        Ok(results)
+        let esp_part = esp_number.map(|n| part_path(&req.device, n));
+        let data_part = part_path(&req.device, data_number);
+        let nodes: Vec<&str> = esp_part
+            .iter()
+            .map(String::as_str)
+            .chain([data_part.as_str()])
+            .collect();
+        wait_for_partitions(&req.device, &nodes)?;
+        Ok((esp_part, data_part))
     }
 
-    fn push_iso(results: &mut Vec<super::IsoEntry>, path: &PathBuf) {
-        if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
-            if ext.eq_ignore_ascii_case("iso") {
-                if let Ok(meta) = fs::metadata(path) {
-                    let title = path
-                        .file_stem()
-                        .and_then(|s| s.to_str())
-                        .unwrap_or("ISO")
-                        .to_string();
-                    results.push(super::IsoEntry {
-                        title,
-                        path: path.display().to_string(),
-                        size_bytes: meta.len(),
-                        params: "quiet splash".to_string(),
-                    });
-                }
-            }
+    const FULL_LAYOUT: [LayoutChange; 4] = [
+        LayoutChange::Partition,
+        LayoutChange::FormatEsp,
+        LayoutChange::FormatData,
+        LayoutChange::RefreshPayload,
+    ];
+
+    const DATA_ONLY_LAYOUT: [LayoutChange; 2] = [LayoutChange::Partition, LayoutChange::FormatData];
+
+    fn full_layout(layout: Layout) -> Vec<LayoutChange> {
+        match layout {
+            Layout::BootAndData => FULL_LAYOUT.to_vec(),
+            Layout::DataOnly => DATA_ONLY_LAYOUT.to_vec(),
         }
     }
 
-    #[cfg(not(test))]
-    fn run(cmd: &str, args: &[&str]) -> Result<()> {
-        let status = Command::new(cmd)
-            .args(args)
-            .status()
-            .map_err(|e| CoreError::Io(e.to_string()))?;
-        if !status.success() {
-            return Err(CoreError::Io(format!("command failed: {cmd}")));
+    /// [`full_layout`], less partitioning when installing to a partition.
+    fn planned_changes(options: &InstallOptions) -> Vec<LayoutChange> {
+        let mut changes = full_layout(options.layout);
+        if matches!(options.mode, InstallMode::Partition { .. }) {
+            changes.retain(|c| *c != LayoutChange::Partition);
         }
-        Ok(())
+        changes
     }
 
-    #[cfg(test)]
-    fn run(_cmd: &str, _args: &[&str]) -> Result<()> {
+    /// Overwrites the first and last MiB of `device` with zeros, in
+    /// 512-byte blocks so the end offset is exact for any sector count.
+    fn zero_device_ends(device: &str, size_bytes: u64) -> Result<()> {
+        for args in zero_ends_args(device, size_bytes) {
+            run("dd", &args.iter().map(String::as_str).collect::<Vec<_>>())?;
+        }
         Ok(())
     }
 
-    #[cfg(not(test))]
-    fn has_cmd(cmd: &str) -> bool {
-        Command::new("sh")
-            .args(["-c", &format!("command -v {cmd} >/dev/null 2>&1")])
-            .status()
-            .map(|s| s.success())
-            .unwrap_or(false)
+    /// The two `dd` commands [`zero_device_ends`] runs.
+    fn zero_ends_args(device: &str, size_bytes: u64) -> [Vec<String>; 2] {
+        const BLOCK: u64 = 512;
+        const BLOCKS_PER_MIB: u64 = (1 << 20) / BLOCK;
+        let of = format!("of={device}");
+        let count = format!("count={BLOCKS_PER_MIB}");
+        let zero = |seek: u64| {
+            let seek = format!("seek={seek}");
+            step(&["if=/dev/zero", &of, "bs=512", &count, &seek, "conv=fsync"])
+        };
+        [
+            zero(0),
+            zero((size_bytes / BLOCK).saturating_sub(BLOCKS_PER_MIB)),
+        ]
     }
 
-    #[cfg(test)]
-    fn has_cmd(_cmd: &str) -> bool {
-        true
+    /// Runs parted in script mode against one device, keeping any
+    /// alignment warnings it prints on stderr.
+    struct Parted<'a> {
+        device: &'a str,
+        warnings: Vec<String>,
     }
 
-    fn part_path(device: &str, idx: u8) -> String {
-        if device.chars().last().map(|c| c.is_ascii_digit()).unwrap_or(false) {
-            format!("{device}p{idx}")
+    impl<'a> Parted<'a> {
+        fn new(device: &'a str) -> Self {
+            Parted {
+                device,
+                warnings: Vec::new(),
+            }
+        }
+
+        fn run(&mut self, args: &[&str]) -> Result<()> {
+            let argv = [&[self.device, "-s"], args].concat();
+            let stderr = run_stderr("parted", &argv)?;
+            self.warnings.extend(alignment_warnings(&stderr));
+            Ok(())
+        }
+    }
+
+    /// parted's "not properly aligned" lines, without their `Warning:`
+    /// prefix. 4Kn disks trip these when partitions start off a 4 KiB
+    /// boundary.
+    fn alignment_warnings(stderr: &str) -> Vec<String> {
+        stderr
+            .lines()
+            .map(str::trim)
+            .filter(|line| line.to_ascii_lowercase().contains("not properly aligned"))
+            .map(|line| {
+                line.strip_prefix("Warning:")
+                    .unwrap_or(line)
+                    .trim()
+                    .to_string()
+            })
+            .collect()
+    }
+
+    fn emit_warnings(sink: &dyn ProgressSink, phase: &str, warnings: Vec<String>) {
+        for warning in warnings {
+            sink.emit(ProgressEvent {
+                phase: phase.to_string(),
+                message: format!("Warning: {warning}"),
+                percent: None,
+                ..Default::default()
+            });
+        }
+    }
+
+    /// Size of the ESP an install creates.
+    fn esp_size_mib(options: &InstallOptions) -> u64 {
+        options.esp_size_mib.unwrap_or(ESP_SIZE_MIB)
+    }
+
+    /// `a + b` MiB, refusing partition sizes too large to lay out.
+    fn add_mib(a: u64, b: u64) -> Result<u64> {
+        a.checked_add(b).ok_or_else(sizes_too_large)
+    }
+
+    /// `mib` in bytes, refusing sizes past what a u64 byte count holds.
+    fn mib_bytes(mib: u64) -> Result<u64> {
+        mib.checked_mul(1 << 20).ok_or_else(sizes_too_large)
+    }
+
+    fn write_blocked() -> CoreError {
+        CoreError::Validation("write blocked: set allow_write to proceed".to_string())
+    }
+
+    fn sizes_too_large() -> CoreError {
+        CoreError::Validation("the requested partition sizes are too large".to_string())
+    }
+
+    /// MiB offset the data partition starts at by default: right after the
+    /// ESP `partition_disk` creates from 1 MiB, or at 1 MiB without one.
+    fn default_data_start_mib(options: &InstallOptions) -> Result<u64> {
+        match options.layout {
+            Layout::BootAndData => add_mib(1, esp_size_mib(options)),
+            Layout::DataOnly => Ok(1),
+        }
+    }
+
+    /// Where the data partition starts on a wiped disk.
+    fn data_start_mib(options: &InstallOptions) -> Result<u64> {
+        match options.data_start_mib {
+            Some(start) => Ok(u64::from(start)),
+            None => default_data_start_mib(options),
+        }
+    }
+
+    /// [`InstallOptions::data_start_mib`] overrides must be 4 MiB aligned.
+    const DATA_START_ALIGN_MIB: u32 = 4;
+
+    fn validate_data_start(start_mib: u32, options: &InstallOptions) -> Result<()> {
+        let earliest = default_data_start_mib(options)?;
+        if u64::from(start_mib) < earliest {
+            return Err(CoreError::Validation(format!(
+                "data partition start {start_mib}MiB overlaps the partitions before it; \
+                 use {earliest}MiB or later"
+            )));
+        }
+        if !start_mib.is_multiple_of(DATA_START_ALIGN_MIB) {
+            return Err(CoreError::Validation(format!(
+                "data partition start {start_mib}MiB is not a multiple of \
+                 {DATA_START_ALIGN_MIB}MiB"
+            )));
+        }
+        Ok(())
+    }
+
+    /// Writes a fresh GPT for the options' layout, sizing and placing the
+    /// partitions as they ask. Returns parted's alignment warnings.
+    fn partition_disk(device: &str, options: &InstallOptions) -> Result<Vec<String>> {
+        let mut parted = Parted::new(device);
+        for step in wipe_parted_steps(options)? {
+            parted.run(&step.iter().map(String::as_str).collect::<Vec<_>>())?;
+        }
+        Ok(parted.warnings)
+    }
+
+    /// The parted commands [`partition_disk`] runs, after `<device> -s`.
+    fn wipe_parted_steps(options: &InstallOptions) -> Result<Vec<Vec<String>>> {
+        let start = data_start_mib(options)?;
+        let data_start = format!("{start}MiB");
+        let data_end = match options.data_size_mib {
+            Some(size) => format!("{}MiB", add_mib(start, size)?),
+            None => "100%".to_string(),
+        };
+        let mut steps = vec![step(&["mklabel", "gpt"])];
+        if options.layout == Layout::BootAndData {
+            let esp_end = format!("{}MiB", add_mib(1, esp_size_mib(options))?);
+            steps.push(step(&["mkpart", "primary", "fat32", "1MiB", &esp_end]));
+            steps.push(step(&["set", "1", "esp", "on"]));
+        }
+        steps.push(step(&["mkpart", "primary", &data_start, &data_end]));
+        steps.push(step(&["print"]));
+        Ok(steps)
+    }
+
+    fn step(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    /// ESP and data partition numbers `partition_disk` creates.
+    fn layout_numbers(layout: Layout) -> (Option<u8>, u8) {
+        match layout {
+            Layout::BootAndData => (Some(1), 2),
+            Layout::DataOnly => (None, 1),
+        }
+    }
+
+    /// Sets the GPT type GUIDs with `sgdisk -t`. Without sgdisk the default
+    /// types are skipped, as parted's `esp` flag already types the ESP;
+    /// explicitly chosen types are an error.
+    fn set_partition_types(
+        device: &str,
+        esp: Option<u8>,
+        data: u8,
+        types: &PartitionTypes,
+    ) -> Result<()> {
+        let Some(args) = partition_type_args(device, esp, data, types)? else {
+            return Ok(());
+        };
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        run("sgdisk", &args)
+    }
+
+    /// The `sgdisk` arguments [`set_partition_types`] runs, or `None` when
+    /// it skips the step.
+    fn partition_type_args(
+        device: &str,
+        esp: Option<u8>,
+        data: u8,
+        types: &PartitionTypes,
+    ) -> Result<Option<Vec<String>>> {
+        if !has_cmd("sgdisk") {
+            if *types == PartitionTypes::default() {
+                return Ok(None);
+            }
+            return Err(CoreError::Io(
+                "sgdisk not found; it is needed to set partition type GUIDs".to_string(),
+            ));
+        }
+        let mut args = Vec::new();
+        if let Some(esp) = esp {
+            args.extend(["-t".to_string(), format!("{esp}:{}", types.esp)]);
+        }
+        args.extend(["-t".to_string(), format!("{data}:{}", types.data)]);
+        args.push(device.to_string());
+        Ok(Some(args))
+    }
+
+    const ESP_SIZE_MIB: u64 = 32;
+
+    const EXTENT_COLUMNS: &str = "NAME,TYPE,START,SIZE,PKNAME";
+
+    /// An existing partition, in bytes from the start of the disk.
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    struct PartExtent {
+        number: u8,
+        start: u64,
+        end: u64,
+    }
+
+    /// A partition to create, in MiB from the start of the disk.
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    struct NewPart {
+        number: u8,
+        start_mib: u64,
+        end_mib: u64,
+    }
+
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    struct FreeSpacePlan {
+        esp: Option<NewPart>,
+        data: NewPart,
+    }
+
+    fn existing_extents(device: &str) -> Result<Vec<PartExtent>> {
+        parse_extents(&lsblk(EXTENT_COLUMNS)?, device)
+    }
+
+    fn parse_extents(json: &[u8], device: &str) -> Result<Vec<PartExtent>> {
+        let parsed = parse_lsblk(json)?;
+        let dev_name = device.trim_start_matches("/dev/");
+        let mut parts = Vec::new();
+        for disk in parsed.blockdevices.iter().filter(|d| d.name == dev_name) {
+            for child in disk.children.iter().flatten() {
+                if child.type_field.as_deref() != Some("part") {
+                    continue;
+                }
+                let (Some(number), Some(start), Some(size)) = (
+                    partition_number(&child.name, dev_name),
+                    child.start,
+                    child.size,
+                ) else {
+                    return Err(CoreError::Parse(format!(
+                        "lsblk gave no number or extent for {}",
+                        child.name
+                    )));
+                };
+                parts.push(PartExtent {
+                    number,
+                    start: start * 512,
+                    end: start * 512 + size,
+                });
+            }
+        }
+        Ok(parts)
+    }
+
+    /// `sdb3` → 3, `nvme0n1p2` → 2.
+    fn partition_number(name: &str, disk: &str) -> Option<u8> {
+        name.strip_prefix(disk)?.trim_start_matches('p').parse().ok()
+    }
+
+    /// Places the ESP (unless data-only) and the data partition in the
+    /// region, after checking it is inside the disk and overlaps nothing.
+    fn plan_free_space(
+        start_mib: u64,
+        size_mib: u64,
+        options: &InstallOptions,
+        disk_size: Option<u64>,
+        existing: &[PartExtent],
+    ) -> Result<FreeSpacePlan> {
+        if start_mib == 0 {
+            return Err(CoreError::Validation(
+                "free region must start at 1 MiB or later; the first MiB holds the GPT".to_string(),
+            ));
+        }
+        if mib_bytes(size_mib)? < MIN_TARGET_BYTES {
+            return Err(CoreError::Validation(format!(
+                "free region is too small ({size_mib} MiB); at least {} MiB required",
+                MIN_TARGET_BYTES >> 20
+            )));
+        }
+        let end_mib = add_mib(start_mib, size_mib)?;
+        let (start, end) = (mib_bytes(start_mib)?, mib_bytes(end_mib)?);
+        // The backup GPT occupies the last sectors of the disk.
+        if let Some(disk_size) = disk_size.filter(|&d| end > d.saturating_sub(1 << 20)) {
+            return Err(CoreError::Validation(format!(
+                "free region ends at {end_mib} MiB, past the usable end of the disk ({} MiB)",
+                (disk_size >> 20).saturating_sub(1)
+            )));
+        }
+        if let Some(part) = existing.iter().find(|p| start < p.end && p.start < end) {
+            return Err(CoreError::Validation(format!(
+                "free region {start_mib}-{end_mib} MiB overlaps partition {}",
+                part.number
+            )));
+        }
+
+        let mut free_numbers = (1..=128u8).filter(|n| !existing.iter().any(|p| p.number == *n));
+        let mut next_number = || {
+            free_numbers
+                .next()
+                .ok_or_else(|| CoreError::Validation("no free GPT partition entries".to_string()))
+        };
+        let esp = match options.layout {
+            Layout::BootAndData => Some(NewPart {
+                number: next_number()?,
+                start_mib,
+                end_mib: add_mib(start_mib, esp_size_mib(options))?,
+            }),
+            Layout::DataOnly => None,
+        };
+        let data_start = esp.as_ref().map_or(start_mib, |e| e.end_mib);
+        let data_end = match options.data_size_mib {
+            Some(size) => add_mib(data_start, size)?,
+            None => end_mib,
+        };
+        if data_end > end_mib || data_start >= end_mib {
+            return Err(CoreError::Validation(format!(
+                "the partitions need {} MiB, more than the {size_mib} MiB free region",
+                data_end.max(data_start.saturating_add(1)) - start_mib
+            )));
+        }
+        let data = NewPart {
+            number: next_number()?,
+            start_mib: data_start,
+            end_mib: data_end,
+        };
+        Ok(FreeSpacePlan { esp, data })
+    }
+
+    /// Adds the planned partitions to the existing table; no `mklabel`.
+    /// Returns parted's alignment warnings.
+    fn partition_free_space(device: &str, plan: &FreeSpacePlan) -> Result<Vec<String>> {
+        if mbr_kind(&read_mbr(device)?) == MbrKind::Missing {
+            return Err(CoreError::Validation(format!(
+                "{device} has no GPT; free-space installs need an existing GPT"
+            )));
+        }
+        let mut parted = Parted::new(device);
+        for step in free_space_parted_steps(plan) {
+            parted.run(&step.iter().map(String::as_str).collect::<Vec<_>>())?;
+        }
+        Ok(parted.warnings)
+    }
+
+    /// The parted commands [`partition_free_space`] runs, after `<device> -s`.
+    fn free_space_parted_steps(plan: &FreeSpacePlan) -> Vec<Vec<String>> {
+        let mut steps = Vec::new();
+        if let Some(esp) = &plan.esp {
+            let (start, end) = (
+                format!("{}MiB", esp.start_mib),
+                format!("{}MiB", esp.end_mib),
+            );
+            steps.push(step(&["mkpart", "primary", "fat32", &start, &end]));
+            steps.push(step(&["set", &esp.number.to_string(), "esp", "on"]));
+        }
+        let data = &plan.data;
+        let (start, end) = (
+            format!("{}MiB", data.start_mib),
+            format!("{}MiB", data.end_mib),
+        );
+        steps.push(step(&["mkpart", "primary", &start, &end]));
+        steps.push(step(&["print"]));
+        steps
+    }
+
+    /// MBR partition type that marks a GPT disk.
+    const MBR_GPT_TYPE: u8 = 0xEE;
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    enum MbrKind {
+        /// A single 0xEE entry covering the disk.
+        Protective,
+        /// 0xEE next to real MBR entries mirroring GPT partitions.
+        Hybrid,
+        /// No valid MBR, or one without an 0xEE entry.
+        Missing,
+    }
+
+    fn mbr_kind(sector: &[u8]) -> MbrKind {
+        if sector.len() < 512 || sector[510..512] != [0x55, 0xAA] {
+            return MbrKind::Missing;
+        }
+        let types: Vec<u8> = (0..4)
+            .map(|i| sector[446 + i * 16 + 4])
+            .filter(|&t| t != 0)
+            .collect();
+        if !types.contains(&MBR_GPT_TYPE) {
+            MbrKind::Missing
+        } else if types.len() == 1 {
+            MbrKind::Protective
         } else {
-            format!("{device}{idx}")
+            MbrKind::Hybrid
+        }
+    }
+
+    /// Checks sector 0 after partitioning so UEFI firmware sees the GPT,
+    /// first adding a hybrid entry for the ESP when `hybrid` is set.
+    fn ensure_mbr(device: &str, hybrid: bool) -> Result<()> {
+        let expected = if hybrid {
+            if !has_cmd("sgdisk") {
+                return Err(CoreError::Io(
+                    "sgdisk not found; it is needed for a hybrid MBR".to_string(),
+                ));
+            }
+            run("sgdisk", &["-h", "1:EE", device])?;
+            run("sgdisk", &["-p", device])?;
+            MbrKind::Hybrid
+        } else {
+            MbrKind::Protective
+        };
+        let found = mbr_kind(&read_mbr(device)?);
+        if found != expected {
+            return Err(CoreError::Io(format!(
+                "{device}: expected a {expected:?} MBR after partitioning, found {found:?}"
+            )));
+        }
+        Ok(())
+    }
+
+    fn read_mbr(device: &str) -> Result<Vec<u8>> {
+        current_runner().read_mbr(device)
+    }
+
+    /// Formats the ESP, returning a warning if the requested format mode
+    /// was not available.
+    fn format_esp(part: &str, options: &InstallOptions) -> Result<Vec<String>> {
+        let mut warnings = Vec::new();
+        run("mkfs.vfat", &esp_mkfs_args(part, options, &mut warnings))?;
+        Ok(warnings)
+    }
+
+    fn esp_mkfs_args<'a>(
+        part: &'a str,
+        options: &'a InstallOptions,
+        warnings: &mut Vec<String>,
+    ) -> Vec<&'a str> {
+        let mode = format_mode_args("mkfs.vfat", options.quick_format, warnings);
+        let mut args = vec!["-F", "32", "-n", ESP_LABEL];
+        args.extend(mode);
+        args.extend(options.esp_mkfs_args.iter().map(String::as_str));
+        args.push(part);
+        args
+    }
+
+    /// Arguments asking `tool` for a quick or full format. mkfs.vfat,
+    /// exfatprogs' mkfs.exfat and mkfs.ext4 format quickly by default; a
+    /// full format is a bad-block scan for vfat and ext4 and a zero-fill
+    /// for exFAT. mkfs.ntfs zero-fills unless asked for a quick format.
+    /// exfat-utils can only format quickly, so a full format request falls
+    /// back to a quick one with a warning. exfat-utils also installs a
+    /// `mkfs.exfat`, so that name only gets `--full-format` when its
+    /// version banner says it is exfatprogs.
+    fn format_mode_args(tool: &str, quick: bool, warnings: &mut Vec<String>) -> Vec<&'static str> {
+        match (tool, quick) {
+            ("mkfs.ntfs", true) => vec!["--quick"],
+            ("mkfs.ntfs", false) => vec![],
+            (_, true) => vec![],
+            ("mkfs.vfat" | "mkfs.ext4", false) => vec!["-c"],
+            ("mkfs.exfat", false) if is_exfatprogs(tool) => vec!["--full-format"],
+            (tool, false) => {
+                warnings.push(format!("{tool} cannot do a full format; doing a quick one"));
+                vec![]
+            }
+        }
+    }
+
+    /// Whether `tool` is exfatprogs' formatter, going by its `--version`
+    /// banner; exfat-utils' rejects the flag and prints no such banner.
+    fn is_exfatprogs(tool: &str) -> bool {
+        tool_version(tool).is_some_and(|version| version.starts_with("exfatprogs"))
+    }
+
+    /// exFAT formatters across distro packagings (exfatprogs, exfat-utils),
+    /// in order of preference.
+    const EXFAT_FORMATTERS: &[&str] = &["mkfs.exfat", "mkfs.exFAT", "mkexfatfs"];
+
+    /// Tools that can set an exFAT label, in order of preference.
+    const EXFAT_LABELERS: &[&str] = &["exfatlabel", "tune.exfat", "fatlabel"];
+
+    /// Entries of `table` for which `has` finds a binary, keeping table order.
+    fn available_tools(table: &[&'static str], has: impl Fn(&str) -> bool) -> Vec<&'static str> {
+        table.iter().copied().filter(|tool| has(tool)).collect()
+    }
+
+    /// Formats the data partition with the filesystem `options` asks for,
+    /// exFAT through the first formatter that works, returning the
+    /// format-mode warning if any.
+    fn format_data(part: &str, options: &InstallOptions) -> Result<Vec<String>> {
+        let label = data_label(options);
+        let mut warnings = Vec::new();
+        match data_mkfs_command(part, label, options, &mut warnings) {
+            Some((tool, args)) => run(tool, &args.iter().map(String::as_str).collect::<Vec<_>>())?,
+            None => return format_exfat(part, label, options),
+        }
+        Ok(warnings)
+    }
+
+    /// The mkfs command for an ext4 or NTFS data partition; `None` for
+    /// exFAT, whose formatter depends on the distro packaging.
+    fn data_mkfs_command(
+        part: &str,
+        label: &str,
+        options: &InstallOptions,
+        warnings: &mut Vec<String>,
+    ) -> Option<(&'static str, Vec<String>)> {
+        let (tool, force) = match options.data_fstype {
+            DataFsType::ExFat => return None,
+            DataFsType::Ext4 => ("mkfs.ext4", &["-F"][..]),
+            DataFsType::Ntfs => ("mkfs.ntfs", &[][..]),
+        };
+        let mode = format_mode_args(tool, options.quick_format, warnings);
+        let extra: Vec<&str> = options.data_mkfs_args.iter().map(String::as_str).collect();
+        let args = [force, &["-L", label], &mode, &extra, &[part]].concat();
+        Some((tool, args.into_iter().map(str::to_string).collect()))
+    }
+
+    /// Formats `part` as exFAT with the first formatter that works,
+    /// labelling it `label` afterwards if that formatter rejected `-n`.
+    fn format_exfat(part: &str, label: &str, options: &InstallOptions) -> Result<Vec<String>> {
+        let extra: Vec<&str> = options.data_mkfs_args.iter().map(String::as_str).collect();
+        let tools = available_tools(EXFAT_FORMATTERS, has_cmd);
+        if tools.is_empty() {
+            return Err(CoreError::Io(format!(
+                "exFAT formatter not found ({})",
+                EXFAT_FORMATTERS.join(", ")
+            )));
+        }
+        let mut last_err = None;
+        for tool in tools {
+            let mut warnings = Vec::new();
+            let mode = format_mode_args(tool, options.quick_format, &mut warnings);
+            let with_label = [&mode[..], &["-n", label], &extra, &[part]].concat();
+            match run(tool, &with_label) {
+                Ok(()) => return Ok(warnings),
+                Err(e) if is_device_gone(&e) => return Err(e),
+                Err(_) => {}
+            }
+            let without_label = [&mode[..], &extra, &[part]].concat();
+            match run(tool, &without_label) {
+                Ok(()) => {
+                    let _ = relabel_exfat(part, label);
+                    return Ok(warnings);
+                }
+                Err(e) if is_device_gone(&e) => return Err(e),
+                Err(e) => last_err = Some(e),
+            }
+        }
+        Err(last_err.unwrap_or_else(|| CoreError::Io("exFAT format failed".to_string())))
+    }
+
+    /// Labels the `data_fs` data partition `part` as `label`.
+    fn relabel_data_part(part: &str, label: &str, data_fs: DataFsType) -> Result<()> {
+        match data_fs {
+            DataFsType::ExFat => relabel_exfat(part, label),
+            DataFsType::Ext4 => run("e2label", &[part, label]),
+            DataFsType::Ntfs => run("ntfslabel", &[part, label]),
+        }
+    }
+
+    fn relabel_exfat(part: &str, label: &str) -> Result<()> {
+        let tools = available_tools(EXFAT_LABELERS, has_cmd);
+        if tools.is_empty() {
+            return Err(CoreError::Io(format!(
+                "exFAT labeling tool not found ({})",
+                EXFAT_LABELERS.join(", ")
+            )));
+        }
+        let mut last_err = None;
+        for tool in tools {
+            let args = match tool {
+                "tune.exfat" => [&["-L", label][..], &[part]].concat(),
+                _ => vec![part, label],
+            };
+            match run(tool, &args) {
+                Ok(()) => return Ok(()),
+                Err(e) => last_err = Some(e),
+            }
+        }
+        Err(last_err.unwrap_or_else(|| CoreError::Io("exFAT relabel failed".to_string())))
+    }
+
+    /// Compares the existing partitions with the RaidhOS layout (vfat ESP
+    /// then a `data_fs` data partition labelled `data_label`) and lists the
+    /// steps needed to reach it.
+    fn plan_layout(
+        parts: &[PartitionInfo],
+        layout: Layout,
+        data_fs: DataFsType,
+        data_label: &str,
+    ) -> Vec<LayoutChange> {
+        if layout == Layout::DataOnly {
+            let [data] = parts else {
+                return DATA_ONLY_LAYOUT.to_vec();
+            };
+            return plan_data(data, data_fs, data_label);
+        }
+        let [esp, data] = parts else {
+            return FULL_LAYOUT.to_vec();
+        };
+        let mut changes = Vec::new();
+        if esp.fstype != "vfat" {
+            changes.push(LayoutChange::FormatEsp);
+        } else if esp.label != ESP_LABEL {
+            changes.push(LayoutChange::RelabelEsp);
+        }
+        changes.extend(plan_data(data, data_fs, data_label));
+        changes.push(LayoutChange::RefreshPayload);
+        changes
+    }
+
+    fn plan_data(data: &PartitionInfo, data_fs: DataFsType, label: &str) -> Vec<LayoutChange> {
+        if data.fstype != data_fs.fstype() {
+            vec![LayoutChange::FormatData]
+        } else if data.label != label {
+            vec![LayoutChange::RelabelData]
+        } else {
+            vec![]
+        }
+    }
+
+    pub fn ensure_layout(req: InstallRequest, sink: &dyn ProgressSink) -> Result<InstallOutcome> {
+        let started = Instant::now();
+        let _lock = if req.dry_run {
+            None
+        } else {
+            Some(super::DeviceLock::acquire(&req.device)?)
+        };
+        let _runner = req.options.runner.as_ref().map(ActiveRunner::start);
+        let _cancel = ActiveCancel::start(&req.options.cancel);
+        let disks = list_disks()?;
+        let parts = list_partitions(req.device.clone())?;
+        report_cancelled(ensure_layout_with(req, sink, &disks, &parts, started), sink)
+    }
+
+    fn ensure_layout_with(
+        req: InstallRequest,
+        sink: &dyn ProgressSink,
+        disks: &[DiskInfo],
+        parts: &[PartitionInfo],
+        started: Instant,
+    ) -> Result<InstallOutcome> {
+        if req.options.mode != InstallMode::Wipe {
+            return Err(CoreError::Validation(
+                "ensure_layout only supports whole-disk installs".to_string(),
+            ));
+        }
+        // Zeroing the ends would wipe the partition table the layout is
+        // being repaired in place from.
+        if req.options.zero_ends {
+            return Err(CoreError::Validation(
+                "ensure_layout keeps the existing partitions; zero_ends is not supported"
+                    .to_string(),
+            ));
+        }
+        let _command_log = req.options.command_log.as_deref().map(CommandLog::start);
+        validate_install(&req, sink, disks)?;
+        let _runner = req.options.runner.as_ref().map(ActiveRunner::start);
+        let _cancel = ActiveCancel::start(&req.options.cancel);
+        let mut req = req;
+        if req.options.unique_labels && req.options.data_label.is_none() {
+            let in_use = labels_in_use(&req.device, &req.options.mode)?;
+            req.options.data_label = Some(crate::suggest_data_label(&in_use));
+        }
+        let label = data_label(&req.options).to_string();
+        let layout = req.options.layout;
+        let changes = plan_layout(parts, layout, req.options.data_fstype, &label);
+        if changes.contains(&LayoutChange::RelabelEsp) && !has_cmd("fatlabel") {
+            return Err(CoreError::Validation(
+                "fatlabel not found; install dosfstools to relabel the ESP".to_string(),
+            ));
+        }
+
+        if req.dry_run {
+            let duration_ms = elapsed_ms(started);
+            sink.emit(ProgressEvent {
+                phase: "complete".to_string(),
+                message: format!(
+                    "Dry-run complete. {} change(s) planned. ({duration_ms} ms)",
+                    changes.len()
+                ),
+                percent: Some(100),
+                ..Default::default()
+            });
+            return Ok(InstallOutcome {
+                device: req.device,
+                dry_run: true,
+                duration_ms,
+                changes,
+                partitions: vec![],
+                grub_entries: None,
+            });
+        }
+        if !req.allow_write {
+            return Err(write_blocked());
+        }
+
+        let part1 = part_path(&req.device, 1);
+        let part2 = part_path(&req.device, 2);
+        let data_part = match layout {
+            Layout::BootAndData => &part2,
+            Layout::DataOnly => &part1,
+        };
+
+        let mut grub_entries = None;
+        for change in &changes {
+            checkpoint(&req.options.cancel, sink)?;
+            sink.emit(ProgressEvent {
+                phase: "layout".to_string(),
+                message: change.to_string(),
+                percent: None,
+                ..Default::default()
+            });
+            match change {
+                LayoutChange::Partition => {
+                    emit_warnings(
+                        sink,
+                        "partition",
+                        partition_disk(&req.device, &req.options)?,
+                    );
+                    let (esp_number, data_number) = layout_numbers(layout);
+                    let types = &req.options.partition_types;
+                    set_partition_types(&req.device, esp_number, data_number, types)?;
+                    ensure_mbr(&req.device, req.options.hybrid_mbr)?;
+                    wait_for_partitions(&req.device, &[&part1, data_part])?
+                }
+                LayoutChange::FormatEsp => {
+                    emit_warnings(sink, "format", format_esp(&part1, &req.options)?)
+                }
+                LayoutChange::RelabelEsp => run("fatlabel", &[&part1, ESP_LABEL])?,
+                LayoutChange::FormatData => {
+                    emit_warnings(sink, "format", format_data(data_part, &req.options)?)
+                }
+                LayoutChange::RelabelData => {
+                    relabel_data_part(data_part, &label, req.options.data_fstype)?
+                }
+                LayoutChange::RefreshPayload => {
+                    let payload = resolve_payload_dir(
+                        req.payload_dir.as_deref(),
+                        std::env::var("RAIDHOS_PAYLOAD_DIR").ok(),
+                    )?;
+                    let auto_entries = payload_copy(
+                        sink,
+                        &req.options,
+                        &payload,
+                        &part1,
+                        &part2,
+                        req.arch,
+                        req.options.mounter.as_deref().unwrap_or(&SystemMounter),
+                    )?;
+                    if req.options.menu_backend == MenuBackend::Syslinux {
+                        emit_warnings(sink, "finalize", install_syslinux(&req.device, &part1)?);
+                    }
+                    grub_entries = auto_entries.or_else(|| grub_entry_count(&payload));
+                }
+            }
+        }
+
+        let duration_ms = elapsed_ms(started);
+        sink.emit(ProgressEvent {
+            phase: "complete".to_string(),
+            message: format!("Layout up to date in {duration_ms} ms."),
+            percent: Some(100),
+            ..Default::default()
+        });
+        Ok(InstallOutcome {
+            partitions: installed_partitions(&req.device).unwrap_or_default(),
+            device: req.device,
+            dry_run: false,
+            duration_ms,
+            changes,
+            grub_entries,
+        })
+    }
+
+    /// Holds the install while `cancel` is paused, and stops it once it is
+    /// cancelled.
+    fn checkpoint(cancel: &CancelToken, sink: &dyn ProgressSink) -> Result<()> {
+        if cancel.is_paused() {
+            sink.emit(ProgressEvent {
+                phase: "paused".to_string(),
+                message: "Install paused.".to_string(),
+                percent: None,
+                ..Default::default()
+            });
+            if cancel.wait_while_paused().is_ok() {
+                sink.emit(ProgressEvent {
+                    phase: "paused".to_string(),
+                    message: "Install resumed.".to_string(),
+                    percent: None,
+                    ..Default::default()
+                });
+            }
+        }
+        cancel.check()
+    }
+
+    /// Ends a cancelled install with a `cancelled` event, wherever it
+    /// stopped: at a checkpoint, before a command, or mid-copy.
+    fn report_cancelled<T>(result: Result<T>, sink: &dyn ProgressSink) -> Result<T> {
+        result.inspect_err(|e| {
+            if matches!(e, CoreError::Cancelled) {
+                sink.emit(ProgressEvent {
+                    phase: "cancelled".to_string(),
+                    message: "Install cancelled.".to_string(),
+                    percent: None,
+                    ..Default::default()
+                })
+            }
+        })
+    }
+
+    fn elapsed_ms(started: Instant) -> u64 {
+        u64::try_from(started.elapsed().as_millis()).unwrap_or(u64::MAX)
+    }
+
+    /// ESP plus a usable data partition.
+    const MIN_TARGET_BYTES: u64 = 64 << 20;
+
+    /// Checks shared by every destructive operation: `device` is a known,
+    /// unmounted, non-system disk.
+    fn validate_target<'a>(device: &str, disks: &'a [DiskInfo]) -> Result<&'a DiskInfo> {
+        check_target(device, disks, |_| {})
+    }
+
+    /// [`validate_target`], calling `passed` with the name of each check
+    /// as it succeeds.
+    fn check_target<'a>(
+        device: &str,
+        disks: &'a [DiskInfo],
+        mut passed: impl FnMut(&str),
+    ) -> Result<&'a DiskInfo> {
+        let target = disks
+            .iter()
+            .find(|d| d.id == device)
+            .ok_or_else(|| CoreError::Validation("device not found".to_string()))?;
+        passed("device lookup");
+
+        if target.is_system {
+            return Err(CoreError::Validation(
+                "refusing to operate on system disk".to_string(),
+            ));
+        }
+        passed("not the system disk");
+
+        if boot_device_in(&proc_mounts(), disks).as_deref() == Some(device) {
+            return Err(CoreError::Validation(
+                "refusing to operate on the stick this system booted from".to_string(),
+            ));
+        }
+        passed("not the boot medium");
+
+        if !target.mountpoints.is_empty() {
+            return Err(CoreError::Validation(
+                "device has mounted partitions; unmount first".to_string(),
+            ));
+        }
+        passed("nothing mounted");
+        Ok(target)
+    }
+
+    /// The disk that `device` is a partition of (lsblk type `part`).
+    fn partition_parent<'a>(device: &str, disks: &'a [DiskInfo]) -> Option<&'a DiskInfo> {
+        disks
+            .iter()
+            .find(|d| d.partitions.iter().any(|p| p == device))
+    }
+
+    /// Percent reported as each validation check passes, in check order.
+    const VALIDATION_CHECKS: &[(&str, u8)] = &[
+        ("device path", 2),
+        ("install options", 4),
+        ("wipe flag", 6),
+        ("device lookup", 8),
+        ("not the system disk", 10),
+        ("not the boot medium", 11),
+        ("nothing mounted", 12),
+        ("existing partitions", 14),
+        ("size", 16),
+    ];
+
+    fn validate_install(req: &InstallRequest, sink: &dyn ProgressSink, disks: &[DiskInfo]) -> Result<()> {
+        let passed = |check: &str| {
+            let percent = VALIDATION_CHECKS
+                .iter()
+                .find(|(name, _)| *name == check)
+                .map(|&(_, percent)| percent);
+            sink.emit(ProgressEvent {
+                phase: "validate".to_string(),
+                message: format!("Check passed: {check}"),
+                percent,
+                ..Default::default()
+            });
+        };
+
+        sink.emit(ProgressEvent {
+            phase: "validate".to_string(),
+            message: format!("Validating target {}", req.device),
+            percent: Some(0),
+            ..Default::default()
+        });
+        if !req.device.starts_with("/dev/") {
+            return Err(CoreError::Validation(
+                "device must be an absolute /dev path".to_string(),
+            ));
+        }
+        argv::validate_device_path(&req.device)?;
+        passed("device path");
+
+        argv::validate_mkfs_args(&req.options.esp_mkfs_args, argv::ESP_MKFS_FLAGS)?;
+        argv::validate_mkfs_args(&req.options.data_mkfs_args, argv::DATA_MKFS_FLAGS)?;
+        argv::validate_type_guid(&req.options.partition_types.esp)?;
+        argv::validate_type_guid(&req.options.partition_types.data)?;
+        if req.options.layout == Layout::DataOnly {
+            if !req.options.esp_mkfs_args.is_empty() {
+                return Err(CoreError::Validation(
+                    "ESP mkfs arguments are not allowed with the data-only layout".to_string(),
+                ));
+            }
+            if req.payload_dir.is_some() {
+                return Err(CoreError::Validation(
+                    "payload_dir is not allowed with the data-only layout".to_string(),
+                ));
+            }
+            if req.options.hybrid_mbr {
+                return Err(CoreError::Validation(
+                    "a hybrid MBR needs an ESP; not allowed with the data-only layout".to_string(),
+                ));
+            }
+        }
+        let free_space = matches!(req.options.mode, InstallMode::FreeSpace { .. });
+        let keeps_table = match &req.options.mode {
+            InstallMode::Wipe => None,
+            InstallMode::FreeSpace { .. } => Some("free space"),
+            InstallMode::Partition { .. } => Some("an existing partition"),
+        };
+        if let Some(place) = keeps_table {
+            if req.options.hybrid_mbr {
+                return Err(CoreError::Validation(format!(
+                    "a hybrid MBR is not allowed when installing to {place}"
+                )));
+            }
+            if req.options.zero_ends {
+                return Err(CoreError::Validation(format!(
+                    "zeroing the device ends is not allowed when installing to {place}"
+                )));
+            }
+        }
+        if let Some(label) = &req.options.data_label {
+            argv::validate_volume_label(label)?;
+        }
+        if let Some(start_mib) = req.options.data_start_mib {
+            if let Some(place) = keeps_table {
+                return Err(CoreError::Validation(format!(
+                    "a data partition start is not allowed when installing to {place}"
+                )));
+            }
+            validate_data_start(start_mib, &req.options)?;
+        }
+        let in_partition = matches!(req.options.mode, InstallMode::Partition { .. });
+        if let Some(size) = req.options.esp_size_mib {
+            if req.options.layout == Layout::DataOnly || in_partition {
+                return Err(CoreError::Validation(
+                    "an ESP size is only used when the install creates an ESP".to_string(),
+                ));
+            }
+            if size < ESP_SIZE_MIB {
+                return Err(CoreError::Validation(format!(
+                    "ESP size {size} MiB is too small; a FAT32 ESP needs at least \
+                     {ESP_SIZE_MIB} MiB"
+                )));
+            }
+        }
+        if let Some(size) = req.options.data_size_mib {
+            if in_partition {
+                return Err(CoreError::Validation(
+                    "a data partition size is not allowed when installing to an existing partition"
+                        .to_string(),
+                ));
+            }
+            if size == 0 {
+                return Err(CoreError::Validation(
+                    "data partition size must be at least 1 MiB".to_string(),
+                ));
+            }
+        }
+        if let InstallMode::Partition { esp } = &req.options.mode {
+            match (req.options.layout, esp) {
+                (Layout::BootAndData, None) => {
+                    return Err(CoreError::Validation(
+                        "installing to a partition needs an ESP partition, or the data-only layout"
+                            .to_string(),
+                    ))
+                }
+                (Layout::DataOnly, Some(_)) => {
+                    return Err(CoreError::Validation(
+                        "an ESP partition is not used with the data-only layout".to_string(),
+                    ))
+                }
+                (_, Some(esp)) => argv::validate_device_path(esp)?,
+                (_, None) => {}
+            }
+            if req.require_empty {
+                return Err(CoreError::Validation(
+                    "require_empty cannot be met when installing to an existing partition"
+                        .to_string(),
+                ));
+            }
+        }
+        passed("install options");
+
+        if !req.wipe && !free_space {
+            return Err(CoreError::Validation(
+                "wipe flag must be set for destructive install".to_string(),
+            ));
+        }
+        passed("wipe flag");
+
+        let target = match &req.options.mode {
+            InstallMode::Partition { esp } => {
+                let disk = partition_parent(&req.device, disks).ok_or_else(|| {
+                    CoreError::Validation(format!("{} is not a partition", req.device))
+                })?;
+                if let Some(esp) = esp {
+                    if esp == &req.device || !disk.partitions.contains(esp) {
+                        return Err(CoreError::Validation(format!(
+                            "ESP {esp} must be another partition on {}",
+                            disk.id
+                        )));
+                    }
+                }
+                check_target(&disk.id, disks, passed)?
+            }
+            _ => {
+                if let Some(disk) = partition_parent(&req.device, disks) {
+                    return Err(CoreError::Validation(format!(
+                        "{} is a partition of {}; install to the disk or to the partition in partition mode",
+                        req.device, disk.id
+                    )));
+                }
+                check_target(&req.device, disks, passed)?
+            }
+        };
+
+        if req.require_empty && !target.partitions.is_empty() {
+            return Err(CoreError::Validation(format!(
+                "{} already has partitions ({}); wipe it first",
+                req.device,
+                target.partitions.join(", ")
+            )));
+        }
+        passed("existing partitions");
+
+        if req.options.zero_ends && target.size_bytes.is_none() {
+            return Err(CoreError::Validation(format!(
+                "size of {} is unknown; cannot zero the end of the device",
+                req.device
+            )));
+        }
+
+        // A free-space install checks the size of its region instead, and a
+        // partition install formats only what is already there.
+        if let Some(size) = target
+            .size_bytes
+            .filter(|&size| size < MIN_TARGET_BYTES && keeps_table.is_none())
+        {
+            return Err(CoreError::Validation(format!(
+                "device is too small ({size} bytes); at least {} MiB required",
+                MIN_TARGET_BYTES >> 20
+            )));
+        }
+        // The partitions of a wiped disk, plus the backup GPT in its last MiB.
+        let data_size_mib = req.options.data_size_mib.unwrap_or(1);
+        let needed_mib = add_mib(add_mib(data_start_mib(&req.options)?, data_size_mib)?, 1)?;
+        let needed_bytes = mib_bytes(needed_mib)?;
+        if let Some(size) = target
+            .size_bytes
+            .filter(|&size| needed_bytes > size && keeps_table.is_none())
+        {
+            return Err(CoreError::Validation(format!(
+                "the requested partitions need {needed_mib} MiB but {} has only {} MiB",
+                req.device,
+                size >> 20
+            )));
+        }
+        passed("size");
+
+        for issue in preflight::check_disk(target) {
+            sink.emit(ProgressEvent {
+                phase: "validate".to_string(),
+                message: format!("Warning: {}", issue.message),
+                percent: None,
+                ..Default::default()
+            });
+        }
+
+        sink.emit(ProgressEvent {
+            phase: "validate".to_string(),
+            message: format!(
+                "{} is ready for payload {}",
+                req.device, req.payload_version
+            ),
+            percent: Some(90),
+            ..Default::default()
+        });
+
+        Ok(())
+    }
+
+    /// Resolves the payload source, preferring an explicit `payload_dir` over
+    /// `RAIDHOS_PAYLOAD_DIR`, and checks it has the `esp/` + `data/` layout.
+    fn resolve_payload_dir(explicit: Option<&str>, env: Option<String>) -> Result<PathBuf> {
+        let (payload, source) = match (explicit, env) {
+            (Some(dir), _) => (PathBuf::from(dir), "payload_dir"),
+            (None, Some(dir)) => (PathBuf::from(dir), "RAIDHOS_PAYLOAD_DIR"),
+            (None, None) => {
+                return Err(CoreError::Validation(
+                    "RAIDHOS_PAYLOAD_DIR is not set".to_string(),
+                ))
+            }
+        };
+        if !payload.exists() {
+            return Err(CoreError::Validation(format!("{source} does not exist")));
+        }
+        if !payload.is_dir() {
+            return Err(CoreError::Validation(format!(
+                "{source} is not a directory"
+            )));
+        }
+        if !payload.join("esp").is_dir() || !payload.join("data").is_dir() {
+            return Err(CoreError::Validation(format!(
+                "{source} must contain esp/ and data/ directories"
+            )));
+        }
+        Ok(payload)
+    }
+
+    /// Where `payload_copy` mounts the ESP and data partitions.
+    struct PayloadMounts {
+        esp: PathBuf,
+        data: PathBuf,
+    }
+
+    impl PayloadMounts {
+        fn standard() -> Self {
+            PayloadMounts {
+                esp: PathBuf::from(ESP_MOUNT),
+                data: PathBuf::from(DATA_MOUNT),
+            }
+        }
+
+        fn unmount(&self, mounter: &dyn Mounter) {
+            let _ = mounter.unmount(&self.esp);
+            let _ = mounter.unmount(&self.data);
+        }
+
+        /// Appends the mount locations to `err` so they can be inspected.
+        fn note_kept(&self, err: CoreError) -> CoreError {
+            let note = format!(
+                "mounts kept for inspection: {} (ESP), {} (data)",
+                self.esp.display(),
+                self.data.display()
+            );
+            match err {
+                CoreError::Io(msg) => CoreError::Io(format!("{msg}; {note}")),
+                CoreError::Validation(msg) => CoreError::Validation(format!("{msg}; {note}")),
+                CoreError::Parse(msg) => CoreError::Parse(format!("{msg}; {note}")),
+                CoreError::NotImplemented(msg) => {
+                    CoreError::NotImplemented(format!("{msg}; {note}"))
+                }
+                CoreError::Command { cmd, code, stderr } => CoreError::Command {
+                    cmd,
+                    code,
+                    stderr: format!("{}\n{note}", stderr.trim_end()),
+                },
+                other => other,
+            }
+        }
+    }
+
+    fn payload_copy(
+        sink: &dyn ProgressSink,
+        options: &InstallOptions,
+        payload: &Path,
+        part1: &str,
+        part2: &str,
+        arch: Arch,
+        mounter: &dyn Mounter,
+    ) -> Result<Option<usize>> {
+        let mounts = PayloadMounts::standard();
+        payload_copy_at(sink, options, payload, part1, part2, arch, &mounts, mounter)
+    }
+
+    /// Mounts both partitions, copies the payload and unmounts, returning the
+    /// entry count of the menu `auto_menu` generated. On failure the mounts
+    /// are released too, unless `keep_mounts_on_error` asks to keep them;
+    /// cancellation always unmounts.
+    #[allow(clippy::too_many_arguments)]
+    fn payload_copy_at(
+        sink: &dyn ProgressSink,
+        options: &InstallOptions,
+        payload: &Path,
+        part1: &str,
+        part2: &str,
+        arch: Arch,
+        mounts: &PayloadMounts,
+        mounter: &dyn Mounter,
+    ) -> Result<Option<usize>> {
+        fs::create_dir_all(&mounts.esp).map_err(|e| CoreError::Io(e.to_string()))?;
+        fs::create_dir_all(&mounts.data).map_err(|e| CoreError::Io(e.to_string()))?;
+
+        let copied = copy_mounted(sink, options, payload, part1, part2, arch, mounts, mounter);
+        let auto_entries = match copied {
+            Ok(entries) => {
+                mounts.unmount(mounter);
+                entries
+            }
+            Err(CoreError::Cancelled) => {
+                mounts.unmount(mounter);
+                return Err(CoreError::Cancelled);
+            }
+            Err(e) if options.keep_mounts_on_error => return Err(mounts.note_kept(e)),
+            Err(e) => {
+                mounts.unmount(mounter);
+                return Err(e);
+            }
+        };
+
+        sink.emit(ProgressEvent {
+            phase: "payload".to_string(),
+            message: "Payload copy complete.".to_string(),
+            percent: Some(90),
+            ..Default::default()
+        });
+
+        Ok(auto_entries)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn copy_mounted(
+        sink: &dyn ProgressSink,
+        options: &InstallOptions,
+        payload: &Path,
+        part1: &str,
+        part2: &str,
+        arch: Arch,
+        mounts: &PayloadMounts,
+        mounter: &dyn Mounter,
+    ) -> Result<Option<usize>> {
+        let esp_payload = payload.join("esp");
+        let data_payload = payload.join("data");
+        let esp_mount = mounts.esp.to_string_lossy();
+        let total = crate::tree_copy::tree_bytes(&esp_payload)?
+            + crate::tree_copy::tree_bytes(&data_payload)?;
+
+        mounter.mount(part1, &mounts.esp)?;
+        mounter.mount(part2, &mounts.data)?;
+
+        sink.emit(ProgressEvent {
+            phase: "payload".to_string(),
+            message: "Copying payload files".to_string(),
+            percent: Some(85),
+            ..Default::default()
+        });
+        let mut progress =
+            crate::PercentProgress::new(sink, "payload", "Copying payload files", total)
+                .within(85, 88)
+                .showing_bytes();
+
+        crate::tree_copy::copy_tree(&esp_payload, &mounts.esp, &mut progress, &options.cancel)?;
+        if super::install_fallback_bootloader(&esp_payload, &mounts.esp, arch)?.is_none() {
+            let args = grub_install_args(arch, &esp_mount);
+            let args: Vec<&str> = args.iter().map(String::as_str).collect();
+            let installed = if has_cmd("grub-install") {
+                match run("grub-install", &args) {
+                    Err(e) if is_device_gone(&e) => return Err(e),
+                    result => result.is_ok(),
+                }
+            } else {
+                false
+            };
+            if !installed {
+                return Err(CoreError::Validation(
+                    "payload esp/ has no GRUB EFI binary and grub-install failed".to_string(),
+                ));
+            }
+        }
+        let label = data_label(options);
+        if label != DATA_LABEL {
+            relabel_grub_cfgs(&mounts.esp, label)?;
+        }
+        let fs = options.data_fstype;
+        if fs != DataFsType::ExFat {
+            rewrite_grub_cfgs(&mounts.esp, |cfg| crate::grub::replace_data_module(cfg, fs))?;
+        }
+        checkpoint(&options.cancel, sink)?;
+        crate::tree_copy::copy_tree(&data_payload, &mounts.data, &mut progress, &options.cancel)?;
+        progress.finish();
+        let mut auto_entries = None;
+        if options.auto_menu {
+            let config = write_auto_menu(&data_payload, &mounts.esp, label, fs)?;
+            let count = config.entries.len();
+            sink.emit(ProgressEvent {
+                phase: "payload".to_string(),
+                message: format!("Generated a boot menu with {count} entries"),
+                percent: Some(88),
+                ..Default::default()
+            });
+            emit_warnings(sink, "payload", crate::large_iso_warnings(&config));
+            auto_entries = Some(count);
+        }
+        if options.menu_backend == MenuBackend::Syslinux {
+            let missing = write_syslinux_menu(&mounts.esp, label)?;
+            let mut warnings = Vec::new();
+            if !missing.is_empty() {
+                let missing = missing.join(", ");
+                warnings.push(format!("syslinux modules not found: {missing}"));
+            }
+            use crate::syslinux::{GRUB_CORE, GRUB_LNXBOOT};
+            if let Some(image) = [GRUB_LNXBOOT, GRUB_CORE]
+                .into_iter()
+                .find(|image| !mounts.esp.join(image).is_file())
+            {
+                warnings.push(format!(
+                    "{image} not on the ESP; the syslinux menu cannot chain-load GRUB"
+                ));
+            }
+            emit_warnings(sink, "payload", warnings);
+        }
+        Ok(auto_entries)
+    }
+
+    /// Host directories syslinux packages put their BIOS modules and MBR
+    /// code in (Debian, Fedora, Arch).
+    #[cfg(not(test))]
+    const SYSLINUX_HOST_DIRS: &[&str] = &[
+        "/usr/lib/syslinux/modules/bios",
+        "/usr/lib/syslinux/mbr",
+        "/usr/lib/syslinux",
+        "/usr/share/syslinux",
+        "/usr/lib/syslinux/bios",
+    ];
+
+    #[cfg(not(test))]
+    fn find_syslinux_file(name: &str) -> Option<PathBuf> {
+        SYSLINUX_HOST_DIRS
+            .iter()
+            .map(|dir| Path::new(dir).join(name))
+            .find(|path| path.is_file())
+    }
+
+    /// Tests must not depend on whether the host has syslinux.
+    #[cfg(test)]
+    fn find_syslinux_file(_name: &str) -> Option<PathBuf> {
+        None
+    }
+
+    /// Writes `syslinux/syslinux.cfg` chain-loading GRUB for the grub.cfg on
+    /// the mounted ESP and copies the modules it loads from the host. Returns the
+    /// modules that could not be found.
+    fn write_syslinux_menu(esp_mount: &Path, data_label: &str) -> Result<Vec<&'static str>> {
+        use crate::syslinux::{SYSLINUX_DIR, SYSLINUX_MODULES};
+        let config = crate::read_grub_cfg(esp_mount).map_err(|e| {
+            CoreError::Validation(format!("syslinux menu mirrors the ESP's grub.cfg: {e}"))
+        })?;
+        let dir = esp_mount.join(SYSLINUX_DIR);
+        fs::create_dir_all(&dir).map_err(|e| CoreError::Io(e.to_string()))?;
+        let cfg = crate::render_syslinux_cfg(&config, data_label);
+        crate::write_atomic(&dir.join("syslinux.cfg"), cfg.as_bytes())?;
+        let mut missing = Vec::new();
+        for module in SYSLINUX_MODULES {
+            match find_syslinux_file(module) {
+                Some(source) => {
+                    fs::copy(source, dir.join(module)).map_err(|e| CoreError::Io(e.to_string()))?;
+                }
+                None => missing.push(*module),
+            }
+        }
+        Ok(missing)
+    }
+
+    /// Installs syslinux's loader on the unmounted ESP, marks the ESP
+    /// legacy-bootable and writes syslinux's GPT boot code to the MBR of
+    /// `disk`. Returns a warning when the boot code is not on the host.
+    fn install_syslinux(disk: &str, esp_part: &str) -> Result<Vec<String>> {
+        let directory = format!("/{}", crate::syslinux::SYSLINUX_DIR);
+        let install = ["--install", "--directory", &directory, esp_part];
+        run("syslinux", &install)?;
+        if let Some(number) = partition_number(esp_part, disk) {
+            Parted::new(disk).run(&["set", &number.to_string(), "legacy_boot", "on"])?;
+        }
+        let Some(mbr) = find_syslinux_file("gptmbr.bin") else {
+            return Ok(vec![
+                "gptmbr.bin not found; BIOS firmware will not start syslinux from this disk"
+                    .to_string(),
+            ]);
+        };
+        let input = format!("if={}", mbr.display());
+        let output = format!("of={disk}");
+        run(
+            "dd",
+            &[&input, &output, "bs=440", "count=1", "conv=notrunc,fsync"],
+        )?;
+        Ok(vec![])
+    }
+
+    /// Renders a menu for the ISOs under `boot/isos` in the data payload,
+    /// which the copy put at the same place on the data partition, and
+    /// writes it over the ESP's grub.cfg, searching for `data_label` on a
+    /// data partition formatted as `data_fstype`. Returns the menu written.
+    fn write_auto_menu(
+        data_payload: &Path,
+        esp_mount: &Path,
+        data_label: &str,
+        data_fstype: DataFsType,
+    ) -> Result<crate::BootConfig> {
+        let isos = data_payload.join("boot").join("isos");
+        let mut entries = scan_isos(vec![isos.display().to_string()])?;
+        for entry in &mut entries {
+            if let Ok(rel) = Path::new(&entry.path).strip_prefix(data_payload) {
+                entry.path = format!("/{}", rel.display());
+            }
+        }
+        let config = crate::BootConfig {
+            data_fstype,
+            ..crate::BootConfig::from_iso_entries(&entries)
+        };
+        crate::grub::write_grub_cfg_to_esp(esp_mount, &config, data_label)?;
+        Ok(config)
+    }
+
+    pub fn scan_isos(dirs: Vec<String>) -> Result<Vec<super::IsoEntry>> {
+        let mut results = Vec::new();
+        for dir in dirs {
+            let root = PathBuf::from(dir);
+            if !root.exists() {
+                continue;
+            }
+            let entries = fs::read_dir(&root).map_err(|e| CoreError::Io(e.to_string()))?;
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if path.is_file() {
+                    push_iso(&mut results, &path);
+                } else if path.is_dir() {
+                    if let Ok(subs) = fs::read_dir(&path) {
+                        for sub in subs.flatten() {
+                            let subpath = sub.path();
+                            if subpath.is_file() {
+                                push_iso(&mut results, &subpath);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        results.sort_by_cached_key(|e| (super::title_sort_key(&e.title), e.path.clone()));
+        Ok(results)
+    }
+
+    fn push_iso(results: &mut Vec<super::IsoEntry>, path: &PathBuf) {
+        if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
+            if ext.eq_ignore_ascii_case("iso") {
+                if let Ok(meta) = fs::metadata(path) {
+                    results.push(super::iso_entry(path, meta.len()));
+                }
+            }
+        }
+    }
+
+    /// What RaidhOS leaves on a stick besides the user's ISOs, relative to
+    /// each partition root.
+    const RAIDHOS_ARTIFACTS: &[&str] = &["raidhos", "EFI/BOOT/grub.cfg", "boot/grub/grub.cfg"];
+
+    pub fn uninstall(device: &str, reformat: bool, allow_write: bool) -> Result<()> {
+        if !device.starts_with("/dev/") {
+            return Err(CoreError::Validation(
+                "device must be an absolute /dev path".to_string(),
+            ));
+        }
+        argv::validate_device_path(device)?;
+        if !allow_write {
+            return Err(write_blocked());
+        }
+        let disks = list_disks()?;
+        let target = validate_target(device, &disks)?;
+        let _lock = super::DeviceLock::acquire(device)?;
+        uninstall_disk(target, reformat, Path::new(SCAN_MOUNT_ROOT))
+    }
+
+    /// Uninstalls from the validated disk `target`: with `reformat`, the
+    /// same one-partition exFAT layout `format_disk` writes; otherwise the
+    /// RaidhOS artifacts are stripped under `mount_root`.
+    fn uninstall_disk(target: &DiskInfo, reformat: bool, mount_root: &Path) -> Result<()> {
+        if reformat {
+            let size_bytes = target.size_bytes.ok_or_else(|| {
+                CoreError::Validation(format!("{}: size unknown; cannot wipe", target.id))
+            })?;
+            return format_whole_disk(&target.id, size_bytes, FormatFs::ExFat, DATA_LABEL);
+        }
+        let parts = parse_partitions(&lsblk(PARTITION_COLUMNS)?, &target.id)?;
+        strip_artifacts(&parts, mount_root).map(|_| ())
+    }
+
+    pub fn format_disk(device: &str, fs: FormatFs, label: &str, allow_write: bool) -> Result<()> {
+        if !device.starts_with("/dev/") {
+            return Err(CoreError::Validation(
+                "device must be an absolute /dev path".to_string(),
+            ));
+        }
+        argv::validate_device_path(device)?;
+        argv::validate_volume_label(label)?;
+        if !allow_write {
+            return Err(write_blocked());
+        }
+        let disks = list_disks()?;
+        let target = validate_target(device, &disks)?;
+        let size_bytes = target
+            .size_bytes
+            .ok_or_else(|| CoreError::Validation(format!("{device}: size unknown; cannot wipe")))?;
+        let _lock = super::DeviceLock::acquire(device)?;
+        format_whole_disk(device, size_bytes, fs, label)
+    }
+
+    /// Zeroes both ends of `device`, writes a GPT with one partition
+    /// spanning it, and formats that partition as `fs`.
+    fn format_whole_disk(device: &str, size_bytes: u64, fs: FormatFs, label: &str) -> Result<()> {
+        zero_device_ends(device, size_bytes)?;
+        let data_only = InstallOptions {
+            layout: Layout::DataOnly,
+            ..Default::default()
+        };
+        partition_disk(device, &data_only)?;
+        let part = part_path(device, 1);
+        wait_for_partitions(device, &[&part])?;
+        match fs {
+            FormatFs::ExFat => {
+                format_exfat(&part, label, &InstallOptions::default())?;
+            }
+            FormatFs::Fat32 => run("mkfs.vfat", &["-F", "32", "-n", label, &part])?,
+            FormatFs::Ext4 => run("mkfs.ext4", &["-F", "-L", label, &part])?,
+        }
+        // parted types new partitions as Linux data, which suits ext4;
+        // Windows only mounts FAT and exFAT on Basic Data.
+        if fs != FormatFs::Ext4 {
+            set_partition_types(device, None, 1, &PartitionTypes::default())?;
+        }
+        Ok(())
+    }
+
+    pub fn relabel_data(device: &str, new_label: &str) -> Result<()> {
+        argv::validate_device_path(device)?;
+        argv::validate_volume_label(new_label)?;
+        let disks = list_disks()?;
+        validate_target(device, &disks)?;
+        let _lock = super::DeviceLock::acquire(device)?;
+        let parts = parse_partitions(&lsblk(PARTITION_COLUMNS)?, device)?;
+        relabel_parts(&parts, new_label, Path::new(SCAN_MOUNT_ROOT)).map(|_| ())
+    }
+
+    /// The data partition among `parts` and the filesystem it was
+    /// formatted with: the first non-ESP partition on a supported one.
+    fn data_partition(parts: &[PartitionInfo]) -> Option<(&PartitionInfo, DataFsType)> {
+        parts
+            .iter()
+            .filter(|p| p.label != ESP_LABEL)
+            .find_map(|p| Some((p, p.fstype.parse().ok()?)))
+    }
+
+    /// Relabels the data partition among `parts`, then mounts the ESP
+    /// under `mount_root` and rewrites the grub.cfg files whose label
+    /// search changed. Returns those as `<partition>:<relative path>`; a
+    /// data-only stick has none.
+    fn relabel_parts(
+        parts: &[PartitionInfo],
+        new_label: &str,
+        mount_root: &Path,
+    ) -> Result<Vec<String>> {
+        let (data, data_fs) = data_partition(parts)
+            .ok_or_else(|| CoreError::Validation("no data partition found".to_string()))?;
+        relabel_data_part(&data.id, new_label, data_fs)?;
+        let Some(esp) = parts.iter().find(|p| p.label == ESP_LABEL) else {
+            return Ok(Vec::new());
+        };
+        let name = esp.id.rsplit('/').next().unwrap_or("part");
+        let target = mount_root.join(format!("relabel-{name}"));
+        let _guard = MountGuard::mount(&SystemMounter, &esp.id, &target, false)?;
+        let rewritten = relabel_grub_cfgs(&target, new_label)?;
+        Ok(rewritten
+            .iter()
+            .map(|path| {
+                let rel = path.strip_prefix(&target).unwrap_or(path);
+                format!("{}:{}", esp.id, rel.display())
+            })
+            .collect())
+    }
+
+    /// Points the label search of each grub.cfg under the ESP mounted at
+    /// `esp_mount` at `new_label`, returning the files that changed.
+    fn relabel_grub_cfgs(esp_mount: &Path, new_label: &str) -> Result<Vec<PathBuf>> {
+        rewrite_grub_cfgs(esp_mount, |cfg| {
+            crate::grub::replace_search_label(cfg, new_label)
+        })
+    }
+
+    /// Applies `edit` to each grub.cfg under the ESP mounted at
+    /// `esp_mount`, returning the files that changed.
+    fn rewrite_grub_cfgs(esp_mount: &Path, edit: impl Fn(&str) -> String) -> Result<Vec<PathBuf>> {
+        let mut rewritten = Vec::new();
+        for path in crate::BootMode::Hybrid.grub_cfg_paths(esp_mount) {
+            let Ok(cfg) = fs::read_to_string(&path) else {
+                continue;
+            };
+            let updated = edit(&cfg);
+            if updated == cfg {
+                continue;
+            }
+            crate::write_atomic(&path, updated.as_bytes())?;
+            rewritten.push(path);
+        }
+        Ok(rewritten)
+    }
+
+    /// Where `measure_write_speed` samples: past the protective MBR and
+    /// primary GPT, 16 MiB long.
+    const SPEED_SAMPLE: (u64, usize) = (1 << 20, 16 << 20);
+
+    pub fn measure_write_speed(device: &str) -> Result<u64> {
+        argv::validate_device_path(device)?;
+        let disks = list_disks()?;
+        let disk = validate_target(device, &disks)?;
+        let (offset, len) = SPEED_SAMPLE;
+        let needed = offset + len as u64;
+        if disk.size_bytes.is_some_and(|size| size < needed) {
+            return Err(CoreError::Validation(format!(
+                "{device} is too small to measure"
+            )));
+        }
+        let _lock = super::DeviceLock::acquire(device)?;
+        let mut file = fs::File::options()
+            .read(true)
+            .write(true)
+            .open(device)
+            .map_err(|e| CoreError::Io(format!("{device}: {e}")))?;
+        crate::speed::rewrite_speed(&mut file, offset, len)
+    }
+
+    /// Mounts the RaidhOS ESP and the data partition under `mount_root`
+    /// and deletes the RaidhOS artifacts found there, leaving ISOs alone.
+    /// Refuses a disk with neither the ESP nor a `raidhos/` directory on
+    /// its data partition, so another installer's boot files survive.
+    /// Returns the removed paths as `<partition>:<relative path>`.
+    fn strip_artifacts(parts: &[PartitionInfo], mount_root: &Path) -> Result<Vec<String>> {
+        let not_ours =
+            || CoreError::Validation("no RaidhOS install found; nothing removed".to_string());
+        let esp = parts.iter().find(|p| p.label == ESP_LABEL);
+        let data = data_partition(parts).map(|(part, _)| part);
+        if esp.is_none() && data.is_none() {
+            return Err(not_ours());
+        }
+        let mut removed = Vec::new();
+        for part in esp.into_iter().chain(data) {
+            let name = part.id.rsplit('/').next().unwrap_or("part");
+            let target = mount_root.join(format!("uninstall-{name}"));
+            let _guard = MountGuard::mount(&SystemMounter, &part.id, &target, false)?;
+            // Without the ESP, the data partition is the only one mounted.
+            if esp.is_none() && !target.join("raidhos").is_dir() {
+                return Err(not_ours());
+            }
+            for rel in RAIDHOS_ARTIFACTS {
+                let path = target.join(rel);
+                let result = if path.is_dir() {
+                    fs::remove_dir_all(&path)
+                } else if path.is_file() {
+                    fs::remove_file(&path)
+                } else {
+                    continue;
+                };
+                result.map_err(|e| CoreError::Io(format!("{}: {e}", path.display())))?;
+                removed.push(format!("{}:{rel}", part.id));
+            }
+        }
+        Ok(removed)
+    }
+
+    pub fn verify_stick(device: &str) -> Result<StickReport> {
+        if !device.starts_with("/dev/") {
+            return Err(CoreError::Validation(
+                "device must be an absolute /dev path".to_string(),
+            ));
+        }
+        argv::validate_device_path(device)?;
+        let _lock = super::DeviceLock::acquire(device)?;
+        let parts = parse_partitions(&lsblk(PARTITION_COLUMNS)?, device)?;
+        Ok(verify_parts(device, &parts, Path::new(SCAN_MOUNT_ROOT)))
+    }
+
+    /// Runs the [`super::verify_stick`] checks over `parts`, mounting the
+    /// ESP and data partition read-only under `mount_root` where they are
+    /// not mounted already. Checks that depend on a missing partition or
+    /// menu are left out rather than failed twice.
+    fn verify_parts(device: &str, parts: &[PartitionInfo], mount_root: &Path) -> StickReport {
+        let mut checks = Vec::new();
+        let mut check = |name: &str, status: CheckStatus, detail: String| {
+            checks.push(StickCheck {
+                name: name.to_string(),
+                status,
+                detail,
+            })
+        };
+        let esp = parts.iter().find(|p| p.label == ESP_LABEL);
+        match esp {
+            None => check(
+                "esp partition",
+                CheckStatus::Fail,
+                format!("no partition labelled {ESP_LABEL}"),
+            ),
+            Some(esp) if esp.fstype != "vfat" => check(
+                "esp partition",
+                CheckStatus::Fail,
+                format!("{} is {}, not vfat", esp.id, esp.fstype),
+            ),
+            Some(esp) => check("esp partition", CheckStatus::Pass, esp.id.clone()),
+        }
+        let data = data_partition(parts).map(|(data, _)| data);
+        match data {
+            None => check(
+                "data partition",
+                CheckStatus::Fail,
+                "no exFAT, ext4 or NTFS data partition".to_string(),
+            ),
+            Some(data) => check(
+                "data partition",
+                CheckStatus::Pass,
+                format!("{} labelled {}", data.id, data.label),
+            ),
+        }
+        let Some(esp) = esp.filter(|p| p.fstype == "vfat") else {
+            return StickReport {
+                device: device.to_string(),
+                checks,
+            };
+        };
+
+        let menu = match mount_read_only(esp, mount_root, "verify") {
+            Err(e) => {
+                check(
+                    "grub.cfg",
+                    CheckStatus::Fail,
+                    format!("cannot mount {}: {e}", esp.id),
+                );
+                None
+            }
+            Ok((root, _guard)) => {
+                let cfgs: Vec<PathBuf> = crate::BootMode::Hybrid
+                    .grub_cfg_paths(&root)
+                    .into_iter()
+                    .filter(|path| path.is_file())
+                    .collect();
+                let text = cfgs.first().and_then(|path| fs::read_to_string(path).ok());
+                match &text {
+                    None => check(
+                        "grub.cfg",
+                        CheckStatus::Fail,
+                        "no readable grub.cfg on the ESP".to_string(),
+                    ),
+                    Some(_) => {
+                        let rel: Vec<String> = cfgs
+                            .iter()
+                            .map(|path| {
+                                path.strip_prefix(&root)
+                                    .unwrap_or(path)
+                                    .display()
+                                    .to_string()
+                            })
+                            .collect();
+                        check("grub.cfg", CheckStatus::Pass, rel.join(", "));
+                    }
+                }
+                match esp_bootloader(&root) {
+                    Some(found) => check("bootloader", CheckStatus::Pass, found),
+                    None => check(
+                        "bootloader",
+                        CheckStatus::Fail,
+                        "no EFI/BOOT/BOOT*.EFI, BIOS GRUB or syslinux on the ESP".to_string(),
+                    ),
+                }
+                text
+            }
+        };
+        let (Some(menu), Some(data)) = (menu, data) else {
+            return StickReport {
+                device: device.to_string(),
+                checks,
+            };
+        };
+
+        if let Some(searched) = crate::grub::search_label(&menu) {
+            if searched.eq_ignore_ascii_case(&data.label) {
+                check("menu label", CheckStatus::Pass, searched);
+            } else {
+                check(
+                    "menu label",
+                    CheckStatus::Fail,
+                    format!(
+                        "grub.cfg searches for {searched}, the data partition is labelled {}",
+                        data.label
+                    ),
+                );
+            }
+        }
+        let config = crate::grub::parse_grub_cfg(&menu);
+        match mount_read_only(data, mount_root, "verify") {
+            Err(e) => check(
+                "isos",
+                CheckStatus::Fail,
+                format!("cannot mount {}: {e}", data.id),
+            ),
+            Ok(_) if config.entries.is_empty() => check(
+                "isos",
+                CheckStatus::Warn,
+                "the boot menu has no entries".to_string(),
+            ),
+            Ok((root, _guard)) => {
+                let missing = crate::grub::missing_isos(&root, &config);
+                if missing.is_empty() {
+                    check(
+                        "isos",
+                        CheckStatus::Pass,
+                        format!("all {} menu entries found", config.entries.len()),
+                    );
+                } else {
+                    check(
+                        "isos",
+                        CheckStatus::Fail,
+                        format!("missing: {}", missing.join(", ")),
+                    );
+                }
+            }
+        }
+        StickReport {
+            device: device.to_string(),
+            checks,
+        }
+    }
+
+    /// The first boot loader found under the ESP mounted at `esp_root`,
+    /// relative to it: the UEFI fallback, BIOS GRUB, or syslinux.
+    fn esp_bootloader(esp_root: &Path) -> Option<String> {
+        let efi = fs::read_dir(esp_root.join("EFI").join("BOOT"))
+            .into_iter()
+            .flatten()
+            .flatten()
+            .map(|entry| entry.file_name().to_string_lossy().into_owned())
+            .find(|name| {
+                let upper = name.to_ascii_uppercase();
+                upper.starts_with("BOOT") && upper.ends_with(".EFI")
+            });
+        if let Some(name) = efi {
+            return Some(format!("EFI/BOOT/{name}"));
+        }
+        ["boot/grub/i386-pc/core.img", "syslinux/ldlinux.sys"]
+            .into_iter()
+            .find(|rel| esp_root.join(rel).is_file())
+            .map(str::to_string)
+    }
+
+    /// Where `part` can be read: its first mountpoint, or a read-only mount
+    /// at `<mount_root>/<prefix>-<name>` held by the returned guard.
+    fn mount_read_only(
+        part: &PartitionInfo,
+        mount_root: &Path,
+        prefix: &str,
+    ) -> Result<(PathBuf, Option<MountGuard<'static>>)> {
+        if let Some(mount) = part.mountpoints.first() {
+            return Ok((PathBuf::from(mount), None));
+        }
+        let name = part.id.rsplit('/').next().unwrap_or("part");
+        let target = mount_root.join(format!("{prefix}-{name}"));
+        let guard = MountGuard::mount(&SystemMounter, &part.id, &target, true)?;
+        Ok((target, Some(guard)))
+    }
+
+    pub fn scan_isos_on_device(device: String) -> Result<Vec<super::IsoEntry>> {
+        argv::validate_device_path(&device)?;
+        let part = find_partition(&lsblk(PARTITION_COLUMNS)?, &device)?
+            .ok_or_else(|| CoreError::Validation(format!("{device} is not a partition")))?;
+        scan_partition(&part, Path::new(SCAN_MOUNT_ROOT))
+    }
+
+    fn find_partition(json: &[u8], device: &str) -> Result<Option<PartitionInfo>> {
+        let parsed = parse_lsblk(json)?;
+        let mut parts = Vec::new();
+        for disk in &parsed.blockdevices {
+            collect_parts(disk, &disk.name, &mut parts);
+        }
+        Ok(parts.into_iter().find(|p| p.id == device))
+    }
+
+    fn scan_partition(part: &PartitionInfo, mount_root: &Path) -> Result<Vec<super::IsoEntry>> {
+        if part.fstype.is_empty() {
+            return Err(CoreError::Validation(format!(
+                "{} has no filesystem",
+                part.id
+            )));
+        }
+        let (root, _guard) = mount_read_only(part, mount_root, "scan")?;
+        let mut entries = scan_isos(vec![root.join("boot").join("isos").display().to_string()])?;
+        for entry in &mut entries {
+            if let Ok(rel) = Path::new(&entry.path).strip_prefix(&root) {
+                entry.path = format!("/{}", rel.display());
+            }
+        }
+        Ok(entries)
+    }
+
+    /// `mount`/`umount` from util-linux.
+    #[derive(Debug)]
+    struct SystemMounter;
+
+    impl Mounter for SystemMounter {
+        fn mount(&self, device: &str, target: &Path) -> Result<()> {
+            run("mount", &[device, &target.to_string_lossy()])
+        }
+
+        fn mount_read_only(&self, device: &str, target: &Path) -> Result<()> {
+            run("mount", &["-o", "ro", device, &target.to_string_lossy()])
+        }
+
+        fn unmount(&self, target: &Path) -> Result<()> {
+            run("umount", &[&target.to_string_lossy()])
+        }
+    }
+
+    /// A mount made by us, unmounted (and its mountpoint removed) on drop.
+    struct MountGuard<'a> {
+        mounter: &'a dyn Mounter,
+        target: PathBuf,
+    }
+
+    impl<'a> MountGuard<'a> {
+        fn mount(
+            mounter: &'a dyn Mounter,
+            device: &str,
+            target: &Path,
+            read_only: bool,
+        ) -> Result<Self> {
+            fs::create_dir_all(target).map_err(|e| CoreError::Io(e.to_string()))?;
+            if read_only {
+                mounter.mount_read_only(device, target)?;
+            } else {
+                mounter.mount(device, target)?;
+            }
+            Ok(MountGuard {
+                mounter,
+                target: target.to_path_buf(),
+            })
+        }
+    }
+
+    impl Drop for MountGuard<'_> {
+        fn drop(&mut self) {
+            let _ = self.mounter.unmount(&self.target);
+            let _ = fs::remove_dir(&self.target);
+        }
+    }
+
+    fn run(cmd: &str, args: &[&str]) -> Result<()> {
+        run_stderr(cmd, args).map(|_| ())
+    }
+
+    /// Runs `cmd` on the active runner and returns its stderr. Nothing new
+    /// starts once the install is cancelled, except `umount`, so a cancelled
+    /// install can still release its mounts.
+    fn run_stderr(cmd: &str, args: &[&str]) -> Result<String> {
+        argv::validate_args(args)?;
+        if cmd != "umount" {
+            cancel::check_active()?;
+        }
+        #[cfg(feature = "test-hooks")]
+        crate::test_hooks::before_run()?;
+        let status = match current_runner().run(cmd, args) {
+            Ok(status) => status,
+            Err(e) => return Err(device_gone(args, &e.to_string()).unwrap_or(e)),
+        };
+        if status.code != Some(0) {
+            if let Some(gone) = device_gone(args, &status.stderr) {
+                return Err(gone);
+            }
+            // Killed by a signal while the install was being cancelled.
+            if status.code.is_none() {
+                cancel::check_active()?;
+            }
+            return Err(CoreError::Command {
+                cmd: cmd.to_string(),
+                code: status.code,
+                stderr: status.stderr,
+            });
+        }
+        Ok(status.stderr)
+    }
+
+    /// What a command fails with once the stick it was writing to is gone.
+    const DEVICE_GONE: &str = "target device disappeared (was it unplugged?)";
+
+    /// The disappeared-device error when a failed command reported ENODEV or
+    /// ENXIO ("No such device", "No such device or address"), or when a
+    /// device node among its arguments no longer exists.
+    fn device_gone(args: &[&str], detail: &str) -> Option<CoreError> {
+        let vanished = args
+            .iter()
+            .filter_map(|arg| arg.find("/dev/").map(|at| &arg[at..]))
+            .any(|node| !device_node_exists(node));
+        (vanished || detail.contains("No such device"))
+            .then(|| CoreError::Io(DEVICE_GONE.to_string()))
+    }
+
+    /// Fallbacks that retry with another tool stop on this error: nothing
+    /// further can succeed, and the install must not carry on writing.
+    fn is_device_gone(err: &CoreError) -> bool {
+        matches!(err, CoreError::Io(msg) if msg == DEVICE_GONE)
+    }
+
+    fn device_node_exists(node: &str) -> bool {
+        current_runner().node_exists(node)
+    }
+
+    /// Times the partition nodes are re-checked after a rescan before
+    /// [`wait_for_partitions`] gives up.
+    const PARTITION_NODE_POLLS: u32 = 5;
+
+    #[cfg(not(test))]
+    const PARTITION_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(500);
+    #[cfg(test)]
+    const PARTITION_POLL_INTERVAL: std::time::Duration = std::time::Duration::ZERO;
+
+    /// Waits until every node in `parts` exists. udev can lag behind
+    /// parted, so a missing node triggers a `partprobe` of `device` and a
+    /// `udevadm settle` before each re-check, up to
+    /// [`PARTITION_NODE_POLLS`] times.
+    fn wait_for_partitions(device: &str, parts: &[&str]) -> Result<()> {
+        let missing = || parts.iter().copied().find(|p| !device_node_exists(p));
+        for _ in 0..PARTITION_NODE_POLLS {
+            if missing().is_none() {
+                return Ok(());
+            }
+            for (cmd, args) in [("partprobe", &[device][..]), ("udevadm", &["settle"][..])] {
+                if !has_cmd(cmd) {
+                    continue;
+                }
+                match run(cmd, args) {
+                    Err(e) if is_device_gone(&e) => return Err(e),
+                    _ => {}
+                }
+            }
+            std::thread::sleep(PARTITION_POLL_INTERVAL);
+        }
+        match missing() {
+            None => Ok(()),
+            Some(part) => Err(CoreError::Io(format!(
+                "partition {part} did not appear after rescanning {device} \
+                 {PARTITION_NODE_POLLS} times; replug the stick and retry"
+            ))),
+        }
+    }
+
+    fn has_cmd(cmd: &str) -> bool {
+        current_runner().has_cmd(cmd)
+    }
+
+    /// The runner from the install's options, else the default one, with
+    /// each command it runs written to the active command log.
+    fn current_runner() -> std::sync::Arc<dyn CommandRunner> {
+        let inner = runner::active().unwrap_or_else(default_runner);
+        std::sync::Arc::new(command_log::Logged(inner))
+    }
+
+    #[cfg(not(test))]
+    fn default_runner() -> std::sync::Arc<dyn CommandRunner> {
+        std::sync::Arc::new(runner::SystemRunner)
+    }
+
+    /// Unit tests never touch the host: commands land in `RUN_LOG`.
+    #[cfg(test)]
+    fn default_runner() -> std::sync::Arc<dyn CommandRunner> {
+        std::sync::Arc::new(StubRunner)
+    }
+
+    #[cfg(test)]
+    thread_local! {
+        static RUN_LOG: std::cell::RefCell<Vec<String>> = const { std::cell::RefCell::new(Vec::new()) };
+        /// Command-line prefix the stub runner fails on.
+        static RUN_FAIL: std::cell::RefCell<Option<String>> = const { std::cell::RefCell::new(None) };
+        /// Command-line prefix and the stderr the stub runner prints for it.
+        static RUN_STDERR: std::cell::RefCell<Option<(String, String)>> = const { std::cell::RefCell::new(None) };
+        /// `/proc/mounts` as the boot-medium check sees it.
+        static PROC_MOUNTS: std::cell::RefCell<String> = const { std::cell::RefCell::new(String::new()) };
+    }
+
+    /// Logs each command line to `RUN_LOG` and exits 1 for those matching
+    /// `RUN_FAIL`.
+    #[cfg(test)]
+    #[derive(Debug)]
+    struct StubRunner;
+
+    #[cfg(test)]
+    impl CommandRunner for StubRunner {
+        fn run(&self, cmd: &str, args: &[&str]) -> Result<runner::CommandStatus> {
+            let line = [&[cmd], args].concat().join(" ");
+            RUN_LOG.with(|log| log.borrow_mut().push(line.clone()));
+            let failed =
+                RUN_FAIL.with(|fail| fail.borrow().as_ref().is_some_and(|p| line.starts_with(p)));
+            let stderr = RUN_STDERR.with(|stderr| match stderr.borrow().as_ref() {
+                Some((prefix, text)) if line.starts_with(prefix.as_str()) => text.clone(),
+                _ => String::new(),
+            });
+            Ok(runner::CommandStatus {
+                code: Some(i32::from(failed)),
+                stderr,
+            })
+        }
+
+        fn has_cmd(&self, _cmd: &str) -> bool {
+            true
+        }
+
+        /// Test device paths never exist on the host.
+        fn node_exists(&self, _node: &str) -> bool {
+            true
+        }
+
+        /// `--version` banners from `VERSION_OUTPUTS` and the lsblk
+        /// partition and extent listings from the fixtures; anything else
+        /// prints nothing.
+        fn output(&self, cmd: &str, args: &[&str]) -> Result<runner::CommandOutput> {
+            let stdout = match (cmd, args) {
+                ("lsblk", [.., columns]) if *columns == PARTITION_COLUMNS => {
+                    tests::PARTITIONS_FIXTURE
+                }
+                ("lsblk", [.., columns]) if *columns == EXTENT_COLUMNS => tests::EXTENTS_FIXTURE,
+                (_, ["--version"]) => tests::VERSION_OUTPUTS
+                    .iter()
+                    .find(|(tool, _)| *tool == cmd)
+                    .map_or("", |(_, out)| *out),
+                _ => "",
+            };
+            Ok(runner::CommandOutput {
+                status: runner::CommandStatus::success(),
+                stdout: stdout.as_bytes().to_vec(),
+            })
+        }
+
+        /// What parted leaves behind, plus the ESP entry once `sgdisk -h`
+        /// ran.
+        fn read_mbr(&self, _device: &str) -> Result<Vec<u8>> {
+            let hybrid =
+                RUN_LOG.with(|log| log.borrow().iter().any(|c| c.starts_with("sgdisk -h")));
+            Ok(tests::mbr_fixture(if hybrid {
+                &[0xEF, MBR_GPT_TYPE]
+            } else {
+                &[MBR_GPT_TYPE]
+            }))
+        }
+    }
+
+    pub fn tool_versions() -> ToolVersions {
+        let exfat = available_tools(EXFAT_FORMATTERS, has_cmd)
+            .into_iter()
+            .find_map(tool_version);
+        ToolVersions {
+            parted: tool_version("parted"),
+            mkfs_vfat: tool_version("mkfs.vfat"),
+            exfat,
+            grub_install: tool_version("grub-install"),
+        }
+    }
+
+    fn tool_version(cmd: &str) -> Option<String> {
+        let output = current_runner().output(cmd, &["--version"]).ok()?;
+        // dosfstools prints its banner on stderr.
+        version_line(&output.stdout).or_else(|| version_line(output.status.stderr.as_bytes()))
+    }
+
+    fn version_line(output: &[u8]) -> Option<String> {
+        String::from_utf8_lossy(output)
+            .lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty())
+            .map(str::to_string)
+    }
+
+    fn part_path(device: &str, idx: u8) -> String {
+        if device.chars().last().map(|c| c.is_ascii_digit()).unwrap_or(false) {
+            format!("{device}p{idx}")
+        } else {
+            format!("{device}{idx}")
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::{PhaseId, BASIC_DATA_TYPE_GUID, ESP_TYPE_GUID, LINUX_DATA_TYPE_GUID};
+
+        struct Sink {
+            events: std::cell::RefCell<Vec<ProgressEvent>>,
+        }
+
+        impl ProgressSink for Sink {
+            fn emit(&self, event: ProgressEvent) {
+                self.events.borrow_mut().push(event);
+            }
+        }
+
+        fn disk(id: &str, mounts: Vec<&str>, is_system: bool) -> DiskInfo {
+            DiskInfo {
+                id: id.to_string(),
+                model: "Test".to_string(),
+                serial: None,
+                size_bytes: Some(16 << 30),
+                removable: true,
+                external: true,
+                transport: Some("usb".to_string()),
+                mountpoints: mounts.into_iter().map(|m| m.to_string()).collect(),
+                is_system,
+                partition_count: 0,
+                partitions: vec![],
+            }
+        }
+
+        fn req(device: &str, wipe: bool, dry_run: bool) -> InstallRequest {
+            InstallRequest {
+                device: device.to_string(),
+                payload_version: "0.1.0".to_string(),
+                wipe,
+                dry_run,
+                allow_write: false,
+                arch: Arch::X86_64,
+                payload_dir: None,
+                require_empty: false,
+                options: Default::default(),
+            }
+        }
+
+        /// `--version` output captured from Debian 12.
+        pub(super) const VERSION_OUTPUTS: &[(&str, &str)] = &[
+            (
+                "parted",
+                "parted (GNU parted) 3.5\nCopyright (C) 2022 Free Software Foundation, Inc.\n",
+            ),
+            ("mkfs.vfat", "mkfs.fat 4.2 (2021-01-31)\n"),
+            ("mkfs.exfat", "\nexfatprogs version : 1.2.0\n"),
+        ];
+
+        /// sdb: partition 1 over 1 MiB-10 GiB, partition 3 at 15 GiB.
+        pub(super) const EXTENTS_FIXTURE: &str = r#"{
+            "blockdevices": [
+                {"name": "sdb", "type": "disk", "start": null, "size": 17179869184,
+                 "children": [
+                    {"name": "sdb1", "type": "part", "start": 2048, "size": 10736369664, "pkname": "sdb"},
+                    {"name": "sdb3", "type": "part", "start": 31457280, "size": 536870912, "pkname": "sdb"}
+                 ]}
+            ]
+        }"#;
+
+        pub(super) fn mbr_fixture(types: &[u8]) -> Vec<u8> {
+            let mut sector = vec![0u8; 512];
+            for (i, t) in types.iter().enumerate() {
+                sector[446 + i * 16 + 4] = *t;
+            }
+            sector[510] = 0x55;
+            sector[511] = 0xAA;
+            sector
+        }
+
+        fn take_run_log() -> Vec<String> {
+            RUN_LOG.with(|log| log.take())
+        }
+
+        fn fail_commands_starting_with(prefix: Option<&str>) {
+            RUN_FAIL.with(|fail| *fail.borrow_mut() = prefix.map(str::to_string));
+        }
+
+        fn stderr_for_commands_starting_with(prefix_and_text: Option<(&str, &str)>) {
+            RUN_STDERR.with(|stderr| {
+                *stderr.borrow_mut() =
+                    prefix_and_text.map(|(prefix, text)| (prefix.to_string(), text.to_string()))
+            });
+        }
+
+        #[test]
+        fn validate_rejects_non_dev_path() {
+            let sink = Sink { events: std::cell::RefCell::new(Vec::new()) };
+            let disks = vec![disk("/dev/sdb", vec![], false)];
+            let err = validate_install(&req("sdb", true, true), &sink, &disks).unwrap_err();
+            assert!(format!("{err}").contains("device must be an absolute /dev path"));
+        }
+
+        #[test]
+        fn validate_rejects_without_wipe_flag() {
+            let sink = Sink { events: std::cell::RefCell::new(Vec::new()) };
+            let disks = vec![disk("/dev/sdb", vec![], false)];
+            let err = validate_install(&req("/dev/sdb", false, true), &sink, &disks).unwrap_err();
+            assert!(format!("{err}").contains("wipe flag"));
+        }
+
+        #[test]
+        fn validate_rejects_system_disk() {
+            let sink = Sink { events: std::cell::RefCell::new(Vec::new()) };
+            let disks = vec![disk("/dev/sda", vec!["/"], true)];
+            let err = validate_install(&req("/dev/sda", true, true), &sink, &disks).unwrap_err();
+            assert!(format!("{err}").contains("system disk"));
+        }
+
+        #[test]
+        fn validate_rejects_mounted_partitions() {
+            let sink = Sink { events: std::cell::RefCell::new(Vec::new()) };
+            let disks = vec![disk("/dev/sdb", vec!["/media/usb"], false)];
+            let err = validate_install(&req("/dev/sdb", true, true), &sink, &disks).unwrap_err();
+            assert!(format!("{err}").contains("mounted"));
+        }
+
+        #[test]
+        fn validate_accepts_safe_disk() {
+            let sink = Sink { events: std::cell::RefCell::new(Vec::new()) };
+            let disks = vec![disk("/dev/sdb", vec![], false)];
+            let ok = validate_install(&req("/dev/sdb", true, true), &sink, &disks);
+            assert!(ok.is_ok());
+            assert!(!sink.events.borrow().is_empty());
+        }
+
+        #[test]
+        fn validation_reports_each_check_in_order() {
+            let sink = Sink { events: std::cell::RefCell::new(Vec::new()) };
+            let disks = vec![disk("/dev/sdb", vec![], false)];
+            validate_install(&req("/dev/sdb", true, true), &sink, &disks).unwrap();
+            let events = sink.events.borrow();
+            let checks: Vec<(&str, Option<u8>)> = events
+                .iter()
+                .filter_map(|e| {
+                    let check = e.message.strip_prefix("Check passed: ")?;
+                    Some((check, e.percent))
+                })
+                .collect();
+            let expected: Vec<(&str, Option<u8>)> = VALIDATION_CHECKS
+                .iter()
+                .map(|&(name, percent)| (name, Some(percent)))
+                .collect();
+            assert_eq!(checks, expected);
+            assert!(events.iter().all(|e| e.phase == "validate"));
+            assert_eq!(events.last().unwrap().percent, Some(90));
+
+            // A failing check is the first one not reported.
+            let sink = Sink { events: std::cell::RefCell::new(Vec::new()) };
+            let disks = vec![disk("/dev/sdb", vec!["/media/usb"], false)];
+            validate_install(&req("/dev/sdb", true, true), &sink, &disks).unwrap_err();
+            let last = sink.events.borrow().last().unwrap().message.clone();
+            assert_eq!(last, "Check passed: not the boot medium");
+        }
+
+        #[test]
+        fn install_refuses_the_stick_the_system_booted_from() {
+            let mut stick = disk("/dev/sdb", vec![], false);
+            stick.partitions = vec!["/dev/sdb1".to_string(), "/dev/sdb2".to_string()];
+            let disks = vec![disk("/dev/nvme0n1", vec!["/"], true), stick];
+            // An Ubuntu ISO loopback-booted from the stick's data partition.
+            let mounts = "/dev/sdb2 /isodevice exfat ro,relatime 0 0\n\
+                          /dev/loop0 /cdrom iso9660 ro,noatime 0 0\n";
+            assert_eq!(boot_device_in(mounts, &disks).as_deref(), Some("/dev/sdb"));
+            let automounted = "/dev/sdb2 /media/data exfat rw 0 0\n";
+            assert_eq!(boot_device_in(automounted, &disks), None);
+
+            PROC_MOUNTS.with(|m| *m.borrow_mut() = mounts.to_string());
+            let sink = Sink { events: std::cell::RefCell::new(Vec::new()) };
+            let err = validate_install(&req("/dev/sdb", true, true), &sink, &disks).unwrap_err();
+            PROC_MOUNTS.with(|m| m.borrow_mut().clear());
+            assert!(err.to_string().contains("booted from"), "{err}");
+            let last = sink.events.borrow().last().unwrap().message.clone();
+            assert_eq!(last, "Check passed: not the system disk");
+            let other = disk("/dev/sdc", vec![], false);
+            let disks = [disks[0].clone(), disks[1].clone(), other];
+            PROC_MOUNTS.with(|m| *m.borrow_mut() = mounts.to_string());
+            let result = validate_target("/dev/sdc", &disks).map(|d| d.id.clone());
+            PROC_MOUNTS.with(|m| m.borrow_mut().clear());
+            assert_eq!(result.unwrap(), "/dev/sdc");
+        }
+
+        pub(super) const PARTITIONS_FIXTURE: &str = r#"{
+            "blockdevices": [
+                {"name": "sdb", "type": "disk", "label": null, "fstype": null,
+                 "fsver": null, "fssize": null, "mountpoints": [null], "pkname": null,
+                 "children": [
+                    {"name": "sdb1", "type": "part", "label": "RAIDHOS_EFI", "fstype": "vfat",
+                     "fsver": "FAT32", "fssize": 33538048, "mountpoints": [null], "pkname": "sdb"},
+                    {"name": "sdb2", "type": "part", "label": "DATA", "fstype": "exfat",
+                     "fsver": "1.0", "fssize": "61991813120", "mountpoints": ["/media/data"],
+                     "pkname": "sdb"}
+                 ]}
+            ]
+        }"#;
+
+        #[test]
+        fn parse_partitions_reads_fsver_and_fssize() {
+            let parts = parse_partitions(PARTITIONS_FIXTURE.as_bytes(), "/dev/sdb").unwrap();
+            assert_eq!(parts.len(), 2);
+            assert_eq!(parts[0].id, "/dev/sdb1");
+            assert_eq!(parts[0].fsver.as_deref(), Some("FAT32"));
+            assert_eq!(parts[0].fssize, Some(33538048));
+            assert_eq!(parts[1].fstype, "exfat");
+            assert_eq!(parts[1].fsver.as_deref(), Some("1.0"));
+            assert_eq!(parts[1].fssize, Some(61991813120));
+            assert_eq!(parts[1].mountpoints, vec!["/media/data".to_string()]);
+        }
+
+        #[test]
+        fn iso_destination_must_be_a_raidhos_data_mount() {
+            let json = PARTITIONS_FIXTURE.as_bytes();
+            assert!(check_data_mount(json, "/media/data").is_ok());
+            assert!(check_data_mount(json, "/media/data/").is_ok());
+            let err = check_data_mount(json, "/media/data/boot").unwrap_err();
+            assert!(format!("{err}").contains("not the mountpoint"));
+
+            let host = r#"{"blockdevices": [
+                {"name": "sda", "type": "disk", "children": [
+                    {"name": "sda1", "type": "part", "label": "EFI", "fstype": "vfat",
+                     "mountpoints": ["/boot/efi"], "pkname": "sda"},
+                    {"name": "sda2", "type": "part", "label": "root", "fstype": "ext4",
+                     "mountpoints": ["/"], "pkname": "sda"}
+                ]},
+                {"name": "sdb", "type": "disk", "children": [
+                    {"name": "sdb1", "type": "part", "label": "RAIDHOS_EFI", "fstype": "vfat",
+                     "mountpoints": ["/mnt/esp"], "pkname": "sdb"}
+                ]}
+            ]}"#;
+            let err = check_data_mount(host.as_bytes(), "/").unwrap_err();
+            assert!(format!("{err}").contains("not on a RaidhOS stick"));
+            let err = check_data_mount(host.as_bytes(), "/mnt/esp").unwrap_err();
+            assert!(format!("{err}").contains("RaidhOS ESP"));
+        }
+
+        #[test]
+        fn esp_check_rejects_non_fat_and_full_mounts() {
+            let json = r#"{"blockdevices": [
+                {"name": "sdb", "type": "disk", "children": [
+                    {"name": "sdb1", "type": "part", "fstype": "vfat", "fsavail": "33000000",
+                     "mountpoints": ["/mnt/esp"]},
+                    {"name": "sdb2", "type": "part", "fstype": "exfat", "fsavail": 61000000000,
+                     "mountpoints": ["/media/data"]}
+                ]},
+                {"name": "sdc", "type": "disk", "children": [
+                    {"name": "sdc1", "type": "part", "fstype": "vfat", "fsavail": 4096,
+                     "mountpoints": ["/mnt/full"]}
+                ]}
+            ]}"#
+            .as_bytes();
+            assert!(check_esp(json, "/mnt/esp/").is_ok());
+            let err = check_esp(json, "/media/data").unwrap_err();
+            assert!(format!("{err}").contains("holds exfat, not FAT"));
+            let err = check_esp(json, "/mnt/full").unwrap_err();
+            assert!(format!("{err}").contains("4096 bytes free"));
+            let err = check_esp(json, "/mnt/esp/EFI").unwrap_err();
+            assert!(format!("{err}").contains("not the mountpoint"));
+        }
+
+        #[test]
+        fn malformed_lsblk_device_is_skipped_with_a_warning() {
+            let json = r#"{"blockdevices": [
+                {"name": "sda", "model": "Internal SSD", "size": 512110190592, "rm": false,
+                 "type": "disk", "tran": "nvme", "mountpoints": ["/"]},
+                {"name": "sdb", "model": "Odd Bridge", "size": 16008609792, "rm": "yes",
+                 "type": "disk", "tran": "usb", "mountpoints": [null]},
+                {"name": "sdc", "model": "USB Stick", "size": 32015679488, "rm": true,
+                 "type": "disk", "tran": "usb", "mountpoints": [null]}
+            ]}"#
+            .as_bytes();
+            let ids: Vec<String> = parse_disks(json)
+                .unwrap()
+                .into_iter()
+                .map(|d| d.id)
+                .collect();
+            assert_eq!(ids, vec!["/dev/sda".to_string(), "/dev/sdc".to_string()]);
+
+            let listing = diagnose_disks(json).unwrap();
+            assert_eq!(listing.disks.len(), 2);
+            assert_eq!(listing.diagnostic, None);
+            assert_eq!(listing.warnings.len(), 1);
+            assert!(listing.warnings[0].starts_with("skipped lsblk device sdb: "));
+
+            assert!(parse_disks(b"{\"blockdevices\": 3}").is_err());
+            assert!(parse_disks(b"not json").is_err());
+        }
+
+        #[test]
+        fn scan_candidates_skip_system_and_pseudo_mounts() {
+            let mounts = "\
+/dev/nvme0n1p2 / ext4 rw,relatime 0 0
+proc /proc proc rw,nosuid,nodev,noexec,relatime 0 0
+tmpfs /run tmpfs rw,nosuid,nodev 0 0
+/dev/nvme0n1p1 /boot/efi vfat rw,relatime,fmask=0077 0 0
+/dev/nvme0n1p3 /home ext4 rw,relatime 0 0
+/dev/sdb1 /run/media/alex/My\\040Stick vfat rw,nosuid,nodev 0 0
+/dev/sdc1 /media/backup exfat rw,nosuid,nodev 0 0
+/dev/sdc1 /media/backup exfat rw,nosuid,nodev 0 0
+/dev/sdd1 /mnt/ntfs ntfs3 rw,relatime 0 0
+/dev/sde2 /mnt/raidhos-data exfat rw,relatime 0 0
+/dev/sde1 /run/raidhos/scan-sde1 vfat ro,relatime 0 0
+/dev/loop3 /snap/core/123 squashfs ro,nodev 0 0
+";
+            assert_eq!(
+                scan_candidates(mounts),
+                vec![
+                    "/home".to_string(),
+                    "/run/media/alex/My Stick".to_string(),
+                    "/media/backup".to_string(),
+                ]
+            );
+        }
+
+        const DISKS_FIXTURE: &str = r#"{
+            "blockdevices": [
+                {"name": "sda", "model": "Internal SSD", "size": 512110190592, "rm": false,
+                 "type": "disk", "tran": "nvme", "mountpoints": [null],
+                 "children": [
+                    {"name": "sda1", "type": "part", "fstype": "vfat", "mountpoints": ["/boot/efi"]},
+                    {"name": "sda2", "type": "part", "fstype": "ext4", "mountpoints": ["/"]}
+                 ]},
+                {"name": "sdb", "model": "USB Stick", "serial": "4C530001230101115432",
+                 "size": "62008590336", "rm": true,
+                 "type": "disk", "tran": "usb", "mountpoints": [null],
+                 "children": [
+                    {"name": "sdb1", "type": "part", "fstype": "exfat", "mountpoints": [null]}
+                 ]},
+                {"name": "sdc", "model": "USB Busy", "size": 16008609792, "rm": true,
+                 "type": "disk", "tran": "usb", "mountpoints": [null],
+                 "children": [
+                    {"name": "sdc1", "type": "part", "fstype": "vfat", "mountpoints": ["/media/usb"]}
+                 ]},
+                {"name": "sdd", "model": "USB Swap", "serial": "0123456789ABCDEF",
+                 "size": 8004304896, "rm": true,
+                 "type": "disk", "tran": "usb", "mountpoints": [null],
+                 "children": [
+                    {"name": "sdd1", "type": "part", "fstype": "swap", "mountpoints": [null]}
+                 ]},
+                {"name": "sde", "model": "USB LVM", "serial": "0123456789ABCDEF",
+                 "size": 8004304896, "rm": true,
+                 "type": "disk", "tran": "usb", "mountpoints": [null],
+                 "children": [
+                    {"name": "sde1", "type": "part", "fstype": "LVM2_member", "mountpoints": [null]}
+                 ]},
+                {"name": "sdf", "model": "SATA Data", "size": 2000398934016, "rm": false,
+                 "type": "disk", "tran": "sata", "mountpoints": [null]},
+                {"name": "sdg", "model": "USB Enclosure", "size": 1000204886016, "rm": false,
+                 "type": "disk", "tran": "usb", "mountpoints": [null]},
+                {"name": "loop0", "size": 4096, "rm": false, "type": "loop", "mountpoints": [null]}
+            ]
+        }"#;
+
+        #[test]
+        fn disks_tree_nests_partitions_under_their_disk() {
+            let tree = parse_disks_tree(PARTITIONS_FIXTURE.as_bytes()).unwrap();
+            assert_eq!(tree.len(), 1);
+            assert_eq!(tree[0].disk.id, "/dev/sdb");
+            assert_eq!(tree[0].disk.mountpoints, vec!["/media/data".to_string()]);
+            let ids: Vec<&str> = tree[0].partitions.iter().map(|p| p.id.as_str()).collect();
+            assert_eq!(ids, vec!["/dev/sdb1", "/dev/sdb2"]);
+            assert_eq!(tree[0].partitions[1].label, "DATA");
+        }
+
+        #[test]
+        fn disks_tree_nests_holders_under_their_partition() {
+            let json = r#"{"blockdevices": [
+                {"name": "sdc", "type": "disk", "mountpoints": [null], "pkname": null,
+                 "children": [
+                    {"name": "sdc1", "type": "part", "fstype": "vfat",
+                     "mountpoints": ["/boot/efi"], "pkname": "sdc"},
+                    {"name": "sdc2", "type": "part", "fstype": "crypto_LUKS",
+                     "mountpoints": [null], "pkname": "sdc",
+                     "children": [
+                        {"name": "luks-5f2a", "type": "crypt", "fstype": "LVM2_member",
+                         "mountpoints": [null], "pkname": "sdc2",
+                         "children": [
+                            {"name": "vg0-root", "type": "lvm", "fstype": "ext4",
+                             "mountpoints": ["/"], "pkname": "luks-5f2a"},
+                            {"name": "vg0-swap", "type": "lvm", "fstype": "swap",
+                             "mountpoints": ["[SWAP]"], "pkname": "luks-5f2a"}
+                         ]}
+                     ]}
+                 ]}
+            ]}"#;
+            let tree = parse_disks_tree(json.as_bytes()).unwrap();
+            let ids: Vec<&str> = tree[0].partitions.iter().map(|p| p.id.as_str()).collect();
+            assert_eq!(ids, vec!["/dev/sdc1", "/dev/sdc2"]);
+            assert!(tree[0].holders.is_empty());
+            assert!(tree[0].partitions[0].holders.is_empty());
+
+            let crypt = &tree[0].partitions[1].holders;
+            assert_eq!(crypt.len(), 1);
+            assert_eq!(crypt[0].name, "luks-5f2a");
+            assert_eq!(crypt[0].kind, "crypt");
+            assert!(crypt[0].mountpoints.is_empty());
+            let lvm: Vec<(&str, &str, Vec<String>)> = crypt[0]
+                .holders
+                .iter()
+                .map(|h| (h.name.as_str(), h.kind.as_str(), h.mountpoints.clone()))
+                .collect();
+            assert_eq!(
+                lvm,
+                vec![
+                    ("vg0-root", "lvm", vec!["/".to_string()]),
+                    ("vg0-swap", "lvm", vec!["[SWAP]".to_string()]),
+                ]
+            );
+            assert!(crypt[0].holders.iter().all(|h| h.holders.is_empty()));
+        }
+
+        #[test]
+        fn data_label_is_read_next_to_esp() {
+            let fixture = PARTITIONS_FIXTURE.replace(
+                r#""label": "RAIDHOS_EFI", "fstype": "vfat",
+                     "fsver": "FAT32", "fssize": 33538048, "mountpoints": [null]"#,
+                r#""label": "RAIDHOS_EFI", "fstype": "vfat",
+                     "fsver": "FAT32", "fssize": 33538048, "mountpoints": ["/media/esp"]"#,
+            )
+            .replace(r#""label": "DATA""#, r#""label": "ISOSTICK""#);
+            let label = parse_data_label(fixture.as_bytes(), "/media/esp/").unwrap();
+            assert_eq!(label.as_deref(), Some("ISOSTICK"));
+            let cfg = crate::render_grub_cfg(&crate::BootConfig::default(), &label.unwrap());
+            assert!(cfg.contains("search --no-floppy --label ISOSTICK --set=root"));
+            assert_eq!(parse_data_label(fixture.as_bytes(), "/mnt/other").unwrap(), None);
+        }
+
+        #[test]
+        fn null_size_is_unknown_and_only_warns() {
+            let json = r#"{"blockdevices": [
+                {"name": "sdh", "model": "Card Reader", "size": null, "rm": true,
+                 "type": "disk", "tran": "usb", "mountpoints": [null]},
+                {"name": "sdi", "model": "Empty", "size": 0, "rm": true,
+                 "type": "disk", "tran": "usb", "mountpoints": [null]}
+            ]}"#;
+            let disks = parse_disks(json.as_bytes()).unwrap();
+            assert_eq!(disks[0].size_bytes, None);
+            assert_eq!(disks[1].size_bytes, Some(0));
+
+            let sink = Sink { events: std::cell::RefCell::new(Vec::new()) };
+            validate_install(&req("/dev/sdh", true, true), &sink, &disks).unwrap();
+            assert!(sink
+                .events
+                .borrow()
+                .iter()
+                .any(|e| e.message.contains("size of /dev/sdh is unknown")));
+
+            let err = validate_install(&req("/dev/sdi", true, true), &sink, &disks).unwrap_err();
+            assert!(format!("{err}").contains("too small"));
+        }
+
+        #[test]
+        fn empty_lsblk_output_is_diagnosed() {
+            let listing = diagnose_disks(br#"{"blockdevices": []}"#).unwrap();
+            assert!(listing.disks.is_empty());
+            assert!(listing.diagnostic.unwrap().contains("could not enumerate"));
+
+            let loops = r#"{"blockdevices": [
+                {"name": "loop0", "type": "loop", "mountpoints": ["/snap/core/1"]}
+            ]}"#;
+            let listing = diagnose_disks(loops.as_bytes()).unwrap();
+            assert_eq!(
+                listing.diagnostic.as_deref(),
+                Some("lsblk found 1 block devices but no disks")
+            );
+
+            let listing = diagnose_disks(DISKS_FIXTURE.as_bytes()).unwrap();
+            assert!(!listing.disks.is_empty());
+            assert_eq!(listing.diagnostic, None);
+        }
+
+        #[test]
+        fn require_empty_rejects_partitioned_disks() {
+            let disks = parse_disks(DISKS_FIXTURE.as_bytes()).unwrap();
+            let sink = Sink { events: std::cell::RefCell::new(Vec::new()) };
+            let target = disks
+                .iter()
+                .find(|d| !d.partitions.is_empty() && !d.is_system)
+                .unwrap();
+            let mut request = req(&target.id, true, true);
+            validate_install(&request, &sink, &disks).unwrap();
+
+            request.require_empty = true;
+            let err = validate_install(&request, &sink, &disks).unwrap_err();
+            assert!(format!("{err}").contains(&target.partitions.join(", ")));
+
+            let empty = disks.iter().find(|d| d.partitions.is_empty()).unwrap();
+            let mut request = req(&empty.id, true, true);
+            request.require_empty = true;
+            validate_install(&request, &sink, &disks).unwrap();
+        }
+
+        #[test]
+        fn internal_disk_warns_during_validation() {
+            let mut internal = disk("/dev/sdc", vec![], false);
+            internal.removable = false;
+            internal.external = false;
+            internal.transport = Some("sata".to_string());
+            let sink = Sink { events: std::cell::RefCell::new(Vec::new()) };
+            validate_install(&req("/dev/sdc", true, true), &sink, &[internal]).unwrap();
+            assert!(sink
+                .events
+                .borrow()
+                .iter()
+                .any(|e| e.message.starts_with("Warning: /dev/sdc is an internal sata disk")));
+        }
+
+        #[test]
+        fn usb_bridge_with_rm_unset_is_external() {
+            let disks = parse_disks(DISKS_FIXTURE.as_bytes()).unwrap();
+            let bridge = disks.iter().find(|d| d.id == "/dev/sdg").unwrap();
+            assert!(!bridge.removable);
+            assert!(bridge.external);
+            let sata = disks.iter().find(|d| d.id == "/dev/sdf").unwrap();
+            assert!(!sata.external);
+
+            assert!(is_external(false, None, Some(true)));
+            assert!(!is_external(false, Some("sata"), Some(false)));
+        }
+
+        #[test]
+        fn partition_count_walks_nested_children() {
+            let json = r#"{"blockdevices": [
+                {"name": "sdb", "size": 8004304896, "rm": true, "type": "disk",
+                 "children": [
+                    {"name": "sdb1", "type": "part"},
+                    {"name": "sdb2", "type": "part", "children": [
+                        {"name": "luks-data", "type": "crypt"}
+                    ]},
+                    {"name": "sdb3", "type": "part"}
+                 ]},
+                {"name": "sdc", "size": 8004304896, "rm": true, "type": "disk"}
+            ]}"#;
+            let disks = parse_disks(json.as_bytes()).unwrap();
+            assert_eq!(disks[0].partition_count, 3);
+            assert_eq!(disks[0].partitions, vec!["/dev/sdb1", "/dev/sdb2", "/dev/sdb3"]);
+            assert_eq!(disks[1].partition_count, 0);
+            let disks = parse_disks(DISKS_FIXTURE.as_bytes()).unwrap();
+            assert_eq!(disks[0].partition_count, 2);
+        }
+
+        #[test]
+        fn parse_disks_skips_non_disks() {
+            let disks = parse_disks(DISKS_FIXTURE.as_bytes()).unwrap();
+            assert_eq!(disks.len(), 7);
+            assert!(disks[0].is_system);
+            assert_eq!(disks[1].size_bytes, Some(62008590336));
+            assert_eq!(disks[1].transport.as_deref(), Some("usb"));
+        }
+
+        #[test]
+        fn serial_resolves_to_exactly_one_disk() {
+            let disks = parse_disks(DISKS_FIXTURE.as_bytes()).unwrap();
+            let stick = crate::find_disk_by_serial(&disks, "4C530001230101115432").unwrap();
+            assert_eq!(stick.id, "/dev/sdb");
+            assert_eq!(disks[0].serial, None);
+
+            let err = crate::find_disk_by_serial(&disks, "0123456789ABCDEF").unwrap_err();
+            assert!(err.to_string().contains("/dev/sdd, /dev/sde"), "{err}");
+            let err = crate::find_disk_by_serial(&disks, "missing").unwrap_err();
+            assert!(err.to_string().contains("no disk has serial"), "{err}");
+        }
+
+        #[test]
+        fn install_targets_keep_only_safe_disks() {
+            let targets = parse_install_targets(DISKS_FIXTURE.as_bytes()).unwrap();
+            let ids: Vec<&str> = targets.iter().map(|d| d.id.as_str()).collect();
+            assert_eq!(ids, vec!["/dev/sdb", "/dev/sdg"]);
+        }
+
+        #[test]
+        fn public_types_round_trip_through_json() {
+            let disks = parse_disks(DISKS_FIXTURE.as_bytes()).unwrap();
+            let json = serde_json::to_string(&disks[1]).unwrap();
+            assert!(json.contains("\"size_bytes\":62008590336"), "{json}");
+            let back: DiskInfo = serde_json::from_str(&json).unwrap();
+            assert_eq!(back.id, disks[1].id);
+            assert_eq!(back.serial, disks[1].serial);
+
+            let parts = parse_partitions(PARTITIONS_FIXTURE.as_bytes(), "/dev/sdb").unwrap();
+            let back: Vec<PartitionInfo> =
+                serde_json::from_str(&serde_json::to_string(&parts).unwrap()).unwrap();
+            assert_eq!(back.len(), parts.len());
+            assert_eq!(back[0].label, parts[0].label);
+
+            let mut request = req("/dev/sdb", true, false);
+            request.arch = Arch::Aarch64;
+            request.options.layout = Layout::DataOnly;
+            request.options.mode = InstallMode::FreeSpace {
+                start_mib: 1024,
+                size_mib: 4096,
+            };
+            let json = serde_json::to_string(&request).unwrap();
+            assert!(json.contains("\"arch\":\"aarch64\""), "{json}");
+            assert!(json.contains("\"layout\":\"data_only\""), "{json}");
+            assert!(!json.contains("runner"), "{json}");
+            let back: InstallRequest = serde_json::from_str(&json).unwrap();
+            assert_eq!(back.device, "/dev/sdb");
+            assert_eq!(back.arch, Arch::Aarch64);
+            assert_eq!(back.options.mode, request.options.mode);
+            assert!(!back.options.cancel.is_cancelled());
+
+            // Options left out take their defaults.
+            let json = r#"{"device": "/dev/sdb", "payload_version": "1.1.10", "wipe": true,
+                "dry_run": true, "allow_write": false, "arch": "x86_64", "payload_dir": null,
+                "require_empty": false}"#;
+            let minimal: InstallRequest = serde_json::from_str(json).unwrap();
+            assert!(minimal.options.quick_format);
+            assert_eq!(minimal.options.partition_types, PartitionTypes::default());
+
+            let event = ProgressEvent {
+                phase: "format".into(),
+                message: "ESP".into(),
+                percent: Some(40),
+                ..Default::default()
+            };
+            let back: ProgressEvent =
+                serde_json::from_str(&serde_json::to_string(&event).unwrap()).unwrap();
+            assert_eq!((back.phase_id(), back.percent), (PhaseId::Format, Some(40)));
+        }
+
+        fn payload_fixture(name: &str) -> PathBuf {
+            let dir = std::env::temp_dir().join(format!("raidhos-{name}-{}", std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(dir.join("esp")).unwrap();
+            fs::create_dir_all(dir.join("data")).unwrap();
+            dir
+        }
+
+        #[test]
+        fn cancelling_mid_run_stops_before_formatting() {
+            struct CancelOnFormat {
+                token: CancelToken,
+                events: std::cell::RefCell<Vec<ProgressEvent>>,
+            }
+            impl ProgressSink for CancelOnFormat {
+                fn emit(&self, event: ProgressEvent) {
+                    if event.phase == "format" {
+                        self.token.cancel();
+                    }
+                    self.events.borrow_mut().push(event);
+                }
+            }
+
+            let payload = payload_fixture("cancel-payload");
+            let mut request = req("/dev/sdb", true, false);
+            request.allow_write = true;
+            request.payload_dir = Some(payload.display().to_string());
+            let sink = CancelOnFormat {
+                token: request.options.cancel.clone(),
+                events: std::cell::RefCell::new(Vec::new()),
+            };
+            let disks = vec![disk("/dev/sdb", vec![], false)];
+
+            take_run_log();
+            let err = install_with_disks(request, &sink, &disks, Instant::now()).unwrap_err();
+            assert!(matches!(err, CoreError::Cancelled));
+            let log = take_run_log();
+            assert!(log.iter().any(|c| c.starts_with("parted")));
+            assert!(!log.iter().any(|c| c.starts_with("mkfs")));
+            assert_eq!(sink.events.borrow().last().unwrap().phase, "cancelled");
+            let _ = fs::remove_dir_all(payload);
+        }
+
+        #[test]
+        fn install_cancelled_up_front_runs_no_commands() {
+            let payload = payload_fixture("cancel-early-payload");
+            let mut request = req("/dev/sdb", true, false);
+            request.allow_write = true;
+            request.payload_dir = Some(payload.display().to_string());
+            request.options.cancel.cancel();
+            let sink = Sink {
+                events: std::cell::RefCell::new(Vec::new()),
+            };
+            let disks = vec![disk("/dev/sdb", vec![], false)];
+
+            take_run_log();
+            let err = install_with_disks(request, &sink, &disks, Instant::now()).unwrap_err();
+            assert!(matches!(err, CoreError::Cancelled));
+            assert_eq!(take_run_log(), Vec::<String>::new());
+            let events = sink.events.borrow();
+            assert_eq!(events.last().unwrap().phase, "cancelled");
+            assert_eq!(events.iter().filter(|e| e.phase == "cancelled").count(), 1);
+            let _ = fs::remove_dir_all(payload);
+        }
+
+        /// Cancels `token` while running the first command starting with
+        /// `cancel_at`, the way Ctrl-C lands during a long `mkfs`.
+        #[derive(Debug)]
+        struct CancellingRunner {
+            cancel_at: &'static str,
+            token: CancelToken,
+            commands: std::sync::Mutex<Vec<String>>,
+        }
+
+        impl CommandRunner for CancellingRunner {
+            fn run(&self, cmd: &str, args: &[&str]) -> Result<runner::CommandStatus> {
+                let line = [&[cmd], args].concat().join(" ");
+                if line.starts_with(self.cancel_at) {
+                    self.token.cancel();
+                }
+                self.commands.lock().unwrap().push(line);
+                Ok(runner::CommandStatus::success())
+            }
+
+            fn has_cmd(&self, _cmd: &str) -> bool {
+                true
+            }
+
+            fn node_exists(&self, _node: &str) -> bool {
+                true
+            }
+
+            fn read_mbr(&self, _device: &str) -> Result<Vec<u8>> {
+                Ok(mbr_fixture(&[MBR_GPT_TYPE]))
+            }
+        }
+
+        /// Reports every command as killed by a signal, cancelling `cancel`
+        /// first the way a Ctrl-C handler would.
+        #[derive(Debug)]
+        struct KilledRunner {
+            cancel: Option<CancelToken>,
+        }
+
+        impl CommandRunner for KilledRunner {
+            fn run(&self, _cmd: &str, _args: &[&str]) -> Result<runner::CommandStatus> {
+                if let Some(token) = &self.cancel {
+                    token.cancel();
+                }
+                Ok(runner::CommandStatus {
+                    code: None,
+                    stderr: String::new(),
+                })
+            }
+
+            fn has_cmd(&self, _cmd: &str) -> bool {
+                true
+            }
+
+            fn node_exists(&self, _node: &str) -> bool {
+                true
+            }
+        }
+
+        #[test]
+        fn signal_killed_command_is_a_cancel_once_cancelled() {
+            let token = CancelToken::new();
+            let _cancel = ActiveCancel::start(&token);
+            let run_killed = |cancel: Option<CancelToken>| {
+                let runner: std::sync::Arc<dyn CommandRunner> =
+                    std::sync::Arc::new(KilledRunner { cancel });
+                let _runner = ActiveRunner::start(&runner);
+                run("mkfs.vfat", &["-F", "32", "/dev/sdb1"]).unwrap_err()
+            };
+
+            let err = run_killed(None);
+            assert!(
+                matches!(err, CoreError::Command { code: None, .. }),
+                "{err:?}"
+            );
+            let err = run_killed(Some(token.clone()));
+            assert!(matches!(err, CoreError::Cancelled), "{err:?}");
+        }
+
+        #[test]
+        fn cancel_during_a_command_stops_before_the_next_one() {
+            let payload = payload_fixture("cancel-command-payload");
+            let mut request = req("/dev/sdb", true, false);
+            request.allow_write = true;
+            request.payload_dir = Some(payload.display().to_string());
+            let runner = std::sync::Arc::new(CancellingRunner {
+                cancel_at: "mkfs.vfat",
+                token: request.options.cancel.clone(),
+                commands: std::sync::Mutex::new(Vec::new()),
+            });
+            request.options.runner = Some(runner.clone());
+            let sink = Sink {
+                events: std::cell::RefCell::new(Vec::new()),
+            };
+            let disks = vec![disk("/dev/sdb", vec![], false)];
+
+            let err = install_with_disks(request, &sink, &disks, Instant::now()).unwrap_err();
+            assert!(matches!(err, CoreError::Cancelled));
+            let commands = runner.commands.lock().unwrap();
+            assert!(
+                commands.last().unwrap().starts_with("mkfs.vfat"),
+                "{commands:?}"
+            );
+            assert!(!commands.iter().any(|c| c.starts_with("mkfs.exfat")));
+            assert_eq!(sink.events.borrow().last().unwrap().phase, "cancelled");
+            let _ = fs::remove_dir_all(payload);
+        }
+
+        #[test]
+        fn dry_run_lists_the_commands_it_would_run() {
+            let payload = payload_fixture("plan-payload");
+            let mut request = req("/dev/sdb", true, true);
+            request.payload_dir = Some(payload.display().to_string());
+            let sink = Sink {
+                events: std::cell::RefCell::new(Vec::new()),
+            };
+            let disks = vec![disk("/dev/sdb", vec![], false)];
+
+            take_run_log();
+            let outcome = install_with_disks(request, &sink, &disks, Instant::now()).unwrap();
+            assert!(outcome.dry_run);
+            assert!(take_run_log().is_empty());
+            let plan: Vec<String> = sink
+                .events
+                .borrow()
+                .iter()
+                .filter(|e| e.phase_id() == PhaseId::Plan)
+                .map(|e| e.message.clone())
+                .collect();
+            let at = |line: &str| {
+                plan.iter()
+                    .position(|c| c == line)
+                    .unwrap_or_else(|| panic!("{line:?} not planned: {plan:#?}"))
+            };
+            let mklabel = at("parted /dev/sdb -s mklabel gpt");
+            let vfat = at("mkfs.vfat -F 32 -n RAIDHOS_EFI /dev/sdb1");
+            let exfat = at("mkfs.exfat -n DATA /dev/sdb2");
+            let mount = at("mount /dev/sdb1 /mnt/raidhos-esp");
+            assert!(mklabel < vfat && vfat < exfat && exfat < mount);
+            // The payload is copied in-process, between the mounts and umounts.
+            assert!(mount < at("umount /mnt/raidhos-data"));
+            assert!(!plan.iter().any(|c| c.starts_with("cp ")));
+            // The fixture payload has no GRUB binary, so grub-install runs.
+            assert!(plan.iter().any(|c| c.starts_with("grub-install")));
+            assert_eq!(sink.events.borrow().last().unwrap().phase, "complete");
+            let _ = fs::remove_dir_all(payload);
+        }
+
+        #[test]
+        fn data_only_plan_on_a_partition_only_formats_it() {
+            let mut request = req("/dev/sdb2", false, true);
+            request.options.layout = Layout::DataOnly;
+            request.options.mode = InstallMode::Partition { esp: None };
+            request.options.data_label = Some("ISOS".to_string());
+            let plan = plan_commands(&request, &[], None, &mut Vec::new()).unwrap();
+            assert_eq!(plan, vec!["mkfs.exfat -n ISOS /dev/sdb2"]);
+        }
+
+        /// Finds every tool but the ones listed.
+        #[derive(Debug)]
+        struct MissingTools(&'static [&'static str]);
+
+        impl CommandRunner for MissingTools {
+            fn run(&self, _cmd: &str, _args: &[&str]) -> Result<runner::CommandStatus> {
+                Ok(runner::CommandStatus::success())
+            }
+
+            fn has_cmd(&self, cmd: &str) -> bool {
+                !self.0.contains(&cmd)
+            }
+
+            fn node_exists(&self, _node: &str) -> bool {
+                true
+            }
+        }
+
+        #[test]
+        fn dry_run_without_an_exfat_formatter_plans_with_a_warning() {
+            let mut request = req("/dev/sdb", true, true);
+            request.options.layout = Layout::DataOnly;
+            request.options.runner = Some(std::sync::Arc::new(MissingTools(EXFAT_FORMATTERS)));
+            let sink = Sink {
+                events: std::cell::RefCell::new(Vec::new()),
+            };
+            let disks = vec![disk("/dev/sdb", vec![], false)];
+
+            install_with_disks(request, &sink, &disks, Instant::now()).unwrap();
+            let events = sink.events.borrow();
+            let plan = events.iter().filter(|e| e.phase == "plan");
+            let mkfs: Vec<&str> = plan
+                .map(|e| e.message.as_str())
+                .filter(|command| command.starts_with("mkfs"))
+                .collect();
+            assert_eq!(mkfs, vec!["mkfs.exfat -n DATA /dev/sdb1"]);
+            let warning = events
+                .iter()
+                .find(|e| e.message.starts_with("Warning: exFAT formatter not found"));
+            assert!(warning.is_some(), "{events:?}");
+        }
+
+        #[test]
+        fn data_fstype_picks_the_planned_mkfs_command() {
+            let mut request = req("/dev/sdb2", false, true);
+            request.options.layout = Layout::DataOnly;
+            request.options.mode = InstallMode::Partition { esp: None };
+            let plan = |request: &InstallRequest| {
+                plan_commands(request, &[], None, &mut Vec::new()).unwrap()
+            };
+            for (fs, quick, full) in [
+                (
+                    DataFsType::ExFat,
+                    "mkfs.exfat -n DATA /dev/sdb2",
+                    "mkfs.exfat --full-format -n DATA /dev/sdb2",
+                ),
+                (
+                    DataFsType::Ext4,
+                    "mkfs.ext4 -F -L DATA /dev/sdb2",
+                    "mkfs.ext4 -F -L DATA -c /dev/sdb2",
+                ),
+                (
+                    DataFsType::Ntfs,
+                    "mkfs.ntfs -L DATA --quick /dev/sdb2",
+                    "mkfs.ntfs -L DATA /dev/sdb2",
+                ),
+            ] {
+                request.options.data_fstype = fs;
+                request.options.quick_format = true;
+                assert_eq!(plan(&request), vec![quick]);
+                take_run_log();
+                format_data("/dev/sdb2", &request.options).unwrap();
+                assert_eq!(take_run_log(), vec![quick]);
+                request.options.quick_format = false;
+                assert_eq!(plan(&request), vec![full]);
+            }
+        }
+
+        #[test]
+        fn ensure_layout_reformats_data_of_another_filesystem() {
+            let parts = vec![
+                part("/dev/sdb1", ESP_LABEL, "vfat"),
+                part("/dev/sdb2", DATA_LABEL, "exfat"),
+            ];
+            assert_eq!(
+                plan_layout(&parts, Layout::BootAndData, DataFsType::Ext4, DATA_LABEL),
+                vec![LayoutChange::FormatData, LayoutChange::RefreshPayload]
+            );
+            let parts = vec![
+                part("/dev/sdb1", ESP_LABEL, "vfat"),
+                part("/dev/sdb2", "OLD", "ntfs"),
+            ];
+            assert_eq!(
+                plan_layout(&parts, Layout::BootAndData, DataFsType::Ntfs, DATA_LABEL),
+                vec![LayoutChange::RelabelData, LayoutChange::RefreshPayload]
+            );
+        }
+
+        #[test]
+        fn failure_after_partitioning_rolls_back_the_mounts() {
+            let payload = payload_fixture("rollback-payload");
+            let mut request = req("/dev/sdb", true, false);
+            request.allow_write = true;
+            request.payload_dir = Some(payload.display().to_string());
+            let sink = Sink {
+                events: std::cell::RefCell::new(Vec::new()),
+            };
+            let disks = vec![disk("/dev/sdb", vec![], false)];
+
+            // The ESP is still mounted from an earlier, interrupted run.
+            PROC_MOUNTS
+                .with(|m| *m.borrow_mut() = "/dev/sdb1 /mnt/raidhos-esp vfat rw 0 0\n".to_string());
+            fail_commands_starting_with(Some("mkfs"));
+            take_run_log();
+            let err = install_with_disks(request, &sink, &disks, Instant::now()).unwrap_err();
+            fail_commands_starting_with(None);
+            PROC_MOUNTS.with(|m| m.borrow_mut().clear());
+
+            assert!(
+                err.to_string().contains("command failed: mkfs.vfat"),
+                "{err}"
+            );
+            let log = take_run_log();
+            assert!(log.iter().any(|c| c.starts_with("parted")));
+            assert_eq!(log.last().unwrap(), "umount /mnt/raidhos-esp");
+            assert!(!log.iter().any(|c| c == "umount /mnt/raidhos-data"));
+            let events = sink.events.borrow();
+            let rollback = events.last().unwrap();
+            assert_eq!(rollback.phase_id(), PhaseId::Rollback);
+            assert!(
+                rollback.message.contains("unmounting /mnt/raidhos-esp"),
+                "{}",
+                rollback.message
+            );
+            let _ = fs::remove_dir_all(payload);
+        }
+
+        #[test]
+        fn failure_before_partitioning_needs_no_rollback() {
+            let mut request = req("/dev/sdb", true, false);
+            request.allow_write = true;
+            request.payload_dir = Some("/nonexistent/raidhos-payload".to_string());
+            let sink = Sink {
+                events: std::cell::RefCell::new(Vec::new()),
+            };
+            let disks = vec![disk("/dev/sdb", vec![], false)];
+
+            take_run_log();
+            assert!(install_with_disks(request, &sink, &disks, Instant::now()).is_err());
+            assert!(take_run_log().is_empty());
+            assert!(!sink.events.borrow().iter().any(|e| e.phase == "rollback"));
+        }
+
+        #[test]
+        fn rollback_keeps_mounts_when_asked() {
+            let mounts = "/dev/sdb2 /mnt/raidhos-data exfat rw 0 0\n\
+                          /dev/sda1 /boot/efi vfat rw 0 0\n\
+                          /dev/sdb1 /mnt/raidhos-esp vfat rw 0 0\n";
+            assert_eq!(
+                mounted_payload_mounts(mounts),
+                vec!["/mnt/raidhos-esp", "/mnt/raidhos-data"]
+            );
+
+            let options = InstallOptions {
+                keep_mounts_on_error: true,
+                ..Default::default()
+            };
+            let sink = Sink {
+                events: std::cell::RefCell::new(Vec::new()),
+            };
+            PROC_MOUNTS.with(|m| *m.borrow_mut() = mounts.to_string());
+            take_run_log();
+            let err = CoreError::Command {
+                cmd: "grub-install".to_string(),
+                code: Some(1),
+                stderr: String::new(),
+            };
+            rollback(&sink, &options, &err, &SystemMounter);
+            assert!(take_run_log().is_empty());
+            // Cancelling always releases the mounts.
+            rollback(&sink, &options, &CoreError::Cancelled, &SystemMounter);
+            PROC_MOUNTS.with(|m| m.borrow_mut().clear());
+            assert_eq!(
+                take_run_log(),
+                vec!["umount /mnt/raidhos-esp", "umount /mnt/raidhos-data"]
+            );
+            let events = sink.events.borrow();
+            assert!(events[0].message.ends_with("mounts kept for inspection."));
+            assert_eq!(events[1].phase, "rollback");
+        }
+
+        #[test]
+        fn data_only_plan_has_single_partition_and_no_esp() {
+            let mut request = req("/dev/sdb", true, false);
+            request.allow_write = true;
+            request.options.layout = Layout::DataOnly;
+            let sink = Sink { events: std::cell::RefCell::new(Vec::new()) };
+            let disks = vec![disk("/dev/sdb", vec![], false)];
+
+            take_run_log();
+            let outcome = install_with_disks(request, &sink, &disks, Instant::now()).unwrap();
+            assert_eq!(outcome.changes, DATA_ONLY_LAYOUT.to_vec());
+            let log = take_run_log();
+            assert_eq!(log.iter().filter(|c| c.contains(" mkpart ")).count(), 1);
+            assert!(log.contains(&"mkfs.exfat -n DATA /dev/sdb1".to_string()));
+            assert!(!log.iter().any(|c| c.starts_with("mkfs.vfat") || c.starts_with("grub")));
+        }
+
+        #[test]
+        fn data_only_rejects_bootloader_options() {
+            let sink = Sink { events: std::cell::RefCell::new(Vec::new()) };
+            let disks = vec![disk("/dev/sdb", vec![], false)];
+            let mut request = req("/dev/sdb", true, true);
+            request.options.layout = Layout::DataOnly;
+            request.options.esp_mkfs_args = vec!["-s".to_string(), "8".to_string()];
+            assert!(validate_install(&request, &sink, &disks).is_err());
+            request.options.esp_mkfs_args.clear();
+            request.payload_dir = Some("/srv/payload".to_string());
+            let err = validate_install(&request, &sink, &disks).unwrap_err();
+            assert!(format!("{err}").contains("data-only"));
+        }
+
+        #[test]
+        fn tool_versions_keep_the_first_line_of_each_tool() {
+            assert_eq!(
+                tool_versions(),
+                ToolVersions {
+                    parted: Some("parted (GNU parted) 3.5".to_string()),
+                    mkfs_vfat: Some("mkfs.fat 4.2 (2021-01-31)".to_string()),
+                    exfat: Some("exfatprogs version : 1.2.0".to_string()),
+                    grub_install: None,
+                }
+            );
+        }
+
+        #[cfg(feature = "test-hooks")]
+        #[test]
+        fn injected_failures_stop_the_install_and_release_mounts() {
+            let payload = payload_fixture("hook-payload");
+            let mut request = req("/dev/sdb", true, false);
+            request.allow_write = true;
+            request.payload_dir = Some(payload.display().to_string());
+            let sink = Sink { events: std::cell::RefCell::new(Vec::new()) };
+            let disks = vec![disk("/dev/sdb", vec![], false)];
+
+            take_run_log();
+            install_with_disks(request.clone(), &sink, &disks, Instant::now()).unwrap();
+            let clean = take_run_log();
+            let index_of = |prefix: &str| clean.iter().position(|c| c.starts_with(prefix)).unwrap();
+
+            // Format phase: nothing runs after the failing mkfs.
+            crate::test_hooks::fail_command_at(index_of("mkfs.vfat"), "mkfs crashed");
+            let err =
+                install_with_disks(request.clone(), &sink, &disks, Instant::now()).unwrap_err();
+            assert!(matches!(err, CoreError::Io(m) if m == "mkfs crashed"));
+            let log = take_run_log();
+            assert_eq!(log, clean[..index_of("mkfs.vfat")]);
+
+            // Payload phase: both mounts are released.
+            crate::test_hooks::fail_command_at(index_of("grub-install"), "grub-install failed");
+            assert!(install_with_disks(request, &sink, &disks, Instant::now()).is_err());
+            let log = take_run_log();
+            assert_eq!(
+                &log[log.len() - 2..],
+                ["umount /mnt/raidhos-esp", "umount /mnt/raidhos-data"]
+            );
+            let _ = fs::remove_dir_all(payload);
+        }
+
+        #[test]
+        fn free_space_install_adds_partitions_without_mklabel() {
+            let payload = payload_fixture("free-space-payload");
+            let mut request = req("/dev/sdb", false, false);
+            request.allow_write = true;
+            request.payload_dir = Some(payload.display().to_string());
+            request.options.mode = InstallMode::FreeSpace {
+                start_mib: 10240,
+                size_mib: 4096,
+            };
+            let sink = Sink { events: std::cell::RefCell::new(Vec::new()) };
+            let disks = vec![disk("/dev/sdb", vec![], false)];
+
+            take_run_log();
+            install_with_disks(request, &sink, &disks, Instant::now()).unwrap();
+            let log = take_run_log();
+            assert!(!log.iter().any(|c| c.contains("mklabel")));
+            let parted: Vec<&String> = log.iter().filter(|c| c.starts_with("parted")).collect();
+            assert_eq!(
+                parted,
+                [
+                    "parted /dev/sdb -s mkpart primary fat32 10240MiB 10272MiB",
+                    "parted /dev/sdb -s set 2 esp on",
+                    "parted /dev/sdb -s mkpart primary 10272MiB 14336MiB",
+                    "parted /dev/sdb -s print",
+                ]
+            );
+            assert!(log.contains(&format!("mkfs.vfat -F 32 -n {ESP_LABEL} /dev/sdb2")));
+            assert!(log.contains(&"mkfs.exfat -n DATA /dev/sdb4".to_string()));
+            let _ = fs::remove_dir_all(payload);
+        }
+
+        #[test]
+        fn partition_target_skips_partitioning() {
+            let payload = payload_fixture("partition-target-payload");
+            let mut request = req("/dev/sdb2", true, false);
+            request.allow_write = true;
+            request.payload_dir = Some(payload.display().to_string());
+            request.options.mode = InstallMode::Partition {
+                esp: Some("/dev/sdb1".to_string()),
+            };
+            let sink = Sink { events: std::cell::RefCell::new(Vec::new()) };
+            let mut sdb = disk("/dev/sdb", vec![], false);
+            sdb.partitions = vec!["/dev/sdb1".to_string(), "/dev/sdb2".to_string()];
+            let disks = vec![sdb, disk("/dev/sdc", vec![], false)];
+
+            take_run_log();
+            let started = Instant::now();
+            let outcome = install_with_disks(request.clone(), &sink, &disks, started).unwrap();
+            let log = take_run_log();
+            let partitioned = |c: &String| c.starts_with("parted") || c.starts_with("sgdisk");
+            assert!(!log.iter().any(partitioned));
+            assert!(log.contains(&format!("mkfs.vfat -F 32 -n {ESP_LABEL} /dev/sdb1")));
+            assert!(log.contains(&"mkfs.exfat -n DATA /dev/sdb2".to_string()));
+            assert!(!outcome.changes.contains(&LayoutChange::Partition));
+
+            let err = |request: &InstallRequest| {
+                let result = validate_install(request, &sink, &disks);
+                result.unwrap_err().to_string()
+            };
+            let mut other = request.clone();
+            other.options.mode = InstallMode::Partition {
+                esp: Some("/dev/sdc1".to_string()),
+            };
+            assert!(err(&other).contains("must be another partition on /dev/sdb"));
+            other.device = "/dev/sdc".to_string();
+            assert!(err(&other).contains("/dev/sdc is not a partition"));
+            other.options.mode = InstallMode::Partition { esp: None };
+            assert!(err(&other).contains("needs an ESP partition"));
+            let mut wipe = request;
+            wipe.options.mode = InstallMode::Wipe;
+            assert!(err(&wipe).contains("/dev/sdb2 is a partition of /dev/sdb"));
+            let _ = fs::remove_dir_all(payload);
+        }
+
+        #[test]
+        fn free_space_region_must_be_free_and_inside_the_disk() {
+            let existing = parse_extents(EXTENTS_FIXTURE.as_bytes(), "/dev/sdb").unwrap();
+            assert_eq!(existing[1].number, 3);
+            let plan = |start, size| {
+                let options = InstallOptions {
+                    layout: Layout::DataOnly,
+                    ..Default::default()
+                };
+                plan_free_space(start, size, &options, Some(16 << 30), &existing)
+            };
+            let err = |start, size| plan(start, size).unwrap_err().to_string();
+            assert_eq!(plan(10240, 5120).unwrap().data.number, 2);
+            assert!(err(10000, 1024).contains("overlaps partition 1"));
+            assert!(err(15000, 1024).contains("overlaps partition 3"));
+            assert!(err(0, 1024).contains("1 MiB or later"));
+            assert!(err(15872, 512).contains("past the usable end"));
+            assert!(err(10240, 16).contains("too small"));
+            assert!(err(10240, 1 << 44).contains("too large"));
+            assert!(err(u64::MAX, 1024).contains("too large"));
+            assert!(err(1 << 44, 1024).contains("too large"));
+
+            // The install reads the same extents through lsblk.
+            let mut request = req("/dev/sdb", false, false);
+            request.allow_write = true;
+            request.options.layout = Layout::DataOnly;
+            request.options.mode = InstallMode::FreeSpace {
+                start_mib: 10000,
+                size_mib: 1024,
+            };
+            let sink = Sink {
+                events: std::cell::RefCell::new(Vec::new()),
+            };
+            let disks = vec![disk("/dev/sdb", vec![], false)];
+            take_run_log();
+            let err = install_with_disks(request, &sink, &disks, Instant::now()).unwrap_err();
+            assert!(err.to_string().contains("overlaps partition 1"), "{err}");
+            assert!(take_run_log().is_empty());
+        }
+
+        #[test]
+        fn partition_types_are_set_after_parted() {
+            let mut request = req("/dev/sdb", true, false);
+            request.allow_write = true;
+            request.options.layout = Layout::DataOnly;
+            let sink = Sink { events: std::cell::RefCell::new(Vec::new()) };
+            let disks = vec![disk("/dev/sdb", vec![], false)];
+
+            take_run_log();
+            install_with_disks(request.clone(), &sink, &disks, Instant::now()).unwrap();
+            let log = take_run_log();
+            let sgdisk = format!("sgdisk -t 1:{BASIC_DATA_TYPE_GUID} /dev/sdb");
+            let at = log.iter().position(|c| *c == sgdisk).unwrap();
+            assert_eq!(log[at - 1], "parted /dev/sdb -s print");
+
+            let payload = payload_fixture("types-payload");
+            request.options.layout = Layout::BootAndData;
+            request.payload_dir = Some(payload.display().to_string());
+            request.options.partition_types.data = LINUX_DATA_TYPE_GUID.to_string();
+            install_with_disks(request.clone(), &sink, &disks, Instant::now()).unwrap();
+            let expected =
+                format!("sgdisk -t 1:{ESP_TYPE_GUID} -t 2:{LINUX_DATA_TYPE_GUID} /dev/sdb");
+            assert!(take_run_log().contains(&expected));
+
+            request.options.partition_types.esp = "EF00".to_string();
+            assert!(validate_install(&request, &sink, &disks).is_err());
+            let _ = fs::remove_dir_all(payload);
+        }
+
+        #[test]
+        fn mbr_kind_tells_protective_from_hybrid() {
+            assert_eq!(mbr_kind(&mbr_fixture(&[0xEE])), MbrKind::Protective);
+            assert_eq!(mbr_kind(&mbr_fixture(&[0xEF, 0xEE])), MbrKind::Hybrid);
+            assert_eq!(mbr_kind(&mbr_fixture(&[0x0C])), MbrKind::Missing);
+            assert_eq!(mbr_kind(&[0u8; 512]), MbrKind::Missing);
+        }
+
+        #[test]
+        fn hybrid_mbr_option_plans_sgdisk_after_parted() {
+            let payload = payload_fixture("hybrid-payload");
+            let mut request = req("/dev/sdb", true, false);
+            request.allow_write = true;
+            request.payload_dir = Some(payload.display().to_string());
+            request.options.hybrid_mbr = true;
+            let sink = Sink { events: std::cell::RefCell::new(Vec::new()) };
+            let disks = vec![disk("/dev/sdb", vec![], false)];
+
+            take_run_log();
+            install_with_disks(request.clone(), &sink, &disks, Instant::now()).unwrap();
+            let log = take_run_log();
+            let hybrid = log
+                .iter()
+                .position(|c| c == "sgdisk -h 1:EE /dev/sdb")
+                .unwrap();
+            assert!(log[..hybrid].contains(&"parted /dev/sdb -s print".to_string()));
+            assert_eq!(log[hybrid + 1], "sgdisk -p /dev/sdb");
+
+            request.options.hybrid_mbr = false;
+            install_with_disks(request, &sink, &disks, Instant::now()).unwrap();
+            assert!(!take_run_log().iter().any(|c| c.starts_with("sgdisk -h")));
+            let _ = fs::remove_dir_all(payload);
+        }
+
+        #[test]
+        fn unique_labels_number_the_data_label_past_attached_sticks() {
+            let json = PARTITIONS_FIXTURE.as_bytes();
+            let wipe = parse_labels_in_use(json, "/dev/sdb", &InstallMode::Wipe).unwrap();
+            assert!(wipe.is_empty());
+            let in_place = InstallMode::Partition { esp: None };
+            let kept = parse_labels_in_use(json, "/dev/sdb2", &in_place).unwrap();
+            assert_eq!(kept, vec!["RAIDHOS_EFI".to_string()]);
+
+            let mut request = req("/dev/sdc", true, false);
+            request.allow_write = true;
+            request.options.layout = Layout::DataOnly;
+            request.options.unique_labels = true;
+            let sink = Sink { events: std::cell::RefCell::new(Vec::new()) };
+            let disks = vec![disk("/dev/sdc", vec![], false)];
+            take_run_log();
+            install_with_disks(request.clone(), &sink, &disks, Instant::now()).unwrap();
+            assert!(take_run_log().contains(&"mkfs.exfat -n DATA2 /dev/sdc1".to_string()));
+
+            request.options.data_label = Some("ISOS".to_string());
+            install_with_disks(request.clone(), &sink, &disks, Instant::now()).unwrap();
+            assert!(take_run_log().contains(&"mkfs.exfat -n ISOS /dev/sdc1".to_string()));
+            request.options.data_label = Some("no spaces".to_string());
+            assert!(validate_install(&request, &sink, &disks).is_err());
+        }
+
+        #[test]
+        fn data_start_override_moves_the_data_mkpart() {
+            let payload = payload_fixture("data-start-payload");
+            let mut request = req("/dev/sdb", true, false);
+            request.allow_write = true;
+            request.payload_dir = Some(payload.display().to_string());
+            request.options.data_start_mib = Some(36);
+            let sink = Sink { events: std::cell::RefCell::new(Vec::new()) };
+            let disks = vec![disk("/dev/sdb", vec![], false)];
+
+            take_run_log();
+            install_with_disks(request.clone(), &sink, &disks, Instant::now()).unwrap();
+            let mkparts: Vec<String> = take_run_log()
+                .into_iter()
+                .filter(|c| c.contains(" mkpart "))
+                .collect();
+            assert_eq!(
+                mkparts,
+                [
+                    "parted /dev/sdb -s mkpart primary fat32 1MiB 33MiB",
+                    "parted /dev/sdb -s mkpart primary 36MiB 100%",
+                ]
+            );
+
+            for (start, expected) in [(32, "overlaps"), (34, "multiple of 4MiB")] {
+                request.options.data_start_mib = Some(start);
+                let err = validate_install(&request, &sink, &disks).unwrap_err();
+                assert!(format!("{err}").contains(expected), "{err}");
+            }
+            request.options.layout = Layout::DataOnly;
+            request.payload_dir = None;
+            request.options.data_start_mib = Some(4);
+            validate_install(&request, &sink, &disks).unwrap();
+            let _ = fs::remove_dir_all(payload);
+        }
+
+        #[test]
+        fn custom_esp_and_data_sizes_set_the_mkpart_bounds() {
+            let payload = payload_fixture("sizes-payload");
+            let mut request = req("/dev/sdb", true, false);
+            request.allow_write = true;
+            request.payload_dir = Some(payload.display().to_string());
+            request.options.esp_size_mib = Some(512);
+            request.options.data_size_mib = Some(8192);
+            let sink = Sink {
+                events: std::cell::RefCell::new(Vec::new()),
+            };
+            let disks = vec![disk("/dev/sdb", vec![], false)];
+
+            take_run_log();
+            install_with_disks(request.clone(), &sink, &disks, Instant::now()).unwrap();
+            let mkparts: Vec<String> = take_run_log()
+                .into_iter()
+                .filter(|c| c.contains(" mkpart "))
+                .collect();
+            assert_eq!(
+                mkparts,
+                [
+                    "parted /dev/sdb -s mkpart primary fat32 1MiB 513MiB",
+                    "parted /dev/sdb -s mkpart primary 513MiB 8705MiB",
+                ]
+            );
+
+            // The data start must now clear the larger ESP.
+            request.options.data_start_mib = Some(256);
+            let err = validate_install(&request, &sink, &disks).unwrap_err();
+            assert!(err.to_string().contains("use 513MiB or later"), "{err}");
+            request.options.data_start_mib = None;
+
+            let mut free = request.clone();
+            free.options.mode = InstallMode::FreeSpace {
+                start_mib: 10240,
+                size_mib: 5120,
+            };
+            free.options.data_size_mib = None;
+            let plan = free_space_for(&free, &disks).unwrap().unwrap();
+            assert_eq!(plan.esp.unwrap().end_mib, 10752);
+            free.options.data_size_mib = Some(1024);
+            let plan = free_space_for(&free, &disks).unwrap().unwrap();
+            assert_eq!(plan.data.end_mib, 11776);
+            free.options.data_size_mib = Some(8192);
+            let err = free_space_for(&free, &disks).unwrap_err();
+            assert!(err.to_string().contains("more than the 5120 MiB"), "{err}");
+            let _ = fs::remove_dir_all(payload);
+        }
+
+        #[test]
+        fn partition_sizes_must_fit_the_disk() {
+            let sink = Sink {
+                events: std::cell::RefCell::new(Vec::new()),
+            };
+            let disks = vec![disk("/dev/sdb", vec![], false)];
+            let err = |request: &InstallRequest| {
+                validate_install(request, &sink, &disks)
+                    .unwrap_err()
+                    .to_string()
+            };
+
+            let mut request = req("/dev/sdb", true, true);
+            request.options.data_size_mib = Some(16 << 10);
+            assert!(
+                err(&request).contains("need 16418 MiB but /dev/sdb has only 16384 MiB"),
+                "{}",
+                err(&request)
+            );
+            request.options.data_size_mib = None;
+            request.options.esp_size_mib = Some(20 << 10);
+            assert!(err(&request).contains("has only 16384 MiB"));
+            request.options.esp_size_mib = Some(16);
+            assert!(err(&request).contains("too small"));
+
+            request.options.esp_size_mib = Some(64);
+            request.options.layout = Layout::DataOnly;
+            assert!(err(&request).contains("only used when the install creates an ESP"));
+            request.options.esp_size_mib = None;
+            request.options.data_size_mib = Some(0);
+            assert!(err(&request).contains("at least 1 MiB"));
+            request.options.data_size_mib = Some(1024);
+            validate_install(&request, &sink, &disks).unwrap();
+
+            request.options.layout = Layout::BootAndData;
+            for (esp, data) in [(Some(u64::MAX), None), (None, Some(u64::MAX - 8))] {
+                request.options.esp_size_mib = esp;
+                request.options.data_size_mib = data;
+                assert!(err(&request).contains("too large"), "{}", err(&request));
+                assert!(wipe_parted_steps(&request.options).is_err());
+            }
+            request.options.data_size_mib = Some(u64::MAX >> 20);
+            assert!(err(&request).contains("too large"), "{}", err(&request));
+        }
+
+        #[test]
+        fn parted_alignment_warnings_become_warning_events() {
+            let payload = payload_fixture("alignment-payload");
+            let mut request = req("/dev/sdb", true, false);
+            request.allow_write = true;
+            request.payload_dir = Some(payload.display().to_string());
+            let sink = Sink { events: std::cell::RefCell::new(Vec::new()) };
+            let disks = vec![disk("/dev/sdb", vec![], false)];
+
+            stderr_for_commands_starting_with(Some((
+                "parted /dev/sdb -s mkpart primary 33MiB",
+                "Warning: The resulting partition is not properly aligned for best performance: 67584s % 4096s != 0s\n",
+            )));
+            let result = install_with_disks(request, &sink, &disks, Instant::now());
+            stderr_for_commands_starting_with(None);
+            take_run_log();
+            result.unwrap();
+
+            let warnings: Vec<_> = sink
+                .events
+                .borrow()
+                .iter()
+                .filter(|e| e.phase == "partition" && e.message.starts_with("Warning: "))
+                .map(|e| (e.message.clone(), e.percent))
+                .collect();
+            assert_eq!(
+                warnings,
+                vec![(
+                    "Warning: The resulting partition is not properly aligned for best performance: 67584s % 4096s != 0s".to_string(),
+                    None
+                )]
+            );
+            assert!(
+                alignment_warnings("Information: You may need to update /etc/fstab.\n").is_empty()
+            );
+        }
+
+        #[test]
+        fn failed_command_keeps_its_exit_code_and_stderr() {
+            fail_commands_starting_with(Some("mkfs.vfat"));
+            stderr_for_commands_starting_with(Some((
+                "mkfs.vfat",
+                "mkfs.fat 4.2 (2021-01-31)\nmkfs.vfat: unable to open /dev/sdb1: Read-only file system\n",
+            )));
+            let err = run("mkfs.vfat", &["-F", "32", "/dev/sdb1"]).unwrap_err();
+            fail_commands_starting_with(None);
+            stderr_for_commands_starting_with(None);
+            take_run_log();
+            let CoreError::Command { cmd, code, stderr } = &err else {
+                panic!("{err:?}");
+            };
+            assert_eq!((cmd.as_str(), *code), ("mkfs.vfat", Some(1)));
+            assert!(stderr.contains("Read-only file system"));
+            assert_eq!(
+                err.to_string(),
+                "command failed: mkfs.vfat (exit code 1): mkfs.fat 4.2 (2021-01-31)\n\
+                 mkfs.vfat: unable to open /dev/sdb1: Read-only file system"
+            );
+
+            let killed = CoreError::Command {
+                cmd: "mkfs.exfat".to_string(),
+                code: None,
+                stderr: format!("{}\nwrite failed", "x".repeat(1000)),
+            };
+            let message = killed.to_string();
+            assert!(message.starts_with("command failed: mkfs.exfat (killed by a signal): ..."));
+            assert!(message.ends_with("\nwrite failed"));
+            assert!(message.len() < 500, "{message}");
+        }
+
+        #[test]
+        fn zero_ends_plans_dd_over_both_ends_before_parted() {
+            let payload = payload_fixture("zero-ends-payload");
+            let mut request = req("/dev/sdb", true, false);
+            request.allow_write = true;
+            request.payload_dir = Some(payload.display().to_string());
+            request.options.zero_ends = true;
+            let sink = Sink { events: std::cell::RefCell::new(Vec::new()) };
+            let disks = vec![disk("/dev/sdb", vec![], false)];
+
+            take_run_log();
+            install_with_disks(request.clone(), &sink, &disks, Instant::now()).unwrap();
+            let log = take_run_log();
+            // 16 GiB is 33554432 sectors; the last MiB starts 2048 before.
+            assert_eq!(
+                log[..3],
+                [
+                    "dd if=/dev/zero of=/dev/sdb bs=512 count=2048 seek=0 conv=fsync",
+                    "dd if=/dev/zero of=/dev/sdb bs=512 count=2048 seek=33552384 conv=fsync",
+                    "parted /dev/sdb -s mklabel gpt",
+                ]
+            );
+
+            request.options.zero_ends = false;
+            install_with_disks(request.clone(), &sink, &disks, Instant::now()).unwrap();
+            assert!(!take_run_log().iter().any(|c| c.starts_with("dd ")));
+
+            request.options.zero_ends = true;
+            request.options.mode = InstallMode::FreeSpace {
+                start_mib: 1,
+                size_mib: 1024,
+            };
+            assert!(validate_install(&request, &sink, &disks).is_err());
+            let _ = fs::remove_dir_all(payload);
+        }
+
+        #[test]
+        fn command_log_has_a_line_per_command() {
+            let log_path = std::env::temp_dir()
+                .join(format!("raidhos-command-log-{}.jsonl", std::process::id()));
+            let _ = fs::remove_file(&log_path);
+            let mut request = req("/dev/sdb", true, false);
+            request.allow_write = true;
+            request.options.layout = Layout::DataOnly;
+            request.options.command_log = Some(log_path.clone());
+            let sink = Sink { events: std::cell::RefCell::new(Vec::new()) };
+            let disks = vec![disk("/dev/sdb", vec![], false)];
+
+            // mklabel succeeds, the first mkpart fails and stops the install.
+            fail_commands_starting_with(Some("parted /dev/sdb -s mkpart"));
+            take_run_log();
+            install_with_disks(request, &sink, &disks, Instant::now()).unwrap_err();
+            fail_commands_starting_with(None);
+            assert_eq!(take_run_log().len(), 2);
+
+            let text = fs::read_to_string(&log_path).unwrap();
+            let lines: Vec<serde_json::Value> = text
+                .lines()
+                .map(|l| serde_json::from_str(l).unwrap())
+                .collect();
+            assert_eq!(lines.len(), 2);
+            assert_eq!(lines[0]["command"], "parted");
+            assert_eq!(lines[0]["args"][3], "gpt");
+            assert_eq!(lines[0]["exit_status"], 0);
+            assert_eq!(lines[1]["args"][2], "mkpart");
+            assert_eq!(lines[1]["exit_status"], 1);
+            assert!(lines[1]["duration_ms"].is_u64());
+            let _ = fs::remove_file(log_path);
+        }
+
+        #[test]
+        fn exfat_tools_pick_first_available_in_table_order() {
+            let fedora =
+                available_tools(EXFAT_FORMATTERS, |t| t == "mkfs.exFAT" || t == "mkexfatfs");
+            assert_eq!(fedora, vec!["mkfs.exFAT", "mkexfatfs"]);
+            let arch = available_tools(EXFAT_LABELERS, |t| t != "exfatlabel");
+            assert_eq!(arch.first(), Some(&"tune.exfat"));
+            assert!(available_tools(EXFAT_FORMATTERS, |_| false).is_empty());
+
+            take_run_log();
+            relabel_exfat("/dev/sdb2", DATA_LABEL).unwrap();
+            assert_eq!(take_run_log(), vec!["exfatlabel /dev/sdb2 DATA"]);
+        }
+
+        #[derive(Debug, Default)]
+        struct RecordingMounter {
+            calls: std::sync::Mutex<Vec<String>>,
+        }
+
+        impl Mounter for RecordingMounter {
+            fn mount(&self, device: &str, target: &Path) -> Result<()> {
+                let call = format!("mount {device} {}", target.display());
+                self.calls.lock().unwrap().push(call);
+                Ok(())
+            }
+
+            fn mount_read_only(&self, device: &str, target: &Path) -> Result<()> {
+                let call = format!("mount-ro {device} {}", target.display());
+                self.calls.lock().unwrap().push(call);
+                Ok(())
+            }
+
+            fn unmount(&self, target: &Path) -> Result<()> {
+                let call = format!("unmount {}", target.display());
+                self.calls.lock().unwrap().push(call);
+                Ok(())
+            }
+        }
+
+        #[test]
+        fn install_mounts_through_the_mounter() {
+            let payload = payload_fixture("mounter-payload");
+            let mut request = req("/dev/sdb", true, false);
+            request.allow_write = true;
+            request.payload_dir = Some(payload.display().to_string());
+            let sink = Sink { events: std::cell::RefCell::new(Vec::new()) };
+            let disks = vec![disk("/dev/sdb", vec![], false)];
+            let mounter = std::sync::Arc::new(RecordingMounter::default());
+            request.options.mounter = Some(mounter.clone());
+
+            take_run_log();
+            install_with_disks(request, &sink, &disks, Instant::now()).unwrap();
+            assert_eq!(
+                *mounter.calls.lock().unwrap(),
+                vec![
+                    "mount /dev/sdb1 /mnt/raidhos-esp",
+                    "mount /dev/sdb2 /mnt/raidhos-data",
+                    "unmount /mnt/raidhos-esp",
+                    "unmount /mnt/raidhos-data",
+                ]
+            );
+            let log = take_run_log();
+            assert!(!log.iter().any(|c| c.contains("mount ")));
+            let _ = fs::remove_dir_all(payload);
+        }
+
+        #[derive(Debug, Default)]
+        struct RecordingRunner {
+            commands: std::sync::Mutex<Vec<String>>,
+        }
+
+        impl CommandRunner for RecordingRunner {
+            fn run(&self, cmd: &str, args: &[&str]) -> Result<runner::CommandStatus> {
+                let line = [&[cmd], args].concat().join(" ");
+                self.commands.lock().unwrap().push(line);
+                Ok(runner::CommandStatus::success())
+            }
+
+            fn has_cmd(&self, cmd: &str) -> bool {
+                cmd != "sgdisk"
+            }
+
+            fn node_exists(&self, _node: &str) -> bool {
+                true
+            }
+
+            /// Serves lsblk from the partitions fixture.
+            fn output(&self, cmd: &str, args: &[&str]) -> Result<runner::CommandOutput> {
+                self.run(cmd, args)?;
+                Ok(runner::CommandOutput {
+                    status: runner::CommandStatus::success(),
+                    stdout: PARTITIONS_FIXTURE.as_bytes().to_vec(),
+                })
+            }
+
+            fn read_mbr(&self, _device: &str) -> Result<Vec<u8>> {
+                Ok(mbr_fixture(&[MBR_GPT_TYPE]))
+            }
+        }
+
+        #[test]
+        fn injected_runner_receives_every_command() {
+            let payload = payload_fixture("runner-payload");
+            let runner = std::sync::Arc::new(RecordingRunner::default());
+            let mut request = req("/dev/sdb", true, false);
+            request.allow_write = true;
+            request.payload_dir = Some(payload.display().to_string());
+            request.options.runner = Some(runner.clone());
+            let sink = Sink { events: std::cell::RefCell::new(Vec::new()) };
+            let disks = vec![disk("/dev/sdb", vec![], false)];
+
+            take_run_log();
+            let outcome = install_with_disks(request, &sink, &disks, Instant::now()).unwrap();
+            assert!(take_run_log().is_empty());
+            assert_eq!(outcome.partitions.len(), 2);
+            // No sgdisk: the runner reports it missing, so type codes are skipped.
+            assert_eq!(
+                *runner.commands.lock().unwrap(),
+                [
+                    "parted /dev/sdb -s mklabel gpt".to_string(),
+                    "parted /dev/sdb -s mkpart primary fat32 1MiB 33MiB".to_string(),
+                    "parted /dev/sdb -s set 1 esp on".to_string(),
+                    "parted /dev/sdb -s mkpart primary 33MiB 100%".to_string(),
+                    "parted /dev/sdb -s print".to_string(),
+                    format!("mkfs.vfat -F 32 -n {ESP_LABEL} /dev/sdb1"),
+                    format!("mkfs.exfat -n {DATA_LABEL} /dev/sdb2"),
+                    "mount /dev/sdb1 /mnt/raidhos-esp".to_string(),
+                    "mount /dev/sdb2 /mnt/raidhos-data".to_string(),
+                    "grub-install --target=x86_64-efi --efi-directory=/mnt/raidhos-esp \
+                     --boot-directory=/mnt/raidhos-esp/boot --removable --no-nvram"
+                        .to_string(),
+                    "umount /mnt/raidhos-esp".to_string(),
+                    "umount /mnt/raidhos-data".to_string(),
+                    format!("lsblk -b -J -o {PARTITION_COLUMNS}"),
+                ]
+            );
+
+            let active: std::sync::Arc<dyn CommandRunner> = runner.clone();
+            let _active = ActiveRunner::start(&active);
+            let parts = list_partitions("/dev/sdb".to_string()).unwrap();
+            assert_eq!(parts.len(), 2);
+            let lsblk = runner.commands.lock().unwrap().pop().unwrap();
+            assert_eq!(lsblk, format!("lsblk -b -J -o {PARTITION_COLUMNS}"));
+            let _ = fs::remove_dir_all(payload);
+        }
+
+        /// Serves lsblk from the disks fixture and records what it was asked.
+        #[derive(Debug, Default)]
+        struct ListingRunner {
+            queries: std::sync::Mutex<Vec<String>>,
+        }
+
+        impl CommandRunner for ListingRunner {
+            fn run(&self, _cmd: &str, _args: &[&str]) -> Result<runner::CommandStatus> {
+                Ok(runner::CommandStatus::success())
+            }
+
+            fn has_cmd(&self, _cmd: &str) -> bool {
+                true
+            }
+
+            fn node_exists(&self, _node: &str) -> bool {
+                true
+            }
+
+            fn output(&self, cmd: &str, args: &[&str]) -> Result<runner::CommandOutput> {
+                let line = [&[cmd], args].concat().join(" ");
+                self.queries.lock().unwrap().push(line);
+                Ok(runner::CommandOutput {
+                    status: runner::CommandStatus::success(),
+                    stdout: DISKS_FIXTURE.as_bytes().to_vec(),
+                })
+            }
+        }
+
+        #[test]
+        fn install_resolves_the_target_through_the_injected_runner() {
+            let runner = std::sync::Arc::new(ListingRunner::default());
+            let mut request = req("/dev/sdb", true, true);
+            request.options.runner = Some(runner.clone());
+            let sink = Sink {
+                events: std::cell::RefCell::new(Vec::new()),
+            };
+
+            let outcome = install(request, &sink).unwrap();
+            assert!(outcome.dry_run);
+            let queries = runner.queries.lock().unwrap();
+            assert_eq!(queries[0], format!("lsblk -b -J -o {DISK_COLUMNS}"));
+        }
+
+        /// Fails every command from the first one starting with `unplug_at`
+        /// on, the way tools fail once the stick is pulled.
+        #[derive(Debug)]
+        struct UnpluggingRunner {
+            unplug_at: &'static str,
+            commands: std::sync::Mutex<Vec<String>>,
+        }
+
+        impl CommandRunner for UnpluggingRunner {
+            fn run(&self, cmd: &str, args: &[&str]) -> Result<runner::CommandStatus> {
+                let line = [&[cmd], args].concat().join(" ");
+                let mut commands = self.commands.lock().unwrap();
+                commands.push(line.clone());
+                if !commands.iter().any(|c| c.starts_with(self.unplug_at)) {
+                    return Ok(runner::CommandStatus::success());
+                }
+                Ok(runner::CommandStatus {
+                    code: Some(1),
+                    stderr: format!("{cmd}: cannot open {}: No such device or address", args[0]),
+                })
+            }
+
+            fn has_cmd(&self, _cmd: &str) -> bool {
+                true
+            }
+
+            fn node_exists(&self, _node: &str) -> bool {
+                true
+            }
+
+            fn read_mbr(&self, _device: &str) -> Result<Vec<u8>> {
+                Ok(mbr_fixture(&[MBR_GPT_TYPE]))
+            }
+        }
+
+        #[test]
+        fn unplugged_target_aborts_with_a_specific_error() {
+            let payload = payload_fixture("unplug-payload");
+            let runner = std::sync::Arc::new(UnpluggingRunner {
+                unplug_at: "mkfs.exfat",
+                commands: std::sync::Mutex::new(Vec::new()),
+            });
+            let mut request = req("/dev/sdb", true, false);
+            request.allow_write = true;
+            request.payload_dir = Some(payload.display().to_string());
+            request.options.runner = Some(runner.clone());
+            let sink = Sink { events: std::cell::RefCell::new(Vec::new()) };
+            let disks = vec![disk("/dev/sdb", vec![], false)];
+
+            let err = install_with_disks(request, &sink, &disks, Instant::now()).unwrap_err();
+            assert_eq!(err.to_string(), format!("io error: {DEVICE_GONE}"));
+            // No fallback formatter, relabel or mount after the stick vanished.
+            let commands = runner.commands.lock().unwrap();
+            assert_eq!(
+                commands.last().unwrap(),
+                &format!("mkfs.exfat -n {DATA_LABEL} /dev/sdb2")
+            );
+            assert_eq!(commands.iter().filter(|c| c.starts_with("mkfs.exfat")).count(), 1);
+            let _ = fs::remove_dir_all(payload);
+        }
+
+        #[test]
+        fn outcome_summarizes_the_installed_stick() {
+            let payload = payload_fixture("summary-payload");
+            let boot = payload.join("esp/EFI/BOOT");
+            fs::create_dir_all(&boot).unwrap();
+            let config = crate::BootConfig {
+                entries: ["Ubuntu", "Debian"]
+                    .iter()
+                    .map(|title| crate::BootEntryConfig {
+                        title: title.to_string(),
+                        path: format!("/boot/isos/{title}.iso"),
+                        params: String::new(),
+                        initrd: String::new(),
+                        kargs: String::new(),
+                        size_bytes: None,
+                    })
+                    .collect(),
+                ..Default::default()
+            };
+            fs::write(boot.join("grub.cfg"), crate::render_grub_cfg(&config, DATA_LABEL)).unwrap();
+            let mut request = req("/dev/sdb", true, false);
+            request.allow_write = true;
+            request.payload_dir = Some(payload.display().to_string());
+            let sink = Sink { events: std::cell::RefCell::new(Vec::new()) };
+            let disks = vec![disk("/dev/sdb", vec![], false)];
+
+            let dry_run = req("/dev/sdb", true, true);
+            let dry = install_with_disks(dry_run, &sink, &disks, Instant::now()).unwrap();
+            assert!(dry.partitions.is_empty());
+            assert_eq!(dry.grub_entries, None);
+
+            take_run_log();
+            let outcome = install_with_disks(request, &sink, &disks, Instant::now()).unwrap();
+            take_run_log();
+            let summary: Vec<(&str, &str, &str)> = outcome
+                .partitions
+                .iter()
+                .map(|p| (p.id.as_str(), p.label.as_str(), p.fstype.as_str()))
+                .collect();
+            assert_eq!(
+                summary,
+                vec![
+                    ("/dev/sdb1", ESP_LABEL, "vfat"),
+                    ("/dev/sdb2", DATA_LABEL, "exfat"),
+                ]
+            );
+            assert_eq!(outcome.grub_entries, Some(2));
+            let _ = fs::remove_dir_all(payload);
+        }
+
+        #[test]
+        fn install_uses_the_shared_defaults() {
+            use crate::defaults;
+
+            let payload = payload_fixture("defaults-payload");
+            let mut request = req("/dev/sdb", true, false);
+            request.allow_write = true;
+            request.payload_dir = Some(payload.display().to_string());
+            let sink = Sink { events: std::cell::RefCell::new(Vec::new()) };
+            let disks = vec![disk("/dev/sdb", vec![], false)];
+            let mounter = std::sync::Arc::new(RecordingMounter::default());
+            request.options.mounter = Some(mounter.clone());
+
+            take_run_log();
+            install_with_disks(request, &sink, &disks, Instant::now()).unwrap();
+            let log = take_run_log();
+            let esp_mkfs = format!("mkfs.vfat -F 32 -n {} /dev/sdb1", defaults::ESP_LABEL);
+            let data_mkfs = format!("mkfs.exfat -n {} /dev/sdb2", defaults::DATA_LABEL);
+            assert!(log.contains(&esp_mkfs));
+            assert!(log.contains(&data_mkfs));
+            let calls = mounter.calls.lock().unwrap();
+            assert_eq!(calls[0], format!("mount /dev/sdb1 {}", defaults::ESP_MOUNT));
+            assert_eq!(
+                calls[1],
+                format!("mount /dev/sdb2 {}", defaults::DATA_MOUNT)
+            );
+            let _ = fs::remove_dir_all(payload);
+        }
+
+        #[test]
+        fn failed_copy_keeps_mounts_only_when_asked() {
+            let payload = payload_fixture("keep-mounts-payload");
+            let root = payload.join("mnt");
+            let mounts = PayloadMounts {
+                esp: root.join("esp"),
+                data: root.join("data"),
+            };
+            let sink = Sink { events: std::cell::RefCell::new(Vec::new()) };
+            let mut options = InstallOptions::default();
+            let copy = |options: &InstallOptions| {
+                let (esp, data, arch) = ("/dev/sdb1", "/dev/sdb2", Arch::X86_64);
+                let mounter = &SystemMounter;
+                payload_copy_at(&sink, options, &payload, esp, data, arch, &mounts, mounter)
+            };
+            let umounts = || {
+                let log = take_run_log();
+                log.iter().filter(|c| c.starts_with("umount")).count()
+            };
+            // A file on the data partition where the payload has a directory.
+            fs::create_dir_all(payload.join("data/boot")).unwrap();
+            fs::write(payload.join("data/boot/menu.txt"), b"menu").unwrap();
+            fs::create_dir_all(&mounts.data).unwrap();
+            fs::write(mounts.data.join("boot"), b"stale").unwrap();
+
+            take_run_log();
+            copy(&options).unwrap_err();
+            assert_eq!(umounts(), 2);
+
+            options.keep_mounts_on_error = true;
+            let err = copy(&options).unwrap_err().to_string();
+            assert!(err.contains(&mounts.esp.display().to_string()), "{err}");
+            assert!(err.contains(&mounts.data.display().to_string()), "{err}");
+            assert_eq!(umounts(), 0);
+
+            fs::remove_file(mounts.data.join("boot")).unwrap();
+            copy(&options).unwrap();
+            assert_eq!(umounts(), 2);
+            assert_eq!(
+                fs::read(mounts.data.join("boot/menu.txt")).unwrap(),
+                b"menu"
+            );
+            let _ = fs::remove_dir_all(payload);
+        }
+
+        #[test]
+        fn auto_menu_has_an_entry_per_copied_iso() {
+            let payload = payload_fixture("auto-menu-payload");
+            let isos = payload.join("data/boot/isos");
+            fs::create_dir_all(&isos).unwrap();
+            fs::write(isos.join("ubuntu-24.04.iso"), b"iso").unwrap();
+            fs::write(isos.join("debian-12.iso"), b"iso").unwrap();
+            let root = payload.join("mnt");
+            let mounts = PayloadMounts {
+                esp: root.join("esp"),
+                data: root.join("data"),
+            };
+            let sink = Sink { events: std::cell::RefCell::new(Vec::new()) };
+            let mounter = RecordingMounter::default();
+            let options = InstallOptions {
+                auto_menu: true,
+                ..Default::default()
+            };
+
+            let copy = |options: &InstallOptions| {
+                let (esp, data, arch) = ("/dev/sdb1", "/dev/sdb2", Arch::X86_64);
+                let copied =
+                    payload_copy_at(&sink, options, &payload, esp, data, arch, &mounts, &mounter);
+                take_run_log();
+                copied
+            };
+
+            assert_eq!(copy(&options).unwrap(), Some(2));
+            let config = crate::read_grub_cfg(&mounts.esp).unwrap();
+            let entries: Vec<(&str, &str)> = config
+                .entries
+                .iter()
+                .map(|e| (e.title.as_str(), e.path.as_str()))
+                .collect();
+            assert_eq!(
+                entries,
+                vec![
+                    ("debian-12", "/boot/isos/debian-12.iso"),
+                    ("ubuntu-24.04", "/boot/isos/ubuntu-24.04.iso"),
+                ]
+            );
+
+            assert_eq!(copy(&InstallOptions::default()).unwrap(), None);
+            let _ = fs::remove_dir_all(payload);
+        }
+
+        #[test]
+        fn syslinux_backend_installs_a_grub_chain_loading_menu() {
+            let payload = payload_fixture("syslinux-payload");
+            let isos = payload.join("data/boot/isos");
+            fs::create_dir_all(&isos).unwrap();
+            fs::write(isos.join("debian-12.iso"), b"iso").unwrap();
+            let root = payload.join("mnt");
+            let mounts = PayloadMounts {
+                esp: root.join("esp"),
+                data: root.join("data"),
+            };
+            let sink = Sink { events: std::cell::RefCell::new(Vec::new()) };
+            let options = InstallOptions {
+                auto_menu: true,
+                menu_backend: MenuBackend::Syslinux,
+                ..Default::default()
+            };
+            let (esp, data, arch) = ("/dev/sdb1", "/dev/sdb2", Arch::X86_64);
+            let mounter = RecordingMounter::default();
+            payload_copy_at(
+                &sink, &options, &payload, esp, data, arch, &mounts, &mounter,
+            )
+            .unwrap();
+            let cfg = fs::read_to_string(mounts.esp.join("syslinux/syslinux.cfg")).unwrap();
+            let block = "MENU LABEL GRUB menu (1 ISO on DATA)\n  \
+                         LINUX /boot/grub/i386-pc/lnxboot.img\n  \
+                         INITRD /boot/grub/i386-pc/core.img\n";
+            assert!(cfg.contains(block), "{cfg}");
+            let events = sink.events.borrow();
+            let missing =
+                "Warning: syslinux modules not found: menu.c32, libutil.c32, libcom32.c32";
+            assert!(events.iter().any(|e| e.message == missing));
+            let no_grub = "Warning: boot/grub/i386-pc/lnxboot.img not on the ESP; \
+                           the syslinux menu cannot chain-load GRUB";
+            assert!(events.iter().any(|e| e.message == no_grub), "{events:?}");
+
+            take_run_log();
+            let warnings = install_syslinux("/dev/sdb", "/dev/sdb1").unwrap();
+            assert_eq!(warnings.len(), 1);
+            assert_eq!(
+                take_run_log(),
+                vec![
+                    "syslinux --install --directory /syslinux /dev/sdb1",
+                    "parted /dev/sdb -s set 1 legacy_boot on",
+                ]
+            );
+            let _ = fs::remove_dir_all(payload);
+        }
+
+        #[test]
+        fn uninstall_strip_removes_artifacts_and_keeps_isos() {
+            let root =
+                std::env::temp_dir().join(format!("raidhos-uninstall-{}", std::process::id()));
+            let esp = root.join("uninstall-sdb1");
+            let data = root.join("uninstall-sdb2");
+            fs::create_dir_all(esp.join("EFI/BOOT")).unwrap();
+            fs::write(esp.join("EFI/BOOT/grub.cfg"), b"cfg").unwrap();
+            fs::write(esp.join("EFI/BOOT/BOOTX64.EFI"), b"efi").unwrap();
+            fs::create_dir_all(data.join("raidhos")).unwrap();
+            fs::write(data.join("raidhos/boot.json"), b"{}").unwrap();
+            fs::create_dir_all(data.join("boot/isos")).unwrap();
+            fs::write(data.join("boot/isos/debian.iso"), b"iso").unwrap();
+            let parts = vec![
+                part("/dev/sdb1", ESP_LABEL, "vfat"),
+                part("/dev/sdb2", DATA_LABEL, "exfat"),
+            ];
+
+            take_run_log();
+            let removed = strip_artifacts(&parts, &root).unwrap();
+            assert_eq!(
+                removed,
+                vec!["/dev/sdb1:EFI/BOOT/grub.cfg", "/dev/sdb2:raidhos"]
+            );
+            assert!(data.join("boot/isos/debian.iso").is_file());
+            assert!(esp.join("EFI/BOOT/BOOTX64.EFI").is_file());
+            let log = take_run_log();
+            assert_eq!(log.iter().filter(|c| c.starts_with("mount ")).count(), 2);
+            assert_eq!(log.iter().filter(|c| c.starts_with("umount ")).count(), 2);
+            assert!(!log.iter().any(|c| c.starts_with("mkfs") || c.starts_with("parted")));
+
+            for fstype in ["ext4", "ntfs"] {
+                fs::create_dir_all(data.join("raidhos")).unwrap();
+                let parts = vec![
+                    part("/dev/sdb1", ESP_LABEL, "vfat"),
+                    part("/dev/sdb2", DATA_LABEL, fstype),
+                ];
+                let removed = strip_artifacts(&parts, &root).unwrap();
+                assert_eq!(removed, vec!["/dev/sdb2:raidhos"], "{fstype}");
+            }
+
+            fs::create_dir_all(data.join("raidhos")).unwrap();
+            let data_only = vec![part("/dev/sdb2", DATA_LABEL, "exfat")];
+            let removed = strip_artifacts(&data_only, &root).unwrap();
+            assert_eq!(removed, vec!["/dev/sdb2:raidhos"]);
+            let _ = fs::remove_dir_all(root);
+        }
+
+        #[test]
+        fn uninstall_needs_allow_write() {
+            take_run_log();
+            for reformat in [true, false] {
+                let err = uninstall("/dev/sdb", reformat, false).unwrap_err();
+                let expected = "write blocked: set allow_write to proceed";
+                assert!(
+                    matches!(&err, CoreError::Validation(m) if m == expected),
+                    "{err}"
+                );
+            }
+            assert!(take_run_log().is_empty());
+        }
+
+        #[test]
+        fn uninstall_strip_refuses_a_disk_without_raidhos() {
+            let root = std::env::temp_dir().join(format!("raidhos-foreign-{}", std::process::id()));
+            let esp = root.join("uninstall-sdb1");
+            let data = root.join("uninstall-sdb2");
+            fs::create_dir_all(esp.join("EFI/BOOT")).unwrap();
+            fs::write(esp.join("EFI/BOOT/grub.cfg"), b"cfg").unwrap();
+            fs::create_dir_all(data.join("boot/grub")).unwrap();
+            fs::write(data.join("boot/grub/grub.cfg"), b"cfg").unwrap();
+            let parts = vec![
+                part("/dev/sdb1", "EFI", "vfat"),
+                part("/dev/sdb2", "ubuntu", "ext4"),
+            ];
+
+            take_run_log();
+            let err = strip_artifacts(&parts, &root).unwrap_err();
+            assert!(matches!(err, CoreError::Validation(_)), "{err}");
+            assert!(esp.join("EFI/BOOT/grub.cfg").is_file());
+            assert!(data.join("boot/grub/grub.cfg").is_file());
+            let log = take_run_log();
+            assert!(!log.iter().any(|c| c.contains("/dev/sdb1")), "{log:?}");
+
+            let swap = vec![part("/dev/sdb1", "", "swap")];
+            assert!(strip_artifacts(&swap, &root).is_err());
+            let _ = fs::remove_dir_all(root);
+        }
+
+        /// A stick as an install leaves it, under `root` where
+        /// `verify_parts` mounts it: a menu with one Debian entry, the
+        /// UEFI fallback loader, and the ISO on the data partition.
+        fn verify_fixture(name: &str) -> PathBuf {
+            let root = std::env::temp_dir().join(format!("raidhos-{name}-{}", std::process::id()));
+            let esp = root.join("verify-sdb1");
+            let data = root.join("verify-sdb2");
+            let config = crate::BootConfig {
+                entries: vec![crate::BootEntryConfig {
+                    title: "Debian".to_string(),
+                    path: "/boot/isos/debian.iso".to_string(),
+                    params: String::new(),
+                    initrd: String::new(),
+                    kargs: String::new(),
+                    size_bytes: None,
+                }],
+                ..Default::default()
+            };
+            fs::create_dir_all(esp.join("EFI/BOOT")).unwrap();
+            fs::write(
+                esp.join("EFI/BOOT/grub.cfg"),
+                crate::render_grub_cfg(&config, DATA_LABEL),
+            )
+            .unwrap();
+            fs::write(esp.join("EFI/BOOT/BOOTX64.EFI"), b"efi").unwrap();
+            fs::create_dir_all(data.join("boot/isos")).unwrap();
+            fs::write(data.join("boot/isos/debian.iso"), b"iso").unwrap();
+            root
+        }
+
+        fn stick_check<'a>(report: &'a StickReport, name: &str) -> &'a StickCheck {
+            report.checks.iter().find(|c| c.name == name).unwrap()
+        }
+
+        #[test]
+        fn verify_passes_a_healthy_stick() {
+            let root = verify_fixture("verify-healthy");
+            let parts = vec![
+                part("/dev/sdb1", ESP_LABEL, "vfat"),
+                part("/dev/sdb2", DATA_LABEL, "exfat"),
+            ];
+
+            take_run_log();
+            let report = verify_parts("/dev/sdb", &parts, &root);
+            let names: Vec<&str> = report.checks.iter().map(|c| c.name.as_str()).collect();
+            assert_eq!(
+                names,
+                vec![
+                    "esp partition",
+                    "data partition",
+                    "grub.cfg",
+                    "bootloader",
+                    "menu label",
+                    "isos"
+                ]
+            );
+            assert_eq!(report.status(), CheckStatus::Pass, "{report:?}");
+            assert_eq!(report.checks[3].detail, "EFI/BOOT/BOOTX64.EFI");
+            let log = take_run_log();
+            assert!(log
+                .iter()
+                .all(|c| c.starts_with("mount -o ro ") || c.starts_with("umount ")));
+
+            fs::remove_file(root.join("verify-sdb2/boot/isos/debian.iso")).unwrap();
+            let report = verify_parts("/dev/sdb", &parts, &root);
+            let isos = stick_check(&report, "isos");
+            assert_eq!(isos.status, CheckStatus::Fail);
+            assert!(isos.detail.contains("/boot/isos/debian.iso"));
+
+            let relabelled = vec![
+                part("/dev/sdb1", ESP_LABEL, "vfat"),
+                part("/dev/sdb2", "STICK2", "exfat"),
+            ];
+            let report = verify_parts("/dev/sdb", &relabelled, &root);
+            let label = stick_check(&report, "menu label");
+            assert_eq!(label.status, CheckStatus::Fail);
+
+            fs::write(root.join("verify-sdb2/boot/isos/debian.iso"), b"iso").unwrap();
+            for fstype in ["ext4", "ntfs"] {
+                let parts = vec![
+                    part("/dev/sdb1", ESP_LABEL, "vfat"),
+                    part("/dev/sdb2", DATA_LABEL, fstype),
+                ];
+                let report = verify_parts("/dev/sdb", &parts, &root);
+                assert_eq!(report.status(), CheckStatus::Pass, "{fstype}: {report:?}");
+            }
+            let _ = fs::remove_dir_all(root);
+        }
+
+        #[test]
+        fn verify_fails_a_stick_without_grub_cfg() {
+            let root = verify_fixture("verify-no-grub");
+            fs::remove_file(root.join("verify-sdb1/EFI/BOOT/grub.cfg")).unwrap();
+            let parts = vec![
+                part("/dev/sdb1", ESP_LABEL, "vfat"),
+                part("/dev/sdb2", DATA_LABEL, "exfat"),
+            ];
+
+            let report = verify_parts("/dev/sdb", &parts, &root);
+            assert_eq!(report.status(), CheckStatus::Fail);
+            let grub = stick_check(&report, "grub.cfg");
+            assert_eq!(grub.status, CheckStatus::Fail);
+            let bootloader = stick_check(&report, "bootloader");
+            assert_eq!(bootloader.status, CheckStatus::Pass);
+            // Without a menu there are no ISO references to check.
+            assert!(!report.checks.iter().any(|c| c.name == "isos"));
+
+            let data_only = vec![part("/dev/sdb1", DATA_LABEL, "exfat")];
+            let report = verify_parts("/dev/sdb", &data_only, &root);
+            assert_eq!(report.checks.len(), 2);
+            assert_eq!(report.checks[0].status, CheckStatus::Fail);
+            let _ = fs::remove_dir_all(root);
+        }
+
+        #[test]
+        fn uninstall_reformat_leaves_one_plain_exfat_partition() {
+            let late: std::sync::Arc<dyn CommandRunner> = std::sync::Arc::new(LateNodeRunner {
+                node: "/dev/sdb1",
+                appears_on: 2,
+                polls: Default::default(),
+            });
+            let target = disk("/dev/sdb", vec![], false);
+            take_run_log();
+            {
+                let _runner = runner::ActiveRunner::start(&late);
+                uninstall_disk(&target, true, Path::new("/nonexistent")).unwrap();
+            }
+            assert_eq!(
+                take_run_log(),
+                vec![
+                    "dd if=/dev/zero of=/dev/sdb bs=512 count=2048 seek=0 conv=fsync",
+                    "dd if=/dev/zero of=/dev/sdb bs=512 count=2048 seek=33552384 conv=fsync",
+                    "parted /dev/sdb -s mklabel gpt",
+                    "parted /dev/sdb -s mkpart primary 1MiB 100%",
+                    "parted /dev/sdb -s print",
+                    "partprobe /dev/sdb",
+                    "udevadm settle",
+                    "mkfs.exfat -n DATA /dev/sdb1",
+                    "sgdisk -t 1:EBD0A0A2-B9E5-4433-87C0-68B6B97199C7 /dev/sdb",
+                ]
+            );
+        }
+
+        #[test]
+        fn payload_dir_flag_overrides_env() {
+            let flag = payload_fixture("payload-flag");
+            let env = payload_fixture("payload-env");
+            let resolved = resolve_payload_dir(
+                Some(flag.to_str().unwrap()),
+                Some(env.to_string_lossy().to_string()),
+            )
+            .unwrap();
+            assert_eq!(resolved, flag);
+            let resolved = resolve_payload_dir(None, Some(env.to_string_lossy().to_string())).unwrap();
+            assert_eq!(resolved, env);
+            let _ = fs::remove_dir_all(flag);
+            let _ = fs::remove_dir_all(env);
+        }
+
+        #[test]
+        fn payload_dir_requires_esp_and_data() {
+            let dir = payload_fixture("payload-layout");
+            fs::remove_dir_all(dir.join("data")).unwrap();
+            let err = resolve_payload_dir(Some(dir.to_str().unwrap()), None).unwrap_err();
+            assert!(format!("{err}").contains("payload_dir must contain esp/ and data/"));
+            let err = resolve_payload_dir(None, None).unwrap_err();
+            assert!(format!("{err}").contains("RAIDHOS_PAYLOAD_DIR is not set"));
+
+            // A file where data/ should be is no better than a missing one.
+            fs::write(dir.join("data"), b"not a dir").unwrap();
+            let err = resolve_payload_dir(Some(dir.to_str().unwrap()), None).unwrap_err();
+            assert!(format!("{err}").contains("payload_dir must contain esp/ and data/"));
+            let _ = fs::remove_dir_all(dir);
+        }
+
+        #[test]
+        fn payload_dir_must_be_a_directory() {
+            let dir = payload_fixture("payload-file");
+            let file = dir.join("payload.tar");
+            fs::write(&file, b"tar").unwrap();
+            let env = Some(file.to_string_lossy().to_string());
+            let err = resolve_payload_dir(None, env).unwrap_err();
+            let expected = "RAIDHOS_PAYLOAD_DIR is not a directory";
+            assert!(matches!(&err, CoreError::Validation(m) if m == expected));
+            let err = resolve_payload_dir(Some(file.to_str().unwrap()), None).unwrap_err();
+            assert!(format!("{err}").contains("payload_dir is not a directory"));
+            let _ = fs::remove_dir_all(dir);
+        }
+
+        fn part(id: &str, label: &str, fstype: &str) -> PartitionInfo {
+            PartitionInfo {
+                id: id.to_string(),
+                label: label.to_string(),
+                fstype: fstype.to_string(),
+                fsver: None,
+                fssize: None,
+                mountpoints: vec![],
+                holders: vec![],
+            }
+        }
+
+        #[test]
+        fn relabel_renames_data_and_rewrites_grub_search() {
+            let root = std::env::temp_dir().join(format!("raidhos-relabel-{}", std::process::id()));
+            let _ = fs::remove_dir_all(&root);
+            let esp = root.join("relabel-sdb1");
+            fs::create_dir_all(esp.join("EFI/BOOT")).unwrap();
+            let cfg = crate::render_grub_cfg(&crate::BootConfig::default(), DATA_LABEL);
+            fs::write(esp.join("EFI/BOOT/grub.cfg"), &cfg).unwrap();
+            let parts = vec![
+                part("/dev/sdb1", ESP_LABEL, "vfat"),
+                part("/dev/sdb2", DATA_LABEL, "exfat"),
+            ];
+
+            take_run_log();
+            let rewritten = relabel_parts(&parts, "STICK2", &root).unwrap();
+            assert_eq!(rewritten, vec!["/dev/sdb1:EFI/BOOT/grub.cfg"]);
+            let log = take_run_log();
+            assert_eq!(log[0], "exfatlabel /dev/sdb2 STICK2");
+            assert!(log[1].starts_with("mount /dev/sdb1 "));
+            let cfg = fs::read_to_string(esp.join("EFI/BOOT/grub.cfg")).unwrap();
+            assert!(cfg.contains("search --no-floppy --label STICK2 --set=root"));
+            assert!(relabel_data("/dev/sdb", "BAD LABEL").is_err());
+
+            for (fstype, relabel) in [
+                ("ext4", "e2label /dev/sdb2 STICK3"),
+                ("ntfs", "ntfslabel /dev/sdb2 STICK3"),
+            ] {
+                let parts = vec![
+                    part("/dev/sdb1", ESP_LABEL, "vfat"),
+                    part("/dev/sdb2", "STICK2", fstype),
+                ];
+                relabel_parts(&parts, "STICK3", &root).unwrap();
+                assert_eq!(take_run_log()[0], relabel);
+            }
+            let esp_only = vec![part("/dev/sdb1", ESP_LABEL, "vfat")];
+            assert!(relabel_parts(&esp_only, "STICK3", &root).is_err());
+            let _ = fs::remove_dir_all(root);
+        }
+
+        #[test]
+        fn ensure_layout_on_correct_stick_only_refreshes_payload() {
+            let parts = vec![
+                part("/dev/sdb1", "RAIDHOS_EFI", "vfat"),
+                part("/dev/sdb2", "DATA", "exfat"),
+            ];
+            assert_eq!(plan_layout(&parts, Layout::BootAndData, DataFsType::ExFat, DATA_LABEL), vec![LayoutChange::RefreshPayload]);
+
+            let sink = Sink { events: std::cell::RefCell::new(Vec::new()) };
+            let disks = vec![disk("/dev/sdb", vec![], false)];
+            let outcome = ensure_layout_with(
+                req("/dev/sdb", true, true),
+                &sink,
+                &disks,
+                &parts,
+                Instant::now(),
+            )
+            .unwrap();
+            assert_eq!(outcome.changes, vec![LayoutChange::RefreshPayload]);
+        }
+
+        #[test]
+        fn ensure_layout_relabels_data_to_the_requested_label() {
+            let parts = vec![part("/dev/sdc1", DATA_LABEL, "exfat")];
+            let disks = vec![disk("/dev/sdc", vec![], false)];
+            let sink = Sink {
+                events: std::cell::RefCell::new(Vec::new()),
+            };
+            let mut request = req("/dev/sdc", true, false);
+            request.allow_write = true;
+            request.options.layout = Layout::DataOnly;
+            request.options.data_label = Some("ISOS".to_string());
+
+            take_run_log();
+            let outcome =
+                ensure_layout_with(request.clone(), &sink, &disks, &parts, Instant::now()).unwrap();
+            assert_eq!(outcome.changes, vec![LayoutChange::RelabelData]);
+            assert_eq!(take_run_log(), vec!["exfatlabel /dev/sdc1 ISOS"]);
+
+            let relabelled = vec![part("/dev/sdc1", "ISOS", "exfat")];
+            let outcome =
+                ensure_layout_with(request.clone(), &sink, &disks, &relabelled, Instant::now())
+                    .unwrap();
+            assert!(outcome.changes.is_empty());
+
+            request.options.data_label = None;
+            request.options.unique_labels = true;
+            ensure_layout_with(request, &sink, &disks, &parts, Instant::now()).unwrap();
+            assert_eq!(take_run_log(), vec!["exfatlabel /dev/sdc1 DATA2"]);
+        }
+
+        #[test]
+        fn ensure_layout_needs_fatlabel_to_relabel_the_esp() {
+            let parts = vec![
+                part("/dev/sdb1", "EFI", "vfat"),
+                part("/dev/sdb2", DATA_LABEL, "exfat"),
+            ];
+            let disks = vec![disk("/dev/sdb", vec![], false)];
+            let sink = Sink {
+                events: std::cell::RefCell::new(Vec::new()),
+            };
+            let mut request = req("/dev/sdb", true, false);
+            request.allow_write = true;
+            request.options.runner = Some(std::sync::Arc::new(MissingTools(&["fatlabel"])));
+
+            let err =
+                ensure_layout_with(request, &sink, &disks, &parts, Instant::now()).unwrap_err();
+            assert!(
+                matches!(&err, CoreError::Validation(m) if m.contains("dosfstools")),
+                "{err}"
+            );
+        }
+
+        #[test]
+        fn ensure_layout_rejects_zero_ends() {
+            let parts = vec![part("/dev/sdb1", DATA_LABEL, "exfat")];
+            let disks = vec![disk("/dev/sdb", vec![], false)];
+            let sink = Sink {
+                events: std::cell::RefCell::new(Vec::new()),
+            };
+            let mut request = req("/dev/sdb", true, true);
+            request.options.zero_ends = true;
+
+            take_run_log();
+            let err =
+                ensure_layout_with(request, &sink, &disks, &parts, Instant::now()).unwrap_err();
+            assert!(matches!(err, CoreError::Validation(_)), "{err}");
+            assert!(take_run_log().is_empty());
+        }
+
+        #[test]
+        fn ensure_layout_fixes_only_what_differs() {
+            let parts = vec![
+                part("/dev/sdb1", "EFI", "vfat"),
+                part("/dev/sdb2", "DATA", "ntfs"),
+            ];
+            assert_eq!(
+                plan_layout(&parts, Layout::BootAndData, DataFsType::ExFat, DATA_LABEL),
+                vec![
+                    LayoutChange::RelabelEsp,
+                    LayoutChange::FormatData,
+                    LayoutChange::RefreshPayload
+                ]
+            );
+            let parts = vec![part("/dev/sdb1", "DATA", "exfat")];
+            assert_eq!(plan_layout(&parts, Layout::BootAndData, DataFsType::ExFat, DATA_LABEL), FULL_LAYOUT.to_vec());
+        }
+
+        #[test]
+        fn extra_mkfs_args_appear_in_plan() {
+            let options = InstallOptions {
+                esp_mkfs_args: vec!["-s".to_string(), "8".to_string()],
+                data_mkfs_args: vec!["--cluster-size=128K".to_string()],
+                ..Default::default()
+            };
+            take_run_log();
+            format_esp("/dev/sdb1", &options).unwrap();
+            format_data("/dev/sdb2", &options).unwrap();
+            assert_eq!(
+                take_run_log(),
+                vec![
+                    "mkfs.vfat -F 32 -n RAIDHOS_EFI -s 8 /dev/sdb1",
+                    "mkfs.exfat -n DATA --cluster-size=128K /dev/sdb2",
+                ]
+            );
+        }
+
+        #[test]
+        fn format_disk_plans_one_partition_of_the_chosen_fs() {
+            take_run_log();
+            format_whole_disk("/dev/sdb", 16 << 30, FormatFs::ExFat, "STICK").unwrap();
+            assert_eq!(
+                take_run_log(),
+                vec![
+                    "dd if=/dev/zero of=/dev/sdb bs=512 count=2048 seek=0 conv=fsync",
+                    "dd if=/dev/zero of=/dev/sdb bs=512 count=2048 seek=33552384 conv=fsync",
+                    "parted /dev/sdb -s mklabel gpt",
+                    "parted /dev/sdb -s mkpart primary 1MiB 100%",
+                    "parted /dev/sdb -s print",
+                    "mkfs.exfat -n STICK /dev/sdb1",
+                    "sgdisk -t 1:EBD0A0A2-B9E5-4433-87C0-68B6B97199C7 /dev/sdb",
+                ]
+            );
+
+            format_whole_disk("/dev/sdb", 16 << 30, FormatFs::Ext4, "STICK").unwrap();
+            let log = take_run_log();
+            assert!(log.contains(&"mkfs.ext4 -F -L STICK /dev/sdb1".to_string()));
+            assert!(!log.iter().any(|c| c.starts_with("sgdisk")));
+            format_whole_disk("/dev/sdb", 16 << 30, FormatFs::Fat32, "STICK").unwrap();
+            assert!(take_run_log().contains(&"mkfs.vfat -F 32 -n STICK /dev/sdb1".to_string()));
+
+            let err = format_disk("/dev/sdb", FormatFs::ExFat, "STICK", false).unwrap_err();
+            assert!(format!("{err}").contains("write blocked"), "{err}");
+            assert!(take_run_log().is_empty());
+
+            assert!("ntfs".parse::<FormatFs>().is_err());
+            assert_eq!("FAT32".parse::<FormatFs>().unwrap(), FormatFs::Fat32);
+        }
+
+        /// Reports `node` missing until it has been polled `appears_on` times.
+        #[derive(Debug)]
+        struct LateNodeRunner {
+            node: &'static str,
+            appears_on: usize,
+            polls: std::sync::atomic::AtomicUsize,
         }
-    }
 
-    #[cfg(test)]
-    mod tests {
-        use super::*;
+        impl CommandRunner for LateNodeRunner {
+            fn run(&self, cmd: &str, args: &[&str]) -> Result<runner::CommandStatus> {
+                StubRunner.run(cmd, args)
+            }
 
-        struct Sink {
-            events: std::cell::RefCell<Vec<ProgressEvent>>,
+            fn has_cmd(&self, _cmd: &str) -> bool {
+                true
+            }
+
+            fn node_exists(&self, node: &str) -> bool {
+                if node != self.node {
+                    return true;
+                }
+                let polls = self.polls.fetch_add(1, std::sync::atomic::Ordering::SeqCst) + 1;
+                polls >= self.appears_on
+            }
         }
 
-        impl ProgressSink for Sink {
-            fn emit(&self, event: ProgressEvent) {
-                self.events.borrow_mut().push(event);
+        #[test]
+        fn late_partition_node_is_rescanned_before_format() {
+            let late: std::sync::Arc<dyn CommandRunner> = std::sync::Arc::new(LateNodeRunner {
+                node: "/dev/sdb1",
+                appears_on: 2,
+                polls: Default::default(),
+            });
+            take_run_log();
+            {
+                let _runner = runner::ActiveRunner::start(&late);
+                format_whole_disk("/dev/sdb", 16 << 30, FormatFs::Fat32, "STICK").unwrap();
             }
+            let log = take_run_log();
+            let probe = log.iter().position(|c| c == "partprobe /dev/sdb").unwrap();
+            assert_eq!(log[probe + 1], "udevadm settle");
+            assert_eq!(log[probe + 2], "mkfs.vfat -F 32 -n STICK /dev/sdb1");
+            assert_eq!(log.iter().filter(|c| c.starts_with("partprobe")).count(), 1);
+
+            let never: std::sync::Arc<dyn CommandRunner> = std::sync::Arc::new(LateNodeRunner {
+                node: "/dev/sdb1",
+                appears_on: usize::MAX,
+                polls: Default::default(),
+            });
+            let err = {
+                let _runner = runner::ActiveRunner::start(&never);
+                format_whole_disk("/dev/sdb", 16 << 30, FormatFs::Fat32, "STICK").unwrap_err()
+            };
+            assert!(
+                format!("{err}").contains("partition /dev/sdb1 did not appear"),
+                "{err}"
+            );
+            let log = take_run_log();
+            let probes = log.iter().filter(|c| c.starts_with("partprobe")).count();
+            assert_eq!(probes, PARTITION_NODE_POLLS as usize);
+            assert!(!log.iter().any(|c| c.starts_with("mkfs")));
         }
 
-        fn disk(id: &str, mounts: Vec<&str>, is_system: bool) -> DiskInfo {
-            DiskInfo {
-                id: id.to_string(),
-                model: "Test".to_string(),
-                size_bytes: 128,
-                removable: true,
-                mountpoints: mounts.into_iter().map(|m| m.to_string()).collect(),
-                is_system,
+        #[test]
+        fn format_mode_picks_each_formatters_flags() {
+            let mut options = InstallOptions::default();
+            assert!(options.quick_format);
+            take_run_log();
+            assert!(format_esp("/dev/sdb1", &options).unwrap().is_empty());
+            assert!(format_data("/dev/sdb2", &options).unwrap().is_empty());
+            options.quick_format = false;
+            assert!(format_esp("/dev/sdb1", &options).unwrap().is_empty());
+            assert!(format_data("/dev/sdb2", &options).unwrap().is_empty());
+            assert_eq!(
+                take_run_log(),
+                vec![
+                    "mkfs.vfat -F 32 -n RAIDHOS_EFI /dev/sdb1",
+                    "mkfs.exfat -n DATA /dev/sdb2",
+                    "mkfs.vfat -F 32 -n RAIDHOS_EFI -c /dev/sdb1",
+                    "mkfs.exfat --full-format -n DATA /dev/sdb2",
+                ]
+            );
+
+            let mut warnings = Vec::new();
+            for tool in EXFAT_FORMATTERS {
+                assert!(format_mode_args(tool, true, &mut warnings).is_empty());
             }
+            assert!(warnings.is_empty());
+            assert!(format_mode_args("mkexfatfs", false, &mut warnings).is_empty());
+            assert_eq!(
+                warnings,
+                vec!["mkexfatfs cannot do a full format; doing a quick one"]
+            );
         }
 
-        fn req(device: &str, wipe: bool, dry_run: bool) -> InstallRequest {
-            InstallRequest {
-                device: device.to_string(),
-                payload_version: "0.1.0".to_string(),
-                wipe,
-                dry_run,
-                allow_write: false,
+        /// exfat-utils' `mkfs.exfat`, which has no `--version` banner.
+        #[derive(Debug)]
+        struct ExfatUtils;
+
+        impl CommandRunner for ExfatUtils {
+            fn run(&self, _cmd: &str, _args: &[&str]) -> Result<runner::CommandStatus> {
+                Ok(runner::CommandStatus::success())
+            }
+
+            fn output(&self, _cmd: &str, _args: &[&str]) -> Result<runner::CommandOutput> {
+                Err(CoreError::Io("mkexfatfs: unrecognized option".to_string()))
+            }
+
+            fn has_cmd(&self, _cmd: &str) -> bool {
+                true
+            }
+
+            fn node_exists(&self, _node: &str) -> bool {
+                true
             }
         }
 
         #[test]
-        fn validate_rejects_non_dev_path() {
-            let sink = Sink { events: std::cell::RefCell::new(Vec::new()) };
-            let disks = vec![disk("/dev/sdb", vec![], false)];
-            let err = validate_install(&req("sdb", true, true), &sink, &disks).unwrap_err();
-            assert!(format!("{err}").contains("device must be an absolute /dev path"));
+        fn exfat_utils_mkfs_exfat_gets_no_full_format_flag() {
+            let runner: std::sync::Arc<dyn CommandRunner> = std::sync::Arc::new(ExfatUtils);
+            let _active = runner::ActiveRunner::start(&runner);
+            let mut warnings = Vec::new();
+            assert!(format_mode_args("mkfs.exfat", false, &mut warnings).is_empty());
+            assert_eq!(
+                warnings,
+                vec!["mkfs.exfat cannot do a full format; doing a quick one"]
+            );
         }
 
         #[test]
-        fn validate_rejects_without_wipe_flag() {
+        fn validate_rejects_dangerous_mkfs_args() {
             let sink = Sink { events: std::cell::RefCell::new(Vec::new()) };
             let disks = vec![disk("/dev/sdb", vec![], false)];
-            let err = validate_install(&req("/dev/sdb", false, true), &sink, &disks).unwrap_err();
-            assert!(format!("{err}").contains("wipe flag"));
+            let mut request = req("/dev/sdb", true, true);
+            request.options.data_mkfs_args = vec!["/dev/sda".to_string()];
+            let err = validate_install(&request, &sink, &disks).unwrap_err();
+            assert!(format!("{err}").contains("mkfs argument not allowed"));
         }
 
         #[test]
-        fn validate_rejects_system_disk() {
-            let sink = Sink { events: std::cell::RefCell::new(Vec::new()) };
-            let disks = vec![disk("/dev/sda", vec!["/"], true)];
-            let err = validate_install(&req("/dev/sda", true, true), &sink, &disks).unwrap_err();
-            assert!(format!("{err}").contains("system disk"));
+        fn scan_on_device_mounts_read_only_and_unmounts() {
+            let root =
+                std::env::temp_dir().join(format!("raidhos-scan-device-{}", std::process::id()));
+            let isos = root.join("scan-sdb2").join("boot").join("isos");
+            fs::create_dir_all(&isos).unwrap();
+            fs::write(isos.join("debian.iso"), b"iso").unwrap();
+            let data = part("/dev/sdb2", "DATA", "exfat");
+
+            take_run_log();
+            let entries = scan_partition(&data, &root).unwrap();
+            assert_eq!(entries.len(), 1);
+            assert_eq!(entries[0].path, "/boot/isos/debian.iso");
+            let target = root.join("scan-sdb2").display().to_string();
+            assert_eq!(
+                take_run_log(),
+                vec![
+                    format!("mount -o ro /dev/sdb2 {target}"),
+                    format!("umount {target}")
+                ]
+            );
+            let _ = fs::remove_dir_all(root);
         }
 
         #[test]
-        fn validate_rejects_mounted_partitions() {
-            let sink = Sink { events: std::cell::RefCell::new(Vec::new()) };
-            let disks = vec![disk("/dev/sdb", vec!["/media/usb"], false)];
-            let err = validate_install(&req("/dev/sdb", true, true), &sink, &disks).unwrap_err();
-            assert!(format!("{err}").contains("mounted"));
+        fn scan_on_device_requires_a_filesystem() {
+            let root = std::env::temp_dir();
+            let err = scan_partition(&part("/dev/sdb2", "", ""), &root).unwrap_err();
+            assert!(format!("{err}").contains("has no filesystem"));
+            let found = find_partition(PARTITIONS_FIXTURE.as_bytes(), "/dev/sdb2").unwrap();
+            assert_eq!(found.map(|p| p.fstype).as_deref(), Some("exfat"));
+            let disk = find_partition(PARTITIONS_FIXTURE.as_bytes(), "/dev/sdb").unwrap();
+            assert!(disk.is_none());
         }
 
         #[test]
-        fn validate_accepts_safe_disk() {
+        fn scan_prefers_iso_volume_label_for_title() {
+            let dir =
+                std::env::temp_dir().join(format!("raidhos-iso-label-{}", std::process::id()));
+            fs::create_dir_all(&dir).unwrap();
+            let image = crate::iso::fixture_header("Ubuntu 24.04 LTS amd64", "Canonical Ltd.");
+            fs::write(dir.join("ubuntu-24.04.iso"), image).unwrap();
+            fs::write(dir.join("plain.iso"), b"no header").unwrap();
+
+            let entries = scan_isos(vec![dir.display().to_string()]).unwrap();
+            let titles: Vec<&str> = entries.iter().map(|e| e.title.as_str()).collect();
+            assert_eq!(titles, vec!["plain", "Ubuntu 24.04 LTS amd64"]);
+            assert_eq!(entries[1].publisher.as_deref(), Some("Canonical Ltd."));
+            assert_eq!(entries[0].publisher, None);
+            // A bare header has no boot record and an empty root directory.
+            assert_eq!(entries[1].is_bootable, Some(false));
+            assert_eq!(entries[0].is_bootable, None);
+            let _ = fs::remove_dir_all(dir);
+        }
+
+        #[test]
+        fn scan_with_checksums_hashes_only_on_request() {
+            let dir = std::env::temp_dir().join(format!("raidhos-iso-sha-{}", std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join("abc.iso"), b"abc").unwrap();
+
+            let dirs = vec![dir.display().to_string()];
+            let plain = scan_isos(dirs.clone()).unwrap();
+            assert_eq!(plain[0].sha256, None);
+            let unhashed = crate::scan_isos_with_checksums(dirs.clone(), false).unwrap();
+            assert_eq!(unhashed[0].sha256, None);
+            let hashed = crate::scan_isos_with_checksums(dirs, true).unwrap();
+            assert_eq!(
+                hashed[0].sha256.as_deref(),
+                Some("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad")
+            );
+            let _ = fs::remove_dir_all(dir);
+        }
+
+        #[test]
+        fn scan_sorts_accented_and_mixed_script_titles() {
+            let dir = std::env::temp_dir().join(format!("raidhos-iso-sort-{}", std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            // "Élive" in decomposed form, as some filesystems store it.
+            let names = [
+                "zorin",
+                "Русский",
+                "E\u{301}live",
+                "Ubuntu",
+                "alpine",
+                "Åland",
+                "ubuntu",
+            ];
+            for name in names {
+                fs::write(dir.join(format!("{name}.iso")), b"no header").unwrap();
+            }
+
+            let entries = scan_isos(vec![dir.display().to_string()]).unwrap();
+            let titles: Vec<&str> = entries.iter().map(|e| e.title.as_str()).collect();
+            let sorted = [
+                "Åland",
+                "alpine",
+                "E\u{301}live",
+                "Ubuntu",
+                "ubuntu",
+                "zorin",
+                "Русский",
+            ];
+            assert_eq!(titles, sorted);
+            let _ = fs::remove_dir_all(dir);
+        }
+
+        #[test]
+        fn scan_summary_totals_across_directories() {
+            let root =
+                std::env::temp_dir().join(format!("raidhos-scan-summary-{}", std::process::id()));
+            let (a, b) = (root.join("a"), root.join("b").join("linux"));
+            fs::create_dir_all(&a).unwrap();
+            fs::create_dir_all(&b).unwrap();
+            fs::write(a.join("ubuntu-24.04-desktop-amd64.iso"), vec![0u8; 1000]).unwrap();
+            fs::write(a.join("notes.txt"), b"not an iso").unwrap();
+            fs::write(b.join("Ubuntu-22.04-server.ISO"), vec![0u8; 24]).unwrap();
+            fs::write(b.join("debian-live-12.iso"), vec![0u8; 7]).unwrap();
+            fs::write(b.join("2024-rescue.iso"), vec![0u8; 3]).unwrap();
+
+            let dirs = vec![a.display().to_string(), root.join("b").display().to_string()];
+            let (entries, summary) = crate::scan_isos_summary(dirs).unwrap();
+            assert_eq!(entries.len(), 4);
+            assert_eq!(summary.count, 4);
+            assert_eq!(summary.total_bytes, 1034);
+            assert_eq!(summary.by_distro.get("ubuntu"), Some(&2));
+            assert_eq!(summary.by_distro.get("debian"), Some(&1));
+            assert_eq!(summary.by_distro.get("other"), Some(&1));
+            let debian = entries.iter().find(|e| e.title.starts_with("debian")).unwrap();
+            assert_eq!(debian.params, "boot=live components quiet splash");
+            let _ = fs::remove_dir_all(root);
+        }
+
+        #[test]
+        fn phase_names_map_to_phase_ids() {
+            let event = |phase: &str| ProgressEvent {
+                phase: phase.to_string(),
+                message: String::new(),
+                percent: None,
+                ..Default::default()
+            };
+            for id in [
+                PhaseId::Validate,
+                PhaseId::Prepare,
+                PhaseId::Partition,
+                PhaseId::Format,
+                PhaseId::Layout,
+                PhaseId::Payload,
+                PhaseId::Write,
+                PhaseId::Finalize,
+                PhaseId::Complete,
+                PhaseId::Cancelled,
+                PhaseId::Paused,
+                PhaseId::Plan,
+                PhaseId::Rollback,
+            ] {
+                assert_eq!(event(id.as_str()).phase_id(), id);
+            }
+            assert_eq!(event("rebooting").phase_id(), PhaseId::Other);
+            assert_eq!(event("").phase_id(), PhaseId::Other);
+        }
+
+        #[test]
+        fn dry_run_outcome_reports_duration() {
             let sink = Sink { events: std::cell::RefCell::new(Vec::new()) };
             let disks = vec![disk("/dev/sdb", vec![], false)];
-            let ok = validate_install(&req("/dev/sdb", true, true), &sink, &disks);
-            assert!(ok.is_ok());
-            assert!(!sink.events.borrow().is_empty());
+            let started = Instant::now() - std::time::Duration::from_millis(25);
+            let outcome =
+                install_with_disks(req("/dev/sdb", true, true), &sink, &disks, started).unwrap();
+            assert!(outcome.dry_run);
+            assert!(outcome.duration_ms >= 25 && outcome.duration_ms < 60_000);
+            let events = sink.events.borrow();
+            let last = events.last().unwrap();
+            assert_eq!(last.phase, "complete");
+            assert!(last.message.contains(&format!("{} ms", outcome.duration_ms)));
         }
     }
 }
 
 #[cfg(target_os = "macos")]
 mod platform {
-    use super::{CoreError, DiskInfo, InstallRequest, ProgressSink, Result};
+    use super::plist;
+    use super::{
+        CoreError, DiskInfo, DiskListing, DiskWithPartitions, InstallOutcome, InstallRequest,
+        ProgressSink, Result, ToolVersions,
+    };
+    use std::process::Command;
+
+    fn diskutil(args: &[&str]) -> Result<String> {
+        let output = Command::new("diskutil")
+            .args(args)
+            .output()
+            .map_err(|e| CoreError::Io(e.to_string()))?;
+        if !output.status.success() {
+            return Err(CoreError::Command {
+                cmd: format!("diskutil {}", args.join(" ")),
+                code: output.status.code(),
+                stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+            });
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
 
+    /// Whole disks from `diskutil list`, each completed by `diskutil info`.
+    /// A disk whose info cannot be read keeps the list's fields.
     pub fn list_disks() -> Result<Vec<DiskInfo>> {
+        let mut disks = plist::parse_diskutil_list(&diskutil(&["list", "-plist"])?)?;
+        for disk in &mut disks {
+            if let Ok(info) = diskutil(&["info", "-plist", &disk.id]) {
+                let _ = plist::apply_diskutil_info(disk, &info);
+            }
+        }
+        Ok(disks)
+    }
+
+    pub fn list_install_targets() -> Result<Vec<DiskInfo>> {
+        Err(CoreError::NotImplemented(
+            "macOS disk discovery not implemented yet".to_string(),
+        ))
+    }
+
+    pub fn list_disks_tree() -> Result<Vec<DiskWithPartitions>> {
+        Err(CoreError::NotImplemented(
+            "macOS disk discovery not implemented yet".to_string(),
+        ))
+    }
+
+    pub fn list_disks_diagnostic() -> Result<DiskListing> {
+        Err(CoreError::NotImplemented(
+            "macOS disk discovery not implemented yet".to_string(),
+        ))
+    }
+
+    pub fn detect_data_label(_esp_mount: &str) -> Result<Option<String>> {
+        Err(CoreError::NotImplemented(
+            "macOS partition discovery not implemented yet".to_string(),
+        ))
+    }
+
+    pub fn iso_destination(_mount_path: &str) -> Result<std::path::PathBuf> {
+        Err(CoreError::NotImplemented(
+            "macOS partition discovery not implemented yet".to_string(),
+        ))
+    }
+
+    pub fn check_esp_mount(_esp_mount: &str) -> Result<()> {
+        Err(CoreError::NotImplemented(
+            "macOS partition discovery not implemented yet".to_string(),
+        ))
+    }
+
+    pub fn free_bytes(_mount_path: &str) -> Result<Option<u64>> {
+        Err(CoreError::NotImplemented(
+            "macOS partition discovery not implemented yet".to_string(),
+        ))
+    }
+
+    pub fn mounted_scan_candidates() -> Result<Vec<String>> {
+        Err(CoreError::NotImplemented(
+            "macOS partition discovery not implemented yet".to_string(),
+        ))
+    }
+
+    pub fn install(_req: InstallRequest, _sink: &dyn ProgressSink) -> Result<InstallOutcome> {
+        Err(CoreError::NotImplemented(
+            "macOS installer not implemented yet".to_string(),
+        ))
+    }
+
+    pub fn uninstall(_device: &str, _reformat: bool, _allow_write: bool) -> Result<()> {
+        Err(CoreError::NotImplemented(
+            "macOS installer not implemented yet".to_string(),
+        ))
+    }
+
+    pub fn relabel_data(_device: &str, _new_label: &str) -> Result<()> {
+        Err(CoreError::NotImplemented(
+            "macOS installer not implemented yet".to_string(),
+        ))
+    }
+
+    pub fn verify_stick(_device: &str) -> Result<super::StickReport> {
+        Err(CoreError::NotImplemented(
+            "macOS partition discovery not implemented yet".to_string(),
+        ))
+    }
+
+    pub fn boot_device() -> Result<Option<String>> {
         Err(CoreError::NotImplemented(
             "macOS disk discovery not implemented yet".to_string(),
         ))
     }
 
-    pub fn install(_req: InstallRequest, _sink: &dyn ProgressSink) -> Result<()> {
+    pub fn format_disk(
+        _device: &str,
+        _fs: super::FormatFs,
+        _label: &str,
+        _allow_write: bool,
+    ) -> Result<()> {
+        Err(CoreError::NotImplemented(
+            "macOS installer not implemented yet".to_string(),
+        ))
+    }
+
+    pub fn ensure_layout(_req: InstallRequest, _sink: &dyn ProgressSink) -> Result<InstallOutcome> {
         Err(CoreError::NotImplemented(
             "macOS installer not implemented yet".to_string(),
         ))
@@ -635,19 +7318,155 @@ mod platform {
             "macOS ISO scan not implemented yet".to_string(),
         ))
     }
+
+    pub fn scan_isos_on_device(_device: String) -> Result<Vec<super::IsoEntry>> {
+        Err(CoreError::NotImplemented(
+            "macOS ISO scan not implemented yet".to_string(),
+        ))
+    }
+
+    pub fn measure_write_speed(_device: &str) -> Result<u64> {
+        Err(CoreError::NotImplemented(
+            "macOS installer not implemented yet".to_string(),
+        ))
+    }
+
+    /// The installer's tools are not used on this platform yet.
+    pub fn tool_versions() -> ToolVersions {
+        ToolVersions::default()
+    }
 }
 
 #[cfg(target_os = "windows")]
 mod platform {
-    use super::{CoreError, DiskInfo, InstallRequest, ProgressSink, Result};
+    use super::windisk;
+    use super::{
+        CoreError, DiskInfo, DiskListing, DiskWithPartitions, InstallOutcome, InstallRequest,
+        ProgressSink, Result, ToolVersions,
+    };
+    use std::process::Command;
+
+    /// Whole disks, as a JSON array even when there is only one.
+    const GET_DISK: &str = "ConvertTo-Json -InputObject @(Get-Disk | Select-Object \
+         Number, FriendlyName, SerialNumber, Size, BusType, IsBoot, IsSystem)";
+
+    /// Partitions and the paths they are mounted at.
+    const GET_PARTITION: &str = "ConvertTo-Json -InputObject @(Get-Partition | \
+         Select-Object DiskNumber, PartitionNumber, AccessPaths)";
+
+    fn powershell(script: &str) -> Result<String> {
+        let output = Command::new("powershell")
+            .args(["-NoProfile", "-NonInteractive", "-Command", script])
+            .output()
+            .map_err(|e| CoreError::Io(e.to_string()))?;
+        if !output.status.success() {
+            return Err(CoreError::Command {
+                cmd: "powershell".to_string(),
+                code: output.status.code(),
+                stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+            });
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
 
+    /// Disks from `Get-Disk`. Mountpoints come from `Get-Partition`; when
+    /// that fails the disks are listed without them.
     pub fn list_disks() -> Result<Vec<DiskInfo>> {
+        let disks = powershell(GET_DISK)?;
+        let partitions = powershell(GET_PARTITION).unwrap_or_default();
+        windisk::parse_get_disk(&disks, &partitions)
+    }
+
+    pub fn list_install_targets() -> Result<Vec<DiskInfo>> {
+        Err(CoreError::NotImplemented(
+            "Windows disk discovery not implemented yet".to_string(),
+        ))
+    }
+
+    pub fn list_disks_tree() -> Result<Vec<DiskWithPartitions>> {
+        Err(CoreError::NotImplemented(
+            "Windows disk discovery not implemented yet".to_string(),
+        ))
+    }
+
+    pub fn list_disks_diagnostic() -> Result<DiskListing> {
+        Err(CoreError::NotImplemented(
+            "Windows disk discovery not implemented yet".to_string(),
+        ))
+    }
+
+    pub fn detect_data_label(_esp_mount: &str) -> Result<Option<String>> {
+        Err(CoreError::NotImplemented(
+            "Windows partition discovery not implemented yet".to_string(),
+        ))
+    }
+
+    pub fn iso_destination(_mount_path: &str) -> Result<std::path::PathBuf> {
+        Err(CoreError::NotImplemented(
+            "Windows partition discovery not implemented yet".to_string(),
+        ))
+    }
+
+    pub fn check_esp_mount(_esp_mount: &str) -> Result<()> {
+        Err(CoreError::NotImplemented(
+            "Windows partition discovery not implemented yet".to_string(),
+        ))
+    }
+
+    pub fn free_bytes(_mount_path: &str) -> Result<Option<u64>> {
+        Err(CoreError::NotImplemented(
+            "Windows partition discovery not implemented yet".to_string(),
+        ))
+    }
+
+    pub fn mounted_scan_candidates() -> Result<Vec<String>> {
+        Err(CoreError::NotImplemented(
+            "Windows partition discovery not implemented yet".to_string(),
+        ))
+    }
+
+    pub fn install(_req: InstallRequest, _sink: &dyn ProgressSink) -> Result<InstallOutcome> {
+        Err(CoreError::NotImplemented(
+            "Windows installer not implemented yet".to_string(),
+        ))
+    }
+
+    pub fn uninstall(_device: &str, _reformat: bool, _allow_write: bool) -> Result<()> {
+        Err(CoreError::NotImplemented(
+            "Windows installer not implemented yet".to_string(),
+        ))
+    }
+
+    pub fn relabel_data(_device: &str, _new_label: &str) -> Result<()> {
+        Err(CoreError::NotImplemented(
+            "Windows installer not implemented yet".to_string(),
+        ))
+    }
+
+    pub fn verify_stick(_device: &str) -> Result<super::StickReport> {
+        Err(CoreError::NotImplemented(
+            "Windows partition discovery not implemented yet".to_string(),
+        ))
+    }
+
+    pub fn boot_device() -> Result<Option<String>> {
         Err(CoreError::NotImplemented(
             "Windows disk discovery not implemented yet".to_string(),
         ))
     }
 
-    pub fn install(_req: InstallRequest, _sink: &dyn ProgressSink) -> Result<()> {
+    pub fn format_disk(
+        _device: &str,
+        _fs: super::FormatFs,
+        _label: &str,
+        _allow_write: bool,
+    ) -> Result<()> {
+        Err(CoreError::NotImplemented(
+            "Windows installer not implemented yet".to_string(),
+        ))
+    }
+
+    pub fn ensure_layout(_req: InstallRequest, _sink: &dyn ProgressSink) -> Result<InstallOutcome> {
         Err(CoreError::NotImplemented(
             "Windows installer not implemented yet".to_string(),
         ))
@@ -658,4 +7477,21 @@ mod platform {
             "Windows ISO scan not implemented yet".to_string(),
         ))
     }
+
+    pub fn scan_isos_on_device(_device: String) -> Result<Vec<super::IsoEntry>> {
+        Err(CoreError::NotImplemented(
+            "Windows ISO scan not implemented yet".to_string(),
+        ))
+    }
+
+    pub fn measure_write_speed(_device: &str) -> Result<u64> {
+        Err(CoreError::NotImplemented(
+            "Windows installer not implemented yet".to_string(),
+        ))
+    }
+
+    /// The installer's tools are not used on this platform yet.
+    pub fn tool_versions() -> ToolVersions {
+        ToolVersions::default()
+    }
 }
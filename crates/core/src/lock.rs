@@ -0,0 +1,57 @@
+//! Advisory per-device locks that keep concurrent installs apart.
+
+use crate::{CoreError, Result};
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::path::Path;
+
+const LOCK_DIR: &str = "/run/raidhos";
+
+/// An exclusive `flock` on `/run/raidhos/<device>.lock`, released on drop.
+#[derive(Debug)]
+pub struct DeviceLock {
+    _file: File,
+}
+
+impl DeviceLock {
+    pub fn acquire(device: &str) -> Result<Self> {
+        Self::acquire_in(Path::new(LOCK_DIR), device)
+    }
+
+    fn acquire_in(dir: &Path, device: &str) -> Result<Self> {
+        let name = device.rsplit('/').next().unwrap_or(device);
+        if name.is_empty() {
+            return Err(CoreError::Validation("device name is empty".to_string()));
+        }
+        fs::create_dir_all(dir).map_err(|e| CoreError::Io(e.to_string()))?;
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(dir.join(format!("{name}.lock")))
+            .map_err(|e| CoreError::Io(e.to_string()))?;
+        match file.try_lock() {
+            Ok(()) => Ok(DeviceLock { _file: file }),
+            Err(TryLockError::WouldBlock) => Err(CoreError::Validation(
+                "another install is in progress for this device".to_string(),
+            )),
+            Err(TryLockError::Error(e)) => Err(CoreError::Io(e.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn second_lock_fails_while_first_is_held() {
+        let dir = std::env::temp_dir().join(format!("raidhos-lock-{}", std::process::id()));
+        let first = DeviceLock::acquire_in(&dir, "/dev/sdb").unwrap();
+        let err = DeviceLock::acquire_in(&dir, "/dev/sdb").unwrap_err();
+        assert!(format!("{err}").contains("another install is in progress"));
+        assert!(DeviceLock::acquire_in(&dir, "/dev/sdc").is_ok());
+        drop(first);
+        assert!(DeviceLock::acquire_in(&dir, "/dev/sdb").is_ok());
+        let _ = fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,90 @@
+//! Default boot parameters keyed by ISO filename pattern.
+//!
+//! The shipped database lives in `data/params_db.json`. It only supplies
+//! defaults for scanned entries; params saved in a user's boot config win.
+
+use crate::{CoreError, Result};
+use regex::{Regex, RegexBuilder};
+use serde::Deserialize;
+use std::sync::OnceLock;
+
+const BUILTIN_DB: &str = include_str!("../data/params_db.json");
+
+#[derive(Debug, Deserialize)]
+struct RawDb {
+    rules: Vec<ParamsRule>,
+}
+
+/// One database entry. `kernel` and `initrd` are paths inside the ISO.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ParamsRule {
+    pub pattern: String,
+    pub params: String,
+    #[serde(default)]
+    pub kernel: Option<String>,
+    #[serde(default)]
+    pub initrd: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct ParamsDb {
+    rules: Vec<(Regex, ParamsRule)>,
+}
+
+impl ParamsDb {
+    /// Parses a database; patterns match case-insensitively against the
+    /// ISO file name.
+    pub fn from_json(json: &str) -> Result<Self> {
+        let raw: RawDb = serde_json::from_str(json).map_err(|e| CoreError::Parse(e.to_string()))?;
+        let rules = raw
+            .rules
+            .into_iter()
+            .map(|rule| {
+                let re = RegexBuilder::new(&rule.pattern)
+                    .case_insensitive(true)
+                    .build()
+                    .map_err(|e| CoreError::Parse(format!("{}: {e}", rule.pattern)))?;
+                Ok((re, rule))
+            })
+            .collect::<Result<_>>()?;
+        Ok(ParamsDb { rules })
+    }
+
+    /// The database shipped with this build.
+    pub fn builtin() -> &'static ParamsDb {
+        static DB: OnceLock<ParamsDb> = OnceLock::new();
+        DB.get_or_init(|| ParamsDb::from_json(BUILTIN_DB).expect("builtin params_db.json is valid"))
+    }
+
+    /// First rule whose pattern matches `file_name`.
+    pub fn lookup(&self, file_name: &str) -> Option<&ParamsRule> {
+        self.rules
+            .iter()
+            .find(|(re, _)| re.is_match(file_name))
+            .map(|(_, rule)| rule)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builtin_matches_well_known_isos() {
+        let db = ParamsDb::builtin();
+        let ubuntu = db.lookup("ubuntu-24.04-desktop-amd64.iso").unwrap();
+        assert_eq!(ubuntu.params, "quiet splash");
+        assert_eq!(ubuntu.kernel.as_deref(), Some("/casper/vmlinuz"));
+        let debian = db.lookup("debian-live-12.5.0-amd64-kde.iso").unwrap();
+        assert!(debian.params.starts_with("boot=live"));
+        let arch = db.lookup("ArchLinux-2024.06.01-x86_64.ISO").unwrap();
+        assert_eq!(arch.params, "archisobasedir=arch");
+        assert!(db.lookup("windows11.iso").is_none());
+    }
+
+    #[test]
+    fn invalid_pattern_is_a_parse_error() {
+        let err = ParamsDb::from_json(r#"{"rules": [{"pattern": "(", "params": ""}]}"#);
+        assert!(matches!(err, Err(CoreError::Parse(_))));
+    }
+}
@@ -0,0 +1,117 @@
+//! Versioned payloads staged side by side under one root, e.g.
+//! `payloads/1.1.10/{esp,data,manifest.json}`.
+
+use crate::{CoreError, InstallRequest, Result};
+use std::cmp::Ordering;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// One installable payload directory.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize)]
+pub struct PayloadSource {
+    /// `version` from `manifest.json`, or the directory name without one.
+    pub version: String,
+    /// `name` from `manifest.json`, if any.
+    pub name: Option<String>,
+    /// The directory holding `esp/`, `data/` and `manifest.json`.
+    pub path: PathBuf,
+}
+
+/// Subdirectories of `root` that hold a complete payload, newest version
+/// first. Directories missing `esp/`, `data/` or a readable JSON
+/// `manifest.json` are skipped.
+pub fn list_payloads(root: &Path) -> Result<Vec<PayloadSource>> {
+    let entries =
+        fs::read_dir(root).map_err(|e| CoreError::Io(format!("{}: {e}", root.display())))?;
+    let mut payloads: Vec<PayloadSource> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.join("esp").is_dir() && path.join("data").is_dir())
+        .filter_map(|path| read_source(&path))
+        .collect();
+    payloads.sort_by(|a, b| compare_versions(&b.version, &a.version));
+    Ok(payloads)
+}
+
+fn read_source(path: &Path) -> Option<PayloadSource> {
+    let body = fs::read(path.join("manifest.json")).ok()?;
+    let manifest: serde_json::Value = serde_json::from_slice(&body).ok()?;
+    let field = |key: &str| manifest.get(key).and_then(|v| v.as_str()).map(String::from);
+    let dir_name = path.file_name()?.to_string_lossy().into_owned();
+    Some(PayloadSource {
+        version: field("version").unwrap_or(dir_name),
+        name: field("name"),
+        path: path.to_path_buf(),
+    })
+}
+
+/// Dotted versions compared part by part, numerically where both parts are
+/// numbers, so `1.10` sorts after `1.9`.
+fn compare_versions(a: &str, b: &str) -> Ordering {
+    let mut a_parts = a.split('.');
+    let mut b_parts = b.split('.');
+    loop {
+        match (a_parts.next(), b_parts.next()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) => {
+                let order = match (x.parse::<u64>(), y.parse::<u64>()) {
+                    (Ok(x), Ok(y)) => x.cmp(&y),
+                    _ => x.cmp(y),
+                };
+                if order != Ordering::Equal {
+                    return order;
+                }
+            }
+        }
+    }
+}
+
+impl InstallRequest {
+    /// Installs from `source`, overriding `payload_dir` and
+    /// `payload_version`.
+    pub fn with_payload(mut self, source: &PayloadSource) -> Self {
+        self.payload_dir = Some(source.path.display().to_string());
+        self.payload_version = source.version.clone();
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stage(root: &Path, dir: &str, manifest: &str) {
+        fs::create_dir_all(root.join(dir).join("esp")).unwrap();
+        fs::create_dir_all(root.join(dir).join("data")).unwrap();
+        fs::write(root.join(dir).join("manifest.json"), manifest).unwrap();
+    }
+
+    #[test]
+    fn lists_versioned_payloads_newest_first() {
+        let root = std::env::temp_dir().join(format!("raidhos-payloads-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        stage(
+            &root,
+            "1.1.9",
+            r#"{"name": "ventoy-payload", "version": "1.1.9"}"#,
+        );
+        stage(
+            &root,
+            "latest",
+            r#"{"name": "ventoy-payload", "version": "1.1.10"}"#,
+        );
+        stage(&root, "broken", "not json");
+        fs::create_dir_all(root.join("no-esp/data")).unwrap();
+        fs::write(root.join("no-esp/manifest.json"), "{}").unwrap();
+
+        let payloads = list_payloads(&root).unwrap();
+        let versions: Vec<&str> = payloads.iter().map(|p| p.version.as_str()).collect();
+        assert_eq!(versions, vec!["1.1.10", "1.1.9"]);
+        assert_eq!(payloads[0].path, root.join("latest"));
+        assert_eq!(payloads[0].name.as_deref(), Some("ventoy-payload"));
+        assert!(list_payloads(&root.join("missing")).is_err());
+        let _ = fs::remove_dir_all(root);
+    }
+}
@@ -0,0 +1,513 @@
+//! Just enough of Apple's XML property list format to read `diskutil`
+//! output, instead of pulling in a full plist crate.
+//!
+//! Supports `dict`, `array`, `string`, `integer`, `true`/`false`, and
+//! keeps `real`, `date` and `data` as their raw text.
+
+use crate::{CoreError, DiskInfo, Result};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Value {
+    Dict(Vec<(String, Value)>),
+    Array(Vec<Value>),
+    String(String),
+    Integer(i64),
+    Bool(bool),
+}
+
+impl Value {
+    pub(crate) fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Dict(entries) => entries.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_u64(&self) -> Option<u64> {
+        match self {
+            Value::Integer(n) => u64::try_from(*n).ok(),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    /// The array's items; empty for anything else.
+    pub(crate) fn items(&self) -> &[Value] {
+        match self {
+            Value::Array(items) => items,
+            _ => &[],
+        }
+    }
+}
+
+/// Parses an XML plist document into its root value.
+pub(crate) fn parse(xml: &str) -> Result<Value> {
+    let mut parser = Parser { rest: xml };
+    let root = parser.tag()?;
+    if root.name != "plist" || root.closing {
+        return Err(parse_error("expected <plist>"));
+    }
+    let value = parser.value()?;
+    let end = parser.tag()?;
+    if end.name != "plist" || !end.closing {
+        return Err(parse_error("expected </plist>"));
+    }
+    Ok(value)
+}
+
+struct Tag<'a> {
+    name: &'a str,
+    closing: bool,
+    /// Self-closing, as in `<true/>` or `<array/>`.
+    empty: bool,
+}
+
+struct Parser<'a> {
+    rest: &'a str,
+}
+
+impl<'a> Parser<'a> {
+    /// The next element tag, skipping the XML declaration, doctype and
+    /// comments.
+    fn tag(&mut self) -> Result<Tag<'a>> {
+        loop {
+            self.rest = self.rest.trim_start();
+            if let Some(comment) = self.rest.strip_prefix("<!--") {
+                let end = comment
+                    .find("-->")
+                    .ok_or_else(|| parse_error("open comment"))?;
+                self.rest = &comment[end + 3..];
+            } else if self.rest.starts_with("<?") || self.rest.starts_with("<!") {
+                let end = self.rest.find('>').ok_or_else(|| parse_error("open tag"))?;
+                self.rest = &self.rest[end + 1..];
+            } else {
+                break;
+            }
+        }
+        let body = self
+            .rest
+            .strip_prefix('<')
+            .ok_or_else(|| parse_error("expected a tag"))?;
+        let end = body.find('>').ok_or_else(|| parse_error("open tag"))?;
+        let inner = &body[..end];
+        self.rest = &body[end + 1..];
+        let (closing, inner) = match inner.strip_prefix('/') {
+            Some(inner) => (true, inner),
+            None => (false, inner),
+        };
+        let (empty, inner) = match inner.strip_suffix('/') {
+            Some(inner) => (true, inner),
+            None => (false, inner),
+        };
+        let name = inner.split_whitespace().next().unwrap_or_default();
+        Ok(Tag {
+            name,
+            closing,
+            empty,
+        })
+    }
+
+    /// Unescaped text up to `</name>`.
+    fn text(&mut self, name: &str) -> Result<String> {
+        let close = format!("</{name}>");
+        let end = self
+            .rest
+            .find(&close)
+            .ok_or_else(|| parse_error(&format!("missing {close}")))?;
+        let raw = &self.rest[..end];
+        self.rest = &self.rest[end + close.len()..];
+        Ok(unescape(raw))
+    }
+
+    fn value(&mut self) -> Result<Value> {
+        let tag = self.tag()?;
+        self.value_from(tag)
+    }
+
+    fn value_from(&mut self, tag: Tag<'a>) -> Result<Value> {
+        if tag.closing {
+            return Err(parse_error(&format!("unexpected </{}>", tag.name)));
+        }
+        Ok(match (tag.name, tag.empty) {
+            ("true", _) => Value::Bool(true),
+            ("false", _) => Value::Bool(false),
+            ("dict", true) => Value::Dict(Vec::new()),
+            ("array", true) => Value::Array(Vec::new()),
+            ("string" | "real" | "date" | "data", true) => Value::String(String::new()),
+            ("dict", false) => {
+                let mut entries = Vec::new();
+                loop {
+                    let tag = self.tag()?;
+                    let key = match (tag.name, tag.closing, tag.empty) {
+                        ("dict", true, _) => break,
+                        ("key", false, true) => String::new(),
+                        ("key", false, false) => self.text("key")?,
+                        _ => return Err(parse_error("expected <key> in <dict>")),
+                    };
+                    entries.push((key, self.value()?));
+                }
+                Value::Dict(entries)
+            }
+            ("array", false) => {
+                let mut items = Vec::new();
+                loop {
+                    let tag = self.tag()?;
+                    if tag.closing && tag.name == "array" {
+                        break;
+                    }
+                    items.push(self.value_from(tag)?);
+                }
+                Value::Array(items)
+            }
+            ("string" | "real" | "date" | "data", false) => Value::String(self.text(tag.name)?),
+            ("integer", false) => {
+                let text = self.text("integer")?;
+                let n = text
+                    .trim()
+                    .parse()
+                    .map_err(|_| parse_error(&format!("bad integer {text:?}")))?;
+                Value::Integer(n)
+            }
+            (name, _) => return Err(parse_error(&format!("unsupported element <{name}>"))),
+        })
+    }
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+fn parse_error(message: &str) -> CoreError {
+    CoreError::Parse(format!("plist: {message}"))
+}
+
+/// Whole disks from `diskutil list -plist`. APFS containers are folded
+/// into the physical disk holding them, so a disk carrying the boot volume
+/// is flagged `is_system`. The list output has no model, bus or removable
+/// flag; those stay empty/false.
+pub(crate) fn parse_diskutil_list(xml: &str) -> Result<Vec<DiskInfo>> {
+    let root = parse(xml)?;
+    let entries = root
+        .get("AllDisksAndPartitions")
+        .ok_or_else(|| parse_error("no AllDisksAndPartitions"))?
+        .items();
+
+    let mut disks: Vec<DiskInfo> = Vec::new();
+    let mut containers = Vec::new();
+    for entry in entries {
+        let Some(id) = entry.get("DeviceIdentifier").and_then(Value::as_str) else {
+            continue;
+        };
+        let mut mounts = Vec::new();
+        let mut partitions = Vec::new();
+        collect_mounts(entry, &mut mounts);
+        for part in entry
+            .get("Partitions")
+            .into_iter()
+            .chain(entry.get("APFSVolumes"))
+            .flat_map(Value::items)
+        {
+            if let Some(part_id) = part.get("DeviceIdentifier").and_then(Value::as_str) {
+                partitions.push(format!("/dev/{part_id}"));
+            }
+            collect_mounts(part, &mut mounts);
+        }
+        if let Some(stores) = entry.get("APFSPhysicalStores") {
+            let stores: Vec<String> = stores
+                .items()
+                .iter()
+                .filter_map(|s| s.get("DeviceIdentifier").and_then(Value::as_str))
+                .map(|s| format!("/dev/{s}"))
+                .collect();
+            containers.push((stores, mounts));
+            continue;
+        }
+        disks.push(DiskInfo {
+            id: format!("/dev/{id}"),
+            model: String::new(),
+            serial: None,
+            size_bytes: entry.get("Size").and_then(Value::as_u64),
+            removable: false,
+            external: false,
+            transport: None,
+            mountpoints: mounts,
+            is_system: false,
+            partition_count: partitions.len(),
+            partitions,
+        });
+    }
+
+    for (stores, mounts) in containers {
+        if let Some(disk) = disks
+            .iter_mut()
+            .find(|d| d.partitions.iter().any(|p| stores.contains(p)))
+        {
+            disk.mountpoints.extend(mounts);
+        }
+    }
+    for disk in &mut disks {
+        disk.is_system = disk.mountpoints.iter().any(|m| m == "/");
+    }
+    Ok(disks)
+}
+
+/// Fills in what `diskutil list` leaves out from `diskutil info -plist
+/// <disk>`: the model, bus and removable flags. `transport` is lowercased
+/// to match lsblk's (`usb`, `sata`).
+pub(crate) fn apply_diskutil_info(disk: &mut DiskInfo, xml: &str) -> Result<()> {
+    let info = parse(xml)?;
+    let flag = |key: &str| info.get(key).and_then(Value::as_bool);
+    if let Some(model) = info.get("MediaName").and_then(Value::as_str) {
+        disk.model = model.trim().to_string();
+    }
+    disk.removable = flag("Removable")
+        .or(flag("RemovableMedia"))
+        .unwrap_or(false)
+        || flag("Ejectable").unwrap_or(false);
+    disk.transport = info
+        .get("BusProtocol")
+        .and_then(Value::as_str)
+        .filter(|bus| !bus.is_empty())
+        .map(str::to_ascii_lowercase);
+    disk.external = disk.removable
+        || flag("Internal") == Some(false)
+        || disk.transport.as_deref() == Some("usb");
+    if disk.size_bytes.is_none() {
+        disk.size_bytes = info
+            .get("TotalSize")
+            .or(info.get("Size"))
+            .and_then(Value::as_u64);
+    }
+    Ok(())
+}
+
+fn collect_mounts(entry: &Value, mounts: &mut Vec<String>) {
+    if let Some(mount) = entry.get("MountPoint").and_then(Value::as_str) {
+        if !mount.is_empty() {
+            mounts.push(mount.to_string());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `diskutil list -plist` from a MacBook with a USB stick plugged in,
+    /// trimmed to the keys this module reads plus a few it skips.
+    const DISKUTIL_LIST: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+	<key>AllDisks</key>
+	<array>
+		<string>disk0</string>
+		<string>disk0s1</string>
+		<string>disk0s2</string>
+		<string>disk1</string>
+		<string>disk4</string>
+		<string>disk4s1</string>
+	</array>
+	<key>AllDisksAndPartitions</key>
+	<array>
+		<dict>
+			<key>Content</key>
+			<string>GUID_partition_scheme</string>
+			<key>DeviceIdentifier</key>
+			<string>disk0</string>
+			<key>OSInternal</key>
+			<false/>
+			<key>Partitions</key>
+			<array>
+				<dict>
+					<key>Content</key>
+					<string>EFI</string>
+					<key>DeviceIdentifier</key>
+					<string>disk0s1</string>
+					<key>Size</key>
+					<integer>314572800</integer>
+					<key>VolumeName</key>
+					<string>EFI</string>
+				</dict>
+				<dict>
+					<key>Content</key>
+					<string>Apple_APFS</string>
+					<key>DeviceIdentifier</key>
+					<string>disk0s2</string>
+					<key>Size</key>
+					<integer>500963174400</integer>
+				</dict>
+			</array>
+			<key>Size</key>
+			<integer>500277790720</integer>
+		</dict>
+		<dict>
+			<key>APFSPhysicalStores</key>
+			<array>
+				<dict>
+					<key>DeviceIdentifier</key>
+					<string>disk0s2</string>
+				</dict>
+			</array>
+			<key>APFSVolumes</key>
+			<array>
+				<dict>
+					<key>DeviceIdentifier</key>
+					<string>disk1s1</string>
+					<key>MountPoint</key>
+					<string>/System/Volumes/Data</string>
+					<key>VolumeName</key>
+					<string>Macintosh HD - Data</string>
+				</dict>
+				<dict>
+					<key>DeviceIdentifier</key>
+					<string>disk1s5</string>
+					<key>MountPoint</key>
+					<string>/</string>
+					<key>VolumeName</key>
+					<string>Macintosh HD</string>
+				</dict>
+			</array>
+			<key>Content</key>
+			<string>EF57347C-0000-11AA-AA11-00306543ECAC</string>
+			<key>DeviceIdentifier</key>
+			<string>disk1</string>
+			<key>OSInternal</key>
+			<false/>
+			<key>Size</key>
+			<integer>500963174400</integer>
+		</dict>
+		<dict>
+			<key>Content</key>
+			<string>FDisk_partition_scheme</string>
+			<key>DeviceIdentifier</key>
+			<string>disk4</string>
+			<key>OSInternal</key>
+			<false/>
+			<key>Partitions</key>
+			<array>
+				<dict>
+					<key>Content</key>
+					<string>DOS_FAT_32</string>
+					<key>DeviceIdentifier</key>
+					<string>disk4s1</string>
+					<key>MountPoint</key>
+					<string>/Volumes/R&amp;D STICK</string>
+					<key>Size</key>
+					<integer>15997075456</integer>
+					<key>VolumeName</key>
+					<string>R&amp;D STICK</string>
+				</dict>
+			</array>
+			<key>Size</key>
+			<integer>16008609792</integer>
+		</dict>
+	</array>
+	<key>VolumesFromDisks</key>
+	<array/>
+	<key>WholeDisks</key>
+	<array>
+		<string>disk0</string>
+		<string>disk1</string>
+		<string>disk4</string>
+	</array>
+</dict>
+</plist>
+"#;
+
+    #[test]
+    fn diskutil_list_maps_to_disk_info() {
+        let disks = parse_diskutil_list(DISKUTIL_LIST).unwrap();
+        let ids: Vec<&str> = disks.iter().map(|d| d.id.as_str()).collect();
+        assert_eq!(ids, vec!["/dev/disk0", "/dev/disk4"]);
+
+        let internal = &disks[0];
+        assert_eq!(internal.size_bytes, Some(500277790720));
+        assert_eq!(internal.partitions, vec!["/dev/disk0s1", "/dev/disk0s2"]);
+        assert_eq!(internal.mountpoints, vec!["/System/Volumes/Data", "/"]);
+        assert!(internal.is_system);
+
+        let stick = &disks[1];
+        assert_eq!(stick.size_bytes, Some(16008609792));
+        assert_eq!(stick.partition_count, 1);
+        assert_eq!(stick.mountpoints, vec!["/Volumes/R&D STICK"]);
+        assert!(!stick.is_system);
+
+        let root = parse(DISKUTIL_LIST).unwrap();
+        assert_eq!(root.get("VolumesFromDisks"), Some(&Value::Array(vec![])));
+        assert_eq!(root.get("WholeDisks").unwrap().items().len(), 3);
+        assert!(parse("<plist><dict><string>x</string></dict></plist>").is_err());
+    }
+
+    /// `diskutil info -plist disk4` for the stick above, trimmed.
+    const DISKUTIL_INFO: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+	<key>BusProtocol</key>
+	<string>USB</string>
+	<key>DeviceIdentifier</key>
+	<string>disk4</string>
+	<key>Ejectable</key>
+	<true/>
+	<key>Internal</key>
+	<false/>
+	<key>MediaName</key>
+	<string>SanDisk Ultra Fit </string>
+	<key>Removable</key>
+	<false/>
+	<key>RemovableMedia</key>
+	<false/>
+	<key>Size</key>
+	<integer>16008609792</integer>
+	<key>WholeDisk</key>
+	<true/>
+</dict>
+</plist>
+"#;
+
+    #[test]
+    fn diskutil_info_fills_model_and_removable() {
+        let mut disks = parse_diskutil_list(DISKUTIL_LIST).unwrap();
+        let stick = &mut disks[1];
+        apply_diskutil_info(stick, DISKUTIL_INFO).unwrap();
+        assert_eq!(stick.model, "SanDisk Ultra Fit");
+        assert!(stick.removable, "Ejectable counts as removable");
+        assert!(stick.external);
+        assert_eq!(stick.transport.as_deref(), Some("usb"));
+        assert_eq!(stick.size_bytes, Some(16008609792));
+
+        let internal = &mut disks[0];
+        let ssd = DISKUTIL_INFO
+            .replace("<string>USB</string>", "<string>Apple Fabric</string>")
+            .replace(
+                "<key>Ejectable</key>\n\t<true/>",
+                "<key>Ejectable</key>\n\t<false/>",
+            )
+            .replace(
+                "<key>Internal</key>\n\t<false/>",
+                "<key>Internal</key>\n\t<true/>",
+            );
+        apply_diskutil_info(internal, &ssd).unwrap();
+        assert!(!internal.removable);
+        assert!(!internal.external);
+        assert!(internal.is_system);
+    }
+}
@@ -0,0 +1,154 @@
+//! Non-destructive checks on a prospective install target.
+
+use crate::{grub, BootConfig, DiskInfo};
+use std::path::Path;
+
+/// Buses that put a disk inside the machine rather than on a port.
+const INTERNAL_TRANSPORTS: &[&str] = &["sata", "ata", "nvme", "sas", "scsi", "ide"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    /// Install may proceed, but the user should confirm explicitly.
+    Warning,
+    /// Install will be refused.
+    Error,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PreflightIssue {
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl PreflightIssue {
+    fn warning(message: String) -> Self {
+        PreflightIssue {
+            severity: Severity::Warning,
+            message,
+        }
+    }
+
+    fn error(message: String) -> Self {
+        PreflightIssue {
+            severity: Severity::Error,
+            message,
+        }
+    }
+}
+
+pub(crate) fn is_internal(disk: &DiskInfo) -> bool {
+    !disk.external
+        && disk
+            .transport
+            .as_deref()
+            .is_some_and(|t| INTERNAL_TRANSPORTS.contains(&t))
+}
+
+/// Issues with `disk` as a target, errors first.
+pub(crate) fn check_disk(disk: &DiskInfo) -> Vec<PreflightIssue> {
+    let mut issues = Vec::new();
+    if disk.is_system {
+        issues.push(PreflightIssue::error(format!(
+            "{} is the system disk",
+            disk.id
+        )));
+    }
+    if !disk.mountpoints.is_empty() {
+        issues.push(PreflightIssue::error(format!(
+            "{} has mounted partitions: {}",
+            disk.id,
+            disk.mountpoints.join(", ")
+        )));
+    }
+    if is_internal(disk) && !disk.is_system {
+        issues.push(PreflightIssue::warning(format!(
+            "{} is an internal {} disk, not removable media; everything on it will be erased",
+            disk.id,
+            disk.transport.as_deref().unwrap_or_default()
+        )));
+    }
+    if disk.size_bytes.is_none() {
+        issues.push(PreflightIssue::warning(format!(
+            "size of {} is unknown; skipping size check",
+            disk.id
+        )));
+    }
+    issues
+}
+
+/// One error per boot entry whose ISO is not on the data partition mounted
+/// at `data_mount` or whose kernel arguments are unsafe, and a warning when
+/// the menu has no entries at all.
+pub fn check_boot_config(data_mount: &Path, config: &BootConfig) -> Vec<PreflightIssue> {
+    check_boot_config_with(data_mount, config, Severity::Warning)
+}
+
+/// [`check_boot_config`], reporting an empty menu at `empty_menu` severity
+/// for callers that refuse to write a menu that boots nothing.
+pub fn check_boot_config_with(
+    data_mount: &Path,
+    config: &BootConfig,
+    empty_menu: Severity,
+) -> Vec<PreflightIssue> {
+    if config.entries.is_empty() {
+        return vec![PreflightIssue {
+            severity: empty_menu,
+            message: "boot menu has no entries and would boot nothing; add an ISO".to_string(),
+        }];
+    }
+    grub::missing_isos(data_mount, config)
+        .into_iter()
+        .map(|iso| PreflightIssue::error(format!("ISO not found on data partition: {iso}")))
+        .chain(
+            grub::unsafe_kargs(config)
+                .into_iter()
+                .map(PreflightIssue::error),
+        )
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn disk(removable: bool, transport: &str) -> DiskInfo {
+        DiskInfo {
+            id: "/dev/sdb".to_string(),
+            model: "Test".to_string(),
+            serial: None,
+            size_bytes: Some(16 << 30),
+            removable,
+            external: removable || transport == "usb",
+            transport: Some(transport.to_string()),
+            mountpoints: vec![],
+            is_system: false,
+            partition_count: 0,
+            partitions: vec![],
+        }
+    }
+
+    #[test]
+    fn internal_non_removable_disk_warns() {
+        let issues = check_disk(&disk(false, "sata"));
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].severity, Severity::Warning);
+        assert!(issues[0].message.contains("internal sata disk"));
+    }
+
+    #[test]
+    fn empty_boot_menu_is_flagged() {
+        let config = BootConfig::default();
+        let issues = check_boot_config(Path::new("/nonexistent"), &config);
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].severity, Severity::Warning);
+        assert!(issues[0].message.contains("no entries"));
+        let issues = check_boot_config_with(Path::new("/nonexistent"), &config, Severity::Error);
+        assert_eq!(issues[0].severity, Severity::Error);
+    }
+
+    #[test]
+    fn usb_stick_has_no_issues() {
+        assert!(check_disk(&disk(true, "usb")).is_empty());
+        assert!(check_disk(&disk(false, "usb")).is_empty());
+    }
+}
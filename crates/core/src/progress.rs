@@ -0,0 +1,275 @@
+//! Percent-complete reporting for long byte copies, such as writing an
+//! image to a device chunk by chunk.
+
+use crate::{ControlToken, CoreError, ProgressEvent, ProgressSink, Result};
+use std::fs::File;
+use std::io::{Read, Write};
+
+/// Turns a byte count into progress events, emitting only when the whole
+/// percent changes. 100% is held back for [`finish`](Self::finish), so it
+/// fires exactly once, after the data is on disk. Each event carries the
+/// byte counts.
+pub struct PercentProgress<'a> {
+    sink: &'a dyn ProgressSink,
+    phase: String,
+    message: String,
+    total: u64,
+    done: u64,
+    last: Option<u8>,
+    /// Percents the copy's 0..=100 is reported as, when it is one step of
+    /// a longer job.
+    span: (u8, u8),
+    show_bytes: bool,
+}
+
+impl<'a> PercentProgress<'a> {
+    pub fn new(sink: &'a dyn ProgressSink, phase: &str, message: &str, total: u64) -> Self {
+        PercentProgress {
+            sink,
+            phase: phase.to_string(),
+            message: message.to_string(),
+            total,
+            done: 0,
+            last: None,
+            span: (0, 100),
+            show_bytes: false,
+        }
+    }
+
+    /// Reports the copy as running from `from`% to `to`% of a longer job,
+    /// still emitting once per whole percent of the copy itself.
+    pub fn within(mut self, from: u8, to: u8) -> Self {
+        self.span = (from, to.max(from));
+        self
+    }
+
+    /// Appends the bytes copied and the total to each message, e.g.
+    /// "Copying (1.5 MiB of 3.0 MiB)".
+    pub fn showing_bytes(mut self) -> Self {
+        self.show_bytes = true;
+        self
+    }
+
+    /// Records `bytes` more copied.
+    pub fn advance(&mut self, bytes: u64) {
+        self.done += bytes;
+        if self.total == 0 {
+            return;
+        }
+        let percent = (self.done.saturating_mul(100) / self.total).min(99) as u8;
+        self.emit(percent);
+    }
+
+    /// Reports 100%; later calls do nothing.
+    pub fn finish(&mut self) {
+        self.emit(100);
+    }
+
+    /// An event in this copy's phase with no percent, e.g. "Paused".
+    fn notice(&self, message: &str) {
+        self.sink.emit(ProgressEvent {
+            phase: self.phase.clone(),
+            message: message.to_string(),
+            percent: None,
+            ..Default::default()
+        });
+    }
+
+    fn emit(&mut self, percent: u8) {
+        if self.last.is_some_and(|last| last >= percent) {
+            return;
+        }
+        self.last = Some(percent);
+        let (from, to) = self.span;
+        let message = if self.show_bytes {
+            let (done, total) = (byte_size(self.done), byte_size(self.total));
+            format!("{} ({done} of {total})", self.message)
+        } else {
+            self.message.clone()
+        };
+        self.sink.emit(ProgressEvent {
+            phase: self.phase.clone(),
+            message,
+            percent: Some(from + ((to - from) as u16 * percent as u16 / 100) as u8),
+            bytes_done: Some(self.done),
+            bytes_total: Some(self.total),
+        });
+    }
+}
+
+/// `bytes` in the largest binary unit it reaches, to one decimal.
+fn byte_size(bytes: u64) -> String {
+    const UNITS: [(u64, &str); 3] = [(1 << 30, "GiB"), (1 << 20, "MiB"), (1 << 10, "KiB")];
+    match UNITS.iter().find(|(unit, _)| bytes >= *unit) {
+        Some((unit, name)) => format!("{:.1} {name}", bytes as f64 / *unit as f64),
+        None => format!("{bytes} B"),
+    }
+}
+
+/// Copies `reader` into `dest` in `chunk_size` pieces, then fsyncs `dest`
+/// before reporting 100%. Returns the number of bytes copied.
+///
+/// `control` is checked before each chunk: a pause blocks the copy, with a
+/// "Paused" and then a "Resumed" event, and a cancel stops it.
+pub fn copy_with_progress(
+    reader: &mut impl Read,
+    dest: &mut File,
+    chunk_size: usize,
+    progress: &mut PercentProgress<'_>,
+    control: &ControlToken,
+) -> Result<u64> {
+    let copied = copy_chunks(reader, dest, chunk_size, progress, control)?;
+    dest.sync_all().map_err(|e| CoreError::Io(e.to_string()))?;
+    progress.finish();
+    Ok(copied)
+}
+
+/// [`copy_with_progress`] without the fsync and the 100% event, for one
+/// file of a larger copy.
+pub(crate) fn copy_chunks(
+    reader: &mut impl Read,
+    dest: &mut impl Write,
+    chunk_size: usize,
+    progress: &mut PercentProgress<'_>,
+    control: &ControlToken,
+) -> Result<u64> {
+    let mut buf = vec![0u8; chunk_size.max(1)];
+    let mut copied = 0u64;
+    loop {
+        if control.is_paused() {
+            progress.notice("Paused");
+            control.wait_while_paused()?;
+            progress.notice("Resumed");
+        }
+        control.check()?;
+        let n = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(CoreError::Io(e.to_string())),
+        };
+        dest.write_all(&buf[..n])
+            .map_err(|e| CoreError::Io(e.to_string()))?;
+        copied += n as u64;
+        progress.advance(n as u64);
+    }
+    Ok(copied)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::sync::Mutex;
+    use std::time::{Duration, Instant};
+
+    struct Percents(RefCell<Vec<u8>>);
+
+    /// Records events and pauses `control` once the copy reaches 50%.
+    struct PauseAtHalf<'a> {
+        events: &'a Mutex<Vec<ProgressEvent>>,
+        control: ControlToken,
+    }
+
+    impl ProgressSink for PauseAtHalf<'_> {
+        fn emit(&self, event: ProgressEvent) {
+            if event.percent == Some(50) {
+                self.control.pause();
+            }
+            self.events.lock().unwrap().push(event);
+        }
+    }
+
+    impl ProgressSink for Percents {
+        fn emit(&self, event: ProgressEvent) {
+            self.0.borrow_mut().push(event.percent.unwrap());
+        }
+    }
+
+    fn copy_image(size: usize, chunk_size: usize) -> Vec<u8> {
+        let sink = Percents(RefCell::new(Vec::new()));
+        let path =
+            std::env::temp_dir().join(format!("raidhos-image-{}-{chunk_size}", std::process::id()));
+        let mut dest = File::create(&path).unwrap();
+        let image = vec![0xA5u8; size];
+        let mut progress = PercentProgress::new(&sink, "write", "Writing image", size as u64);
+        let control = ControlToken::new();
+        let mut reader = image.as_slice();
+        let copied =
+            copy_with_progress(&mut reader, &mut dest, chunk_size, &mut progress, &control)
+                .unwrap();
+        progress.finish();
+        assert_eq!(copied, size as u64);
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), size as u64);
+        let _ = std::fs::remove_file(path);
+        sink.0.into_inner()
+    }
+
+    #[test]
+    fn emits_once_per_whole_percent() {
+        // 160 chunks of 64 KiB: one event for each of 0..=99, then 100.
+        let percents = copy_image(10 << 20, 64 << 10);
+        assert_eq!(percents.len(), 101);
+        assert_eq!(percents, (0..=100).collect::<Vec<u8>>());
+    }
+
+    #[test]
+    fn hundred_percent_fires_exactly_once() {
+        // 10 chunks of 1 MiB: the last chunk is held at 99 until the fsync.
+        let percents = copy_image(10 << 20, 1 << 20);
+        assert_eq!(percents, vec![10, 20, 30, 40, 50, 60, 70, 80, 90, 99, 100]);
+        assert_eq!(percents.iter().filter(|&&p| p == 100).count(), 1);
+    }
+
+    #[test]
+    fn pausing_holds_progress_until_resumed() {
+        let control = ControlToken::new();
+        let events = Mutex::new(Vec::new());
+        let path = std::env::temp_dir().join(format!("raidhos-pause-{}", std::process::id()));
+        let mut dest = File::create(&path).unwrap();
+        let image = vec![0x5Au8; 100 << 10];
+        let len = |events: &Mutex<Vec<ProgressEvent>>| events.lock().unwrap().len();
+
+        std::thread::scope(|scope| {
+            let copy = scope.spawn(|| {
+                let sink = PauseAtHalf {
+                    events: &events,
+                    control: control.clone(),
+                };
+                let mut progress = PercentProgress::new(&sink, "payload", "Copying", 100 << 10);
+                let mut reader = image.as_slice();
+                copy_with_progress(&mut reader, &mut dest, 1 << 10, &mut progress, &control)
+            });
+
+            let deadline = Instant::now() + Duration::from_secs(5);
+            while !events.lock().unwrap().iter().any(|e| e.message == "Paused") {
+                assert!(Instant::now() < deadline, "copy never paused");
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            let held = len(&events);
+            std::thread::sleep(Duration::from_millis(50));
+            assert_eq!(len(&events), held);
+
+            control.resume();
+            assert_eq!(copy.join().unwrap().unwrap(), 100 << 10);
+        });
+
+        let events = events.into_inner().unwrap();
+        let messages: Vec<(&str, Option<u8>)> = events
+            .iter()
+            .map(|e| (e.message.as_str(), e.percent))
+            .filter(|(_, p)| !matches!(p, Some(p) if *p != 50 && *p != 51))
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                ("Copying", Some(50)),
+                ("Paused", None),
+                ("Resumed", None),
+                ("Copying", Some(51)),
+            ]
+        );
+        assert_eq!(events.last().unwrap().percent, Some(100));
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -0,0 +1,182 @@
+//! Where the external commands of an install actually run.
+//!
+//! Every command goes through one executor, which hands it to the runner
+//! set in `InstallOptions::runner` for the duration of the install. Callers
+//! outside this crate can pass their own runner to record or fake the
+//! commands; without one, commands run on the host.
+
+use crate::{CoreError, Result};
+use std::cell::RefCell;
+use std::fmt;
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+
+/// How a command finished.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CommandStatus {
+    /// Exit code; `None` when the command died from a signal.
+    pub code: Option<i32>,
+    pub stderr: String,
+}
+
+impl CommandStatus {
+    /// Exit code 0 with nothing on stderr.
+    pub fn success() -> Self {
+        CommandStatus {
+            code: Some(0),
+            stderr: String::new(),
+        }
+    }
+}
+
+/// How a command whose output is read finished.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CommandOutput {
+    pub status: CommandStatus,
+    pub stdout: Vec<u8>,
+}
+
+pub trait CommandRunner: fmt::Debug + Send + Sync {
+    /// Runs `cmd` to completion. `Err` means it could not be started; a
+    /// nonzero exit is reported in the status.
+    fn run(&self, cmd: &str, args: &[&str]) -> Result<CommandStatus>;
+
+    /// True when `cmd` can be run.
+    fn has_cmd(&self, cmd: &str) -> bool;
+
+    /// True when the device node `node` exists. Runners that fake the
+    /// commands should fake this too, as their device paths are not real.
+    fn node_exists(&self, node: &str) -> bool {
+        std::path::Path::new(node).exists()
+    }
+
+    /// Runs `cmd` to completion and captures its stdout, for the queries
+    /// (`lsblk`) whose output an install reads. Runs on the host unless
+    /// overridden.
+    fn output(&self, cmd: &str, args: &[&str]) -> Result<CommandOutput> {
+        let output = host_command(cmd, args)
+            .stdout(Stdio::piped())
+            .output()
+            .map_err(|e| CoreError::Io(format!("{cmd}: {e}")))?;
+        Ok(CommandOutput {
+            status: CommandStatus {
+                code: output.status.code(),
+                stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+            },
+            stdout: output.stdout,
+        })
+    }
+
+    /// The first 512-byte sector of `device`, where the MBR lives. Reads
+    /// the host device unless overridden, like [`Self::node_exists`].
+    fn read_mbr(&self, device: &str) -> Result<Vec<u8>> {
+        use std::io::Read;
+        let mut sector = vec![0u8; 512];
+        std::fs::File::open(device)
+            .and_then(|mut f| f.read_exact(&mut sector))
+            .map_err(|e| CoreError::Io(format!("{device}: {e}")))?;
+        Ok(sector)
+    }
+}
+
+/// `cmd args` with stderr captured, in a process group of its own: that
+/// keeps a terminal Ctrl-C from killing the tool midway through a write;
+/// the caller cancels through its token and stops before the next command
+/// instead.
+fn host_command(cmd: &str, args: &[&str]) -> Command {
+    let mut command = Command::new(cmd);
+    command.args(args).stderr(Stdio::piped());
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(&mut command, 0);
+    command
+}
+
+/// Runs commands on the host, capturing stderr.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemRunner;
+
+impl CommandRunner for SystemRunner {
+    fn run(&self, cmd: &str, args: &[&str]) -> Result<CommandStatus> {
+        let output = host_command(cmd, args)
+            .spawn()
+            .and_then(|child| child.wait_with_output())
+            .map_err(|e| CoreError::Io(format!("{cmd}: {e}")))?;
+        Ok(CommandStatus {
+            code: output.status.code(),
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        })
+    }
+
+    fn has_cmd(&self, cmd: &str) -> bool {
+        Command::new("sh")
+            .args(["-c", &format!("command -v {cmd} >/dev/null 2>&1")])
+            .status()
+            .map(|s| s.success())
+            .unwrap_or(false)
+    }
+}
+
+thread_local! {
+    static ACTIVE: RefCell<Option<Arc<dyn CommandRunner>>> = const { RefCell::new(None) };
+}
+
+/// Sends commands on this thread to `runner` until the guard is dropped.
+pub(crate) struct ActiveRunner {
+    previous: Option<Arc<dyn CommandRunner>>,
+}
+
+impl ActiveRunner {
+    pub(crate) fn start(runner: &Arc<dyn CommandRunner>) -> Self {
+        let previous = ACTIVE.with(|a| a.borrow_mut().replace(Arc::clone(runner)));
+        ActiveRunner { previous }
+    }
+}
+
+impl Drop for ActiveRunner {
+    fn drop(&mut self) {
+        ACTIVE.with(|a| *a.borrow_mut() = self.previous.take());
+    }
+}
+
+/// The runner started on this thread, if any.
+pub(crate) fn active() -> Option<Arc<dyn CommandRunner>> {
+    ACTIVE.with(|a| a.borrow().clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug)]
+    struct Named(&'static str);
+
+    impl CommandRunner for Named {
+        fn run(&self, _cmd: &str, _args: &[&str]) -> Result<CommandStatus> {
+            Err(CoreError::Io(self.0.to_string()))
+        }
+
+        fn has_cmd(&self, _cmd: &str) -> bool {
+            false
+        }
+    }
+
+    fn active_name() -> Option<String> {
+        active().map(|r| r.run("true", &[]).unwrap_err().to_string())
+    }
+
+    #[test]
+    fn nested_runners_restore_the_outer_one() {
+        assert!(active().is_none());
+        let outer: Arc<dyn CommandRunner> = Arc::new(Named("outer"));
+        let inner: Arc<dyn CommandRunner> = Arc::new(Named("inner"));
+        {
+            let _outer = ActiveRunner::start(&outer);
+            {
+                let _inner = ActiveRunner::start(&inner);
+                assert_eq!(active_name().as_deref(), Some("io error: inner"));
+            }
+            assert_eq!(active_name().as_deref(), Some("io error: outer"));
+        }
+        assert!(active().is_none());
+    }
+}
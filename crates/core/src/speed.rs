@@ -0,0 +1,64 @@
+//! Rough install-time estimates from a measured write speed.
+
+use crate::{CoreError, Result};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::time::Instant;
+
+/// Partitioning, formatting and bootloader setup, which take about the same
+/// time whatever the payload size.
+const SETUP_SECS: u64 = 10;
+
+/// Seconds to expect for an install that writes `payload_bytes` at
+/// `write_speed_bytes_per_sec`, rounded up. A speed of 0 (unknown) is
+/// treated as 1 byte/s rather than dividing by zero.
+pub fn estimate_install_secs(payload_bytes: u64, write_speed_bytes_per_sec: u64) -> u64 {
+    payload_bytes
+        .div_ceil(write_speed_bytes_per_sec.max(1))
+        .saturating_add(SETUP_SECS)
+}
+
+/// Reads `len` bytes at `offset`, writes the same bytes back and fsyncs,
+/// returning the write speed in bytes/s. The contents are left as found.
+pub(crate) fn rewrite_speed(file: &mut File, offset: u64, len: usize) -> Result<u64> {
+    let io = |e: std::io::Error| CoreError::Io(e.to_string());
+    let mut block = vec![0u8; len];
+    file.seek(SeekFrom::Start(offset)).map_err(io)?;
+    file.read_exact(&mut block).map_err(io)?;
+
+    let started = Instant::now();
+    file.seek(SeekFrom::Start(offset)).map_err(io)?;
+    file.write_all(&block).map_err(io)?;
+    file.sync_all().map_err(io)?;
+    let secs = started.elapsed().as_secs_f64().max(1e-6);
+    Ok((len as f64 / secs) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn estimate_rounds_up_and_adds_setup() {
+        let mib = 1 << 20;
+        assert_eq!(estimate_install_secs(0, 10 * mib), SETUP_SECS);
+        assert_eq!(estimate_install_secs(100 * mib, 10 * mib), 10 + SETUP_SECS);
+        assert_eq!(
+            estimate_install_secs(100 * mib + 1, 10 * mib),
+            11 + SETUP_SECS
+        );
+        assert_eq!(estimate_install_secs(5, 0), 5 + SETUP_SECS);
+        assert_eq!(estimate_install_secs(u64::MAX, 1), u64::MAX);
+    }
+
+    #[test]
+    fn rewrite_leaves_contents_unchanged() {
+        let path = std::env::temp_dir().join(format!("raidhos-speed-{}", std::process::id()));
+        let contents: Vec<u8> = (0..=255u8).cycle().take(64 << 10).collect();
+        std::fs::write(&path, &contents).unwrap();
+        let mut file = File::options().read(true).write(true).open(&path).unwrap();
+        assert!(rewrite_speed(&mut file, 4096, 32 << 10).unwrap() > 0);
+        assert_eq!(std::fs::read(&path).unwrap(), contents);
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -0,0 +1,130 @@
+//! syslinux menu rendering, for BIOS machines that boot syslinux more
+//! reliably than GRUB.
+//!
+//! syslinux cannot loop-mount an ISO the way GRUB does, so its menu
+//! chain-loads the BIOS GRUB image on the ESP, which then shows the GRUB
+//! menu and boots the chosen ISO straight from the data partition.
+
+use crate::grub::sanitize;
+use crate::{defaults, BootConfig, CoreError, Result};
+use std::fmt::Write;
+use std::str::FromStr;
+
+/// Boot menu written to the ESP by an install.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MenuBackend {
+    /// `EFI/BOOT/grub.cfg`, loop-mounting each ISO.
+    #[default]
+    Grub,
+    /// `syslinux/syslinux.cfg` for BIOS boot, next to the GRUB menu.
+    Syslinux,
+}
+
+impl FromStr for MenuBackend {
+    type Err = CoreError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "grub" => Ok(MenuBackend::Grub),
+            "syslinux" | "isolinux" => Ok(MenuBackend::Syslinux),
+            other => Err(CoreError::Validation(format!(
+                "unsupported menu backend: {other}"
+            ))),
+        }
+    }
+}
+
+/// Directory on the ESP that holds `syslinux.cfg` and the modules it loads.
+pub(crate) const SYSLINUX_DIR: &str = "syslinux";
+
+/// Modules `syslinux.cfg` loads besides `ldlinux.c32`, which
+/// `syslinux --install` writes itself.
+pub(crate) const SYSLINUX_MODULES: &[&str] = &["menu.c32", "libutil.c32", "libcom32.c32"];
+
+/// GRUB's Linux-kernel header, which lets syslinux load the BIOS core
+/// image as if it were a kernel. Both live where `grub-install
+/// --boot-directory=<esp>/boot` puts them.
+pub(crate) const GRUB_LNXBOOT: &str = "boot/grub/i386-pc/lnxboot.img";
+
+/// BIOS GRUB core image, passed to `lnxboot.img` as its initrd.
+pub(crate) const GRUB_CORE: &str = "boot/grub/i386-pc/core.img";
+
+/// Renders a `syslinux.cfg` whose one entry chain-loads BIOS GRUB, which
+/// reads `boot/grub/grub.cfg` and loop-mounts the ISO picked there.
+/// `config` and `data_label` only name the ISOs in the menu label.
+pub fn render_syslinux_cfg(config: &BootConfig, data_label: &str) -> String {
+    let mut out = String::new();
+    let label = sanitize(data_label);
+    let _ = writeln!(out, "# RaidhOS boot menu; GRUB boots the ISOs from {label}");
+    out.push_str("UI menu.c32\n");
+    out.push_str("PROMPT 0\n");
+    // syslinux counts the timeout in tenths of a second.
+    let _ = writeln!(out, "TIMEOUT {}", defaults::GRUB_TIMEOUT_SECS * 10);
+    out.push_str("MENU TITLE RaidhOS\n");
+    out.push_str("DEFAULT grub\n");
+    let count = config.entries.len();
+    let isos = if count == 1 { "ISO" } else { "ISOs" };
+    let _ = write!(
+        out,
+        "\nLABEL grub\n  MENU LABEL GRUB menu ({count} {isos} on {label})\n  \
+         LINUX /{GRUB_LNXBOOT}\n  INITRD /{GRUB_CORE}\n"
+    );
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BootEntryConfig;
+
+    fn entry(title: &str, path: &str) -> BootEntryConfig {
+        BootEntryConfig {
+            title: title.to_string(),
+            path: path.to_string(),
+            params: "quiet splash".to_string(),
+            initrd: String::new(),
+            kargs: String::new(),
+            size_bytes: None,
+        }
+    }
+
+    #[test]
+    fn syslinux_cfg_chain_loads_bios_grub() {
+        let config = BootConfig {
+            entries: vec![
+                entry("Ubuntu \"24.04\"", "boot/isos/ubuntu.iso"),
+                entry("Debian", "/boot/isos/debian.iso"),
+            ],
+            ..Default::default()
+        };
+        let cfg = render_syslinux_cfg(&config, "RAIDHOS_DATA");
+        assert_eq!(
+            cfg,
+            "# RaidhOS boot menu; GRUB boots the ISOs from RAIDHOS_DATA\n\
+             UI menu.c32\n\
+             PROMPT 0\n\
+             TIMEOUT 50\n\
+             MENU TITLE RaidhOS\n\
+             DEFAULT grub\n\
+             \n\
+             LABEL grub\n\
+             \x20 MENU LABEL GRUB menu (2 ISOs on RAIDHOS_DATA)\n\
+             \x20 LINUX /boot/grub/i386-pc/lnxboot.img\n\
+             \x20 INITRD /boot/grub/i386-pc/core.img\n"
+        );
+        assert!(!cfg.contains("memdisk"));
+    }
+
+    #[test]
+    fn empty_config_still_offers_grub_and_backend_parses() {
+        let cfg = render_syslinux_cfg(&BootConfig::default(), "DATA");
+        assert!(cfg.contains("MENU LABEL GRUB menu (0 ISOs on DATA)\n"));
+        assert_eq!(
+            "ISOLINUX".parse::<MenuBackend>().unwrap(),
+            MenuBackend::Syslinux
+        );
+        assert_eq!("grub".parse::<MenuBackend>().unwrap(), MenuBackend::Grub);
+        assert!("lilo".parse::<MenuBackend>().is_err());
+    }
+}
@@ -0,0 +1,67 @@
+//! Failure injection for integration tests, behind the `test-hooks` feature.
+//!
+//! Lets a test make the Nth external command of an install fail, so cleanup
+//! on error can be exercised end-to-end without real hardware. State is per
+//! thread; installs run on the calling thread.
+
+use crate::{CoreError, Result};
+use std::cell::RefCell;
+
+struct Failure {
+    /// Commands still allowed to run before the failing one.
+    remaining: usize,
+    message: String,
+}
+
+thread_local! {
+    static FAILURE: RefCell<Option<Failure>> = const { RefCell::new(None) };
+}
+
+/// Makes the `index`-th command run from now on (0-based) fail with
+/// `CoreError::Io(message)` instead of running. Fires once.
+pub fn fail_command_at(index: usize, message: &str) {
+    FAILURE.with(|f| {
+        *f.borrow_mut() = Some(Failure {
+            remaining: index,
+            message: message.to_string(),
+        })
+    });
+}
+
+/// Disarms a pending [`fail_command_at`].
+pub fn clear() {
+    FAILURE.with(|f| *f.borrow_mut() = None);
+}
+
+/// Called by the command executor before each command.
+pub(crate) fn before_run() -> Result<()> {
+    FAILURE.with(|f| {
+        let mut slot = f.borrow_mut();
+        let Some(failure) = slot.as_mut() else {
+            return Ok(());
+        };
+        if failure.remaining > 0 {
+            failure.remaining -= 1;
+            return Ok(());
+        }
+        let message = slot.take().map(|f| f.message).unwrap_or_default();
+        Err(CoreError::Io(message))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fails_only_the_chosen_command_once() {
+        fail_command_at(2, "injected");
+        assert!(before_run().is_ok());
+        assert!(before_run().is_ok());
+        assert!(matches!(before_run(), Err(CoreError::Io(m)) if m == "injected"));
+        assert!(before_run().is_ok());
+        fail_command_at(0, "again");
+        clear();
+        assert!(before_run().is_ok());
+    }
+}
@@ -0,0 +1,151 @@
+//! Recursive directory copies with byte-level progress, standing in for
+//! `cp -a` when an install copies its payload.
+
+use crate::progress::{copy_chunks, PercentProgress};
+use crate::{ControlToken, CoreError, Result};
+use std::fs::{self, File};
+use std::path::Path;
+
+/// Read and write size for each file copied.
+const CHUNK_SIZE: usize = 1 << 20;
+
+/// Total size of the regular files under `dir`. Symlinks are not followed.
+pub(crate) fn tree_bytes(dir: &Path) -> Result<u64> {
+    let mut total = 0;
+    for entry in sorted_entries(dir)? {
+        let path = entry.path();
+        let meta = fs::symlink_metadata(&path).map_err(|e| io_error(&path, e))?;
+        if meta.is_dir() {
+            total += tree_bytes(&path)?;
+        } else if meta.is_file() {
+            total += meta.len();
+        }
+    }
+    Ok(total)
+}
+
+/// Copies the contents of `src` into the existing directory `dest`,
+/// advancing `progress` as file data is written. Like `cp -a`, symlinks are
+/// recreated rather than followed and permissions and modification times
+/// are kept; filesystems that cannot store those (FAT, exFAT) get the files
+/// without them. FIFOs, sockets and device nodes are skipped: opening a
+/// FIFO would block, and FAT cannot hold any of them. `control` is checked
+/// before each chunk.
+pub(crate) fn copy_tree(
+    src: &Path,
+    dest: &Path,
+    progress: &mut PercentProgress<'_>,
+    control: &ControlToken,
+) -> Result<()> {
+    for entry in sorted_entries(src)? {
+        let from = entry.path();
+        let to = dest.join(entry.file_name());
+        let meta = fs::symlink_metadata(&from).map_err(|e| io_error(&from, e))?;
+        if meta.file_type().is_symlink() {
+            let target = fs::read_link(&from).map_err(|e| io_error(&from, e))?;
+            if fs::symlink_metadata(&to).is_ok() {
+                fs::remove_file(&to).map_err(|e| io_error(&to, e))?;
+            }
+            std::os::unix::fs::symlink(&target, &to).map_err(|e| io_error(&to, e))?;
+            continue;
+        }
+        if meta.is_dir() {
+            match fs::create_dir(&to) {
+                Err(e) if e.kind() != std::io::ErrorKind::AlreadyExists => {
+                    return Err(io_error(&to, e));
+                }
+                _ => {}
+            }
+            copy_tree(&from, &to, progress, control)?;
+        } else if meta.is_file() {
+            let mut reader = File::open(&from).map_err(|e| io_error(&from, e))?;
+            let mut writer = File::create(&to).map_err(|e| io_error(&to, e))?;
+            copy_chunks(&mut reader, &mut writer, CHUNK_SIZE, progress, control)?;
+            if let Ok(modified) = meta.modified() {
+                let _ = writer.set_modified(modified);
+            }
+        } else {
+            continue;
+        }
+        // After the contents, so a read-only directory can still be filled.
+        let _ = fs::set_permissions(&to, meta.permissions());
+    }
+    Ok(())
+}
+
+/// Entries of `dir` by name, so copies run in a stable order.
+fn sorted_entries(dir: &Path) -> Result<Vec<fs::DirEntry>> {
+    let mut entries = fs::read_dir(dir)
+        .and_then(|entries| entries.collect::<std::io::Result<Vec<_>>>())
+        .map_err(|e| io_error(dir, e))?;
+    entries.sort_by_key(|entry| entry.file_name());
+    Ok(entries)
+}
+
+fn io_error(path: &Path, e: std::io::Error) -> CoreError {
+    CoreError::Io(format!("{}: {e}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ProgressEvent, ProgressSink};
+    use std::cell::RefCell;
+    use std::os::unix::fs::PermissionsExt;
+
+    struct Events(RefCell<Vec<ProgressEvent>>);
+
+    impl ProgressSink for Events {
+        fn emit(&self, event: ProgressEvent) {
+            self.0.borrow_mut().push(event);
+        }
+    }
+
+    #[test]
+    fn copies_a_tree_with_rising_byte_progress() {
+        let root = std::env::temp_dir().join(format!("raidhos-tree-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let (src, dest) = (root.join("src"), root.join("dest"));
+        fs::create_dir_all(src.join("boot/grub")).unwrap();
+        fs::create_dir_all(&dest).unwrap();
+        fs::write(src.join("boot/grub/grub.cfg"), vec![b'g'; 3 << 20]).unwrap();
+        fs::write(src.join("boot/vmlinuz"), vec![b'k'; 5 << 20]).unwrap();
+        fs::write(src.join("run.sh"), b"#!/bin/sh\n").unwrap();
+        fs::set_permissions(src.join("run.sh"), fs::Permissions::from_mode(0o755)).unwrap();
+        std::os::unix::fs::symlink("boot/vmlinuz", src.join("kernel")).unwrap();
+        let fifo = std::process::Command::new("mkfifo")
+            .arg(src.join("pipe"))
+            .status()
+            .unwrap();
+        assert!(fifo.success());
+
+        let total = tree_bytes(&src).unwrap();
+        assert_eq!(total, (8 << 20) + 10);
+        let sink = Events(RefCell::new(Vec::new()));
+        let mut progress = PercentProgress::new(&sink, "payload", "Copying", total)
+            .within(85, 90)
+            .showing_bytes();
+        copy_tree(&src, &dest, &mut progress, &ControlToken::new()).unwrap();
+        progress.finish();
+
+        assert_eq!(fs::read(dest.join("boot/vmlinuz")).unwrap().len(), 5 << 20);
+        let link = fs::read_link(dest.join("kernel")).unwrap();
+        assert_eq!(link, Path::new("boot/vmlinuz"));
+        let perms = fs::metadata(dest.join("run.sh")).unwrap().permissions();
+        assert_eq!(perms.mode() & 0o777, 0o755);
+        assert!(fs::symlink_metadata(dest.join("pipe")).is_err());
+
+        let events = sink.0.into_inner();
+        assert!(events.len() > 2, "{events:?}");
+        for pair in events.windows(2) {
+            assert!(pair[0].bytes_done < pair[1].bytes_done, "{pair:?}");
+            assert!(pair[0].percent <= pair[1].percent, "{pair:?}");
+        }
+        assert!(events.iter().all(|e| e.bytes_total == Some(total)));
+        assert!(events.iter().all(|e| matches!(e.percent, Some(85..=90))));
+        let last = events.last().unwrap();
+        assert_eq!((last.percent, last.bytes_done), (Some(90), Some(total)));
+        assert_eq!(last.message, "Copying (8.0 MiB of 8.0 MiB)");
+        let _ = fs::remove_dir_all(root);
+    }
+}
@@ -0,0 +1,242 @@
+//! Windows disk discovery from the JSON PowerShell's storage cmdlets print.
+//!
+//! `ConvertTo-Json` renders a lone object without the surrounding array,
+//! and enum properties such as `BusType` as numbers or names depending on
+//! the PowerShell version, so both shapes are accepted.
+
+use crate::{CoreError, DiskInfo, Result};
+use serde::Deserialize;
+
+/// `STORAGE_BUS_TYPE` values of the buses removable media sit on, and the
+/// transport names they are reported as.
+const REMOVABLE_BUS_TYPES: &[(u64, &str)] = &[(7, "usb"), (12, "sd"), (13, "mmc")];
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct WinDisk {
+    number: u32,
+    #[serde(default)]
+    friendly_name: Option<String>,
+    #[serde(default)]
+    serial_number: Option<String>,
+    #[serde(default)]
+    size: Option<u64>,
+    #[serde(default)]
+    bus_type: Option<serde_json::Value>,
+    #[serde(default)]
+    is_boot: Option<bool>,
+    #[serde(default)]
+    is_system: Option<bool>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct WinPartition {
+    disk_number: u32,
+    partition_number: u32,
+    #[serde(default)]
+    access_paths: Option<Vec<String>>,
+}
+
+/// One object or an array of them.
+fn list<T: for<'de> Deserialize<'de>>(json: &str) -> Result<Vec<T>> {
+    let value: serde_json::Value = match json.trim() {
+        "" => return Ok(Vec::new()),
+        text => serde_json::from_str(text).map_err(|e| CoreError::Parse(e.to_string()))?,
+    };
+    let items = match value {
+        serde_json::Value::Array(items) => items,
+        serde_json::Value::Null => Vec::new(),
+        single => vec![single],
+    };
+    items
+        .into_iter()
+        .map(|item| T::deserialize(item).map_err(|e| CoreError::Parse(e.to_string())))
+        .collect()
+}
+
+/// The bus name in lowercase, or its numeric `STORAGE_BUS_TYPE` value with
+/// the removable buses named.
+fn bus_name(bus: &serde_json::Value) -> Option<String> {
+    match bus {
+        serde_json::Value::String(name) => Some(name.to_ascii_lowercase()),
+        serde_json::Value::Number(n) => Some(
+            REMOVABLE_BUS_TYPES
+                .iter()
+                .find(|(value, _)| n.as_u64() == Some(*value))
+                .map_or_else(|| n.to_string(), |(_, name)| name.to_string()),
+        ),
+        _ => None,
+    }
+}
+
+/// Disks from `Get-Disk` JSON, with partitions and mountpoints from
+/// `Get-Partition` JSON. Disks are named `\\.\PhysicalDriveN`, which
+/// stays the same for as long as the disk is attached; partitions by
+/// their `\\?\GLOBALROOT` device path. Volume GUID paths are not listed
+/// as mountpoints, only drive letters and folder mounts. `Get-Disk` has no
+/// removable-media property, so USB, SD and MMC disks count as removable.
+/// Partition JSON that does not parse leaves the disks without partitions.
+pub(crate) fn parse_get_disk(disks_json: &str, partitions_json: &str) -> Result<Vec<DiskInfo>> {
+    let parts: Vec<WinPartition> = list(partitions_json).unwrap_or_default();
+    let mut disks: Vec<DiskInfo> = list::<WinDisk>(disks_json)?
+        .into_iter()
+        .map(|disk| {
+            let transport = disk.bus_type.as_ref().and_then(bus_name);
+            let removable = transport
+                .as_deref()
+                .is_some_and(|bus| REMOVABLE_BUS_TYPES.iter().any(|(_, name)| *name == bus));
+            DiskInfo {
+                id: format!(r"\\.\PhysicalDrive{}", disk.number),
+                model: disk.friendly_name.unwrap_or_default().trim().to_string(),
+                serial: disk
+                    .serial_number
+                    .map(|s| s.trim().to_string())
+                    .filter(|s| !s.is_empty()),
+                size_bytes: disk.size,
+                removable,
+                external: removable,
+                transport,
+                mountpoints: Vec::new(),
+                is_system: disk.is_boot.unwrap_or(false) || disk.is_system.unwrap_or(false),
+                partition_count: 0,
+                partitions: Vec::new(),
+            }
+        })
+        .collect();
+    for part in &parts {
+        let id = format!(r"\\.\PhysicalDrive{}", part.disk_number);
+        let Some(disk) = disks.iter_mut().find(|d| d.id == id) else {
+            continue;
+        };
+        disk.partitions.push(format!(
+            r"\\?\GLOBALROOT\Device\Harddisk{}\Partition{}",
+            part.disk_number, part.partition_number
+        ));
+        disk.mountpoints.extend(
+            part.access_paths
+                .iter()
+                .flatten()
+                .filter(|path| !path.starts_with(r"\\?\"))
+                .cloned(),
+        );
+    }
+    for disk in &mut disks {
+        disk.partition_count = disk.partitions.len();
+    }
+    Ok(disks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `Get-Disk` output from a laptop with a USB stick plugged in, from
+    /// Windows PowerShell 5.1, which prints `BusType` as a number.
+    const GET_DISK_JSON: &str = r#"[
+    {
+        "Number":  0,
+        "FriendlyName":  "SAMSUNG MZVLB512HBJQ-000L7",
+        "SerialNumber":  "0025_388B_91B2_3A41.",
+        "Size":  512110190592,
+        "BusType":  17,
+        "IsBoot":  true,
+        "IsSystem":  true
+    },
+    {
+        "Number":  1,
+        "FriendlyName":  "SanDisk Ultra Fit ",
+        "SerialNumber":  "  4C530001230714115392",
+        "Size":  30752000000,
+        "BusType":  7,
+        "IsBoot":  false,
+        "IsSystem":  false
+    }
+]"#;
+
+    const GET_PARTITION_JSON: &str = r#"[
+    {
+        "DiskNumber":  0,
+        "PartitionNumber":  1,
+        "AccessPaths":  [
+                            "\\\\?\\Volume{1b9f6a2e-0000-0000-0000-100000000000}\\"
+                        ]
+    },
+    {
+        "DiskNumber":  0,
+        "PartitionNumber":  3,
+        "AccessPaths":  [
+                            "C:\\",
+                            "\\\\?\\Volume{1b9f6a2e-0000-0000-0000-501f00000000}\\"
+                        ]
+    },
+    {
+        "DiskNumber":  1,
+        "PartitionNumber":  1,
+        "AccessPaths":  [
+                            "E:\\",
+                            "\\\\?\\Volume{8c3e0d41-0000-0000-0000-100000000000}\\"
+                        ]
+    },
+    {
+        "DiskNumber":  1,
+        "PartitionNumber":  2,
+        "AccessPaths":  null
+    }
+]"#;
+
+    #[test]
+    fn get_disk_json_maps_to_disk_info() {
+        let disks = parse_get_disk(GET_DISK_JSON, GET_PARTITION_JSON).unwrap();
+        let ids: Vec<&str> = disks.iter().map(|d| d.id.as_str()).collect();
+        assert_eq!(ids, vec![r"\\.\PhysicalDrive0", r"\\.\PhysicalDrive1"]);
+
+        let internal = &disks[0];
+        assert!(internal.is_system);
+        assert!(!internal.removable);
+        assert_eq!(internal.transport.as_deref(), Some("17"));
+        assert_eq!(internal.mountpoints, vec![r"C:\"]);
+        assert_eq!(internal.partition_count, 2);
+
+        let stick = &disks[1];
+        assert_eq!(stick.model, "SanDisk Ultra Fit");
+        assert_eq!(stick.serial.as_deref(), Some("4C530001230714115392"));
+        assert_eq!(stick.size_bytes, Some(30752000000));
+        assert!(stick.removable && stick.external);
+        assert!(!stick.is_system);
+        assert_eq!(stick.transport.as_deref(), Some("usb"));
+        assert_eq!(stick.mountpoints, vec![r"E:\"]);
+        assert_eq!(
+            stick.partitions,
+            vec![
+                r"\\?\GLOBALROOT\Device\Harddisk1\Partition1",
+                r"\\?\GLOBALROOT\Device\Harddisk1\Partition2",
+            ]
+        );
+    }
+
+    #[test]
+    fn lone_disk_object_and_named_bus_type_are_accepted() {
+        let json = r#"{"Number": 2, "FriendlyName": "Card Reader", "BusType": "USB",
+            "IsBoot": false, "IsSystem": false}"#;
+        let disks = parse_get_disk(json, "").unwrap();
+        assert_eq!(disks.len(), 1);
+        assert!(disks[0].removable);
+        assert_eq!(disks[0].size_bytes, None);
+        assert_eq!(disks[0].serial, None);
+        assert!(parse_get_disk("[{\"FriendlyName\": \"x\"}]", "").is_err());
+    }
+
+    #[test]
+    fn sd_bus_is_removable_and_bad_partition_json_is_ignored() {
+        let json = r#"{"Number": 3, "FriendlyName": "SD Card", "BusType": 12}"#;
+        let disks = parse_get_disk(json, "{not json").unwrap();
+        assert!(disks[0].removable && disks[0].external);
+        assert_eq!(disks[0].transport.as_deref(), Some("sd"));
+
+        let disks = parse_get_disk(GET_DISK_JSON, "{not json").unwrap();
+        assert_eq!(disks.len(), 2);
+        assert!(disks.iter().all(|d| d.partitions.is_empty()));
+        assert!(disks[1].removable);
+    }
+}
@@ -0,0 +1,173 @@
+//! Line protocol between the UI and its elevated install worker.
+//!
+//! The worker prints one JSON object per line on stdout: any number of
+//! `{"type":"progress",...}` lines, then a single `{"type":"result",...}`.
+//! Lines that are not protocol messages (output of tools the worker runs)
+//! are skipped by the reader.
+
+use crate::{CoreError, Envelope, ProgressEvent, Result};
+use serde::{Deserialize, Serialize};
+use std::io::BufRead;
+
+/// Final outcome reported by the worker, in the shared [`Envelope`] shape.
+pub type HelperResponse = Envelope<String>;
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Message {
+    Progress {
+        phase: String,
+        message: String,
+        percent: Option<u8>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        bytes_done: Option<u64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        bytes_total: Option<u64>,
+    },
+    Result(HelperResponse),
+}
+
+/// Protocol line for a progress event, without the trailing newline.
+pub fn progress_line(event: &ProgressEvent) -> String {
+    serde_json::to_string(&Message::Progress {
+        phase: event.phase.clone(),
+        message: event.message.clone(),
+        percent: event.percent,
+        bytes_done: event.bytes_done,
+        bytes_total: event.bytes_total,
+    })
+    .expect("progress message serializes")
+}
+
+/// Protocol line for the final response, without the trailing newline.
+pub fn result_line(response: &HelperResponse) -> String {
+    serde_json::to_string(&Message::Result(response.clone())).expect("result message serializes")
+}
+
+/// Reads a worker's stdout, yielding progress events as they arrive.
+/// Call [`finish`](Self::finish) once iteration ends for the response.
+pub struct WorkerProgress<R> {
+    reader: R,
+    response: Option<HelperResponse>,
+    error: Option<CoreError>,
+}
+
+impl<R: BufRead> WorkerProgress<R> {
+    pub fn new(reader: R) -> Self {
+        WorkerProgress {
+            reader,
+            response: None,
+            error: None,
+        }
+    }
+
+    /// Drains any unread lines and returns the worker's response. Errors
+    /// when the stream could not be read or ended without a result line.
+    pub fn finish(mut self) -> Result<HelperResponse> {
+        for _ in self.by_ref() {}
+        if let Some(err) = self.error {
+            return Err(err);
+        }
+        self.response
+            .ok_or_else(|| CoreError::Parse("worker exited without reporting a result".to_string()))
+    }
+}
+
+impl<R: BufRead> Iterator for WorkerProgress<R> {
+    type Item = ProgressEvent;
+
+    fn next(&mut self) -> Option<ProgressEvent> {
+        if self.response.is_some() || self.error.is_some() {
+            return None;
+        }
+        let mut line = String::new();
+        loop {
+            line.clear();
+            match self.reader.read_line(&mut line) {
+                Ok(0) => return None,
+                Ok(_) => {}
+                Err(e) => {
+                    self.error = Some(CoreError::Io(e.to_string()));
+                    return None;
+                }
+            }
+            match serde_json::from_str(line.trim()) {
+                Ok(Message::Progress {
+                    phase,
+                    message,
+                    percent,
+                    bytes_done,
+                    bytes_total,
+                }) => {
+                    return Some(ProgressEvent {
+                        phase,
+                        message,
+                        percent,
+                        bytes_done,
+                        bytes_total,
+                    })
+                }
+                Ok(Message::Result(response)) => {
+                    self.response = Some(response);
+                    return None;
+                }
+                Err(_) => continue,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STREAM: &str = r#"{"type":"progress","phase":"validate","message":"Validating target /dev/sdb","percent":5}
+mkfs.fat 4.2 (2021-01-31)
+{"type":"progress","phase":"format","message":"Formatting partitions","percent":60}
+
+{"type":"progress","phase":"complete","message":"Install complete in 812 ms.","percent":100}
+{"type":"result","ok":true,"data":"install complete","error":null}
+{"type":"progress","phase":"late","message":"ignored","percent":null}
+"#;
+
+    #[test]
+    fn reads_events_then_response() {
+        let mut reader = WorkerProgress::new(STREAM.as_bytes());
+        let phases: Vec<String> = reader.by_ref().map(|e| e.phase).collect();
+        assert_eq!(phases, vec!["validate", "format", "complete"]);
+        let response = reader.finish().unwrap();
+        assert!(response.ok);
+        assert_eq!(response.data.as_deref(), Some("install complete"));
+    }
+
+    #[test]
+    fn lines_round_trip() {
+        let event = ProgressEvent {
+            phase: "payload".to_string(),
+            message: "Copying \"payload\" files".to_string(),
+            percent: Some(85),
+            bytes_done: Some(3 << 20),
+            bytes_total: Some(8 << 20),
+        };
+        let failed = HelperResponse {
+            ok: false,
+            data: None,
+            error: Some("device not found".to_string()),
+        };
+        let stream = format!("{}\n{}\n", progress_line(&event), result_line(&failed));
+        let mut reader = WorkerProgress::new(stream.as_bytes());
+        let read = reader.next().unwrap();
+        assert_eq!(read.message, event.message);
+        assert_eq!(read.bytes_done, event.bytes_done);
+        assert_eq!(read.bytes_total, event.bytes_total);
+        assert_eq!(reader.finish().unwrap(), failed);
+    }
+
+    #[test]
+    fn stream_without_result_is_an_error() {
+        let stream =
+            "{\"type\":\"progress\",\"phase\":\"validate\",\"message\":\"x\",\"percent\":5}\n";
+        let reader = WorkerProgress::new(stream.as_bytes());
+        assert!(matches!(reader.finish(), Err(CoreError::Parse(_))));
+    }
+}
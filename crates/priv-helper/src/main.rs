@@ -1,39 +1,23 @@
 use raidhos_core as core;
-use serde::Serialize;
-
-#[derive(Serialize)]
-struct HelperResponse<T> {
-    ok: bool,
-    data: Option<T>,
-    error: Option<String>,
-}
 
 fn main() {
     let mut args = std::env::args().skip(1);
-    let cmd = args.next().unwrap_or_else(|| "".to_string());
+    let cmd = args.next().unwrap_or_default();
 
     match cmd.as_str() {
         "list-disks" => {
-            let resp = match core::list_disks() {
-                Ok(disks) => HelperResponse {
-                    ok: true,
-                    data: Some(disks),
-                    error: None,
-                },
-                Err(err) => HelperResponse::<Vec<core::DiskInfo>> {
-                    ok: false,
-                    data: None,
-                    error: Some(err.to_string()),
-                },
-            };
-
-            println!("{}", serde_json::to_string_pretty(&resp).unwrap());
+            println!(
+                "{}",
+                core::Envelope::from_result(core::list_disks()).to_json()
+            );
         }
         "install" => {
             let device = args.next().unwrap_or_default();
             let payload = args.next().unwrap_or_else(|| "1.1.10".to_string());
             let wipe = args.next().as_deref() == Some("true");
             let dry_run = args.next().as_deref() == Some("true");
+            let allow_write = args.next().as_deref() == Some("true");
+            let payload_dir = args.next().filter(|d| !d.is_empty());
 
             let sink = StdoutSink;
             let req = core::InstallRequest {
@@ -41,22 +25,15 @@ fn main() {
                 payload_version: payload,
                 wipe,
                 dry_run,
+                allow_write,
+                arch: core::Arch::default(),
+                payload_dir,
+                require_empty: false,
+                options: core::InstallOptions::default(),
             };
 
-            let resp = match core::install(req, &sink) {
-                Ok(_) => HelperResponse::<()> {
-                    ok: true,
-                    data: Some(()),
-                    error: None,
-                },
-                Err(err) => HelperResponse::<()> {
-                    ok: false,
-                    data: None,
-                    error: Some(err.to_string()),
-                },
-            };
-
-            println!("{}", serde_json::to_string_pretty(&resp).unwrap());
+            let resp = core::Envelope::from_result(core::install(req, &sink).map(|_| ()));
+            println!("{}", resp.to_json());
         }
         _ => {
             eprintln!("usage: raidhos-priv-helper <list-disks|install> [args]");
@@ -1,63 +1,70 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
 use raidhos_core as core;
-mod grub;
+use raidhos_core::{DiskInfo, Holder, IsoEntry, PartitionInfo, ProgressEvent};
 
 use serde::{Deserialize, Serialize};
 use std::sync::Mutex;
 use tauri::State;
 
-#[derive(Clone, Default)]
+#[derive(Default)]
 struct AppState {
     last_events: Mutex<Vec<ProgressEvent>>,
 }
 
 #[derive(Serialize)]
-struct DiskInfo {
-    id: String,
-    model: String,
-    size_bytes: u64,
-    removable: bool,
-    mountpoints: Vec<String>,
-    is_system: bool,
+struct IsoScanSummary {
+    count: usize,
+    total_bytes: u64,
+    by_distro: std::collections::HashMap<String, usize>,
+}
+
+#[derive(Serialize)]
+struct IsoScanResult {
+    entries: Vec<IsoEntry>,
+    summary: IsoScanSummary,
 }
 
-#[derive(Serialize, Clone)]
-struct ProgressEvent {
-    phase: String,
+#[derive(Serialize)]
+struct PreflightIssue {
+    severity: String,
     message: String,
-    percent: Option<u8>,
 }
 
 #[derive(Serialize)]
-struct IsoEntry {
-    title: String,
-    path: String,
+struct CopyItem {
+    source: String,
+    dest: String,
     size_bytes: u64,
-    params: String,
 }
 
 #[derive(Serialize)]
-struct PartitionInfo {
-    id: String,
-    label: String,
-    fstype: String,
-    mountpoints: Vec<String>,
+struct CopyFailure {
+    source: String,
+    error: String,
 }
 
-#[derive(Deserialize)]
-struct BootConfig {
-    entries: Vec<BootEntryConfig>,
-    default_entry: Option<String>,
+#[derive(Serialize)]
+struct CopyReport {
+    copied: Vec<CopyItem>,
+    skipped: Vec<String>,
+    failed: Vec<CopyFailure>,
+    menu_failed: Vec<CopyFailure>,
 }
 
-#[derive(Deserialize)]
-struct BootEntryConfig {
-    title: String,
-    path: String,
-    params: String,
-    initrd: String,
-    kargs: String,
+#[derive(Serialize)]
+struct ToolVersions {
+    parted: Option<String>,
+    mkfs_vfat: Option<String>,
+    exfat: Option<String>,
+    grub_install: Option<String>,
+}
+
+#[derive(Serialize)]
+struct DiskWithPartitions {
+    disk: DiskInfo,
+    partitions: Vec<PartitionInfo>,
+    holders: Vec<Holder>,
 }
 
 struct VecSink<'a> {
@@ -67,11 +74,7 @@ struct VecSink<'a> {
 impl<'a> core::ProgressSink for VecSink<'a> {
     fn emit(&self, event: core::ProgressEvent) {
         let mut guard = self.events.lock().expect("lock events");
-        guard.push(ProgressEvent {
-            phase: event.phase,
-            message: event.message,
-            percent: event.percent,
-        });
+        guard.push(event);
     }
 }
 
@@ -82,22 +85,42 @@ struct InstallArgs {
     wipe: bool,
     dry_run: bool,
     allow_write: bool,
+    #[serde(default)]
+    arch: Option<String>,
+    #[serde(default)]
+    payload_dir: Option<String>,
+    #[serde(default)]
+    data_only: bool,
 }
 
 #[tauri::command]
 fn list_disks() -> Result<Vec<DiskInfo>, String> {
     let disks = core::list_disks().map_err(|e| e.to_string())?;
-    Ok(disks
-        .into_iter()
-        .map(|d| DiskInfo {
-            id: d.id,
-            model: d.model,
-            size_bytes: d.size_bytes,
-            removable: d.removable,
-            mountpoints: d.mountpoints,
-            is_system: d.is_system,
-        })
-        .collect())
+    Ok(disks)
+}
+
+#[derive(Serialize)]
+struct DiskListing {
+    disks: Vec<DiskInfo>,
+    diagnostic: Option<String>,
+    warnings: Vec<String>,
+}
+
+/// `list_disks`, with a reason when an empty list is the environment's fault.
+#[tauri::command]
+fn list_disks_diagnostic() -> Result<DiskListing, String> {
+    let listing = core::list_disks_diagnostic().map_err(|e| e.to_string())?;
+    Ok(DiskListing {
+        disks: listing.disks,
+        diagnostic: listing.diagnostic,
+        warnings: listing.warnings,
+    })
+}
+
+#[tauri::command]
+fn list_install_targets() -> Result<Vec<DiskInfo>, String> {
+    let disks = core::list_install_targets().map_err(|e| e.to_string())?;
+    Ok(disks)
 }
 
 #[tauri::command]
@@ -111,12 +134,27 @@ fn install(args: InstallArgs, state: State<'_, AppState>) -> Result<Vec<Progress
         events: &state.last_events,
     };
 
+    let arch = match args.arch {
+        Some(a) => a.parse().map_err(|e: core::CoreError| e.to_string())?,
+        None => core::Arch::default(),
+    };
     let req = core::InstallRequest {
         device: args.device,
         payload_version: args.payload_version,
         wipe: args.wipe,
         dry_run: args.dry_run,
         allow_write: args.allow_write,
+        arch,
+        payload_dir: args.payload_dir,
+        require_empty: false,
+        options: core::InstallOptions {
+            layout: if args.data_only {
+                core::Layout::DataOnly
+            } else {
+                core::Layout::BootAndData
+            },
+            ..Default::default()
+        },
     };
 
     core::install(req, &sink).map_err(|e| e.to_string())?;
@@ -126,52 +164,108 @@ fn install(args: InstallArgs, state: State<'_, AppState>) -> Result<Vec<Progress
 }
 
 #[tauri::command]
-fn scan_isos(dirs: Vec<String>) -> Result<Vec<IsoEntry>, String> {
-    let entries = core::scan_isos(dirs).map_err(|e| e.to_string())?;
-    Ok(entries
+fn check_target(device: String) -> Result<Vec<PreflightIssue>, String> {
+    let issues = core::check_target(&device).map_err(|e| e.to_string())?;
+    Ok(issues
         .into_iter()
-        .map(|e| IsoEntry {
-            title: e.title,
-            path: e.path,
-            size_bytes: e.size_bytes,
-            params: e.params,
+        .map(|i| PreflightIssue {
+            severity: match i.severity {
+                core::Severity::Warning => "warning".to_string(),
+                core::Severity::Error => "error".to_string(),
+            },
+            message: i.message,
         })
         .collect())
 }
 
+#[tauri::command]
+fn scan_isos_summary(dirs: Vec<String>) -> Result<IsoScanResult, String> {
+    let (entries, summary) = core::scan_isos_summary(dirs).map_err(|e| e.to_string())?;
+    Ok(IsoScanResult {
+        entries,
+        summary: IsoScanSummary {
+            count: summary.count,
+            total_bytes: summary.total_bytes,
+            by_distro: summary.by_distro,
+        },
+    })
+}
+
+#[tauri::command]
+fn scan_isos_on_device(device: String) -> Result<Vec<IsoEntry>, String> {
+    let entries = core::scan_isos_on_device(device).map_err(|e| e.to_string())?;
+    Ok(entries)
+}
+
+#[tauri::command]
+fn mounted_scan_candidates() -> Result<Vec<String>, String> {
+    core::mounted_scan_candidates().map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn scan_isos(dirs: Vec<String>) -> Result<Vec<IsoEntry>, String> {
+    let entries = core::scan_isos(dirs).map_err(|e| e.to_string())?;
+    Ok(entries)
+}
+
+/// Like `scan_isos`, with a SHA-256 per ISO when `compute_hashes` is set.
+#[tauri::command]
+fn scan_isos_with_checksums(
+    dirs: Vec<String>,
+    compute_hashes: bool,
+) -> Result<Vec<IsoEntry>, String> {
+    let entries =
+        core::scan_isos_with_checksums(dirs, compute_hashes).map_err(|e| e.to_string())?;
+    Ok(entries)
+}
+
 #[tauri::command]
 fn list_partitions(device: String) -> Result<Vec<PartitionInfo>, String> {
     let parts = core::list_partitions(device).map_err(|e| e.to_string())?;
-    Ok(parts
+    Ok(parts)
+}
+
+#[tauri::command]
+fn list_disks_tree() -> Result<Vec<DiskWithPartitions>, String> {
+    let tree = core::list_disks_tree().map_err(|e| e.to_string())?;
+    Ok(tree
         .into_iter()
-        .map(|p| PartitionInfo {
-            id: p.id,
-            label: p.label,
-            fstype: p.fstype,
-            mountpoints: p.mountpoints,
+        .map(|t| DiskWithPartitions {
+            disk: t.disk,
+            partitions: t.partitions,
+            holders: t.holders,
         })
         .collect())
 }
 
 #[tauri::command]
-fn save_boot_config(config: BootConfig) -> Result<(), String> {
+fn save_boot_config(config: core::BootConfig) -> Result<(), String> {
     let home = std::env::var("HOME").map_err(|_| "HOME not set".to_string())?;
     let dir = std::path::Path::new(&home).join(".config").join("raidhos");
     std::fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
     let path = dir.join("boot.json");
     let body = serde_json::to_vec_pretty(&config).map_err(|e| e.to_string())?;
-    std::fs::write(path, body).map_err(|e| e.to_string())?;
-    Ok(())
+    core::write_atomic(&path, &body).map_err(|e| e.to_string())
 }
 
 #[tauri::command]
-fn write_boot_config_to_device(mount_path: String, config: BootConfig) -> Result<(), String> {
+fn write_boot_config_to_device(mount_path: String, config: core::BootConfig) -> Result<(), String> {
     let dir = std::path::Path::new(&mount_path).join("raidhos");
     std::fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
     let path = dir.join("boot.json");
     let body = serde_json::to_vec_pretty(&config).map_err(|e| e.to_string())?;
-    std::fs::write(path, body).map_err(|e| e.to_string())?;
-    Ok(())
+    core::write_atomic(&path, &body).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn tool_versions() -> ToolVersions {
+    let v = core::tool_versions();
+    ToolVersions {
+        parted: v.parted,
+        mkfs_vfat: v.mkfs_vfat,
+        exfat: v.exfat,
+        grub_install: v.grub_install,
+    }
 }
 
 #[tauri::command]
@@ -193,10 +287,16 @@ fn get_payload_version() -> Result<String, String> {
     Ok("unknown".to_string())
 }
 
+/// Runs the install through the elevated worker, forwarding each progress
+/// line to the window as an `install-progress` event while it runs.
 #[tauri::command]
-fn install_elevated(device: String, payload_version: String) -> Result<String, String> {
+fn install_elevated(
+    window: tauri::Window,
+    device: String,
+    payload_version: String,
+) -> Result<String, String> {
     let current_exe = std::env::current_exe().map_err(|e| e.to_string())?;
-    let output = std::process::Command::new("pkexec")
+    let mut child = std::process::Command::new("pkexec")
         .arg(current_exe)
         .arg("internal-worker")
         .arg("--task")
@@ -205,14 +305,36 @@ fn install_elevated(device: String, payload_version: String) -> Result<String, S
         .arg(device)
         .arg("--payload-version")
         .arg(payload_version)
-        .output()
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
         .map_err(|e| format!("Failed to launch pkexec: {e}"))?;
 
-    if output.status.success() {
-        Ok(String::from_utf8_lossy(&output.stdout).to_string())
-    } else {
-        let err = String::from_utf8_lossy(&output.stderr).to_string();
-        Err(if err.is_empty() { "Elevation failed or was cancelled by user".to_string() } else { err })
+    let stdout = child.stdout.take().ok_or("worker stdout unavailable")?;
+    let mut stderr = child.stderr.take().ok_or("worker stderr unavailable")?;
+    // Read stderr alongside stdout: a worker that fills the stderr pipe
+    // would otherwise block before closing stdout.
+    let stderr_reader = std::thread::spawn(move || {
+        let mut text = String::new();
+        let _ = std::io::Read::read_to_string(&mut stderr, &mut text);
+        text
+    });
+    let mut progress = core::WorkerProgress::new(std::io::BufReader::new(stdout));
+    for event in progress.by_ref() {
+        let _ = window.emit("install-progress", event);
+    }
+    let response = progress.finish();
+    child.wait().map_err(|e| e.to_string())?;
+    let stderr = stderr_reader.join().unwrap_or_default();
+
+    match response {
+        Ok(core::HelperResponse { ok: true, data, .. }) => Ok(data.unwrap_or_default()),
+        Ok(core::HelperResponse { error, .. }) => {
+            Err(error.unwrap_or_else(|| "install failed".to_string()))
+        }
+        Err(_) => {
+            Err(if stderr.is_empty() { "Elevation failed or was cancelled by user".to_string() } else { stderr })
+        }
     }
 }
 
@@ -246,16 +368,13 @@ fn maybe_run_internal_worker() -> bool {
 
     if task == "install" && !device.is_empty() {
         let res = run_worker_install(&device, &payload_version);
-        match res {
-            Ok(msg) => {
-                println!("{msg}");
-                std::process::exit(0);
-            }
-            Err(err) => {
-                eprintln!("{err}");
-                std::process::exit(1);
-            }
-        }
+        let ok = res.is_ok();
+        let (data, error) = match res {
+            Ok(msg) => (Some(msg), None),
+            Err(err) => (None, Some(err)),
+        };
+        println!("{}", core::result_line(&core::HelperResponse { ok, data, error }));
+        std::process::exit(if ok { 0 } else { 1 });
     }
 
     eprintln!("invalid internal-worker invocation");
@@ -276,8 +395,7 @@ fn run_worker_install(device: &str, payload_version: &str) -> Result<String, Str
     struct StdoutSink;
     impl core::ProgressSink for StdoutSink {
         fn emit(&self, event: core::ProgressEvent) {
-            let pct = event.percent.map(|p| format!("{p}%")).unwrap_or_default();
-            println!("{} {} {}", event.phase, event.message, pct);
+            println!("{}", core::progress_line(&event));
         }
     }
 
@@ -287,6 +405,10 @@ fn run_worker_install(device: &str, payload_version: &str) -> Result<String, Str
         wipe: true,
         dry_run: false,
         allow_write: true,
+        arch: core::Arch::default(),
+        payload_dir: None,
+        require_empty: false,
+        options: core::InstallOptions::default(),
     };
 
     core::install(req, &StdoutSink).map_err(|e| e.to_string())?;
@@ -294,36 +416,117 @@ fn run_worker_install(device: &str, payload_version: &str) -> Result<String, Str
 }
 
 #[tauri::command]
-fn write_grub_cfg_to_esp(esp_mount: String, config: BootConfig, data_label: String) -> Result<(), String> {
-    let cfg = grub::render_grub_cfg(&config, &data_label);
-    let path = std::path::Path::new(&esp_mount)
-        .join("EFI")
-        .join("BOOT")
-        .join("grub.cfg");
-    std::fs::create_dir_all(path.parent().unwrap()).map_err(|e| e.to_string())?;
-    std::fs::write(path, cfg).map_err(|e| e.to_string())?;
+fn write_grub_cfg_to_esp(
+    esp_mount: String,
+    config: core::BootConfig,
+    data_label: Option<String>,
+    boot_mode: Option<String>,
+) -> Result<(), String> {
+    let boot_mode: core::BootMode = match boot_mode {
+        Some(mode) => mode.parse().map_err(|e: core::CoreError| e.to_string())?,
+        None => core::BootMode::default(),
+    };
+    let data_label = match data_label.filter(|l| !l.is_empty()) {
+        Some(label) => label,
+        None => core::detect_data_label(&esp_mount)
+            .map_err(|e| e.to_string())?
+            .unwrap_or_else(|| core::defaults::DATA_LABEL.to_string()),
+    };
+    core::check_esp_mount(&esp_mount).map_err(|e| e.to_string())?;
+    let esp_root = std::path::Path::new(&esp_mount);
+    core::write_grub_cfg(&boot_mode.grub_cfg_paths(esp_root), &config, &data_label)
+        .map_err(|e| e.to_string())?;
+    if config.save_default {
+        core::ensure_grubenv(esp_root).map_err(|e| e.to_string())?;
+    }
+    core::install_fallback_bootloader(esp_root, esp_root, core::Arch::default())
+        .map_err(|e| e.to_string())?;
     Ok(())
 }
 
 #[tauri::command]
-fn copy_isos_to_data(mount_path: String, sources: Vec<String>) -> Result<Vec<String>, String> {
-    let dest_dir = std::path::Path::new(&mount_path).join("boot").join("isos");
-    std::fs::create_dir_all(&dest_dir).map_err(|e| e.to_string())?;
-    let mut copied = Vec::new();
-    for src in sources {
-        let src_path = std::path::Path::new(&src);
-        if !src_path.exists() {
-            continue;
-        }
-        if let Some(name) = src_path.file_name() {
-            let dest = dest_dir.join(name);
-            std::fs::copy(&src_path, &dest).map_err(|e| e.to_string())?;
-            copied.push(dest.display().to_string());
-        }
-    }
-    Ok(copied)
+fn uninstall(device: String, reformat: bool, allow_write: bool) -> Result<(), String> {
+    core::uninstall(&device, reformat, allow_write).map_err(|e| e.to_string())
 }
 
+/// Wipes `device` into one plain `fs` partition; no ESP, no payload.
+#[tauri::command]
+fn format_disk(device: String, fs: String, label: String, allow_write: bool) -> Result<(), String> {
+    let fs = fs.parse().map_err(|e: core::CoreError| e.to_string())?;
+    core::format_disk(&device, fs, &label, allow_write).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn relabel_data(device: String, label: String) -> Result<(), String> {
+    core::relabel_data(&device, &label).map_err(|e| e.to_string())
+}
+
+/// Health check behind the "Verify stick" button.
+#[tauri::command]
+fn verify_stick(device: String) -> Result<core::StickReport, String> {
+    core::verify_stick(&device).map_err(|e| e.to_string())
+}
+
+/// The menu installed on the ESP at `esp_mount`, for editing in place.
+#[tauri::command]
+fn read_grub_cfg(esp_mount: String) -> Result<core::BootConfig, String> {
+    core::read_grub_cfg(std::path::Path::new(&esp_mount)).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn preview_grub_cfg(config: core::BootConfig, data_label: String) -> Result<String, String> {
+    Ok(core::render_grub_cfg(&config, &data_label))
+}
+
+/// ISO paths referenced by `config` that are missing from the data partition.
+#[tauri::command]
+fn verify_boot_config(data_mount: String, config: core::BootConfig) -> Vec<String> {
+    core::missing_isos(std::path::Path::new(&data_mount), &config)
+}
+
+#[tauri::command]
+fn copy_isos_to_data(
+    mount_path: String,
+    sources: Vec<String>,
+    dry_run: Option<bool>,
+    exclude: Option<Vec<String>>,
+    update_menu: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<CopyReport, String> {
+    state.last_events.lock().expect("lock events").clear();
+    let sink = VecSink {
+        events: &state.last_events,
+    };
+    let options = core::CopyOptions {
+        dry_run: dry_run.unwrap_or(false),
+        exclude: exclude.unwrap_or_default(),
+        update_menu: update_menu.map(std::path::PathBuf::from),
+    };
+    let report = core::copy_isos_to_data(&mount_path, &sources, &options, &sink)
+        .map_err(|e| e.to_string())?;
+    Ok(CopyReport {
+        copied: report
+            .copied
+            .into_iter()
+            .map(|c| CopyItem {
+                source: c.source,
+                dest: c.dest,
+                size_bytes: c.size_bytes,
+            })
+            .collect(),
+        skipped: report.skipped,
+        failed: report
+            .failed
+            .into_iter()
+            .map(|(source, error)| CopyFailure { source, error })
+            .collect(),
+        menu_failed: report
+            .menu_failed
+            .into_iter()
+            .map(|(source, error)| CopyFailure { source, error })
+            .collect(),
+    })
+}
 
 fn main() {
     if maybe_run_internal_worker() {
@@ -334,15 +537,31 @@ fn main() {
         .manage(AppState::default())
         .invoke_handler(tauri::generate_handler![
             list_disks,
+            list_disks_diagnostic,
+            list_install_targets,
+            check_target,
             install,
             scan_isos,
+            scan_isos_with_checksums,
+            scan_isos_on_device,
+            scan_isos_summary,
+            mounted_scan_candidates,
             save_boot_config,
             write_boot_config_to_device,
             get_payload_version,
+            tool_versions,
             list_partitions,
+            list_disks_tree,
             write_grub_cfg_to_esp,
+            preview_grub_cfg,
+            read_grub_cfg,
+            verify_boot_config,
             copy_isos_to_data,
-            install_elevated
+            install_elevated,
+            uninstall,
+            relabel_data,
+            format_disk,
+            verify_stick
         ])
         .run(tauri::generate_context!())
         .expect("error while running RaidhOS");